@@ -0,0 +1,333 @@
+use std::io::{self, Read, Write};
+
+use crate::deflate::bits::{BitReader, BitWriter};
+
+/// One-pass adaptive Huffman coding (the FGK algorithm).
+///
+/// Encoder and decoder start from the same tree holding only the NYT ("not
+/// yet transmitted") node and update it identically after every byte, so
+/// no table is sent and the data is read only once. A byte seen for the
+/// first time is sent as the NYT code followed by its 8 bits.
+pub struct AdaptiveHuffmanCodec;
+
+impl crate::codec::Codec for AdaptiveHuffmanCodec {
+    fn name(&self) -> &'static str {
+        "adaptive-huffman"
+    }
+
+    fn encode(&self, data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
+        encode(data, writer)
+    }
+
+    fn decode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+        decode(reader, writer)
+    }
+}
+
+/// At most 256 leaves, 255 internal nodes and the NYT node.
+const MAX_NODES: usize = 2 * 256 + 1;
+
+/// The root never moves, it is the first node created.
+const ROOT: usize = 0;
+
+#[derive(Debug, Clone)]
+struct Node {
+    weight: u64,
+    /// Position in the sibling order; the root has the highest.
+    number: usize,
+    parent: Option<usize>,
+    /// Children for the 0 and 1 bit.
+    children: Option<[usize; 2]>,
+    symbol: Option<u8>,
+}
+
+/// A Huffman tree kept optimal for the bytes seen so far by maintaining the
+/// sibling property: ordered by `number`, node weights never decrease, and
+/// siblings are adjacent.
+struct AdaptiveTree {
+    nodes: Vec<Node>,
+    /// Node id for each number.
+    by_number: Vec<Option<usize>>,
+    leaves: [Option<usize>; 256],
+    nyt: usize,
+}
+
+impl AdaptiveTree {
+    fn new() -> Self {
+        let mut by_number = vec![None; MAX_NODES];
+        by_number[MAX_NODES - 1] = Some(ROOT);
+        Self {
+            nodes: vec![Node {
+                weight: 0,
+                number: MAX_NODES - 1,
+                parent: None,
+                children: None,
+                symbol: None,
+            }],
+            by_number,
+            leaves: [None; 256],
+            nyt: ROOT,
+        }
+    }
+
+    /// Bits leading from the root to `node`.
+    fn code(&self, mut node: usize) -> Vec<bool> {
+        let mut bits = Vec::new();
+        while let Some(parent) = self.nodes[node].parent {
+            bits.push(self.child_slot(parent, node) == 1);
+            node = parent;
+        }
+        bits.reverse();
+        bits
+    }
+
+    /// Splits the NYT node into a new NYT node and a leaf for `symbol`.
+    fn add_leaf(&mut self, symbol: u8) -> usize {
+        let parent = self.nyt;
+        let number = self.nodes[parent].number;
+        let nyt = self.push(Node {
+            weight: 0,
+            number: number - 2,
+            parent: Some(parent),
+            children: None,
+            symbol: None,
+        });
+        let leaf = self.push(Node {
+            weight: 0,
+            number: number - 1,
+            parent: Some(parent),
+            children: None,
+            symbol: Some(symbol),
+        });
+
+        self.nodes[parent].children = Some([nyt, leaf]);
+        self.nyt = nyt;
+        self.leaves[symbol as usize] = Some(leaf);
+        leaf
+    }
+
+    fn push(&mut self, node: Node) -> usize {
+        let id = self.nodes.len();
+        self.by_number[node.number] = Some(id);
+        self.nodes.push(node);
+        id
+    }
+
+    /// Counts one more occurrence of `symbol`. On the way up to the root,
+    /// each node first swaps places with the highest numbered node of the
+    /// same weight, so incrementing it keeps the sibling property.
+    fn update(&mut self, symbol: u8) {
+        let mut node = match self.leaves[symbol as usize] {
+            Some(leaf) => leaf,
+            None => self.add_leaf(symbol),
+        };
+
+        loop {
+            let leader = self.block_leader(node);
+            if leader != node && self.nodes[node].parent != Some(leader) {
+                self.swap(node, leader);
+            }
+            self.nodes[node].weight += 1;
+            match self.nodes[node].parent {
+                Some(parent) => node = parent,
+                None => break,
+            }
+        }
+    }
+
+    /// Highest numbered node with the same weight as `node`.
+    fn block_leader(&self, node: usize) -> usize {
+        let weight = self.nodes[node].weight;
+        let mut leader = node;
+        for number in self.nodes[node].number + 1..MAX_NODES {
+            match self.by_number[number] {
+                Some(next) if self.nodes[next].weight == weight => leader = next,
+                _ => break,
+            }
+        }
+        leader
+    }
+
+    /// Exchanges the places of two nodes, with their subtrees, in the tree
+    /// and in the numbering. Neither may be the root.
+    fn swap(&mut self, a: usize, b: usize) {
+        let parent_a = self.nodes[a].parent.unwrap();
+        let parent_b = self.nodes[b].parent.unwrap();
+        let slot_a = self.child_slot(parent_a, a);
+        let slot_b = self.child_slot(parent_b, b);
+
+        self.nodes[parent_a].children.as_mut().unwrap()[slot_a] = b;
+        self.nodes[parent_b].children.as_mut().unwrap()[slot_b] = a;
+        self.nodes[a].parent = Some(parent_b);
+        self.nodes[b].parent = Some(parent_a);
+
+        let (number_a, number_b) = (self.nodes[a].number, self.nodes[b].number);
+        self.nodes[a].number = number_b;
+        self.nodes[b].number = number_a;
+        self.by_number[number_a] = Some(b);
+        self.by_number[number_b] = Some(a);
+    }
+
+    fn child_slot(&self, parent: usize, child: usize) -> usize {
+        let children = self.nodes[parent].children.unwrap();
+        if children[0] == child {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+/// Encodes data with adaptive Huffman coding.
+///
+/// The bit stream, packed LSB first and padded with zeros to a full byte,
+/// ends with the NYT code followed by a byte that is already in the tree,
+/// which can never introduce a new byte. Empty input encodes to nothing.
+///
+/// Returns the number of bits before padding.
+pub fn encode(data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
+    let Some(&first) = data.first() else {
+        return Ok(0);
+    };
+    let mut tree = AdaptiveTree::new();
+    let mut bits = BitWriter::new(writer);
+
+    for &byte in data {
+        match tree.leaves[byte as usize] {
+            Some(leaf) => write_code(&mut bits, &tree.code(leaf))?,
+            None => {
+                write_code(&mut bits, &tree.code(tree.nyt))?;
+                bits.write_bits(byte as u32, 8)?;
+            }
+        }
+        tree.update(byte);
+    }
+
+    write_code(&mut bits, &tree.code(tree.nyt))?;
+    bits.write_bits(first as u32, 8)?;
+    let num_bits = bits.bits_written();
+    bits.align_to_byte()?;
+
+    Ok(num_bits)
+}
+
+/// Decodes data produced by `encode`.
+///
+/// Returns the number of bytes written to output.
+pub fn decode(reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+    let mut input = Vec::new();
+    reader.read_to_end(&mut input)?;
+    if input.is_empty() {
+        return Ok(0);
+    }
+
+    let mut tree = AdaptiveTree::new();
+    let mut bits = BitReader::new(input.as_slice());
+    let mut output = Vec::new();
+
+    loop {
+        let mut node = ROOT;
+        while let Some(children) = tree.nodes[node].children {
+            node = children[bits.read_bit().map_err(truncated)? as usize];
+        }
+
+        let byte = match tree.nodes[node].symbol {
+            Some(byte) => byte,
+            None => {
+                let byte = bits.read_bits(8).map_err(truncated)? as u8;
+                if tree.leaves[byte as usize].is_some() {
+                    break;
+                }
+                byte
+            }
+        };
+        output.push(byte);
+        tree.update(byte);
+    }
+
+    writer.write_all(&output)?;
+    Ok(output.len())
+}
+
+fn write_code<W: Write>(bits: &mut BitWriter<W>, code: &[bool]) -> io::Result<()> {
+    for &bit in code {
+        bits.write_bits(bit as u32, 1)?;
+    }
+    Ok(())
+}
+
+fn truncated(e: io::Error) -> io::Error {
+    if e.kind() != io::ErrorKind::UnexpectedEof {
+        return e;
+    }
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Adaptive Huffman decode error: data ends before the end marker",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::tests::LOREM;
+
+    fn roundtrip(data: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        let mut decoded = Vec::new();
+
+        encode(data, &mut encoded).expect("Failed to encode");
+        decode(&mut encoded.as_slice(), &mut decoded).expect("Failed to decode");
+        decoded
+    }
+
+    #[test]
+    fn encodes_and_decodes_text() {
+        assert_eq!(roundtrip(LOREM), LOREM);
+    }
+
+    #[test]
+    fn encodes_and_decodes_edge_cases() {
+        assert_eq!(roundtrip(b""), b"");
+        assert_eq!(roundtrip(b"a"), b"a");
+        assert_eq!(roundtrip(b"aaaaaaaa"), b"aaaaaaaa");
+
+        let all_bytes: Vec<u8> = (0..=255).cycle().take(2000).collect();
+        assert_eq!(roundtrip(&all_bytes), all_bytes);
+    }
+
+    #[test]
+    fn keeps_the_sibling_property() {
+        let mut tree = AdaptiveTree::new();
+        for &byte in b"abracadabra, the quick brown fox" {
+            tree.update(byte);
+
+            let weights: Vec<u64> = tree
+                .by_number
+                .iter()
+                .flatten()
+                .map(|&node| tree.nodes[node].weight)
+                .collect();
+            assert!(weights.windows(2).all(|pair| pair[0] <= pair[1]));
+        }
+        assert_eq!(tree.nodes[ROOT].weight, 32);
+    }
+
+    #[test]
+    fn needs_no_header_for_short_messages() {
+        let mut encoded = Vec::new();
+        encode(b"hello hello hello", &mut encoded).unwrap();
+
+        // Shorter than the 17 byte message itself
+        assert!(encoded.len() < 17);
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let mut encoded = Vec::new();
+        encode(LOREM, &mut encoded).unwrap();
+        encoded.truncate(encoded.len() / 2);
+
+        let err = decode(&mut encoded.as_slice(), &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
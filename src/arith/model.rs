@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+/// Number of symbols: every byte value and the end of data marker.
+pub const NUM_SYMBOLS: usize = 257;
+
+/// Symbol that ends the data.
+pub const END: u16 = 256;
+
+/// Frequencies are kept at or below this total, so a symbol always gets a
+/// nonzero share of the coder's range.
+pub const MAX_TOTAL: u32 = 1 << 16;
+
+/// Highest context order of `AdaptiveModel`; the context is then the
+/// previous 4 bytes packed into a `u32`.
+pub const MAX_ORDER: u8 = 4;
+
+/// Most contexts an adaptive model keeps frequencies for, about 1K each.
+/// Once full, it forgets them all and starts learning again, which bounds
+/// the memory high orders take on data with few repeated contexts.
+pub const MAX_CONTEXTS: usize = 1 << 14;
+
+/// How much an adaptive model adds to a symbol's frequency each time it is
+/// seen. Larger steps learn faster, the starting frequency of 1 for unseen
+/// symbols becomes small next to it.
+const INCREMENT: u32 = 32;
+
+/// Symbol frequencies, from which the coder takes each symbol's slice
+/// `low..high` of the cumulative total.
+#[derive(Debug, Clone)]
+pub struct Frequencies {
+    counts: Vec<u32>,
+    total: u32,
+}
+
+impl Frequencies {
+    /// Every symbol with a frequency of 1.
+    pub fn uniform() -> Self {
+        Self {
+            counts: vec![1; NUM_SYMBOLS],
+            total: NUM_SYMBOLS as u32,
+        }
+    }
+
+    fn from_counts(counts: Vec<u32>) -> Self {
+        let total = counts.iter().sum();
+        Self { counts, total }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+
+    /// The cumulative range of `symbol`. Empty for symbols that never occur.
+    pub fn range(&self, symbol: u16) -> (u32, u32) {
+        let low = self.counts[..symbol as usize].iter().sum();
+        (low, low + self.counts[symbol as usize])
+    }
+
+    /// The symbol whose range contains `target`, which must be below the
+    /// total, with that range.
+    pub fn find(&self, target: u32) -> (u16, u32, u32) {
+        let mut low = 0;
+        for (symbol, &count) in self.counts.iter().enumerate() {
+            if target < low + count {
+                return (symbol as u16, low, low + count);
+            }
+            low += count;
+        }
+        unreachable!("target {} is beyond the total {}", target, self.total)
+    }
+
+    /// Counts another occurrence of `symbol`, halving all frequencies once
+    /// the total grows too large. Halving also makes older statistics count
+    /// less than recent ones.
+    fn increment(&mut self, symbol: u16) {
+        self.counts[symbol as usize] += INCREMENT;
+        self.total += INCREMENT;
+        if self.total > MAX_TOTAL {
+            for count in &mut self.counts {
+                *count = count.div_ceil(2);
+            }
+            self.total = self.counts.iter().sum();
+        }
+    }
+}
+
+/// A probability model for the arithmetic coder. Encoder and decoder run
+/// the same model and see the same symbols, so they always agree on the
+/// frequencies.
+pub trait Model {
+    /// Frequencies to code the next symbol with.
+    fn frequencies(&mut self) -> &Frequencies;
+
+    /// Learns from `symbol`, which was just coded.
+    fn update(&mut self, symbol: u16);
+}
+
+/// Fixed order-0 frequencies counted up front and stored in a header.
+#[derive(Debug, Clone)]
+pub struct StaticModel {
+    frequencies: Frequencies,
+}
+
+impl StaticModel {
+    /// Counts the bytes of `data`, scaled down to fit `MAX_TOTAL`. Bytes
+    /// that occur keep a frequency of at least 1, the end marker gets 1.
+    pub fn new(data: &[u8]) -> Self {
+        let mut counts = [0u64; 256];
+        for &byte in data {
+            counts[byte as usize] += 1;
+        }
+
+        // Leaves room for each byte being rounded up to 1, and for the end
+        let scale = (MAX_TOTAL - NUM_SYMBOLS as u32) as u64;
+        let len = data.len().max(1) as u64;
+        let mut scaled: Vec<u32> = counts
+            .iter()
+            .map(|&count| match count {
+                0 => 0,
+                count => (count * scale / len).max(1) as u32,
+            })
+            .collect();
+        scaled.push(1);
+
+        Self {
+            frequencies: Frequencies::from_counts(scaled),
+        }
+    }
+
+    /// Writes the number of distinct bytes (2 bytes), then each byte with
+    /// its frequency (2 bytes).
+    pub fn serialize<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        let entries: Vec<(u8, u16)> = self.frequencies.counts[..256]
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(byte, &count)| (byte as u8, count as u16))
+            .collect();
+
+        writer.write_all(&(entries.len() as u16).to_be_bytes())?;
+        for (byte, count) in entries {
+            writer.write_all(&[byte])?;
+            writer.write_all(&count.to_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn deserialize<R: Read + ?Sized>(reader: &mut R) -> io::Result<Self> {
+        let mut len = [0u8; 2];
+        reader.read_exact(&mut len)?;
+        let len = u16::from_be_bytes(len);
+        if len > 256 {
+            return Err(invalid_header());
+        }
+
+        let mut counts = vec![0u32; NUM_SYMBOLS];
+        counts[END as usize] = 1;
+        for _ in 0..len {
+            let mut entry = [0u8; 3];
+            reader.read_exact(&mut entry)?;
+            let count = u16::from_be_bytes([entry[1], entry[2]]) as u32;
+            if count == 0 || counts[entry[0] as usize] != 0 {
+                return Err(invalid_header());
+            }
+            counts[entry[0] as usize] = count;
+        }
+
+        let frequencies = Frequencies::from_counts(counts);
+        if frequencies.total > MAX_TOTAL {
+            return Err(invalid_header());
+        }
+        Ok(Self { frequencies })
+    }
+}
+
+impl Model for StaticModel {
+    fn frequencies(&mut self) -> &Frequencies {
+        &self.frequencies
+    }
+
+    fn update(&mut self, _symbol: u16) {}
+}
+
+/// Frequencies learned while coding, kept separately for each context of
+/// the previous `order` bytes. Nothing is stored in the output, every
+/// context starts out uniform.
+///
+/// Higher orders predict better once they have learned, but spread what
+/// they learn over many more contexts, of which at most `MAX_CONTEXTS` are
+/// kept.
+#[derive(Debug, Clone)]
+pub struct AdaptiveModel {
+    mask: u32,
+    context: u32,
+    contexts: HashMap<u32, Frequencies>,
+    /// Frequencies of the contexts not seen yet.
+    uniform: Frequencies,
+}
+
+impl AdaptiveModel {
+    /// `order` must be at most `MAX_ORDER`.
+    pub fn new(order: u8) -> Self {
+        assert!(order <= MAX_ORDER, "context order above {}", MAX_ORDER);
+        Self {
+            mask: ((1u64 << (8 * order)) - 1) as u32,
+            context: 0,
+            contexts: HashMap::new(),
+            uniform: Frequencies::uniform(),
+        }
+    }
+}
+
+impl Model for AdaptiveModel {
+    fn frequencies(&mut self) -> &Frequencies {
+        self.contexts.get(&self.context).unwrap_or(&self.uniform)
+    }
+
+    fn update(&mut self, symbol: u16) {
+        if self.contexts.len() == MAX_CONTEXTS && !self.contexts.contains_key(&self.context) {
+            self.contexts.clear();
+        }
+        self.contexts
+            .entry(self.context)
+            .or_insert_with(Frequencies::uniform)
+            .increment(symbol);
+        self.context = ((self.context as u64) << 8 | symbol as u64) as u32 & self.mask;
+    }
+}
+
+fn invalid_header() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Arithmetic decode error: invalid frequency table",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_symbols_by_cumulative_frequency() {
+        let mut counts = vec![0; NUM_SYMBOLS];
+        counts[b'a' as usize] = 3;
+        counts[b'b' as usize] = 1;
+        counts[END as usize] = 1;
+        let frequencies = Frequencies::from_counts(counts);
+
+        assert_eq!(frequencies.range(b'a' as u16), (0, 3));
+        assert_eq!(frequencies.range(b'b' as u16), (3, 4));
+        assert_eq!(frequencies.find(2), (b'a' as u16, 0, 3));
+        assert_eq!(frequencies.find(4), (END, 4, 5));
+    }
+
+    #[test]
+    fn scales_static_frequencies_to_fit() {
+        let mut data = vec![b'a'; 1_000_000];
+        data.push(b'b');
+        let mut model = StaticModel::new(&data);
+
+        let frequencies = model.frequencies();
+        assert!(frequencies.total() <= MAX_TOTAL);
+        assert_eq!(
+            frequencies.range(b'b' as u16).1 - frequencies.range(b'b' as u16).0,
+            1
+        );
+
+        let mut header = Vec::new();
+        model.serialize(&mut header).unwrap();
+        assert_eq!(header.len(), 2 + 2 * 3);
+        let mut read = StaticModel::deserialize(&mut header.as_slice()).unwrap();
+        assert_eq!(read.frequencies().counts, model.frequencies().counts);
+    }
+
+    #[test]
+    fn rejects_invalid_static_headers() {
+        let duplicate = [0, 2, b'a', 0, 1, b'a', 0, 1];
+        assert!(StaticModel::deserialize(&mut &duplicate[..]).is_err());
+
+        let too_large = [0, 2, b'a', 255, 0, b'b', 255, 0];
+        assert!(StaticModel::deserialize(&mut &too_large[..]).is_err());
+    }
+
+    #[test]
+    fn adaptive_model_learns_per_context() {
+        let mut model = AdaptiveModel::new(1);
+        for &byte in b"ab".repeat(20).iter() {
+            model.update(byte as u16);
+        }
+
+        // After an 'a' a 'b' is likely, after a 'b' an 'a'
+        let after_b = model.frequencies().clone();
+        model.update(b'a' as u16);
+        let after_a = model.frequencies();
+        let (low, high) = after_a.range(b'b' as u16);
+        assert!(high - low > after_a.total() / 2);
+        let (low, high) = after_b.range(b'a' as u16);
+        assert!(high - low > after_b.total() / 2);
+    }
+
+    #[test]
+    fn limits_the_number_of_contexts() {
+        let mut model = AdaptiveModel::new(MAX_ORDER);
+        // Looking at a context does not keep it
+        model.frequencies();
+        assert!(model.contexts.is_empty());
+
+        let mut state = 1u32;
+        let mut cleared = false;
+        for _ in 0..4 * MAX_CONTEXTS {
+            let len = model.contexts.len();
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            model.update((state >> 24) as u16);
+            assert!(model.contexts.len() <= MAX_CONTEXTS);
+            cleared |= model.contexts.len() < len;
+        }
+        assert!(cleared);
+    }
+
+    #[test]
+    fn halves_frequencies_at_the_limit() {
+        let mut model = AdaptiveModel::new(0);
+        for _ in 0..10_000 {
+            model.update(b'x' as u16);
+        }
+
+        let frequencies = model.frequencies();
+        assert!(frequencies.total() <= MAX_TOTAL);
+        assert_eq!(frequencies.range(0), (0, 1));
+    }
+}
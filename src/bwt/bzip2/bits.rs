@@ -0,0 +1,145 @@
+use std::io::{self, BufRead, Write};
+
+/// Reads a bzip2 bit stream: bits are packed starting at the most
+/// significant bit of each byte.
+pub struct BitReader<R: BufRead> {
+    inner: R,
+    bit_buf: u64,
+    bit_count: u8,
+}
+
+impl<R: BufRead> BitReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            bit_buf: 0,
+            bit_count: 0,
+        }
+    }
+
+    /// Reads `count` (at most 32) bits as an integer, first bit in the MSB.
+    pub fn read_bits(&mut self, count: u8) -> io::Result<u32> {
+        while self.bit_count < count {
+            let byte = match self.inner.fill_buf()? {
+                [] => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                [byte, ..] => *byte,
+            };
+            self.inner.consume(1);
+            self.bit_buf = self.bit_buf << 8 | byte as u64;
+            self.bit_count += 8;
+        }
+
+        self.bit_count -= count;
+        let value = (self.bit_buf >> self.bit_count) & ((1u64 << count) - 1);
+        Ok(value as u32)
+    }
+
+    pub fn read_bit(&mut self) -> io::Result<bool> {
+        Ok(self.read_bits(1)? == 1)
+    }
+
+    /// Drops the bits remaining in the current byte.
+    pub fn align_to_byte(&mut self) {
+        self.bit_count -= self.bit_count % 8;
+    }
+
+    /// True once every bit was read.
+    pub fn is_empty(&mut self) -> io::Result<bool> {
+        Ok(self.bit_count == 0 && self.inner.fill_buf()?.is_empty())
+    }
+}
+
+/// Writes a bzip2 bit stream, in whole bytes once there are enough of them.
+pub struct BitWriter<W: Write> {
+    inner: W,
+    bit_buf: u64,
+    bit_count: u8,
+    buffer: Vec<u8>,
+    pub bytes_written: u64,
+}
+
+impl<W: Write> BitWriter<W> {
+    const FLUSH_LEN: usize = 1 << 16;
+
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            bit_buf: 0,
+            bit_count: 0,
+            buffer: Vec::new(),
+            bytes_written: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Writes the low `count` (at most 32) bits of `value`, MSB first.
+    pub fn write_bits(&mut self, value: u32, count: u8) -> io::Result<()> {
+        self.bit_buf = self.bit_buf << count | value as u64 & ((1u64 << count) - 1);
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.bit_count -= 8;
+            self.buffer.push((self.bit_buf >> self.bit_count) as u8);
+        }
+        if self.buffer.len() >= Self::FLUSH_LEN {
+            self.write_buffer()?;
+        }
+        Ok(())
+    }
+
+    pub fn write_bit(&mut self, bit: bool) -> io::Result<()> {
+        self.write_bits(bit as u32, 1)
+    }
+
+    /// Pads the last byte with zeros and writes out everything.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.bit_count > 0 {
+            self.write_bits(0, 8 - self.bit_count)?;
+        }
+        self.write_buffer()
+    }
+
+    fn write_buffer(&mut self) -> io::Result<()> {
+        self.inner.write_all(&self.buffer)?;
+        self.bytes_written += self.buffer.len() as u64;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Flushes the underlying writer. Bits of a partial byte stay behind.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.write_buffer()?;
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_bits_msb_first() {
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(0b101, 3).unwrap();
+        writer.write_bits(0x3141_5926, 32).unwrap();
+        writer.write_bit(true).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(writer.bytes_written, 5);
+        let data = writer.into_inner();
+        assert_eq!(data[0], 0b1010_0110);
+
+        let mut reader = BitReader::new(&data[..]);
+        assert_eq!(reader.read_bits(3).unwrap(), 0b101);
+        assert_eq!(reader.read_bits(32).unwrap(), 0x3141_5926);
+        assert!(reader.read_bit().unwrap());
+        reader.align_to_byte();
+        assert!(reader.is_empty().unwrap());
+        assert!(reader.read_bit().is_err());
+    }
+}
@@ -0,0 +1,435 @@
+//! The contents of a bzip2 block after its CRC: the BWT's origin row, the
+//! bytes in use, Huffman tables with the selectors picking one for every 50
+//! symbols, and the move-to-front indices with their runs of zeros coded
+//! in bijective base 2 as in `zero_run`.
+
+use std::io::{self, BufRead, Write};
+
+use super::bits::{BitReader, BitWriter};
+use super::{invalid_data, RAND_NUMS};
+use crate::bwt::{inverse_cyclic, mtf, transform_cyclic};
+use crate::deflate::codes::code_lengths;
+
+/// Symbols worth 1 and 2 in a zero run's length.
+const RUN_A: u16 = 0;
+const RUN_B: u16 = 1;
+
+/// Symbols coded with the same table.
+const GROUP_SIZE: usize = 50;
+const MIN_TABLES: usize = 2;
+const MAX_TABLES: usize = 6;
+/// Most selectors used; later ones, written by some encoders, are ignored.
+const MAX_SELECTORS: usize = 18002;
+const MAX_CODE_LEN: u8 = 20;
+/// Longest code written, the same limit as the reference encoder.
+const ENCODE_CODE_LEN: u8 = 17;
+/// Passes refining the tables and the selectors.
+const TABLE_ITERATIONS: usize = 4;
+
+/// Writes the block made of `block`, the data after its runs of 4 to 255
+/// equal bytes are shortened.
+pub fn write_block<W: Write>(bits: &mut BitWriter<W>, block: &[u8]) -> io::Result<()> {
+    // Bytes are numbered by rank among those in use
+    let mut in_use = [false; 256];
+    for &byte in block {
+        in_use[byte as usize] = true;
+    }
+    let mut ranks = [0u8; 256];
+    let mut num_in_use = 0;
+    for byte in 0..256 {
+        if in_use[byte] {
+            ranks[byte] = num_in_use as u8;
+            num_in_use += 1;
+        }
+    }
+
+    let (last, origin) = transform_cyclic(block);
+    let ranked: Vec<u8> = last.iter().map(|&byte| ranks[byte as usize]).collect();
+    let symbols = zero_runs(&mtf::encode(&ranked), num_in_use);
+    let alphabet_size = num_in_use + 2;
+    let (tables, selectors) = choose_tables(&symbols, alphabet_size);
+
+    bits.write_bits(0, 1)?; // Not randomized
+    bits.write_bits(origin as u32, 24)?;
+
+    let groups: Vec<bool> = in_use.chunks(16).map(|g| g.contains(&true)).collect();
+    for &used in &groups {
+        bits.write_bit(used)?;
+    }
+    for (group, _) in in_use.chunks(16).zip(&groups).filter(|(_, &used)| used) {
+        for &used in group {
+            bits.write_bit(used)?;
+        }
+    }
+
+    bits.write_bits(tables.len() as u32, 3)?;
+    bits.write_bits(selectors.len() as u32, 15)?;
+    let selector_indices: Vec<u8> = mtf::encode(&selectors);
+    for &index in &selector_indices {
+        for _ in 0..index {
+            bits.write_bit(true)?;
+        }
+        bits.write_bit(false)?;
+    }
+
+    // Code lengths, each as a difference from the one before
+    for lengths in &tables {
+        let mut current = lengths[0];
+        bits.write_bits(current as u32, 5)?;
+        for &len in lengths {
+            while current != len {
+                bits.write_bits(if current < len { 0b10 } else { 0b11 }, 2)?;
+                current = if current < len {
+                    current + 1
+                } else {
+                    current - 1
+                };
+            }
+            bits.write_bit(false)?;
+        }
+    }
+
+    let codes: Vec<Vec<u32>> = tables.iter().map(|lengths| assign_codes(lengths)).collect();
+    for (group, &selector) in symbols.chunks(GROUP_SIZE).zip(&selectors) {
+        let (codes, lengths) = (&codes[selector as usize], &tables[selector as usize]);
+        for &symbol in group {
+            bits.write_bits(codes[symbol as usize], lengths[symbol as usize])?;
+        }
+    }
+    Ok(())
+}
+
+/// Turns move-to-front indices into symbols: runs of zeros as `RUN_A` and
+/// `RUN_B` digits, other indices shifted up by one, then the end of block
+/// symbol.
+fn zero_runs(indices: &[u8], num_in_use: usize) -> Vec<u16> {
+    let mut symbols = Vec::with_capacity(indices.len() + 1);
+    let mut run = 0usize;
+    let push_run = |symbols: &mut Vec<u16>, run: &mut usize| {
+        while *run > 0 {
+            let digit = (*run - 1) & 1;
+            symbols.push(RUN_A + digit as u16);
+            *run = (*run - 1 - digit) / 2;
+        }
+    };
+
+    for &index in indices {
+        if index == 0 {
+            run += 1;
+            continue;
+        }
+        push_run(&mut symbols, &mut run);
+        symbols.push(index as u16 + 1);
+    }
+    push_run(&mut symbols, &mut run);
+    symbols.push(num_in_use as u16 + 1);
+    symbols
+}
+
+/// Picks code lengths for 2 to 6 tables and the table for each group of
+/// symbols, like the reference encoder: the tables start out covering
+/// slices of the alphabet of about equal frequency, then each group moves
+/// to the table coding it in the fewest bits and the tables are rebuilt
+/// from the groups they got, a few times over.
+fn choose_tables(symbols: &[u16], alphabet_size: usize) -> (Vec<Vec<u8>>, Vec<u8>) {
+    let num_tables = match symbols.len() {
+        0..200 => 2,
+        200..600 => 3,
+        600..1200 => 4,
+        1200..2400 => 5,
+        _ => 6,
+    };
+    let mut freqs = vec![0u32; alphabet_size];
+    for &symbol in symbols {
+        freqs[symbol as usize] += 1;
+    }
+
+    let mut tables = vec![vec![15u8; alphabet_size]; num_tables];
+    let mut remaining = symbols.len() as u32;
+    let mut start = 0;
+    for part in (0..num_tables).rev() {
+        let target = remaining / (part as u32 + 1);
+        let mut end = start;
+        let mut taken = 0;
+        while taken < target && end < alphabet_size {
+            taken += freqs[end];
+            end += 1;
+        }
+        // Alternate slices stop one symbol short, as in the reference
+        if end > start + 1
+            && part != num_tables - 1
+            && part != 0
+            && (num_tables - 1 - part) % 2 == 1
+        {
+            end -= 1;
+            taken -= freqs[end];
+        }
+        tables[part][start..end].fill(0);
+        remaining -= taken;
+        start = end;
+    }
+
+    let mut selectors = Vec::new();
+    for _ in 0..TABLE_ITERATIONS {
+        let mut table_freqs = vec![vec![0u32; alphabet_size]; num_tables];
+        selectors.clear();
+        for group in symbols.chunks(GROUP_SIZE) {
+            let cost = |lengths: &Vec<u8>| -> u32 {
+                group.iter().map(|&s| lengths[s as usize] as u32).sum()
+            };
+            let best = (0..num_tables).min_by_key(|&t| cost(&tables[t])).unwrap();
+            for &symbol in group {
+                table_freqs[best][symbol as usize] += 1;
+            }
+            selectors.push(best as u8);
+        }
+        for (lengths, freqs) in tables.iter_mut().zip(&mut table_freqs) {
+            // Every symbol needs a code, even those never used
+            for freq in freqs.iter_mut() {
+                *freq = (*freq).max(1);
+            }
+            *lengths = code_lengths(freqs, ENCODE_CODE_LEN);
+        }
+    }
+
+    (tables, selectors)
+}
+
+/// Canonical codes for the given lengths, by length and then by symbol.
+fn assign_codes(lengths: &[u8]) -> Vec<u32> {
+    let mut codes = vec![0; lengths.len()];
+    let mut code = 0;
+    for len in 1..=MAX_CODE_LEN {
+        for (symbol, _) in lengths.iter().enumerate().filter(|(_, &l)| l == len) {
+            codes[symbol] = code;
+            code += 1;
+        }
+        code <<= 1;
+    }
+    codes
+}
+
+/// Decodes a canonical code by reading bits until the code read so far is
+/// one of those of its length, which form a contiguous range.
+struct Table {
+    min_len: u8,
+    /// First code of each length, and its index in `symbols`.
+    first: [u32; MAX_CODE_LEN as usize + 2],
+    offsets: [u32; MAX_CODE_LEN as usize + 2],
+    counts: [u32; MAX_CODE_LEN as usize + 2],
+    symbols: Vec<u16>,
+}
+
+impl Table {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u32; MAX_CODE_LEN as usize + 2];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        let mut first = [0u32; MAX_CODE_LEN as usize + 2];
+        let mut offsets = [0u32; MAX_CODE_LEN as usize + 2];
+        let (mut code, mut offset) = (0u32, 0u32);
+        for len in 1..=MAX_CODE_LEN as usize {
+            first[len] = code;
+            offsets[len] = offset;
+            code = (code + counts[len]) << 1;
+            offset += counts[len];
+        }
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16).collect();
+        symbols.sort_by_key(|&symbol| lengths[symbol as usize]);
+
+        Self {
+            min_len: lengths.iter().copied().min().unwrap_or(1),
+            first,
+            offsets,
+            counts,
+            symbols,
+        }
+    }
+
+    fn decode<R: BufRead>(&self, bits: &mut BitReader<R>) -> io::Result<u16> {
+        let mut len = self.min_len as usize;
+        let mut code = bits.read_bits(self.min_len)?;
+        loop {
+            let index = code.wrapping_sub(self.first[len]);
+            if index < self.counts[len] {
+                return Ok(self.symbols[(self.offsets[len] + index) as usize]);
+            }
+            len += 1;
+            if len > MAX_CODE_LEN as usize {
+                return Err(invalid_data("invalid Huffman code"));
+            }
+            code = code << 1 | bits.read_bits(1)?;
+        }
+    }
+}
+
+/// Reads the block after its CRC, up to the data with runs of equal bytes
+/// still shortened. `max_len` is the block size of the stream.
+pub fn read_block<R: BufRead>(bits: &mut BitReader<R>, max_len: usize) -> io::Result<Vec<u8>> {
+    let randomized = bits.read_bit()?;
+    let origin = bits.read_bits(24)? as usize;
+
+    let groups = bits.read_bits(16)?;
+    let mut bytes_in_use = Vec::new();
+    for group in 0..16 {
+        if groups & (0x8000 >> group) != 0 {
+            let used = bits.read_bits(16)?;
+            for i in 0..16 {
+                if used & (0x8000 >> i) != 0 {
+                    bytes_in_use.push((group * 16 + i) as u8);
+                }
+            }
+        }
+    }
+    if bytes_in_use.is_empty() {
+        return Err(invalid_data("block uses no bytes"));
+    }
+    let alphabet_size = bytes_in_use.len() + 2;
+    let end_of_block = alphabet_size as u16 - 1;
+
+    let num_tables = bits.read_bits(3)? as usize;
+    if !(MIN_TABLES..=MAX_TABLES).contains(&num_tables) {
+        return Err(invalid_data("invalid number of Huffman tables"));
+    }
+    let num_selectors = bits.read_bits(15)? as usize;
+    if num_selectors == 0 {
+        return Err(invalid_data("block has no selectors"));
+    }
+    let mut selector_indices = Vec::with_capacity(num_selectors.min(MAX_SELECTORS));
+    for i in 0..num_selectors {
+        let mut index = 0;
+        while bits.read_bit()? {
+            index += 1;
+            if index >= num_tables {
+                return Err(invalid_data("invalid selector"));
+            }
+        }
+        if i < MAX_SELECTORS {
+            selector_indices.push(index as u8);
+        }
+    }
+    let selectors = mtf::decode(&selector_indices);
+
+    let mut tables = Vec::with_capacity(num_tables);
+    let mut lengths = vec![0u8; alphabet_size];
+    for _ in 0..num_tables {
+        let mut current = bits.read_bits(5)? as u8;
+        for len in lengths.iter_mut() {
+            loop {
+                if !(1..=MAX_CODE_LEN).contains(&current) {
+                    return Err(invalid_data("invalid code length"));
+                }
+                if !bits.read_bit()? {
+                    break;
+                }
+                if bits.read_bit()? {
+                    current -= 1;
+                } else {
+                    current += 1;
+                }
+            }
+            *len = current;
+        }
+        tables.push(Table::new(&lengths));
+    }
+
+    let mut indices = Vec::new();
+    let mut run = 0usize;
+    let mut weight = 1usize;
+    let mut group_left = 0;
+    let mut selectors = selectors.iter();
+    let mut table = &tables[0];
+    loop {
+        if group_left == 0 {
+            let selector = selectors
+                .next()
+                .ok_or_else(|| invalid_data("ran out of selectors"))?;
+            table = &tables[*selector as usize];
+            group_left = GROUP_SIZE;
+        }
+        group_left -= 1;
+
+        let symbol = table.decode(bits)?;
+        if symbol == RUN_A || symbol == RUN_B {
+            run += weight << symbol;
+            weight <<= 1;
+            if run > max_len {
+                return Err(invalid_data("block longer than the block size"));
+            }
+            continue;
+        }
+        if indices.len() + run > max_len {
+            return Err(invalid_data("block longer than the block size"));
+        }
+        indices.resize(indices.len() + run, 0);
+        (run, weight) = (0, 1);
+        if symbol == end_of_block {
+            break;
+        }
+        indices.push((symbol - 1) as u8);
+    }
+    if indices.len() > max_len {
+        return Err(invalid_data("block longer than the block size"));
+    }
+
+    let last: Vec<u8> = mtf::decode(&indices)
+        .into_iter()
+        .map(|rank| bytes_in_use[rank as usize])
+        .collect();
+    let mut data = inverse_cyclic(&last, origin)?;
+    if randomized {
+        derandomize(&mut data);
+    }
+    Ok(data)
+}
+
+/// Undoes the randomization of blocks written by bzip2 0.9.0, which flipped
+/// the lowest bit of bytes at pseudo-random intervals to help its sort on
+/// repetitive data.
+fn derandomize(data: &mut [u8]) {
+    let mut intervals = RAND_NUMS.iter().cycle();
+    let mut left = 0u16;
+    for byte in data {
+        if left == 0 {
+            left = *intervals.next().unwrap();
+        }
+        left -= 1;
+        if left == 1 {
+            *byte ^= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_zero_runs_as_bzip2_does() {
+        // Runs of 1 to 4: A, B, AA, BA
+        assert_eq!(
+            zero_runs(&[0, 3, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 0], 4),
+            vec![RUN_A, 4, RUN_B, 2, RUN_A, RUN_A, 3, RUN_B, RUN_A, 5]
+        );
+    }
+
+    #[test]
+    fn decodes_canonical_codes() {
+        let lengths = [2, 1, 3, 3];
+        let codes = assign_codes(&lengths);
+        assert_eq!(codes, vec![0b10, 0b0, 0b110, 0b111]);
+
+        let mut writer = BitWriter::new(Vec::new());
+        for symbol in [3, 0, 1, 2, 1] {
+            writer.write_bits(codes[symbol], lengths[symbol]).unwrap();
+        }
+        writer.finish().unwrap();
+        let data = writer.into_inner();
+        let mut bits = BitReader::new(&data[..]);
+        let table = Table::new(&lengths);
+        for symbol in [3, 0, 1, 2, 1] {
+            assert_eq!(table.decode(&mut bits).unwrap(), symbol);
+        }
+    }
+}
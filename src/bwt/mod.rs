@@ -0,0 +1,295 @@
+use std::io::{self, Read, Write};
+
+pub mod bzip2;
+pub mod mtf;
+pub mod suffix;
+pub mod zero_run;
+
+use self::suffix::{rotation_array, suffix_array};
+
+/// bzip2-style pipeline: the Burrows-Wheeler transform, move-to-front,
+/// zero-run encoding and finally Huffman coding.
+///
+/// The BWT sorts every rotation of the data and keeps the last byte of
+/// each. Bytes preceding similar contexts end up next to each other, so in
+/// text the output is made of long stretches of a few bytes, which the
+/// later stages turn into runs of zeros and then into very few bits.
+pub struct BwtCodec;
+
+impl crate::codec::Codec for BwtCodec {
+    fn name(&self) -> &'static str {
+        "bwt"
+    }
+
+    fn encode(&self, data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
+        encode(data, writer)
+    }
+
+    fn decode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+        decode(reader, writer, crate::codec::MAX_DECODED_LEN)
+    }
+}
+
+/// The Burrows-Wheeler transform of `data` with an end marker, smaller than
+/// every byte, appended. Returns the last column of the sorted rotations
+/// without the end marker, and the row the end marker was left out of.
+pub fn transform(data: &[u8]) -> (Vec<u8>, usize) {
+    let mut last = Vec::with_capacity(data.len());
+    let mut primary = 0;
+
+    for (row, &start) in suffix_array(data).iter().enumerate() {
+        match start {
+            0 => primary = row,
+            start => last.push(data[start as usize - 1]),
+        }
+    }
+
+    (last, primary)
+}
+
+/// Undoes `transform`.
+///
+/// Row `r` of the sorted rotations is the rotation one step to the left of
+/// row `lf[r]`: it ends with the byte that rotation starts with. Equal
+/// bytes keep their relative order between the first and last column, so
+/// `lf` follows from counting bytes. Starting at the row that begins with
+/// the end marker, the walk produces the data from back to front.
+pub fn inverse(last: &[u8], primary: usize) -> io::Result<Vec<u8>> {
+    let n = last.len();
+    if (n == 0 && primary != 0) || (n > 0 && !(1..=n).contains(&primary)) {
+        return Err(corrupted());
+    }
+    let row_byte = |row: usize| {
+        if row < primary {
+            last[row]
+        } else {
+            last[row - 1]
+        }
+    };
+
+    // Rows start with the end marker, then each byte value in order
+    let mut starts = [0u32; 256];
+    let mut start = 1;
+    let mut counts = [0u32; 256];
+    for &byte in last {
+        counts[byte as usize] += 1;
+    }
+    for (s, &count) in starts.iter_mut().zip(&counts) {
+        *s = start;
+        start += count;
+    }
+
+    let mut lf = vec![0u32; n + 1];
+    for (row, next) in lf.iter_mut().enumerate() {
+        if row != primary {
+            let byte = row_byte(row) as usize;
+            *next = starts[byte];
+            starts[byte] += 1;
+        }
+    }
+
+    let mut data = vec![0u8; n];
+    let mut row = 0;
+    for i in (0..n).rev() {
+        if row == primary {
+            return Err(corrupted());
+        }
+        data[i] = row_byte(row);
+        row = lf[row] as usize;
+    }
+
+    Ok(data)
+}
+
+/// The Burrows-Wheeler transform the way bzip2 does it: of the rotations of
+/// `data` alone, with no end marker. Returns the last column of the sorted
+/// rotations and the row `data` itself is in.
+pub fn transform_cyclic(data: &[u8]) -> (Vec<u8>, usize) {
+    let n = data.len();
+    let mut origin = 0;
+    let last = rotation_array(data)
+        .iter()
+        .enumerate()
+        .map(|(row, &start)| {
+            if start == 0 {
+                origin = row;
+            }
+            data[(start as usize + n - 1) % n]
+        })
+        .collect();
+
+    (last, origin)
+}
+
+/// Undoes `transform_cyclic`.
+///
+/// `next[r]` is the row whose last byte is the first byte of row `r`, found
+/// by counting bytes as in `inverse`. That row is the rotation starting one
+/// byte further on, so the walk from `origin` produces the data front to
+/// back.
+pub fn inverse_cyclic(last: &[u8], origin: usize) -> io::Result<Vec<u8>> {
+    let n = last.len();
+    if origin >= n.max(1) {
+        return Err(corrupted());
+    }
+
+    let mut starts = [0u32; 256];
+    for &byte in last {
+        starts[byte as usize] += 1;
+    }
+    let mut start = 0;
+    for s in starts.iter_mut() {
+        (*s, start) = (start, start + *s);
+    }
+
+    let mut next = vec![0u32; n];
+    for (row, &byte) in last.iter().enumerate() {
+        next[starts[byte as usize] as usize] = row as u32;
+        starts[byte as usize] += 1;
+    }
+
+    let mut data = Vec::with_capacity(n);
+    let mut row = origin;
+    for _ in 0..n {
+        row = next[row] as usize;
+        data.push(last[row]);
+    }
+
+    Ok(data)
+}
+
+/// Encodes data with the BWT pipeline. The payload is the BWT's primary
+/// row (4 bytes) followed by a `huffman` payload of the zero-run encoded
+/// move-to-front indices. Empty input encodes to nothing.
+///
+/// Returns the number of bits in the Huffman coded data.
+pub fn encode(data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
+    if data.is_empty() {
+        return Ok(0);
+    }
+    if u32::try_from(data.len()).is_err() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "input too large for BWT",
+        ));
+    }
+
+    let (last, primary) = transform(data);
+    let runs = zero_run::encode(&mtf::encode(&last));
+
+    writer.write_all(&(primary as u32).to_be_bytes())?;
+    crate::huffman::encode(&runs, writer)
+}
+
+/// Decodes data produced by `encode`, failing if it is longer than
+/// `max_len`.
+///
+/// Returns the number of bytes written to output.
+pub fn decode(reader: &mut dyn Read, writer: &mut dyn Write, max_len: usize) -> io::Result<usize> {
+    let mut input = Vec::new();
+    reader.read_to_end(&mut input)?;
+    if input.is_empty() {
+        return Ok(0);
+    }
+    if input.len() < 4 {
+        return Err(corrupted());
+    }
+    let primary = u32::from_be_bytes(input[..4].try_into().unwrap()) as usize;
+
+    let mut runs = Vec::new();
+    crate::huffman::decode(&mut &input[4..], &mut runs)?;
+    let last = mtf::decode(&zero_run::decode(&runs, max_len)?);
+    let data = inverse(&last, primary)?;
+
+    writer.write_all(&data)?;
+    Ok(data.len())
+}
+
+fn corrupted() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "BWT decode error: invalid primary row",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::tests::LOREM;
+
+    fn roundtrip(data: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        let mut decoded = Vec::new();
+
+        encode(data, &mut encoded).expect("Failed to encode");
+        decode(&mut encoded.as_slice(), &mut decoded, data.len()).expect("Failed to decode");
+        decoded
+    }
+
+    #[test]
+    fn transforms_and_inverts() {
+        // Rotations of "banana$" sorted: $banana, a$banan, ana$ban, anana$b,
+        // banana$, na$bana, nana$ba
+        let (last, primary) = transform(b"banana");
+        assert_eq!(last, b"annbaa");
+        assert_eq!(primary, 4);
+        assert_eq!(inverse(&last, primary).unwrap(), b"banana");
+
+        for data in [&b""[..], b"a", b"aaaa", b"abracadabra", LOREM] {
+            let (last, primary) = transform(data);
+            assert_eq!(inverse(&last, primary).unwrap(), data);
+        }
+        assert!(inverse(b"annbaa", 0).is_err());
+        assert!(inverse(b"annbaa", 7).is_err());
+    }
+
+    #[test]
+    fn transforms_rotations_and_inverts() {
+        // Rotations of "banana" sorted: abanan, anaban, ananab, banana,
+        // nabana, nanaba
+        let (last, origin) = transform_cyclic(b"banana");
+        assert_eq!(last, b"nnbaaa");
+        assert_eq!(origin, 3);
+        assert_eq!(inverse_cyclic(&last, origin).unwrap(), b"banana");
+
+        for data in [&b""[..], b"a", b"aaaa", b"abab", b"abracadabra", LOREM] {
+            let (last, origin) = transform_cyclic(data);
+            assert_eq!(inverse_cyclic(&last, origin).unwrap(), data);
+        }
+        assert!(inverse_cyclic(b"nnbaaa", 6).is_err());
+    }
+
+    #[test]
+    fn encodes_and_decodes_text() {
+        assert_eq!(roundtrip(LOREM), LOREM);
+    }
+
+    #[test]
+    fn encodes_and_decodes_edge_cases() {
+        assert_eq!(roundtrip(b""), b"");
+        assert_eq!(roundtrip(b"a"), b"a");
+        assert_eq!(roundtrip(&[0; 10_000]), [0; 10_000]);
+
+        let all_bytes: Vec<u8> = (0..=255).cycle().take(5000).collect();
+        assert_eq!(roundtrip(&all_bytes), all_bytes);
+    }
+
+    #[test]
+    fn rejects_data_longer_than_the_limit() {
+        let mut encoded = Vec::new();
+        encode(&[0; 10_000], &mut encoded).unwrap();
+
+        let err = decode(&mut encoded.as_slice(), &mut Vec::new(), 9_999).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn compresses_text_better_than_huffman() {
+        let mut huffman = Vec::new();
+        let mut bwt = Vec::new();
+
+        crate::huffman::encode(LOREM, &mut huffman).unwrap();
+        encode(LOREM, &mut bwt).unwrap();
+        assert!(bwt.len() < huffman.len() * 2 / 3);
+    }
+}
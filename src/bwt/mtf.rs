@@ -0,0 +1,66 @@
+use std::io::{self, Read, Write};
+
+/// The move-to-front transform on its own, as a pipeline stage. It changes
+/// no sizes, only the statistics for the stages after it.
+pub struct MtfCodec;
+
+impl crate::codec::Codec for MtfCodec {
+    fn name(&self) -> &'static str {
+        "mtf"
+    }
+
+    fn encode(&self, data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
+        writer.write_all(&encode(data))?;
+        Ok(data.len() as u64 * 8)
+    }
+
+    fn decode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+        let mut indices = Vec::new();
+        reader.read_to_end(&mut indices)?;
+        writer.write_all(&decode(&indices))?;
+        Ok(indices.len())
+    }
+}
+
+/// Move-to-front transform: each byte becomes its position in a list of all
+/// byte values, and then moves to the front of the list. Recently seen
+/// bytes get small numbers, so the clusters of equal bytes the BWT makes
+/// turn into runs of zeros.
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut list: [u8; 256] = std::array::from_fn(|i| i as u8);
+    data.iter()
+        .map(|&byte| {
+            let index = list.iter().position(|&b| b == byte).unwrap();
+            list.copy_within(..index, 1);
+            list[0] = byte;
+            index as u8
+        })
+        .collect()
+}
+
+pub fn decode(indices: &[u8]) -> Vec<u8> {
+    let mut list: [u8; 256] = std::array::from_fn(|i| i as u8);
+    indices
+        .iter()
+        .map(|&index| {
+            let byte = list[index as usize];
+            list.copy_within(..index as usize, 1);
+            list[0] = byte;
+            byte
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_recent_bytes_to_the_front() {
+        assert_eq!(encode(b"bbbaaac"), vec![98, 0, 0, 98, 0, 0, 99]);
+        assert_eq!(decode(&[98, 0, 0, 98, 0, 0, 99]), b"bbbaaac");
+
+        let all_bytes: Vec<u8> = (0..=255).rev().cycle().take(1000).collect();
+        assert_eq!(decode(&encode(&all_bytes)), all_bytes);
+    }
+}
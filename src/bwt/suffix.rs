@@ -0,0 +1,138 @@
+/// Sorts the suffixes of `data` followed by an end marker that is smaller
+/// than every byte. Returns the start of each suffix in order, `n + 1` of
+/// them for `n` bytes; the first is always the empty suffix at `n`.
+///
+/// Uses prefix doubling: after round `h` suffixes are ordered by their first
+/// `2^h` bytes, each with a class number that is equal for equal prefixes.
+/// The order by `2^(h+1)` bytes is the order by the pair of classes of a
+/// suffix's two halves, found with a counting sort in linear time, so the
+/// whole sort takes O(n log n). With the end marker in the text, sorting
+/// the rotations of the text sorts its suffixes.
+pub fn suffix_array(data: &[u8]) -> Vec<u32> {
+    sort_cyclic(data.len() + 1, |i| {
+        data.get(i).map_or(0, |&b| b as usize + 1)
+    })
+}
+
+/// Sorts the rotations of `data` itself, with no end marker, as bzip2's
+/// transform does. Returns the start of each rotation in order. Data made
+/// of a repeated pattern has equal rotations, which come in any order.
+pub fn rotation_array(data: &[u8]) -> Vec<u32> {
+    sort_cyclic(data.len(), |i| data[i] as usize + 1)
+}
+
+/// Sorts the `n` rotations of a text of symbols below 257 by prefix
+/// doubling, stopping once all are told apart or fully compared.
+fn sort_cyclic(n: usize, symbol: impl Fn(usize) -> usize) -> Vec<u32> {
+    // Round 0: sort by the first byte alone
+    let mut counts = vec![0usize; 257.max(n)];
+    for i in 0..n {
+        counts[symbol(i)] += 1;
+    }
+    for c in 1..257 {
+        counts[c] += counts[c - 1];
+    }
+    let mut order = vec![0u32; n];
+    for i in (0..n).rev() {
+        counts[symbol(i)] -= 1;
+        order[counts[symbol(i)]] = i as u32;
+    }
+
+    let mut classes = vec![0u32; n];
+    let mut num_classes = 1;
+    for i in 1..n {
+        if symbol(order[i] as usize) != symbol(order[i - 1] as usize) {
+            num_classes += 1;
+        }
+        classes[order[i] as usize] = num_classes as u32 - 1;
+    }
+
+    let mut shifted = vec![0u32; n];
+    let mut next_classes = vec![0u32; n];
+    let mut half = 1;
+    while num_classes < n && half < n {
+        // Suffixes ordered by their second half, which is already sorted
+        for (s, &i) in shifted.iter_mut().zip(&order) {
+            *s = ((i as usize + n - half) % n) as u32;
+        }
+
+        // Stable counting sort by the first half
+        counts[..num_classes].fill(0);
+        for &i in &shifted {
+            counts[classes[i as usize] as usize] += 1;
+        }
+        for c in 1..num_classes {
+            counts[c] += counts[c - 1];
+        }
+        for &i in shifted.iter().rev() {
+            let class = classes[i as usize] as usize;
+            counts[class] -= 1;
+            order[counts[class]] = i;
+        }
+
+        let key = |i: u32| {
+            let i = i as usize;
+            (classes[i], classes[(i + half) % n])
+        };
+        next_classes[order[0] as usize] = 0;
+        num_classes = 1;
+        for i in 1..n {
+            if key(order[i]) != key(order[i - 1]) {
+                num_classes += 1;
+            }
+            next_classes[order[i] as usize] = num_classes as u32 - 1;
+        }
+        std::mem::swap(&mut classes, &mut next_classes);
+        half *= 2;
+    }
+
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn naive_suffix_array(data: &[u8]) -> Vec<u32> {
+        let mut suffixes: Vec<u32> = (0..=data.len() as u32).collect();
+        suffixes.sort_by_key(|&i| &data[i as usize..]);
+        suffixes
+    }
+
+    fn naive_rotation_array(data: &[u8]) -> Vec<u32> {
+        let mut rotations: Vec<u32> = (0..data.len() as u32).collect();
+        let rotation = |i: u32| [&data[i as usize..], &data[..i as usize]].concat();
+        rotations.sort_by_key(|&i| (rotation(i), i));
+        rotations
+    }
+
+    #[test]
+    fn sorts_suffixes() {
+        assert_eq!(suffix_array(b"banana"), vec![6, 5, 3, 1, 0, 4, 2]);
+        assert_eq!(suffix_array(b""), vec![0]);
+        assert_eq!(suffix_array(b"aaaa"), vec![4, 3, 2, 1, 0]);
+
+        for data in [
+            &b"mississippi"[..],
+            b"abracadabra abracadabra",
+            &[0, 0, 255, 0, 1, 0, 0, 255],
+        ] {
+            assert_eq!(suffix_array(data), naive_suffix_array(data));
+        }
+    }
+
+    #[test]
+    fn sorts_rotations() {
+        assert_eq!(rotation_array(b"banana"), vec![5, 3, 1, 0, 4, 2]);
+        assert_eq!(rotation_array(b""), Vec::<u32>::new());
+
+        for data in [&b"mississippi"[..], b"abracadabra", &[3, 0, 0, 255, 0, 0]] {
+            assert_eq!(rotation_array(data), naive_rotation_array(data));
+        }
+        // Equal rotations sort in any order
+        let mut order = rotation_array(b"abab");
+        order[..2].sort();
+        order[2..].sort();
+        assert_eq!(order, vec![0, 2, 1, 3]);
+    }
+}
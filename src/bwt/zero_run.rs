@@ -0,0 +1,132 @@
+use std::io;
+
+/// Digit worth 1 of a zero run's length.
+const RUN_A: u8 = 0;
+/// Digit worth 2 of a zero run's length.
+const RUN_B: u8 = 1;
+/// Prefix of the two largest values, which have no byte of their own.
+const ESCAPE: u8 = 255;
+
+/// Replaces runs of zeros, which make up most of the move-to-front output
+/// of a BWT, with their length, the way bzip2 does.
+///
+/// A run of `n` zeros is written as `n` in bijective base 2, least
+/// significant digit first, with `RUN_A` for a 1 and `RUN_B` for a 2, so
+/// no digit is wasted on a terminator. Other values shift up by one to make
+/// room for the two digits; 254 and 255 become `ESCAPE` followed by 0 or 1.
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    let mut run = 0usize;
+
+    for &value in data {
+        if value == 0 {
+            run += 1;
+            continue;
+        }
+        push_run(&mut output, run);
+        run = 0;
+        match value {
+            254 | 255 => output.extend([ESCAPE, value - 254]),
+            _ => output.push(value + 1),
+        }
+    }
+    push_run(&mut output, run);
+
+    output
+}
+
+fn push_run(output: &mut Vec<u8>, mut run: usize) {
+    while run > 0 {
+        if run & 1 == 1 {
+            output.push(RUN_A);
+            run = (run - 1) / 2;
+        } else {
+            output.push(RUN_B);
+            run = (run - 2) / 2;
+        }
+    }
+}
+
+/// Undoes `encode`, failing once the output would be longer than `max_len`.
+pub fn decode(data: &[u8], max_len: usize) -> io::Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(data.len().min(max_len));
+    let mut run = 0usize;
+    let mut weight = 1usize;
+    let mut bytes = data.iter().copied();
+
+    while let Some(byte) = bytes.next() {
+        if output.len() + run >= max_len {
+            return Err(too_long());
+        }
+        if byte == RUN_A || byte == RUN_B {
+            run += weight << byte;
+            weight <<= 1;
+            continue;
+        }
+        output.resize(output.len() + run, 0);
+        run = 0;
+        weight = 1;
+
+        match byte {
+            ESCAPE => match bytes.next() {
+                Some(offset @ (0 | 1)) => output.push(254 + offset),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "BWT decode error: invalid escape in zero runs",
+                    ))
+                }
+            },
+            _ => output.push(byte - 1),
+        }
+    }
+    if output.len() + run > max_len {
+        return Err(too_long());
+    }
+    output.resize(output.len() + run, 0);
+
+    Ok(output)
+}
+
+fn too_long() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "BWT decode error: zero runs decode to too many bytes",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_run_lengths_in_bijective_base_2() {
+        // 1 = A, 2 = B, 3 = AA, 4 = BA, 5 = AB, 6 = BB, 7 = AAA
+        assert_eq!(encode(&[0]), vec![RUN_A]);
+        assert_eq!(encode(&[0; 4]), vec![RUN_B, RUN_A]);
+        assert_eq!(encode(&[0; 7]), vec![RUN_A, RUN_A, RUN_A]);
+        assert_eq!(
+            encode(&[0, 0, 5, 0, 254, 255]),
+            vec![RUN_B, 6, RUN_A, 255, 0, 255, 1]
+        );
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let mut data = vec![0; 1000];
+        data.extend([3, 0, 0, 0, 255, 254, 253, 1, 0]);
+        data.extend((0..=255).cycle().take(600));
+
+        assert_eq!(decode(&encode(&data), data.len()).unwrap(), data);
+        assert_eq!(decode(&[], 0).unwrap(), b"");
+        assert!(decode(&[ESCAPE], 10).is_err());
+    }
+
+    #[test]
+    fn stops_at_the_length_limit() {
+        assert_eq!(decode(&[RUN_B, RUN_A], 4).unwrap(), [0; 4]);
+        assert!(decode(&[RUN_B, RUN_A], 3).is_err());
+        assert!(decode(&[RUN_A, 5], 1).is_err());
+        assert!(decode(&[RUN_B; 80], 1 << 20).is_err());
+    }
+}
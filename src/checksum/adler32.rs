@@ -0,0 +1,69 @@
+/// Largest prime smaller than 2^16.
+const MODULUS: u32 = 65521;
+
+/// Bytes that can be summed before `b` may overflow a `u32`.
+const CHUNK_SIZE: usize = 5552;
+
+/// Incremental Adler-32 hasher, as used by zlib.
+#[derive(Debug, Clone)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Adler32 {
+    pub fn new() -> Self {
+        Self { a: 1, b: 0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for chunk in data.chunks(CHUNK_SIZE) {
+            for &byte in chunk {
+                self.a += byte as u32;
+                self.b += self.a;
+            }
+            self.a %= MODULUS;
+            self.b %= MODULUS;
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Adler-32 of a whole buffer.
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut hasher = Adler32::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_known_values() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn handles_long_input_without_overflow() {
+        let data = vec![0xFF; 100_000];
+        let mut hasher = Adler32::new();
+        for chunk in data.chunks(777) {
+            hasher.update(chunk);
+        }
+
+        assert_eq!(hasher.finish(), adler32(&data));
+        assert_eq!(adler32(&data), 0x149A_302C);
+    }
+}
@@ -0,0 +1,200 @@
+//! Checksums used by the container formats.
+
+use std::fmt;
+use std::io;
+
+mod adler32;
+mod crc32;
+mod crc32c;
+mod crc64;
+mod xxhash32;
+mod xxhash64;
+
+pub use self::adler32::{adler32, Adler32};
+pub use self::crc32::{crc32, Crc32};
+pub use self::crc32c::{crc32c, Crc32c};
+pub use self::crc64::{crc64, Crc64};
+pub use self::xxhash32::{xxhash32, XxHash32};
+pub use self::xxhash64::{xxhash64, XxHash64};
+
+/// Which checksum, if any, protects the uncompressed data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumKind {
+    None,
+    Crc32,
+    Adler32,
+    XxHash32,
+}
+
+impl ChecksumKind {
+    pub const ALL: [ChecksumKind; 4] = [
+        ChecksumKind::None,
+        ChecksumKind::Crc32,
+        ChecksumKind::Adler32,
+        ChecksumKind::XxHash32,
+    ];
+
+    pub fn id(self) -> u8 {
+        match self {
+            ChecksumKind::None => 0,
+            ChecksumKind::Crc32 => 1,
+            ChecksumKind::Adler32 => 2,
+            ChecksumKind::XxHash32 => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.id() == id)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ChecksumKind::None => "none",
+            ChecksumKind::Crc32 => "crc32",
+            ChecksumKind::Adler32 => "adler32",
+            ChecksumKind::XxHash32 => "xxhash32",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            ChecksumKind::None => Hasher::None,
+            ChecksumKind::Crc32 => Hasher::Crc32(Crc32::new()),
+            ChecksumKind::Adler32 => Hasher::Adler32(Adler32::new()),
+            ChecksumKind::XxHash32 => Hasher::XxHash32(XxHash32::new()),
+        }
+    }
+}
+
+/// A running checksum of any supported kind.
+#[derive(Debug, Clone)]
+pub enum Hasher {
+    None,
+    Crc32(Crc32),
+    Adler32(Adler32),
+    XxHash32(XxHash32),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::None => {}
+            Hasher::Crc32(h) => h.update(data),
+            Hasher::Adler32(h) => h.update(data),
+            Hasher::XxHash32(h) => h.update(data),
+        }
+    }
+
+    /// The checksum so far. Always 0 for `Hasher::None`.
+    pub fn finish(&self) -> u32 {
+        match self {
+            Hasher::None => 0,
+            Hasher::Crc32(h) => h.finish(),
+            Hasher::Adler32(h) => h.finish(),
+            Hasher::XxHash32(h) => h.finish(),
+        }
+    }
+}
+
+/// The decoded data does not match the checksum stored alongside it, i.e.
+/// the compressed input was corrupted.
+///
+/// Returned wrapped in an `io::Error` of kind `InvalidData`; use
+/// `err.get_ref().and_then(|e| e.downcast_ref::<ChecksumMismatch>())` to
+/// tell corruption apart from other decode failures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChecksumMismatch {
+    /// Name of the checksum, e.g. `crc32` or `bzip2 block crc`.
+    pub checksum: &'static str,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "data is corrupted: {} checksum mismatch (expected {:08x}, got {:08x})",
+            self.checksum, self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+/// Fails with a `ChecksumMismatch` unless `expected == actual`.
+pub fn verify(kind: ChecksumKind, expected: u32, actual: u32) -> io::Result<()> {
+    verify_named(kind.name(), expected.into(), actual.into())
+}
+
+/// Like `verify`, for checksums that only other formats store, such as the
+/// 64-bit ones, named by `checksum`.
+pub fn verify_named(checksum: &'static str, expected: u64, actual: u64) -> io::Result<()> {
+    if expected == actual {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        ChecksumMismatch {
+            checksum,
+            expected,
+            actual,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kinds_roundtrip_through_id_and_name() {
+        for kind in ChecksumKind::ALL {
+            assert_eq!(ChecksumKind::from_id(kind.id()), Some(kind));
+            assert_eq!(ChecksumKind::from_name(kind.name()), Some(kind));
+        }
+        assert_eq!(ChecksumKind::from_id(200), None);
+        assert_eq!(ChecksumKind::from_name("crc32c"), None);
+        assert_eq!(ChecksumKind::from_name("crc64"), None);
+    }
+
+    #[test]
+    fn hashers_match_standalone_functions() {
+        let data = b"The quick brown fox jumps over the lazy dog";
+
+        let mut crc = ChecksumKind::Crc32.hasher();
+        crc.update(data);
+        assert_eq!(crc.finish(), crc32(data));
+
+        let mut adler = ChecksumKind::Adler32.hasher();
+        adler.update(data);
+        assert_eq!(adler.finish(), adler32(data));
+
+        let mut xxh = ChecksumKind::XxHash32.hasher();
+        xxh.update(data);
+        assert_eq!(xxh.finish(), xxhash32(data));
+    }
+
+    #[test]
+    fn mismatch_is_recognizable() {
+        let err = verify(ChecksumKind::Crc32, 1, 2).unwrap_err();
+        let mismatch = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<ChecksumMismatch>())
+            .unwrap();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(mismatch.expected, 1);
+        assert!(verify(ChecksumKind::Crc32, 7, 7).is_ok());
+
+        // All 64 bits are kept and shown
+        let err = verify_named("xxhash64", 1 << 40, 1).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("expected 10000000000, got 00000001"));
+    }
+}
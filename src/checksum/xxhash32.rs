@@ -0,0 +1,145 @@
+const PRIME_1: u32 = 0x9E37_79B1;
+const PRIME_2: u32 = 0x85EB_CA77;
+const PRIME_3: u32 = 0xC2B2_AE3D;
+const PRIME_4: u32 = 0x27D4_EB2F;
+const PRIME_5: u32 = 0x1656_67B1;
+
+/// Incremental 32-bit xxHash (XXH32).
+///
+/// Input is consumed in 16 byte stripes across four accumulators; a partial
+/// stripe is buffered until more data arrives or the hash is finished.
+#[derive(Debug, Clone)]
+pub struct XxHash32 {
+    seed: u32,
+    acc: [u32; 4],
+    buf: [u8; 16],
+    buf_len: usize,
+    total_len: u64,
+}
+
+impl XxHash32 {
+    pub fn new() -> Self {
+        Self::with_seed(0)
+    }
+
+    pub fn with_seed(seed: u32) -> Self {
+        Self {
+            seed,
+            acc: [
+                seed.wrapping_add(PRIME_1).wrapping_add(PRIME_2),
+                seed.wrapping_add(PRIME_2),
+                seed,
+                seed.wrapping_sub(PRIME_1),
+            ],
+            buf: [0; 16],
+            buf_len: 0,
+            total_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+
+        if self.buf_len > 0 {
+            let take = (16 - self.buf_len).min(data.len());
+            self.buf[self.buf_len..self.buf_len + take].copy_from_slice(&data[..take]);
+            self.buf_len += take;
+            data = &data[take..];
+            if self.buf_len < 16 {
+                return;
+            }
+            let stripe = self.buf;
+            self.process_stripe(&stripe);
+            self.buf_len = 0;
+        }
+
+        let mut stripes = data.chunks_exact(16);
+        for stripe in &mut stripes {
+            self.process_stripe(stripe);
+        }
+        let rest = stripes.remainder();
+        self.buf[..rest.len()].copy_from_slice(rest);
+        self.buf_len = rest.len();
+    }
+
+    pub fn finish(&self) -> u32 {
+        let mut h = if self.total_len >= 16 {
+            self.acc[0]
+                .rotate_left(1)
+                .wrapping_add(self.acc[1].rotate_left(7))
+                .wrapping_add(self.acc[2].rotate_left(12))
+                .wrapping_add(self.acc[3].rotate_left(18))
+        } else {
+            self.seed.wrapping_add(PRIME_5)
+        };
+        h = h.wrapping_add(self.total_len as u32);
+
+        let mut words = self.buf[..self.buf_len].chunks_exact(4);
+        for word in &mut words {
+            let lane = u32::from_le_bytes(word.try_into().unwrap());
+            h = h.wrapping_add(lane.wrapping_mul(PRIME_3));
+            h = h.rotate_left(17).wrapping_mul(PRIME_4);
+        }
+        for &byte in words.remainder() {
+            h = h.wrapping_add((byte as u32).wrapping_mul(PRIME_5));
+            h = h.rotate_left(11).wrapping_mul(PRIME_1);
+        }
+
+        h ^= h >> 15;
+        h = h.wrapping_mul(PRIME_2);
+        h ^= h >> 13;
+        h = h.wrapping_mul(PRIME_3);
+        h ^= h >> 16;
+        h
+    }
+
+    fn process_stripe(&mut self, stripe: &[u8]) {
+        for (acc, lane) in self.acc.iter_mut().zip(stripe.chunks_exact(4)) {
+            let lane = u32::from_le_bytes(lane.try_into().unwrap());
+            *acc = acc
+                .wrapping_add(lane.wrapping_mul(PRIME_2))
+                .rotate_left(13)
+                .wrapping_mul(PRIME_1);
+        }
+    }
+}
+
+impl Default for XxHash32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// XXH32 with seed 0 of a whole buffer.
+pub fn xxhash32(data: &[u8]) -> u32 {
+    let mut hasher = XxHash32::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_known_values() {
+        assert_eq!(xxhash32(b""), 0x02CC_5D05);
+        assert_eq!(xxhash32(b"abc"), 0x32D1_53FF);
+        // Content checksum of an lz4 frame made by the lz4 tool
+        assert_eq!(
+            xxhash32(b"The quick brown fox jumps over the lazy dog, again and again and again."),
+            0x2596_A2D3
+        );
+    }
+
+    #[test]
+    fn incremental_updates_match_single_pass() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 31 % 256) as u8).collect();
+        let mut hasher = XxHash32::new();
+        for chunk in data.chunks(7) {
+            hasher.update(chunk);
+        }
+
+        assert_eq!(hasher.finish(), xxhash32(&data));
+    }
+}
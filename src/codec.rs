@@ -4,21 +4,220 @@ use std::io::{self, Read, Write};
 ///
 /// To add a new algorithm:
 /// 1. Create a module (e.g. `src/bpe/mod.rs`) with your encode/decode logic
-/// 2. Define a unit struct (e.g. `pub struct BpeCodec;`)
+/// 2. Define a struct (e.g. `pub struct BpeCodec;`), with fields for any encoder options
 /// 3. Implement this trait for it
-/// 4. Add a match arm in `get_codec` below
-pub trait Codec {
+/// 4. Add a match arm in `get_codec` below and an entry with a new id to `CODECS`
+///
+/// Codecs are shared between the threads that code blocks in parallel, so
+/// they must be `Send + Sync`; `encode` and `decode` may run concurrently.
+pub trait Codec: Send + Sync {
+    /// The name this codec is registered under in `CODECS`.
+    fn name(&self) -> &'static str;
     fn encode(&self, data: &[u8], writer: &mut dyn Write) -> io::Result<u64>;
     fn decode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize>;
 }
 
+/// A registered codec.
+pub struct CodecInfo {
+    /// Name used on the command line and by `get_codec`.
+    pub name: &'static str,
+    /// Identifier recorded in the container header. Never reuse an id.
+    pub id: u8,
+    /// Standard interchange formats (e.g. gzip) carry their own header and
+    /// are written as-is so other tools can read them.
+    pub standalone: bool,
+}
+
+pub const CODECS: &[CodecInfo] = &[
+    CodecInfo {
+        name: "huffman",
+        id: 1,
+        standalone: false,
+    },
+    CodecInfo {
+        name: "rle",
+        id: 2,
+        standalone: false,
+    },
+    CodecInfo {
+        name: "lz77",
+        id: 3,
+        standalone: false,
+    },
+    CodecInfo {
+        name: "lzh",
+        id: 4,
+        standalone: false,
+    },
+    CodecInfo {
+        name: "deflate",
+        id: 5,
+        standalone: true,
+    },
+    CodecInfo {
+        name: "zlib",
+        id: 6,
+        standalone: true,
+    },
+    CodecInfo {
+        name: "gzip",
+        id: 7,
+        standalone: true,
+    },
+    CodecInfo {
+        name: "adaptive-huffman",
+        id: 8,
+        standalone: false,
+    },
+    CodecInfo {
+        name: "context-huffman",
+        id: 9,
+        standalone: false,
+    },
+    CodecInfo {
+        name: "arith",
+        id: 10,
+        standalone: false,
+    },
+    CodecInfo {
+        name: "rans",
+        id: 11,
+        standalone: false,
+    },
+    CodecInfo {
+        name: "bwt",
+        id: 12,
+        standalone: false,
+    },
+    CodecInfo {
+        name: "pipeline",
+        id: 13,
+        standalone: false,
+    },
+    CodecInfo {
+        name: "mtf",
+        id: 14,
+        standalone: false,
+    },
+    CodecInfo {
+        name: "lzw",
+        id: 15,
+        standalone: true,
+    },
+    CodecInfo {
+        name: "lz78",
+        id: 16,
+        standalone: false,
+    },
+    CodecInfo {
+        name: "lz4",
+        id: 17,
+        standalone: true,
+    },
+    CodecInfo {
+        name: "snappy",
+        id: 18,
+        standalone: true,
+    },
+    CodecInfo {
+        name: "zstd",
+        id: 19,
+        standalone: true,
+    },
+    CodecInfo {
+        name: "lzma",
+        id: 20,
+        standalone: true,
+    },
+    CodecInfo {
+        name: "bzip2",
+        id: 21,
+        standalone: true,
+    },
+];
+
 /// Look up a codec by name. Returns None for unknown algorithms.
+///
+/// Names joined by `+` or `,` (e.g. `mtf+rle+huffman`) build a `Pipeline`
+/// of those codecs.
 pub fn get_codec(name: &str) -> Option<Box<dyn Codec>> {
     match name {
-        "huffman" => Some(Box::new(crate::huffman::HuffmanCodec)),
+        name if name.contains(crate::pipeline::SEPARATORS) => {
+            Some(Box::new(crate::pipeline::Pipeline::parse(name)?))
+        }
+        "huffman" => Some(Box::new(crate::huffman::HuffmanCodec::default())),
         "rle" => Some(Box::new(crate::rle::RleCodec)),
+        "lz77" => Some(Box::new(crate::lz77::Lz77Codec::default())),
+        "lzh" => Some(Box::new(crate::lzh::LzhCodec)),
+        "deflate" => Some(Box::new(crate::deflate::DeflateCodec)),
+        "zlib" => Some(Box::new(crate::deflate::zlib::ZlibCodec)),
+        "gzip" => Some(Box::new(crate::deflate::gzip::GzipCodec)),
+        "adaptive-huffman" => Some(Box::new(crate::adaptive_huffman::AdaptiveHuffmanCodec)),
+        "context-huffman" => Some(Box::new(crate::huffman::context::ContextHuffmanCodec)),
+        "arith" => Some(Box::new(crate::arith::ArithCodec::default())),
+        "rans" => Some(Box::new(crate::rans::RansCodec)),
+        "bwt" => Some(Box::new(crate::bwt::BwtCodec)),
+        "pipeline" => Some(Box::new(crate::pipeline::Pipeline::default())),
+        "mtf" => Some(Box::new(crate::bwt::mtf::MtfCodec)),
+        "lzw" => Some(Box::new(crate::lzw::LzwCodec::default())),
+        "lz78" => Some(Box::new(crate::lz78::Lz78Codec::default())),
+        "lz4" => Some(Box::new(crate::lz4::Lz4Codec::default())),
+        "snappy" => Some(Box::new(crate::snappy::SnappyCodec::default())),
+        "zstd" => Some(Box::new(crate::zstd::ZstdCodec)),
+        "lzma" => Some(Box::new(crate::lzma::LzmaCodec::default())),
+        "bzip2" => Some(Box::new(crate::bwt::bzip2::Bzip2Codec::default())),
         _ => None,
     }
 }
 
+pub fn codec_info(name: &str) -> Option<&'static CodecInfo> {
+    CODECS.iter().find(|info| info.name == name)
+}
+
+pub fn codec_info_by_id(id: u8) -> Option<&'static CodecInfo> {
+    CODECS.iter().find(|info| info.id == id)
+}
+
+/// Comma separated list of all codec names, for help and error messages.
+pub fn available() -> String {
+    CODECS
+        .iter()
+        .map(|info| info.name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 pub const DEFAULT_ALGORITHM: &str = "huffman";
+
+/// Most bytes a codec decodes one payload to. Lengths read from a payload
+/// past this are rejected as corrupt instead of being allocated. Container
+/// blocks hold at most `container::MAX_BLOCK_SIZE` bytes, and a pipeline
+/// refuses stages that grow them beyond this, encoding or decoding.
+pub const MAX_DECODED_LEN: usize = 16 * crate::container::MAX_BLOCK_SIZE;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_registered_codec_can_be_constructed() {
+        for info in CODECS {
+            let codec = get_codec(info.name).expect("registered codec is missing");
+            assert_eq!(codec.name(), info.name);
+        }
+    }
+
+    #[test]
+    fn builds_pipelines_from_joined_names() {
+        assert_eq!(get_codec("mtf+rle+huffman").unwrap().name(), "pipeline");
+        assert_eq!(get_codec("bwt,huffman").unwrap().name(), "pipeline");
+        assert!(get_codec("mtf+unknown").is_none());
+    }
+
+    #[test]
+    fn codec_ids_are_unique() {
+        for info in CODECS {
+            assert_eq!(codec_info_by_id(info.id).unwrap().name, info.name);
+        }
+    }
+}
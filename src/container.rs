@@ -0,0 +1,993 @@
+//! Self-describing wrapper around the output of comprust's own codecs.
+//!
+//! Layout (integers big-endian):
+//! 1. Magic bytes `CPRS` (4 bytes)
+//! 2. Format version (1 byte)
+//! 3. Codec id from `codec::CODECS` (1 byte)
+//! 4. Checksum kind, see `ChecksumKind::id` (1 byte)
+//! 5. Block size, the most input bytes in one block (4 bytes)
+//! 6. Blocks, each starting with a type byte:
+//!    - `BLOCK_COMPRESSED`: original length (4 bytes), payload length
+//!      (4 bytes), then the codec's encoding of the block
+//!    - `BLOCK_STORED`: original length (4 bytes), then the data as-is, used
+//!      when the codec would make the block larger
+//!    - `BLOCK_END`: no more blocks
+//! 7. Original length in bytes (8 bytes)
+//! 8. Checksum of the original data (4 bytes, 0 when the kind is none)
+//!
+//! Each block is encoded on its own, so codecs build their statistics (like
+//! the Huffman table) per block and adapt as the data changes, and memory is
+//! bounded by the block size no matter how large the input is. The length
+//! and checksum come last because they are only known at the end.
+//!
+//! Being independent, blocks can also be coded on several threads at once:
+//! `Writer` and `Reader` take up to `threads` blocks at a time, code them in
+//! parallel and write or return them in order. The output is the same for
+//! any number of threads.
+//!
+//! Standalone formats such as gzip are written without the wrapper;
+//! `stream::Decoder` recognizes them by their own magic bytes instead.
+
+use std::io::{self, Read, Write};
+use std::{mem, panic, thread};
+
+use crate::checksum::{self, ChecksumKind, Hasher};
+use crate::codec::{self, Codec};
+use crate::stream::{Decoder, Encoder, EncoderOptions};
+
+pub const MAGIC: [u8; 4] = *b"CPRS";
+
+pub const FORMAT_VERSION: u8 = 5;
+
+pub const HEADER_LEN: usize = 11;
+
+pub const MIN_BLOCK_SIZE: usize = 64 * 1024;
+pub const MAX_BLOCK_SIZE: usize = 4 * 1024 * 1024;
+pub const DEFAULT_BLOCK_SIZE: usize = 1024 * 1024;
+
+pub const BLOCK_END: u8 = 0;
+pub const BLOCK_COMPRESSED: u8 = 1;
+pub const BLOCK_STORED: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub codec_id: u8,
+    pub checksum_kind: ChecksumKind,
+    pub block_size: u32,
+}
+
+impl Header {
+    pub fn write<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[FORMAT_VERSION, self.codec_id, self.checksum_kind.id()])?;
+        writer.write_all(&self.block_size.to_be_bytes())
+    }
+
+    /// Parses a header, including the magic bytes.
+    pub fn parse(bytes: &[u8; HEADER_LEN]) -> io::Result<Self> {
+        if bytes[..4] != MAGIC {
+            return Err(invalid_data("missing container magic bytes"));
+        }
+        if bytes[4] != FORMAT_VERSION {
+            return Err(invalid_data(&format!(
+                "unsupported format version {} (expected {})",
+                bytes[4], FORMAT_VERSION
+            )));
+        }
+
+        let checksum_kind = ChecksumKind::from_id(bytes[6])
+            .ok_or_else(|| invalid_data(&format!("unknown checksum kind {}", bytes[6])))?;
+
+        let block_size = u32::from_be_bytes([bytes[7], bytes[8], bytes[9], bytes[10]]);
+        check_block_size(block_size as usize).map_err(|e| invalid_data(&e.to_string()))?;
+
+        Ok(Self {
+            codec_id: bytes[5],
+            checksum_kind,
+            block_size,
+        })
+    }
+}
+
+/// Fails unless `size` is between `MIN_BLOCK_SIZE` and `MAX_BLOCK_SIZE`.
+pub fn check_block_size(size: usize) -> io::Result<()> {
+    if (MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&size) {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "block size {} is outside {}..={}",
+            size, MIN_BLOCK_SIZE, MAX_BLOCK_SIZE
+        ),
+    ))
+}
+
+/// Number of worker threads to use for a `threads` option, where 0 means
+/// one per available core.
+pub fn worker_count(threads: usize) -> usize {
+    match threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+}
+
+/// Applies `f` to every item, each on its own scoped thread, and returns the
+/// results in order. A single item is handled on the calling thread.
+fn map_in_parallel<T, U, F>(items: impl IntoIterator<Item = T>, f: F) -> Vec<U>
+where
+    T: Send,
+    U: Send,
+    F: Fn(T) -> U + Sync,
+{
+    let mut items: Vec<T> = items.into_iter().collect();
+    if items.len() <= 1 {
+        return items.drain(..).map(f).collect();
+    }
+
+    let f = &f;
+    thread::scope(|scope| {
+        let handles: Vec<_> = items
+            .into_iter()
+            .map(|item| scope.spawn(move || f(item)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+            .collect()
+    })
+}
+
+/// Writes a container as data is written to it, one block per
+/// `options.block_size` bytes of input, encoding up to `options.threads`
+/// blocks in parallel. Call `finish` to write the last blocks and the
+/// trailer.
+pub struct Writer<W: Write> {
+    writer: W,
+    codec: Box<dyn Codec>,
+    header: Header,
+    hasher: Hasher,
+    threads: usize,
+    /// The block being filled.
+    buf: Vec<u8>,
+    /// Full blocks waiting to be encoded together.
+    pending: Vec<Vec<u8>>,
+    total_in: u64,
+    payload_bits: u64,
+    header_written: bool,
+    finished: bool,
+}
+
+impl<W: Write> Writer<W> {
+    /// Fails if `codec` is not registered or is a standalone format, or if
+    /// the block size is out of range.
+    pub fn new(writer: W, codec: Box<dyn Codec>, options: &EncoderOptions) -> io::Result<Self> {
+        let info = codec::codec_info(codec.name()).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("codec '{}' is not registered", codec.name()),
+            )
+        })?;
+        if info.standalone {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' is a standalone format", codec.name()),
+            ));
+        }
+        check_block_size(options.block_size)?;
+
+        Ok(Self {
+            writer,
+            codec,
+            header: Header {
+                codec_id: info.id,
+                checksum_kind: options.checksum,
+                block_size: options.block_size as u32,
+            },
+            hasher: options.checksum.hasher(),
+            threads: worker_count(options.threads),
+            buf: Vec::with_capacity(options.block_size),
+            pending: Vec::new(),
+            total_in: 0,
+            payload_bits: 0,
+            header_written: false,
+            finished: false,
+        })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Bits of codec output so far as reported by the codec, plus the data
+    /// of stored blocks. Excludes the container's own headers and trailer.
+    pub fn payload_bits(&self) -> u64 {
+        self.payload_bits
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            self.header.write(&mut self.writer)?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    /// Encodes the pending blocks in parallel and writes them in order. A
+    /// block that encoding does not make any smaller is stored instead.
+    fn write_blocks(&mut self) -> io::Result<()> {
+        self.write_header()?;
+
+        let codec = self.codec.as_ref();
+        let encoded = map_in_parallel(&self.pending, |block| {
+            let mut payload = Vec::new();
+            codec
+                .encode(block, &mut payload)
+                .map(|bits| (bits, payload))
+        });
+
+        for (block, result) in self.pending.iter().zip(encoded) {
+            let (bits, payload) = result?;
+            let raw_len = (block.len() as u32).to_be_bytes();
+
+            if payload.len() < block.len() {
+                self.writer.write_all(&[BLOCK_COMPRESSED])?;
+                self.writer.write_all(&raw_len)?;
+                self.writer
+                    .write_all(&(payload.len() as u32).to_be_bytes())?;
+                self.writer.write_all(&payload)?;
+                self.payload_bits += bits;
+            } else {
+                self.writer.write_all(&[BLOCK_STORED])?;
+                self.writer.write_all(&raw_len)?;
+                self.writer.write_all(block)?;
+                self.payload_bits += block.len() as u64 * 8;
+            }
+        }
+
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Queues the filled block, encoding the queue once every thread has a
+    /// block to work on.
+    fn end_block(&mut self) -> io::Result<()> {
+        let block_size = self.header.block_size as usize;
+        let block = mem::replace(&mut self.buf, Vec::with_capacity(block_size));
+        self.pending.push(block);
+        if self.pending.len() == self.threads {
+            self.write_blocks()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the last blocks and the trailer. Does nothing if the container
+    /// is already finished.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            if !self.buf.is_empty() {
+                self.pending.push(mem::take(&mut self.buf));
+            }
+            self.write_blocks()?;
+            self.writer.write_all(&[BLOCK_END])?;
+            self.writer.write_all(&self.total_in.to_be_bytes())?;
+            self.writer.write_all(&self.hasher.finish().to_be_bytes())?;
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::other("write after the container was finished"));
+        }
+        let block_size = self.header.block_size as usize;
+        if self.buf.len() == block_size {
+            self.end_block()?;
+        }
+
+        let n = data.len().min(block_size - self.buf.len());
+        self.buf.extend_from_slice(&data[..n]);
+        self.hasher.update(&data[..n]);
+        self.total_in += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A block as read from the container, before decoding.
+struct RawBlock {
+    compressed: bool,
+    raw_len: usize,
+    data: Vec<u8>,
+}
+
+impl RawBlock {
+    fn decode(self, codec: &dyn Codec) -> io::Result<Vec<u8>> {
+        if !self.compressed {
+            return Ok(self.data);
+        }
+        let mut output = BlockWriter {
+            data: Vec::with_capacity(self.raw_len),
+            limit: self.raw_len,
+        };
+        codec.decode(&mut self.data.as_slice(), &mut output)?;
+        if output.data.len() != self.raw_len {
+            return Err(invalid_data(&format!(
+                "block decoded to {} bytes but its header says {}",
+                output.data.len(),
+                self.raw_len
+            )));
+        }
+        Ok(output.data)
+    }
+}
+
+/// Collects a block's decoded data, failing as soon as the codec writes more
+/// than `limit` bytes so a corrupt payload cannot grow past the block.
+struct BlockWriter {
+    data: Vec<u8>,
+    limit: usize,
+}
+
+impl Write for BlockWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > self.limit - self.data.len() {
+            return Err(invalid_data(&format!(
+                "block decodes to more than the {} bytes its header says",
+                self.limit
+            )));
+        }
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads the blocks of a container whose header has already been consumed,
+/// decoding up to `threads` blocks in parallel and verifying the length and
+/// checksum in the trailer once the end is reached.
+pub struct Reader<R: Read> {
+    reader: R,
+    codec: Box<dyn Codec>,
+    header: Header,
+    hasher: Hasher,
+    threads: usize,
+    buf: Vec<u8>,
+    pos: usize,
+    total_out: u64,
+    done: bool,
+}
+
+impl<R: Read> Reader<R> {
+    /// Fails if the header names an unknown codec. A `threads` of 0 uses one
+    /// thread per available core.
+    pub fn new(reader: R, header: Header, threads: usize) -> io::Result<Self> {
+        Ok(Self {
+            reader,
+            codec: codec_by_id(header.codec_id)?,
+            header,
+            hasher: header.checksum_kind.hasher(),
+            threads: worker_count(threads),
+            buf: Vec::new(),
+            pos: 0,
+            total_out: 0,
+            done: false,
+        })
+    }
+
+    pub fn codec(&self) -> &dyn Codec {
+        self.codec.as_ref()
+    }
+
+    /// Reads the next block, or returns `None` at the end marker.
+    fn read_raw_block(&mut self) -> io::Result<Option<RawBlock>> {
+        let mut block_type = [0; 1];
+        self.reader.read_exact(&mut block_type)?;
+        if block_type[0] == BLOCK_END {
+            return Ok(None);
+        }
+        if block_type[0] != BLOCK_COMPRESSED && block_type[0] != BLOCK_STORED {
+            return Err(invalid_data(&format!(
+                "unknown block type {}",
+                block_type[0]
+            )));
+        }
+
+        let raw_len = read_u32(&mut self.reader)? as usize;
+        if raw_len == 0 || raw_len > self.header.block_size as usize {
+            return Err(invalid_data(&format!(
+                "block of {} bytes does not fit the block size {}",
+                raw_len, self.header.block_size
+            )));
+        }
+
+        let compressed = block_type[0] == BLOCK_COMPRESSED;
+        let len = if compressed {
+            let payload_len = read_u32(&mut self.reader)? as usize;
+            if payload_len >= raw_len {
+                return Err(invalid_data("compressed block is larger than its data"));
+            }
+            payload_len
+        } else {
+            raw_len
+        };
+        let mut data = Vec::with_capacity(len);
+        read_exactly(&mut self.reader, len, &mut data)?;
+
+        Ok(Some(RawBlock {
+            compressed,
+            raw_len,
+            data,
+        }))
+    }
+
+    /// Decodes the next blocks into `buf`, one per thread, and checks the
+    /// trailer if there are no blocks left.
+    fn read_blocks(&mut self) -> io::Result<()> {
+        let mut blocks = Vec::with_capacity(self.threads);
+        let mut at_end = false;
+        while blocks.len() < self.threads {
+            match self.read_raw_block()? {
+                Some(block) => blocks.push(block),
+                None => {
+                    at_end = true;
+                    break;
+                }
+            }
+        }
+
+        let codec = self.codec.as_ref();
+        let decoded = map_in_parallel(blocks, |block| block.decode(codec));
+
+        self.buf.clear();
+        self.pos = 0;
+        for data in decoded {
+            let data = data?;
+            self.hasher.update(&data);
+            self.total_out += data.len() as u64;
+            self.buf.extend_from_slice(&data);
+        }
+
+        if at_end {
+            self.read_trailer()?;
+        }
+        Ok(())
+    }
+
+    fn read_trailer(&mut self) -> io::Result<()> {
+        let mut trailer = [0; 12];
+        self.reader.read_exact(&mut trailer)?;
+        let mut length = [0; 8];
+        length.copy_from_slice(&trailer[..8]);
+        let mut checksum = [0; 4];
+        checksum.copy_from_slice(&trailer[8..]);
+
+        let length = u64::from_be_bytes(length);
+        if self.total_out != length {
+            return Err(invalid_data(&format!(
+                "decoded {} bytes but the trailer says {}",
+                self.total_out, length
+            )));
+        }
+        checksum::verify(
+            self.header.checksum_kind,
+            u32::from_be_bytes(checksum),
+            self.hasher.finish(),
+        )?;
+
+        self.done = true;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() && !self.done {
+            self.read_blocks()?;
+        }
+
+        let n = buf.len().min(self.buf.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+/// Appends exactly `len` bytes from `reader` to `buf`.
+fn read_exactly<R: Read>(reader: &mut R, len: usize, buf: &mut Vec<u8>) -> io::Result<()> {
+    let read = reader.take(len as u64).read_to_end(buf)?;
+    if read != len {
+        return Err(invalid_data("truncated block"));
+    }
+    Ok(())
+}
+
+/// Result of a successful `decode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decoded {
+    /// Name of the codec the input was encoded with.
+    pub codec: &'static str,
+    pub bytes_written: usize,
+}
+
+/// Encodes `data` with `codec`, wrapped in a container unless the codec is a
+/// standalone format. The trailer records a `checksum` of `data` that
+/// `decode` verifies; standalone formats use their own checksums and ignore
+/// it. Returns the number of bits in the codec's payload, as reported by the
+/// codec.
+pub fn encode(
+    codec: Box<dyn Codec>,
+    checksum: ChecksumKind,
+    data: &[u8],
+    writer: &mut dyn Write,
+) -> io::Result<u64> {
+    let options = EncoderOptions {
+        checksum,
+        ..EncoderOptions::default()
+    };
+    let mut encoder = Encoder::with_options(writer, codec, &options)?;
+    encoder.write_all(data)?;
+    encoder.try_finish()?;
+    Ok(encoder.payload_bits())
+}
+
+/// Decodes input produced by `encode`, picking the codec from the container
+/// header or, for standalone formats, from their magic bytes.
+///
+/// Fails with a `checksum::ChecksumMismatch` if the decoded data does not
+/// match the checksum in the trailer.
+pub fn decode(reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<Decoded> {
+    copy_all(Decoder::new(reader)?, writer)
+}
+
+/// Decodes input that is expected to be encoded with `codec`. Containers
+/// written by a different codec are rejected; input without a container
+/// header is only accepted for standalone formats, which is how those that
+/// cannot be detected (like raw deflate) are decoded.
+pub fn decode_with(
+    codec: &dyn Codec,
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+) -> io::Result<Decoded> {
+    copy_all(Decoder::with_codec(reader, codec)?, writer)
+}
+
+fn copy_all<R: Read>(mut decoder: Decoder<R>, writer: &mut dyn Write) -> io::Result<Decoded> {
+    let bytes_written = io::copy(&mut decoder, writer)?;
+    Ok(Decoded {
+        codec: decoder.codec(),
+        bytes_written: bytes_written as usize,
+    })
+}
+
+fn codec_by_id(id: u8) -> io::Result<Box<dyn Codec>> {
+    codec::codec_info_by_id(id)
+        .and_then(|info| codec::get_codec(info.name))
+        .ok_or_else(|| invalid_data(&format!("unknown codec id {}", id)))
+}
+
+pub(crate) fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Container error: {}", msg),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{get_codec, CODECS};
+    use crate::deflate::tests::LOREM;
+    use crate::stream::DecoderOptions;
+
+    fn rle() -> Box<dyn Codec> {
+        get_codec("rle").unwrap()
+    }
+
+    fn roundtrip(name: &str, data: &[u8]) -> (Decoded, Vec<u8>) {
+        let codec = get_codec(name).unwrap();
+        let mut enc_buf: Vec<u8> = Vec::new();
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        encode(codec, ChecksumKind::Crc32, data, &mut enc_buf).expect("Failed to encode");
+        let decoded = decode(&mut enc_buf.as_slice(), &mut dec_buf).expect("Failed to decode");
+
+        (decoded, dec_buf)
+    }
+
+    fn encode_with(name: &str, options: &EncoderOptions, data: &[u8]) -> Vec<u8> {
+        let mut encoder =
+            Encoder::with_options(Vec::new(), get_codec(name).unwrap(), options).unwrap();
+        encoder.write_all(data).expect("Failed to encode");
+        encoder.finish().expect("Failed to encode")
+    }
+
+    fn with_block_size(block_size: usize) -> EncoderOptions {
+        EncoderOptions {
+            block_size,
+            ..EncoderOptions::default()
+        }
+    }
+
+    /// Walks the blocks of a container and returns their types.
+    fn block_types(encoded: &[u8]) -> Vec<u8> {
+        let mut rest = &encoded[HEADER_LEN..];
+        let mut types = Vec::new();
+        loop {
+            let block_type = rest[0];
+            types.push(block_type);
+            let raw_len = u32::from_be_bytes(rest[1..5].try_into().unwrap()) as usize;
+            rest = match block_type {
+                BLOCK_COMPRESSED => {
+                    let len = u32::from_be_bytes(rest[5..9].try_into().unwrap()) as usize;
+                    &rest[9 + len..]
+                }
+                BLOCK_STORED => &rest[5 + raw_len..],
+                _ => return types,
+            };
+        }
+    }
+
+    #[test]
+    fn writes_header_blocks_and_trailer() {
+        let mut buf: Vec<u8> = Vec::new();
+
+        encode(rle(), ChecksumKind::Adler32, b"aaaa", &mut buf).expect("Failed to encode");
+        // adler32("aaaa") = 0x03CE_0185
+        #[rustfmt::skip]
+        assert_eq!(
+            buf,
+            vec![
+                b'C', b'P', b'R', b'S', 5, 2, 2, 0, 0x10, 0, 0, // header, 1 MiB blocks
+                BLOCK_COMPRESSED, 0, 0, 0, 4, 0, 0, 0, 2, 0x82, b'a', // one block
+                BLOCK_END,
+                0, 0, 0, 0, 0, 0, 0, 4, 0x03, 0xCE, 0x01, 0x85, // trailer
+            ]
+        );
+    }
+
+    #[test]
+    fn detects_every_detectable_codec() {
+        let data = b"abracadabra abracadabra abracadabra";
+
+        // Raw deflate has no magic bytes and zstd is only decoded
+        for info in CODECS
+            .iter()
+            .filter(|info| !matches!(info.name, "deflate" | "zstd"))
+        {
+            let (decoded, output) = roundtrip(info.name, data);
+            assert_eq!(decoded.codec, info.name);
+            assert_eq!(decoded.bytes_written, data.len());
+            assert_eq!(output, data);
+        }
+    }
+
+    #[test]
+    fn encodes_and_decodes_empty_input() {
+        // Huffman cannot encode empty input by itself
+        let (decoded, output) = roundtrip("huffman", b"");
+
+        assert_eq!(decoded.bytes_written, 0);
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn decodes_lz77_with_a_non_default_window() {
+        // Repeats 40K bytes later, only in reach of the wider window
+        let mut state = 1u32;
+        let mut data: Vec<u8> = (0..40_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 24) as u8
+            })
+            .collect();
+        data.extend_from_within(..);
+        let tokens = crate::lz77::tokenize(&data, 64 * 1024, 3).unwrap();
+        assert!(tokens
+            .iter()
+            .any(|t| t.offset > crate::lz77::DEFAULT_WINDOW_SIZE));
+
+        let codec = Box::new(crate::lz77::Lz77Codec::new(64 * 1024, 3));
+        let mut encoded = Vec::new();
+        let mut decoded = Vec::new();
+        encode(codec, ChecksumKind::Crc32, &data, &mut encoded).expect("Failed to encode");
+        decode(&mut encoded.as_slice(), &mut decoded).expect("Failed to decode");
+
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn splits_input_into_blocks() {
+        let data: Vec<u8> = (0..2 * MIN_BLOCK_SIZE + 10)
+            .map(|i| (i / 1000) as u8)
+            .collect();
+
+        let encoded = encode_with("rle", &with_block_size(MIN_BLOCK_SIZE), &data);
+        assert_eq!(
+            block_types(&encoded),
+            vec![
+                BLOCK_COMPRESSED,
+                BLOCK_COMPRESSED,
+                BLOCK_COMPRESSED,
+                BLOCK_END
+            ]
+        );
+
+        let mut dec_buf: Vec<u8> = Vec::new();
+        decode(&mut encoded.as_slice(), &mut dec_buf).expect("Failed to decode");
+        assert_eq!(dec_buf, data);
+    }
+
+    #[test]
+    fn stores_blocks_the_codec_cannot_shrink() {
+        let mut data: Vec<u8> = b"abcdefgh".repeat(MIN_BLOCK_SIZE / 8);
+        data.extend(vec![0; MIN_BLOCK_SIZE]);
+
+        // RLE makes runless data slightly larger
+        let encoded = encode_with("rle", &with_block_size(MIN_BLOCK_SIZE), &data);
+        assert_eq!(
+            block_types(&encoded),
+            vec![BLOCK_STORED, BLOCK_COMPRESSED, BLOCK_END]
+        );
+
+        let mut dec_buf: Vec<u8> = Vec::new();
+        decode(&mut encoded.as_slice(), &mut dec_buf).expect("Failed to decode");
+        assert_eq!(dec_buf, data);
+    }
+
+    #[test]
+    fn threads_do_not_change_the_output() {
+        // Ten blocks, more than the threads so they are coded in batches
+        let data: Vec<u8> = LOREM
+            .iter()
+            .copied()
+            .cycle()
+            .take(10 * MIN_BLOCK_SIZE - 3)
+            .collect();
+        let single = encode_with("lzh", &with_block_size(MIN_BLOCK_SIZE), &data);
+
+        for threads in [0, 3, 16] {
+            let options = EncoderOptions {
+                threads,
+                ..with_block_size(MIN_BLOCK_SIZE)
+            };
+            assert_eq!(encode_with("lzh", &options, &data), single);
+
+            let options = DecoderOptions { threads };
+            let mut decoder = Decoder::with_options(single.as_slice(), None, &options).unwrap();
+            let mut output = Vec::new();
+            decoder.read_to_end(&mut output).unwrap();
+            assert_eq!(output, data);
+        }
+    }
+
+    #[test]
+    fn detects_corruption_in_parallel_blocks() {
+        let data: Vec<u8> = LOREM
+            .iter()
+            .copied()
+            .cycle()
+            .take(4 * MIN_BLOCK_SIZE)
+            .collect();
+        let mut encoded = encode_with("lzh", &with_block_size(MIN_BLOCK_SIZE), &data);
+        let middle = encoded.len() / 2;
+        encoded[middle] ^= 0x10;
+
+        let options = DecoderOptions { threads: 4 };
+        let mut decoder = Decoder::with_options(encoded.as_slice(), None, &options).unwrap();
+        assert!(decoder.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn small_blocks_adapt_to_changing_data() {
+        // Text followed by bytes spread over the whole alphabet, like a tarball
+        // of sources and binaries
+        let mut data: Vec<u8> = LOREM.iter().copied().cycle().take(1 << 18).collect();
+        data.extend((0..1u32 << 18).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8));
+
+        let small = encode_with("huffman", &with_block_size(MIN_BLOCK_SIZE), &data);
+        let large = encode_with("huffman", &with_block_size(MAX_BLOCK_SIZE), &data);
+        assert!(small.len() < large.len());
+    }
+
+    #[test]
+    fn rejects_block_size_out_of_range() {
+        for size in [0, MIN_BLOCK_SIZE - 1, MAX_BLOCK_SIZE + 1] {
+            let result = Writer::new(Vec::new(), rle(), &with_block_size(size));
+            assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+        }
+
+        let mut encoded = encode_with("rle", &EncoderOptions::default(), b"aaaa");
+        encoded[7..11].copy_from_slice(&1u32.to_be_bytes());
+        assert!(decode(&mut encoded.as_slice(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn rejects_block_larger_than_block_size() {
+        let data = vec![7; MIN_BLOCK_SIZE + 1];
+        let mut encoded = encode_with("rle", &with_block_size(2 * MIN_BLOCK_SIZE), &data);
+        encoded[7..11].copy_from_slice(&(MIN_BLOCK_SIZE as u32).to_be_bytes());
+
+        let err = decode(&mut encoded.as_slice(), &mut Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("block size"));
+    }
+
+    #[test]
+    fn rejects_payload_larger_than_its_block() {
+        let data = vec![b'a'; MIN_BLOCK_SIZE];
+        // Codecs whose output only their own end marker bounds
+        for name in ["rle", "lzh", "arith", "lz78"] {
+            let mut encoded = encode_with(name, &with_block_size(MIN_BLOCK_SIZE), &data);
+            // The payload still decodes to the whole block, not the 2000
+            // bytes claimed
+            let raw_len = HEADER_LEN + 1;
+            encoded[raw_len..raw_len + 4].copy_from_slice(&2000u32.to_be_bytes());
+
+            let err = decode(&mut encoded.as_slice(), &mut Vec::new()).unwrap_err();
+            assert!(
+                err.to_string().contains("more than the 2000 bytes"),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_payload_length_larger_than_its_block() {
+        let options = EncoderOptions {
+            checksum: ChecksumKind::None,
+            ..with_block_size(MIN_BLOCK_SIZE)
+        };
+        let mut encoded = encode_with("rans", &options, &vec![b'a'; MIN_BLOCK_SIZE]);
+        // The length at the start of the rANS payload
+        let len = HEADER_LEN + 9;
+        encoded[len..len + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+
+        let err = decode(&mut encoded.as_slice(), &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn decodes_raw_deflate_when_codec_is_given() {
+        let codec = get_codec("deflate").unwrap();
+        let mut enc_buf: Vec<u8> = Vec::new();
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        encode(
+            get_codec("deflate").unwrap(),
+            ChecksumKind::None,
+            b"hello hello",
+            &mut enc_buf,
+        )
+        .expect("Failed to encode");
+        assert!(decode(&mut enc_buf.as_slice(), &mut Vec::new()).is_err());
+
+        decode_with(codec.as_ref(), &mut enc_buf.as_slice(), &mut dec_buf)
+            .expect("Failed to decode");
+        assert_eq!(dec_buf, b"hello hello");
+    }
+
+    #[test]
+    fn rejects_container_from_other_codec() {
+        let mut enc_buf: Vec<u8> = Vec::new();
+        encode(rle(), ChecksumKind::Crc32, b"aaaa", &mut enc_buf).unwrap();
+
+        let huffman = get_codec("huffman").unwrap();
+        let err =
+            decode_with(huffman.as_ref(), &mut enc_buf.as_slice(), &mut Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("'rle'"));
+    }
+
+    #[test]
+    fn rejects_unknown_input() {
+        let err = decode(
+            &mut b"definitely not compressed".as_slice(),
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_unknown_codec_and_version() {
+        let mut enc_buf: Vec<u8> = Vec::new();
+        encode(rle(), ChecksumKind::Crc32, b"aaaa", &mut enc_buf).unwrap();
+
+        let mut unknown_codec = enc_buf.clone();
+        unknown_codec[5] = 250;
+        assert!(decode(&mut unknown_codec.as_slice(), &mut Vec::new()).is_err());
+
+        let mut unknown_version = enc_buf.clone();
+        unknown_version[4] = 99;
+        assert!(decode(&mut unknown_version.as_slice(), &mut Vec::new()).is_err());
+
+        let mut unknown_checksum = enc_buf;
+        unknown_checksum[6] = 99;
+        assert!(decode(&mut unknown_checksum.as_slice(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn rejects_length_mismatch() {
+        let mut enc_buf: Vec<u8> = Vec::new();
+        encode(rle(), ChecksumKind::Crc32, b"aaaa", &mut enc_buf).unwrap();
+        // Lowest byte of the original length in the trailer
+        let len = enc_buf.len();
+        enc_buf[len - 5] = 5;
+
+        assert!(decode(&mut enc_buf.as_slice(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let mut enc_buf: Vec<u8> = Vec::new();
+        encode(rle(), ChecksumKind::Crc32, b"abcdefgh", &mut enc_buf).unwrap();
+
+        for len in HEADER_LEN..enc_buf.len() {
+            assert!(decode(&mut &enc_buf[..len], &mut Vec::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn roundtrips_with_every_checksum_kind() {
+        let data = b"abracadabra abracadabra abracadabra";
+
+        for kind in ChecksumKind::ALL {
+            let mut enc_buf: Vec<u8> = Vec::new();
+            let mut dec_buf: Vec<u8> = Vec::new();
+
+            encode(rle(), kind, data, &mut enc_buf).expect("Failed to encode");
+            decode(&mut enc_buf.as_slice(), &mut dec_buf).expect("Failed to decode");
+            assert_eq!(dec_buf, data);
+        }
+    }
+
+    /// Position of the last byte of "abcdefgh" once encoded: RLE can't shrink
+    /// it, so it is stored after the header and the block type and length.
+    const LAST_LITERAL: usize = HEADER_LEN + 5 + 7;
+
+    #[test]
+    fn detects_corrupted_payload() {
+        let mut enc_buf: Vec<u8> = Vec::new();
+        encode(rle(), ChecksumKind::XxHash32, b"abcdefgh", &mut enc_buf).unwrap();
+        // Flip a bit in one of the literal bytes, same length so only the checksum can tell
+        enc_buf[LAST_LITERAL] ^= 0x01;
+
+        let err = decode(&mut enc_buf.as_slice(), &mut Vec::new()).unwrap_err();
+        let mismatch = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<checksum::ChecksumMismatch>());
+        assert!(mismatch.is_some());
+    }
+
+    #[test]
+    fn corruption_goes_unnoticed_without_checksum() {
+        let mut enc_buf: Vec<u8> = Vec::new();
+        encode(rle(), ChecksumKind::None, b"abcdefgh", &mut enc_buf).unwrap();
+        enc_buf[LAST_LITERAL] ^= 0x01;
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        decode(&mut enc_buf.as_slice(), &mut dec_buf).expect("Failed to decode");
+        assert_eq!(dec_buf, b"abcdefgi");
+    }
+}
@@ -0,0 +1,169 @@
+use std::io::{self, Read, Write};
+
+/// Reads a DEFLATE bit stream: bits are packed starting at the least
+/// significant bit of each byte.
+///
+/// Bytes are pulled from the underlying reader one at a time and only when
+/// needed, so once the stream ends on a byte boundary the reader is left
+/// positioned right after it (e.g. at a gzip trailer).
+pub struct BitReader<R: Read> {
+    inner: R,
+    bit_buf: u32,
+    bit_count: u8,
+}
+
+impl<R: Read> BitReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            bit_buf: 0,
+            bit_count: 0,
+        }
+    }
+
+    /// The underlying reader. Bits of a partially consumed byte stay behind
+    /// in the `BitReader`.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads `count` (at most 24) bits as an integer, first bit in the LSB.
+    pub fn read_bits(&mut self, count: u8) -> io::Result<u32> {
+        while self.bit_count < count {
+            let mut byte = [0; 1];
+            self.inner.read_exact(&mut byte)?;
+            self.bit_buf |= (byte[0] as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+
+        let value = self.bit_buf & ((1u32 << count) - 1);
+        self.bit_buf >>= count;
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    pub fn read_bit(&mut self) -> io::Result<bool> {
+        Ok(self.read_bits(1)? == 1)
+    }
+
+    /// Drops the bits remaining in the current byte.
+    pub fn align_to_byte(&mut self) {
+        self.bit_buf = 0;
+        self.bit_count = 0;
+    }
+
+    /// Reads whole bytes. Must only be called on a byte boundary.
+    pub fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        debug_assert_eq!(self.bit_count, 0);
+        self.inner.read_exact(buf)
+    }
+}
+
+/// Writes a DEFLATE bit stream, the counterpart of `BitReader`.
+pub struct BitWriter<W: Write> {
+    inner: W,
+    bit_buf: u64,
+    bit_count: u8,
+    bits_written: u64,
+}
+
+impl<W: Write> BitWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            bit_buf: 0,
+            bit_count: 0,
+            bits_written: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// The underlying writer. Must only be written to on a byte boundary.
+    pub fn get_mut(&mut self) -> &mut W {
+        debug_assert_eq!(self.bit_count, 0);
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Writes the low `count` (at most 32) bits of `value`, LSB first.
+    pub fn write_bits(&mut self, value: u32, count: u8) -> io::Result<()> {
+        self.bit_buf |= ((value as u64) & ((1u64 << count) - 1)) << self.bit_count;
+        self.bit_count += count;
+        self.bits_written += count as u64;
+
+        while self.bit_count >= 8 {
+            self.inner.write_all(&[self.bit_buf as u8])?;
+            self.bit_buf >>= 8;
+            self.bit_count -= 8;
+        }
+        Ok(())
+    }
+
+    /// Pads with zero bits up to the next byte boundary.
+    pub fn align_to_byte(&mut self) -> io::Result<()> {
+        if self.bit_count > 0 {
+            self.write_bits(0, 8 - self.bit_count)?;
+        }
+        Ok(())
+    }
+
+    /// Writes whole bytes. Must only be called on a byte boundary.
+    pub fn write_bytes(&mut self, buf: &[u8]) -> io::Result<()> {
+        debug_assert_eq!(self.bit_count, 0);
+        self.bits_written += buf.len() as u64 * 8;
+        self.inner.write_all(buf)
+    }
+
+    /// Total number of bits written so far, including padding.
+    pub fn bits_written(&self) -> u64 {
+        self.bits_written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_and_reads_bits_lsb_first() {
+        let mut buf: Vec<u8> = Vec::new();
+        let mut writer = BitWriter::new(&mut buf);
+        writer.write_bits(0b1, 1).unwrap();
+        writer.write_bits(0b01, 2).unwrap();
+        writer.write_bits(0x1FF, 9).unwrap();
+        writer.align_to_byte().unwrap();
+        assert_eq!(writer.bits_written(), 16);
+        assert_eq!(buf, vec![0b1111_1011, 0b0000_1111]);
+
+        let mut slice = buf.as_slice();
+        let mut reader = BitReader::new(&mut slice);
+        assert_eq!(reader.read_bits(1).unwrap(), 0b1);
+        assert_eq!(reader.read_bits(2).unwrap(), 0b01);
+        assert_eq!(reader.read_bits(9).unwrap(), 0x1FF);
+    }
+
+    #[test]
+    fn leaves_reader_after_last_consumed_byte() {
+        let data = [0xFF, 0xAB, 0xCD];
+        let mut slice = &data[..];
+        let mut reader = BitReader::new(&mut slice);
+
+        reader.read_bits(3).unwrap();
+        reader.align_to_byte();
+        let mut rest = [0; 1];
+        reader.read_bytes(&mut rest).unwrap();
+
+        assert_eq!(rest, [0xAB]);
+        assert_eq!(slice, &[0xCD]);
+    }
+}
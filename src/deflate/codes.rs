@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+use super::bits::BitReader;
+use super::invalid_data;
+use crate::huffman::limit::limited_code_lengths;
+use crate::huffman::tree::HuffmanTree;
+
+/// Longest code allowed in the literal/length and distance alphabets.
+pub const MAX_BITS: u8 = 15;
+
+/// Decodes symbols of a canonical Huffman code given only its code lengths.
+///
+/// Codes are read one bit at a time, MSB of the code first. At each length
+/// the codes form a contiguous range starting at `first`, so a code can be
+/// resolved as soon as it falls below `first + count`.
+pub struct Decoder {
+    counts: [u16; MAX_BITS as usize + 1],
+    symbols: Vec<u16>,
+}
+
+impl Decoder {
+    /// Builds a decoder from per-symbol code lengths (0 = symbol unused).
+    /// Incomplete codes are accepted, over-subscribed ones are not.
+    pub fn from_lengths(lengths: &[u8]) -> io::Result<Self> {
+        let mut counts = [0u16; MAX_BITS as usize + 1];
+        for &len in lengths {
+            if len > MAX_BITS {
+                return Err(invalid_data("code length too long"));
+            }
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(invalid_data("over-subscribed code lengths"));
+            }
+        }
+
+        // Symbols ordered by (length, symbol), which is canonical code order
+        let mut offsets = [0u16; MAX_BITS as usize + 2];
+        for len in 1..=MAX_BITS as usize {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; offsets[MAX_BITS as usize + 1] as usize];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len > 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        Ok(Self { counts, symbols })
+    }
+
+    pub fn decode<R: Read>(&self, bits: &mut BitReader<R>) -> io::Result<u16> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+
+        for len in 1..=MAX_BITS as usize {
+            code |= bits.read_bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(invalid_data("invalid Huffman code"))
+    }
+}
+
+/// Assigns canonical codes to the given lengths (RFC 1951 section 3.2.2).
+/// Codes are returned bit-reversed, ready to be written LSB first.
+pub fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut bl_count = [0u16; MAX_BITS as usize + 1];
+    for &len in lengths {
+        bl_count[len as usize] += 1;
+    }
+    bl_count[0] = 0;
+
+    let mut next_code = [0u16; MAX_BITS as usize + 1];
+    let mut code = 0u16;
+    for bits in 1..=MAX_BITS as usize {
+        code = (code + bl_count[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+
+    lengths
+        .iter()
+        .map(|&len| {
+            if len == 0 {
+                return 0;
+            }
+            let code = next_code[len as usize];
+            next_code[len as usize] += 1;
+            code.reverse_bits() >> (16 - len)
+        })
+        .collect()
+}
+
+/// Computes Huffman code lengths for the given symbol frequencies, with no
+/// code longer than `max_bits`. Unused symbols get length 0.
+pub fn code_lengths(freqs: &[u32], max_bits: u8) -> Vec<u8> {
+    let counts: HashMap<u16, u64> = freqs
+        .iter()
+        .enumerate()
+        .filter(|(_, &f)| f > 0)
+        .map(|(s, &f)| (s as u16, f as u64))
+        .collect();
+
+    let mut lengths = vec![0u8; freqs.len()];
+    let Some(tree) = HuffmanTree::from_counts(&counts) else {
+        return lengths;
+    };
+    let mut code_lengths = tree.code_lengths();
+    if code_lengths.values().any(|&len| len > max_bits) {
+        code_lengths =
+            limited_code_lengths(&counts, max_bits).expect("DEFLATE alphabets fit in max_bits");
+    }
+    for (symbol, len) in code_lengths {
+        lengths[symbol as usize] = len;
+    }
+    lengths
+}
+
+/// Code lengths of the fixed literal/length code (RFC 1951 section 3.2.6).
+pub fn fixed_literal_lengths() -> Vec<u8> {
+    let mut lengths = vec![8u8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths
+}
+
+/// Code lengths of the fixed distance code.
+pub fn fixed_distance_lengths() -> Vec<u8> {
+    vec![5u8; 30]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assigns_canonical_codes_from_rfc_example() {
+        // RFC 1951 example: ABCDEFGH with lengths (3, 3, 3, 3, 3, 2, 4, 4)
+        // gives codes 010 011 100 101 110 00 1110 1111
+        let codes = canonical_codes(&[3, 3, 3, 3, 3, 2, 4, 4]);
+        let expected: Vec<u16> = [
+            (0b010, 3),
+            (0b011, 3),
+            (0b100, 3),
+            (0b101, 3),
+            (0b110, 3),
+            (0b00, 2),
+            (0b1110, 4),
+            (0b1111, 4),
+        ]
+        .iter()
+        .map(|&(code, len): &(u16, u8)| code.reverse_bits() >> (16 - len))
+        .collect();
+
+        assert_eq!(codes, expected);
+    }
+
+    #[test]
+    fn decodes_canonical_codes() {
+        let decoder = Decoder::from_lengths(&[3, 3, 3, 3, 3, 2, 4, 4]).unwrap();
+        // F (00), H (1111), A (010), written MSB of each code first
+        let bits = [0, 0, 1, 1, 1, 1, 0, 1, 0];
+        let mut packed = [0u8; 2];
+        for (i, &b) in bits.iter().enumerate() {
+            packed[i / 8] |= b << (i % 8);
+        }
+
+        let mut slice = &packed[..];
+        let mut reader = BitReader::new(&mut slice);
+        assert_eq!(decoder.decode(&mut reader).unwrap(), 5);
+        assert_eq!(decoder.decode(&mut reader).unwrap(), 7);
+        assert_eq!(decoder.decode(&mut reader).unwrap(), 0);
+    }
+
+    #[test]
+    fn rejects_over_subscribed_lengths() {
+        assert!(Decoder::from_lengths(&[1, 1, 1]).is_err());
+    }
+
+    #[test]
+    fn limits_code_lengths() {
+        // Fibonacci frequencies produce a maximally skewed tree
+        let mut freqs = vec![1u32, 1];
+        for i in 2..20 {
+            freqs.push(freqs[i - 1] + freqs[i - 2]);
+        }
+
+        assert!(code_lengths(&freqs, 20).iter().any(|&len| len > 15));
+
+        let lengths = code_lengths(&freqs, 15);
+        assert!(lengths.iter().all(|&len| len > 0 && len <= 15));
+        let kraft: u32 = lengths.iter().map(|&len| 1 << (15 - len)).sum();
+        assert_eq!(kraft, 1 << 15);
+        assert!(Decoder::from_lengths(&lengths).is_ok());
+    }
+
+    #[test]
+    fn gives_single_symbol_a_one_bit_code() {
+        assert_eq!(code_lengths(&[0, 5, 0], 15), vec![0, 1, 0]);
+    }
+}
@@ -0,0 +1,400 @@
+use std::io::{self, Write};
+
+use super::bits::BitWriter;
+use super::codes::{
+    canonical_codes, code_lengths, fixed_distance_lengths, fixed_literal_lengths, MAX_BITS,
+};
+use super::CODE_LENGTH_ORDER;
+use crate::lzh::symbols::{distance_symbol, length_symbol, END_OF_BLOCK};
+use crate::lzh::{tokenize, Token};
+
+/// Tokens per block. Each block gets its own Huffman codes.
+const BLOCK_TOKENS: usize = 1 << 14;
+
+/// Largest payload of a single stored block.
+const MAX_STORED: usize = u16::MAX as usize;
+
+/// Longest code allowed in the code length alphabet.
+const MAX_CODE_LENGTH_BITS: u8 = 7;
+
+/// Input compressed at a time by `DeflateEncoder`. Matches never reach back
+/// into the previous chunk.
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Compresses data into a raw DEFLATE stream (RFC 1951) as it is written.
+///
+/// Input is buffered and compressed a chunk at a time, each block written as
+/// whichever of stored, fixed or dynamic Huffman comes out smallest. Call
+/// `finish` to write the final block; dropping the encoder without it leaves
+/// the stream truncated.
+pub struct DeflateEncoder<W: Write> {
+    bits: BitWriter<W>,
+    buf: Vec<u8>,
+    finished: bool,
+}
+
+impl<W: Write> DeflateEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            bits: BitWriter::new(writer),
+            buf: Vec::new(),
+            finished: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.bits.get_ref()
+    }
+
+    /// The underlying writer. Only safe to write to before any data or
+    /// after `try_finish`, e.g. for a zlib or gzip header and trailer.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.bits.get_mut()
+    }
+
+    /// Compresses what is left and writes the final block. Does nothing if
+    /// the stream is already finished.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            compress_chunk(&mut self.bits, &self.buf, true)?;
+            self.bits.align_to_byte()?;
+            self.buf.clear();
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.bits.into_inner())
+    }
+
+    /// Number of compressed bits written so far, including the padding of
+    /// the last byte once finished.
+    pub fn bits_written(&self) -> u64 {
+        self.bits.bits_written()
+    }
+}
+
+impl<W: Write> Write for DeflateEncoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::other(
+                "write after the DEFLATE stream was finished",
+            ));
+        }
+        // A full chunk is only compressed once more data arrives, so the
+        // last one can be marked final
+        if self.buf.len() == CHUNK_SIZE {
+            compress_chunk(&mut self.bits, &self.buf, false)?;
+            self.buf.clear();
+        }
+        let n = data.len().min(CHUNK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.bits.get_mut().flush()
+    }
+}
+
+/// Compresses data into a raw DEFLATE stream (RFC 1951). Returns the number
+/// of bits written, including the padding of the last byte.
+pub fn deflate<W: Write + ?Sized>(data: &[u8], writer: &mut W) -> io::Result<u64> {
+    let mut encoder = DeflateEncoder::new(writer);
+    encoder.write_all(data)?;
+    encoder.try_finish()?;
+    Ok(encoder.bits_written())
+}
+
+fn compress_chunk<W: Write>(
+    bits: &mut BitWriter<W>,
+    data: &[u8],
+    is_final: bool,
+) -> io::Result<()> {
+    let tokens = tokenize(data);
+
+    if tokens.is_empty() && is_final {
+        write_block(bits, &[], &[], true)?;
+    }
+
+    let mut start = 0;
+    let num_blocks = tokens.len().div_ceil(BLOCK_TOKENS);
+    for (i, block) in tokens.chunks(BLOCK_TOKENS).enumerate() {
+        let len: usize = block.iter().map(token_len).sum();
+        write_block(
+            bits,
+            block,
+            &data[start..start + len],
+            is_final && i + 1 == num_blocks,
+        )?;
+        start += len;
+    }
+    Ok(())
+}
+
+fn token_len(token: &Token) -> usize {
+    match *token {
+        Token::Literal(_) => 1,
+        Token::Match { length, .. } => length as usize,
+    }
+}
+
+/// Code lengths and header for a dynamic block.
+struct DynamicCodes {
+    lit_lengths: Vec<u8>,
+    dist_lengths: Vec<u8>,
+    cl_lengths: Vec<u8>,
+    cl_symbols: Vec<(u8, u8)>,
+    hclen: usize,
+}
+
+impl DynamicCodes {
+    fn new(lit_freqs: &[u32], dist_freqs: &[u32]) -> Self {
+        let mut lit_lengths = code_lengths(lit_freqs, MAX_BITS);
+        let mut dist_lengths = code_lengths(dist_freqs, MAX_BITS);
+        if dist_lengths.iter().all(|&len| len == 0) {
+            // At least one distance code must be present, even if unused
+            dist_lengths[0] = 1;
+        }
+
+        let hlit = (257..=lit_lengths.len())
+            .rev()
+            .find(|&n| lit_lengths[n - 1] > 0)
+            .unwrap_or(257);
+        let hdist = (1..=dist_lengths.len())
+            .rev()
+            .find(|&n| dist_lengths[n - 1] > 0)
+            .unwrap_or(1);
+        lit_lengths.truncate(hlit);
+        dist_lengths.truncate(hdist);
+
+        let all_lengths: Vec<u8> = lit_lengths.iter().chain(&dist_lengths).copied().collect();
+        let cl_symbols = run_length_encode(&all_lengths);
+
+        let mut cl_freqs = [0u32; 19];
+        for &(symbol, _) in &cl_symbols {
+            cl_freqs[symbol as usize] += 1;
+        }
+        let cl_lengths = code_lengths(&cl_freqs, MAX_CODE_LENGTH_BITS);
+        let hclen = (4..=19)
+            .rev()
+            .find(|&n| cl_lengths[CODE_LENGTH_ORDER[n - 1]] > 0)
+            .unwrap_or(4);
+
+        Self {
+            lit_lengths,
+            dist_lengths,
+            cl_lengths,
+            cl_symbols,
+            hclen,
+        }
+    }
+
+    fn header_cost(&self) -> u64 {
+        let symbols: u64 = self
+            .cl_symbols
+            .iter()
+            .map(|&(symbol, _)| self.cl_lengths[symbol as usize] as u64 + extra_bits(symbol) as u64)
+            .sum();
+        5 + 5 + 4 + 3 * self.hclen as u64 + symbols
+    }
+
+    fn write_header<W: Write>(&self, bits: &mut BitWriter<W>) -> io::Result<()> {
+        bits.write_bits(self.lit_lengths.len() as u32 - 257, 5)?;
+        bits.write_bits(self.dist_lengths.len() as u32 - 1, 5)?;
+        bits.write_bits(self.hclen as u32 - 4, 4)?;
+        for &index in &CODE_LENGTH_ORDER[..self.hclen] {
+            bits.write_bits(self.cl_lengths[index] as u32, 3)?;
+        }
+
+        let cl_codes = canonical_codes(&self.cl_lengths);
+        for &(symbol, extra) in &self.cl_symbols {
+            bits.write_bits(
+                cl_codes[symbol as usize] as u32,
+                self.cl_lengths[symbol as usize],
+            )?;
+            bits.write_bits(extra as u32, extra_bits(symbol))?;
+        }
+        Ok(())
+    }
+}
+
+fn extra_bits(cl_symbol: u8) -> u8 {
+    match cl_symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+/// Run-length encodes code lengths into `(symbol, extra bits value)` pairs of
+/// the code length alphabet: 0-15 literal lengths, 16 repeats the previous
+/// length 3-6 times, 17 and 18 repeat zero 3-10 and 11-138 times.
+fn run_length_encode(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut symbols = Vec::new();
+    let mut i = 0;
+
+    while i < lengths.len() {
+        let value = lengths[i];
+        let mut run = lengths[i..].iter().take_while(|&&len| len == value).count();
+        i += run;
+
+        if value == 0 {
+            while run >= 11 {
+                let n = run.min(138);
+                symbols.push((18, (n - 11) as u8));
+                run -= n;
+            }
+            if run >= 3 {
+                symbols.push((17, (run - 3) as u8));
+                run = 0;
+            }
+        } else {
+            symbols.push((value, 0));
+            run -= 1;
+            while run >= 3 {
+                let n = run.min(6);
+                symbols.push((16, (n - 3) as u8));
+                run -= n;
+            }
+        }
+
+        symbols.extend(std::iter::repeat_n((value, 0), run));
+    }
+
+    symbols
+}
+
+fn write_block<W: Write>(
+    bits: &mut BitWriter<W>,
+    tokens: &[Token],
+    raw: &[u8],
+    is_final: bool,
+) -> io::Result<()> {
+    let mut lit_freqs = [0u32; 286];
+    let mut dist_freqs = [0u32; 30];
+    let mut extra_cost: u64 = 0;
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => lit_freqs[byte as usize] += 1,
+            Token::Match { length, distance } => {
+                let len = length_symbol(length);
+                let dist = distance_symbol(distance);
+                lit_freqs[len.symbol as usize] += 1;
+                dist_freqs[dist.symbol as usize] += 1;
+                extra_cost += len.extra_bits as u64 + dist.extra_bits as u64;
+            }
+        }
+    }
+    lit_freqs[END_OF_BLOCK as usize] += 1;
+
+    let payload_cost = |lit_lengths: &[u8], dist_lengths: &[u8]| -> u64 {
+        let lit: u64 = lit_lengths
+            .iter()
+            .zip(&lit_freqs)
+            .map(|(&len, &f)| len as u64 * f as u64)
+            .sum();
+        let dist: u64 = dist_lengths
+            .iter()
+            .zip(&dist_freqs)
+            .map(|(&len, &f)| len as u64 * f as u64)
+            .sum();
+        lit + dist + extra_cost
+    };
+
+    let dynamic = DynamicCodes::new(&lit_freqs, &dist_freqs);
+    let dynamic_cost =
+        dynamic.header_cost() + payload_cost(&dynamic.lit_lengths, &dynamic.dist_lengths);
+    let fixed_lit = fixed_literal_lengths();
+    let fixed_dist = fixed_distance_lengths();
+    let fixed_cost = payload_cost(&fixed_lit, &fixed_dist);
+    let stored_cost =
+        (raw.len().div_ceil(MAX_STORED).max(1) as u64) * (3 + 7 + 32) + raw.len() as u64 * 8;
+
+    if stored_cost < dynamic_cost.min(fixed_cost) {
+        return write_stored(bits, raw, is_final);
+    }
+
+    bits.write_bits(is_final as u32, 1)?;
+    if dynamic_cost < fixed_cost {
+        bits.write_bits(2, 2)?;
+        dynamic.write_header(bits)?;
+        write_tokens(bits, tokens, &dynamic.lit_lengths, &dynamic.dist_lengths)
+    } else {
+        bits.write_bits(1, 2)?;
+        write_tokens(bits, tokens, &fixed_lit, &fixed_dist)
+    }
+}
+
+fn write_stored<W: Write>(bits: &mut BitWriter<W>, raw: &[u8], is_final: bool) -> io::Result<()> {
+    let num_chunks = raw.len().div_ceil(MAX_STORED).max(1);
+    for i in 0..num_chunks {
+        let chunk = &raw[(i * MAX_STORED).min(raw.len())..((i + 1) * MAX_STORED).min(raw.len())];
+        let len = chunk.len() as u16;
+
+        bits.write_bits((is_final && i + 1 == num_chunks) as u32, 1)?;
+        bits.write_bits(0, 2)?;
+        bits.align_to_byte()?;
+        bits.write_bytes(&len.to_le_bytes())?;
+        bits.write_bytes(&(!len).to_le_bytes())?;
+        bits.write_bytes(chunk)?;
+    }
+    Ok(())
+}
+
+fn write_tokens<W: Write>(
+    bits: &mut BitWriter<W>,
+    tokens: &[Token],
+    lit_lengths: &[u8],
+    dist_lengths: &[u8],
+) -> io::Result<()> {
+    let lit_codes = canonical_codes(lit_lengths);
+    let dist_codes = canonical_codes(dist_lengths);
+    let write_symbol = |bits: &mut BitWriter<W>, codes: &[u16], lengths: &[u8], symbol: u16| {
+        bits.write_bits(codes[symbol as usize] as u32, lengths[symbol as usize])
+    };
+
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => write_symbol(bits, &lit_codes, lit_lengths, byte as u16)?,
+            Token::Match { length, distance } => {
+                let len = length_symbol(length);
+                write_symbol(bits, &lit_codes, lit_lengths, len.symbol)?;
+                bits.write_bits(len.extra_value as u32, len.extra_bits)?;
+
+                let dist = distance_symbol(distance);
+                write_symbol(bits, &dist_codes, dist_lengths, dist.symbol)?;
+                bits.write_bits(dist.extra_value as u32, dist.extra_bits)?;
+            }
+        }
+    }
+    write_symbol(bits, &lit_codes, lit_lengths, END_OF_BLOCK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_length_encodes_code_lengths() {
+        let mut lengths = vec![8u8; 5];
+        lengths.extend(vec![0u8; 150]);
+        lengths.extend([3, 3]);
+
+        assert_eq!(
+            run_length_encode(&lengths),
+            vec![(8, 0), (16, 1), (18, 127), (18, 1), (3, 0), (3, 0)]
+        );
+    }
+
+    #[test]
+    fn writes_empty_input_as_single_fixed_block() {
+        let mut buf: Vec<u8> = Vec::new();
+        deflate(b"", &mut buf).unwrap();
+
+        assert_eq!(buf, vec![0x03, 0x00]);
+    }
+}
@@ -0,0 +1,304 @@
+use std::io::{self, Read, Write};
+
+use super::{invalid_data, DeflateDecoder, DeflateEncoder};
+use crate::checksum::{self, ChecksumKind, Crc32};
+
+pub const MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// Compression method 8, the only one defined.
+const CM_DEFLATE: u8 = 8;
+
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+/// Operating system field value for "unknown".
+const OS_UNKNOWN: u8 = 255;
+
+/// gzip file (RFC 1952): a header, a DEFLATE stream and a trailer with the
+/// CRC-32 and length of the uncompressed data.
+pub struct GzipCodec;
+
+impl crate::codec::Codec for GzipCodec {
+    fn name(&self) -> &'static str {
+        "gzip"
+    }
+
+    fn encode(&self, data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
+        encode(data, writer)
+    }
+
+    fn decode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+        decode(reader, writer)
+    }
+}
+
+/// Compresses data into a single gzip member as it is written, with a
+/// minimal header (no file name, modification time 0). Call `finish` to
+/// write the final block and the trailer.
+pub struct GzEncoder<W: Write> {
+    deflate: DeflateEncoder<W>,
+    crc: Crc32,
+    size: u32,
+    header_written: bool,
+    finished: bool,
+}
+
+impl<W: Write> GzEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            deflate: DeflateEncoder::new(writer),
+            crc: Crc32::new(),
+            size: 0,
+            header_written: false,
+            finished: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.deflate.get_ref()
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            let writer = self.deflate.get_mut();
+            writer.write_all(&MAGIC)?;
+            writer.write_all(&[CM_DEFLATE, 0, 0, 0, 0, 0, 0, OS_UNKNOWN])?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    /// Writes the final block and the trailer. Does nothing if the member is
+    /// already finished.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            self.write_header()?;
+            self.deflate.try_finish()?;
+            let writer = self.deflate.get_mut();
+            writer.write_all(&self.crc.finish().to_le_bytes())?;
+            writer.write_all(&self.size.to_le_bytes())?;
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        self.deflate.finish()
+    }
+}
+
+impl<W: Write> Write for GzEncoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.write_header()?;
+        let n = self.deflate.write(data)?;
+        self.crc.update(&data[..n]);
+        // ISIZE is the length modulo 2^32
+        self.size = self.size.wrapping_add(n as u32);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.deflate.flush()
+    }
+}
+
+/// Decompresses a gzip file as it is read, verifying the CRC-32 and length
+/// at the end of each member.
+///
+/// Concatenated members are decoded one after the other, as `gzip -d` does.
+pub struct GzDecoder<R: Read> {
+    deflate: DeflateDecoder<R>,
+    crc: Crc32,
+    size: u32,
+    done: bool,
+}
+
+impl<R: Read> GzDecoder<R> {
+    /// Reads and checks the header of the first member.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; 2];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid_data("not a gzip file"));
+        }
+        read_member_header(&mut reader)?;
+
+        Ok(Self {
+            deflate: DeflateDecoder::new(reader),
+            crc: Crc32::new(),
+            size: 0,
+            done: false,
+        })
+    }
+
+    /// Checks the trailer of the member just decoded and moves on to the
+    /// next one, if any.
+    fn end_member(&mut self) -> io::Result<()> {
+        let reader = self.deflate.get_mut();
+
+        let mut trailer = [0; 8];
+        reader.read_exact(&mut trailer)?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let isize = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        checksum::verify(ChecksumKind::Crc32, crc, self.crc.finish())?;
+        if isize != self.size {
+            return Err(invalid_data("uncompressed length mismatch"));
+        }
+
+        // Another member may follow, otherwise we are at the end
+        let mut magic = [0; 2];
+        if reader.read(&mut magic[..1])? == 0 {
+            self.done = true;
+            return Ok(());
+        }
+        reader.read_exact(&mut magic[1..])?;
+        if magic != MAGIC {
+            return Err(invalid_data("not a gzip file"));
+        }
+        read_member_header(reader)?;
+
+        self.deflate.reset();
+        self.crc = Crc32::new();
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for GzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.done {
+                return Ok(0);
+            }
+
+            let n = self.deflate.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                self.crc.update(&buf[..n]);
+                self.size = self.size.wrapping_add(n as u32);
+                return Ok(n);
+            }
+            self.end_member()?;
+        }
+    }
+}
+
+/// Compresses data into a single gzip member. Returns the number of bits
+/// written, including header and trailer.
+pub fn encode<W: Write + ?Sized>(data: &[u8], writer: &mut W) -> io::Result<u64> {
+    let mut encoder = GzEncoder::new(writer);
+    encoder.write_all(data)?;
+    encoder.try_finish()?;
+
+    Ok(encoder.deflate.bits_written() + 18 * 8)
+}
+
+/// Decompresses a gzip file, verifying the CRC-32 and length of each member.
+///
+/// Concatenated members are decoded one after the other, as `gzip -d` does.
+/// Returns the number of bytes written to output.
+pub fn decode<R: Read + ?Sized, W: Write + ?Sized>(
+    reader: &mut R,
+    writer: &mut W,
+) -> io::Result<usize> {
+    let bytes_written = io::copy(&mut GzDecoder::new(reader)?, writer)?;
+    Ok(bytes_written as usize)
+}
+
+/// Skips the rest of a member header, starting right after its magic bytes.
+fn read_member_header<R: Read + ?Sized>(reader: &mut R) -> io::Result<()> {
+    let mut header = [0; 8];
+    reader.read_exact(&mut header)?;
+    let [cm, flags, ..] = header;
+
+    if cm != CM_DEFLATE {
+        return Err(invalid_data("unknown gzip compression method"));
+    }
+    if flags & FEXTRA != 0 {
+        let mut xlen = [0; 2];
+        reader.read_exact(&mut xlen)?;
+        io::copy(
+            &mut reader.take(u16::from_le_bytes(xlen) as u64),
+            &mut io::sink(),
+        )?;
+    }
+    if flags & FNAME != 0 {
+        skip_zero_terminated(reader)?;
+    }
+    if flags & FCOMMENT != 0 {
+        skip_zero_terminated(reader)?;
+    }
+    if flags & FHCRC != 0 {
+        reader.read_exact(&mut [0; 2])?;
+    }
+    Ok(())
+}
+
+fn skip_zero_terminated<R: Read + ?Sized>(reader: &mut R) -> io::Result<()> {
+    let mut byte = [0; 1];
+    loop {
+        reader.read_exact(&mut byte)?;
+        if byte[0] == 0 {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::tests::LOREM;
+
+    #[test]
+    fn decodes_file_from_gzip() {
+        // gzip -9 lorem.txt, so the header carries a file name
+        let data = include_bytes!("testdata/lorem.txt.gz");
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        decode(&mut data.as_slice(), &mut dec_buf).expect("Failed to decode");
+        assert_eq!(dec_buf, LOREM);
+    }
+
+    #[test]
+    fn decodes_concatenated_members() {
+        let mut data = include_bytes!("testdata/lorem.txt.gz").to_vec();
+        encode(b"tail", &mut data).expect("Failed to encode");
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        let bytes = decode(&mut data.as_slice(), &mut dec_buf).expect("Failed to decode");
+        assert_eq!(bytes, LOREM.len() + 4);
+        assert_eq!(&dec_buf[..LOREM.len()], LOREM);
+        assert_eq!(&dec_buf[LOREM.len()..], b"tail");
+    }
+
+    #[test]
+    fn encodes_and_decodes() {
+        let mut enc_buf: Vec<u8> = Vec::new();
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        encode(LOREM, &mut enc_buf).expect("Failed to encode");
+        assert_eq!(&enc_buf[..2], &MAGIC);
+
+        decode(&mut enc_buf.as_slice(), &mut dec_buf).expect("Failed to decode");
+        assert_eq!(dec_buf, LOREM);
+    }
+
+    #[test]
+    fn rejects_corrupted_payload() {
+        let mut data = include_bytes!("testdata/lorem.txt.gz").to_vec();
+        data[100] ^= 0x10;
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        assert!(decode(&mut data.as_slice(), &mut dec_buf).is_err());
+    }
+
+    #[test]
+    fn rejects_wrong_magic() {
+        let data = [0x1F, 0x8C, 8, 0, 0, 0, 0, 0, 0, 0];
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        assert!(decode(&mut data.as_slice(), &mut dec_buf).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+use std::io::{self, Read, Write};
+
+use super::bits::BitReader;
+use super::codes::{fixed_distance_lengths, fixed_literal_lengths, Decoder};
+use super::{invalid_data, CODE_LENGTH_ORDER, WINDOW_SIZE};
+use crate::lzh::symbols::{DISTANCE_CODES, END_OF_BLOCK, FIRST_LENGTH_SYMBOL, LENGTH_CODES};
+
+/// Output decoded per call to `fill`, give or take one stored block.
+const FILL_SIZE: usize = WINDOW_SIZE;
+
+/// Where the decoder is within the stream.
+enum State {
+    /// A block header comes next.
+    BlockStart,
+    /// Inside a Huffman coded block.
+    Codes { lit: Decoder, dist: Decoder },
+    /// Past the end of the final block.
+    Done,
+}
+
+/// Decompresses a raw DEFLATE stream (RFC 1951) as it is read.
+///
+/// Reads exactly up to the end of the final block, so any trailer that
+/// follows (zlib/gzip) can be read from `get_mut` afterwards. Memory use is
+/// bounded by the window that back-references may point into, plus the
+/// output of one `fill` waiting to be read.
+pub struct DeflateDecoder<R: Read> {
+    bits: BitReader<R>,
+    state: State,
+    is_final: bool,
+    /// Decoded output. Bytes before `pos` have been read already and are
+    /// only kept while back-references may still point at them.
+    window: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> DeflateDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            bits: BitReader::new(reader),
+            state: State::BlockStart,
+            is_final: false,
+            window: Vec::with_capacity(4 * WINDOW_SIZE),
+            pos: 0,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        self.bits.get_mut()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.bits.into_inner()
+    }
+
+    /// Starts over with a new stream from the same reader, such as the next
+    /// member of a gzip file.
+    pub fn reset(&mut self) {
+        self.bits.align_to_byte();
+        self.state = State::BlockStart;
+        self.is_final = false;
+        self.window.clear();
+        self.pos = 0;
+    }
+
+    /// Decodes roughly `FILL_SIZE` more bytes into the window, or less when
+    /// the stream ends.
+    fn fill(&mut self) -> io::Result<()> {
+        self.discard_history();
+        let target = self.window.len() + FILL_SIZE;
+
+        while self.window.len() < target {
+            match &self.state {
+                State::Done => break,
+                State::BlockStart => self.read_block_header()?,
+                State::Codes { lit, dist } => {
+                    if inflate_codes(&mut self.bits, &mut self.window, lit, dist, target)? {
+                        self.end_block();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_block_header(&mut self) -> io::Result<()> {
+        self.is_final = self.bits.read_bit()?;
+        match self.bits.read_bits(2)? {
+            0 => {
+                inflate_stored(&mut self.bits, &mut self.window)?;
+                self.end_block();
+            }
+            1 => {
+                self.state = State::Codes {
+                    lit: Decoder::from_lengths(&fixed_literal_lengths())?,
+                    dist: Decoder::from_lengths(&fixed_distance_lengths())?,
+                };
+            }
+            2 => {
+                let (lit, dist) = read_dynamic_codes(&mut self.bits)?;
+                self.state = State::Codes { lit, dist };
+            }
+            _ => return Err(invalid_data("invalid block type")),
+        }
+        Ok(())
+    }
+
+    fn end_block(&mut self) {
+        self.state = if self.is_final {
+            State::Done
+        } else {
+            State::BlockStart
+        };
+    }
+
+    /// Drops output that has been read and is too far back to be referenced.
+    fn discard_history(&mut self) {
+        let cut = self.pos.min(self.window.len().saturating_sub(WINDOW_SIZE));
+        if cut >= WINDOW_SIZE {
+            self.window.drain(..cut);
+            self.pos -= cut;
+        }
+    }
+}
+
+impl<R: Read> Read for DeflateDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.window.len() && !matches!(self.state, State::Done) {
+            self.fill()?;
+        }
+
+        let n = buf.len().min(self.window.len() - self.pos);
+        buf[..n].copy_from_slice(&self.window[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Decompresses a raw DEFLATE stream (RFC 1951) from `reader` into `writer`.
+///
+/// Reads exactly up to the end of the final block, so any trailer that
+/// follows (zlib/gzip) can be read from `reader` afterwards. Returns the
+/// number of bytes written.
+pub fn inflate<R: Read + ?Sized, W: Write + ?Sized>(
+    reader: &mut R,
+    writer: &mut W,
+) -> io::Result<usize> {
+    let bytes_written = io::copy(&mut DeflateDecoder::new(reader), writer)?;
+    Ok(bytes_written as usize)
+}
+
+fn inflate_stored<R: Read>(bits: &mut BitReader<R>, window: &mut Vec<u8>) -> io::Result<()> {
+    bits.align_to_byte();
+
+    let mut header = [0; 4];
+    bits.read_bytes(&mut header)?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let nlen = u16::from_le_bytes([header[2], header[3]]);
+    if len != !nlen {
+        return Err(invalid_data(
+            "stored block length does not match its complement",
+        ));
+    }
+
+    let start = window.len();
+    window.resize(start + len as usize, 0);
+    bits.read_bytes(&mut window[start..])
+}
+
+/// Decodes symbols until the end of the block, returning true, or until the
+/// window has grown to `target` bytes, returning false.
+fn inflate_codes<R: Read>(
+    bits: &mut BitReader<R>,
+    window: &mut Vec<u8>,
+    lit: &Decoder,
+    dist: &Decoder,
+    target: usize,
+) -> io::Result<bool> {
+    while window.len() < target {
+        let symbol = lit.decode(bits)?;
+        if symbol < END_OF_BLOCK {
+            window.push(symbol as u8);
+            continue;
+        }
+        if symbol == END_OF_BLOCK {
+            return Ok(true);
+        }
+
+        let (base, extra) = *LENGTH_CODES
+            .get((symbol - FIRST_LENGTH_SYMBOL) as usize)
+            .ok_or_else(|| invalid_data("invalid length symbol"))?;
+        let length = base as usize + bits.read_bits(extra)? as usize;
+
+        let dist_symbol = dist.decode(bits)?;
+        let (base, extra) = *DISTANCE_CODES
+            .get(dist_symbol as usize)
+            .ok_or_else(|| invalid_data("invalid distance symbol"))?;
+        let distance = base as usize + bits.read_bits(extra)? as usize;
+
+        if distance > window.len() {
+            return Err(invalid_data("distance points before start of output"));
+        }
+        let start = window.len() - distance;
+        for k in 0..length {
+            window.push(window[start + k]);
+        }
+    }
+    Ok(false)
+}
+
+/// Reads the header of a dynamic block: the code length code, then the
+/// run-length coded literal/length and distance code lengths.
+fn read_dynamic_codes<R: Read>(bits: &mut BitReader<R>) -> io::Result<(Decoder, Decoder)> {
+    let hlit = bits.read_bits(5)? as usize + 257;
+    let hdist = bits.read_bits(5)? as usize + 1;
+    let hclen = bits.read_bits(4)? as usize + 4;
+    if hlit > 286 || hdist > 30 {
+        return Err(invalid_data("too many length or distance codes"));
+    }
+
+    let mut cl_lengths = [0u8; 19];
+    for &index in &CODE_LENGTH_ORDER[..hclen] {
+        cl_lengths[index] = bits.read_bits(3)? as u8;
+    }
+    let cl_decoder = Decoder::from_lengths(&cl_lengths)?;
+
+    let mut lengths = Vec::with_capacity(hlit + hdist);
+    while lengths.len() < hlit + hdist {
+        let symbol = cl_decoder.decode(bits)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| invalid_data("repeat with no previous length"))?;
+                (previous, 3 + bits.read_bits(2)? as usize)
+            }
+            17 => (0, 3 + bits.read_bits(3)? as usize),
+            _ => (0, 11 + bits.read_bits(7)? as usize),
+        };
+        if lengths.len() + repeat > hlit + hdist {
+            return Err(invalid_data("code lengths overflow the alphabet"));
+        }
+        lengths.extend(std::iter::repeat_n(value, repeat));
+    }
+
+    if lengths[END_OF_BLOCK as usize] == 0 {
+        return Err(invalid_data("missing end-of-block code"));
+    }
+
+    let lit = Decoder::from_lengths(&lengths[..hlit])?;
+    let dist = Decoder::from_lengths(&lengths[hlit..])?;
+    Ok((lit, dist))
+}
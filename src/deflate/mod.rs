@@ -0,0 +1,155 @@
+use std::io::{self, Read, Write};
+
+pub(crate) mod bits;
+pub(crate) mod codes;
+mod compress;
+pub mod gzip;
+mod inflate;
+pub mod zlib;
+
+pub use self::compress::{deflate, DeflateEncoder};
+pub use self::inflate::{inflate, DeflateDecoder};
+use crate::format;
+
+/// Back-references in a DEFLATE stream reach at most this far.
+pub const WINDOW_SIZE: usize = 32 * 1024;
+
+/// Order in which code length code lengths are sent in a dynamic block header.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Raw DEFLATE stream (RFC 1951), without any zlib or gzip wrapper.
+pub struct DeflateCodec;
+
+impl crate::codec::Codec for DeflateCodec {
+    fn name(&self) -> &'static str {
+        "deflate"
+    }
+
+    fn encode(&self, data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
+        deflate(data, writer)
+    }
+
+    fn decode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+        inflate(reader, writer)
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    format::invalid_data("DEFLATE", msg)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub const LOREM: &[u8] = include_bytes!("testdata/lorem.txt");
+
+    fn roundtrip(data: &[u8]) -> Vec<u8> {
+        let mut enc_buf: Vec<u8> = Vec::new();
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        deflate(data, &mut enc_buf).expect("Failed to encode");
+        inflate(&mut enc_buf.as_slice(), &mut dec_buf).expect("Failed to decode");
+
+        dec_buf
+    }
+
+    #[test]
+    fn inflates_stored_block() {
+        // zlib level 0, raw deflate
+        let data = include_bytes!("testdata/lorem.stored.deflate");
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        inflate(&mut data.as_slice(), &mut dec_buf).expect("Failed to decode");
+        assert_eq!(dec_buf, LOREM);
+    }
+
+    #[test]
+    fn inflates_fixed_block() {
+        // zlib.compress(b"hello hello hello", wbits=-15)
+        let data = [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x00];
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        inflate(&mut data.as_slice(), &mut dec_buf).expect("Failed to decode");
+        assert_eq!(dec_buf, b"hello hello hello");
+    }
+
+    #[test]
+    fn inflates_dynamic_blocks() {
+        // zlib level 9, raw deflate
+        let data = include_bytes!("testdata/lorem.deflate");
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        let bytes = inflate(&mut data.as_slice(), &mut dec_buf).expect("Failed to decode");
+        assert_eq!(bytes, LOREM.len());
+        assert_eq!(dec_buf, LOREM);
+    }
+
+    #[test]
+    fn stops_reading_at_end_of_stream() {
+        let mut data = vec![0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x00];
+        data.extend([0xAA, 0xBB]);
+        let mut slice = data.as_slice();
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        inflate(&mut slice, &mut dec_buf).expect("Failed to decode");
+        assert_eq!(slice, &[0xAA, 0xBB]);
+    }
+
+    #[test]
+    fn encodes_and_decodes_text() {
+        let mut enc_buf: Vec<u8> = Vec::new();
+        deflate(LOREM, &mut enc_buf).expect("Failed to encode");
+
+        assert!(enc_buf.len() < LOREM.len() / 2);
+        assert_eq!(roundtrip(LOREM), LOREM);
+    }
+
+    #[test]
+    fn encodes_and_decodes_empty_input() {
+        assert_eq!(roundtrip(b""), b"");
+    }
+
+    #[test]
+    fn encodes_incompressible_data_as_stored_blocks() {
+        let mut state: u32 = 12345;
+        let data: Vec<u8> = (0..100_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+        let mut enc_buf: Vec<u8> = Vec::new();
+
+        deflate(&data, &mut enc_buf).expect("Failed to encode");
+        assert!(enc_buf.len() < data.len() + 100);
+        assert_eq!(roundtrip(&data), data);
+    }
+
+    #[test]
+    fn encodes_and_decodes_large_repetitive_data() {
+        let mut data = vec![0x00; 100_000];
+        data.extend((0..200_000u32).map(|i| (i * 7 % 251) as u8));
+        data.extend(LOREM);
+
+        assert_eq!(roundtrip(&data), data);
+    }
+
+    #[test]
+    fn rejects_invalid_block_type() {
+        let data = [0x07];
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        assert!(inflate(&mut data.as_slice(), &mut dec_buf).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_stream() {
+        let data = include_bytes!("testdata/lorem.deflate");
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        assert!(inflate(&mut &data[..data.len() / 2], &mut dec_buf).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use std::io::{self, Read, Write};
+
+use super::{invalid_data, DeflateDecoder, DeflateEncoder};
+use crate::checksum::{self, Adler32, ChecksumKind};
+
+/// Compression method 8 (DEFLATE) with a 32 KiB window (CINFO = 7).
+const CMF: u8 = 0x78;
+
+/// Default compression level, no preset dictionary, FCHECK filled in so that
+/// `CMF * 256 + FLG` is a multiple of 31.
+const FLG: u8 = 0x9C;
+
+const FDICT: u8 = 0x20;
+
+/// zlib stream (RFC 1950): a two byte header, a DEFLATE stream and the
+/// Adler-32 of the uncompressed data.
+pub struct ZlibCodec;
+
+impl crate::codec::Codec for ZlibCodec {
+    fn name(&self) -> &'static str {
+        "zlib"
+    }
+
+    fn encode(&self, data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
+        encode(data, writer)
+    }
+
+    fn decode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+        decode(reader, writer)
+    }
+}
+
+/// Compresses data into a zlib stream as it is written. Call `finish` to
+/// write the final block and the checksum.
+pub struct ZlibEncoder<W: Write> {
+    deflate: DeflateEncoder<W>,
+    adler: Adler32,
+    header_written: bool,
+    finished: bool,
+}
+
+impl<W: Write> ZlibEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            deflate: DeflateEncoder::new(writer),
+            adler: Adler32::new(),
+            header_written: false,
+            finished: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.deflate.get_ref()
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            self.deflate.get_mut().write_all(&[CMF, FLG])?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    /// Writes the final block and the checksum. Does nothing if the stream
+    /// is already finished.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            self.write_header()?;
+            self.deflate.try_finish()?;
+            let adler = self.adler.finish();
+            self.deflate.get_mut().write_all(&adler.to_be_bytes())?;
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        self.deflate.finish()
+    }
+}
+
+impl<W: Write> Write for ZlibEncoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.write_header()?;
+        let n = self.deflate.write(data)?;
+        self.adler.update(&data[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.deflate.flush()
+    }
+}
+
+/// Decompresses a zlib stream as it is read, verifying its Adler-32 checksum
+/// once the end is reached.
+pub struct ZlibDecoder<R: Read> {
+    deflate: DeflateDecoder<R>,
+    adler: Adler32,
+    done: bool,
+}
+
+impl<R: Read> ZlibDecoder<R> {
+    /// Reads and checks the zlib header.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 2];
+        reader.read_exact(&mut header)?;
+        let [cmf, flg] = header;
+
+        if cmf & 0x0F != 8 || cmf >> 4 > 7 {
+            return Err(invalid_data("not a zlib stream with DEFLATE compression"));
+        }
+        if u16::from_be_bytes(header) % 31 != 0 {
+            return Err(invalid_data("zlib header check failed"));
+        }
+        if flg & FDICT != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "zlib streams with a preset dictionary are not supported",
+            ));
+        }
+
+        Ok(Self {
+            deflate: DeflateDecoder::new(reader),
+            adler: Adler32::new(),
+            done: false,
+        })
+    }
+}
+
+impl<R: Read> Read for ZlibDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done {
+            return Ok(0);
+        }
+
+        let n = self.deflate.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            let mut trailer = [0; 4];
+            self.deflate.get_mut().read_exact(&mut trailer)?;
+            checksum::verify(
+                ChecksumKind::Adler32,
+                u32::from_be_bytes(trailer),
+                self.adler.finish(),
+            )?;
+            self.done = true;
+        }
+        self.adler.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Compresses data into a zlib stream. Returns the number of bits written,
+/// including header and trailer.
+pub fn encode<W: Write + ?Sized>(data: &[u8], writer: &mut W) -> io::Result<u64> {
+    let mut encoder = ZlibEncoder::new(writer);
+    encoder.write_all(data)?;
+    encoder.try_finish()?;
+
+    Ok(encoder.deflate.bits_written() + 6 * 8)
+}
+
+/// Decompresses a zlib stream, verifying its Adler-32 checksum.
+///
+/// Returns the number of bytes written to output.
+pub fn decode<R: Read + ?Sized, W: Write + ?Sized>(
+    reader: &mut R,
+    writer: &mut W,
+) -> io::Result<usize> {
+    let bytes_written = io::copy(&mut ZlibDecoder::new(reader)?, writer)?;
+    Ok(bytes_written as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::tests::LOREM;
+
+    #[test]
+    fn decodes_stream_from_zlib() {
+        // python3 -c "zlib.compress(lorem, 6)"
+        let data = include_bytes!("testdata/lorem.zlib");
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        decode(&mut data.as_slice(), &mut dec_buf).expect("Failed to decode");
+        assert_eq!(dec_buf, LOREM);
+    }
+
+    #[test]
+    fn encodes_and_decodes() {
+        let mut enc_buf: Vec<u8> = Vec::new();
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        encode(LOREM, &mut enc_buf).expect("Failed to encode");
+        assert_eq!(&enc_buf[..2], &[0x78, 0x9C]);
+
+        decode(&mut enc_buf.as_slice(), &mut dec_buf).expect("Failed to decode");
+        assert_eq!(dec_buf, LOREM);
+    }
+
+    #[test]
+    fn rejects_corrupted_checksum() {
+        let mut data = include_bytes!("testdata/lorem.zlib").to_vec();
+        let last = data.len() - 1;
+        data[last] ^= 0x01;
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        assert!(decode(&mut data.as_slice(), &mut dec_buf).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        let data = [0x78, 0x9D, 0x03, 0x00];
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        assert!(decode(&mut data.as_slice(), &mut dec_buf).is_err());
+    }
+}
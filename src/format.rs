@@ -0,0 +1,75 @@
+//! Pieces shared by the readers of the standard formats: their decode
+//! errors, little-endian integers and the skippable frames of LZ4 and zstd.
+
+use std::io::{self, Read};
+
+/// Skippable frames have magic numbers 0x184D2A50 to 0x184D2A5F and carry
+/// data for other tools, which decoders ignore. LZ4 and zstd share them.
+const SKIPPABLE_MAGIC: u32 = 0x184D2A50;
+const SKIPPABLE_MASK: u32 = 0xFFFF_FFF0;
+
+/// The error for corrupt or unsupported `format` input.
+pub(crate) fn invalid_data(format: &str, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} decode error: {}", format, msg),
+    )
+}
+
+pub(crate) fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut byte = [0; 1];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+pub(crate) fn read_u32_le<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// The little-endian integer in the 4 bytes of `data` at `pos`.
+pub(crate) fn u32_le_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+/// Reads the magic number of the next frame, skipping skippable frames on
+/// the way. None at the end of the input.
+pub(crate) fn next_frame_magic<R: Read>(reader: &mut R) -> io::Result<Option<[u8; 4]>> {
+    loop {
+        let mut magic = [0; 4];
+        if reader.read(&mut magic[..1])? == 0 {
+            return Ok(None);
+        }
+        reader.read_exact(&mut magic[1..])?;
+        if u32::from_le_bytes(magic) & SKIPPABLE_MASK != SKIPPABLE_MAGIC {
+            return Ok(Some(magic));
+        }
+
+        let len = read_u32_le(reader)? as u64;
+        if io::copy(&mut reader.take(len), &mut io::sink())? != len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_skippable_frames() {
+        let mut data = 0x184D2A5Fu32.to_le_bytes().to_vec();
+        data.extend(2u32.to_le_bytes());
+        data.extend(b"xyMAGIabc");
+        let mut reader = data.as_slice();
+
+        assert_eq!(next_frame_magic(&mut reader).unwrap(), Some(*b"MAGI"));
+        assert_eq!(reader, b"abc");
+        assert_eq!(next_frame_magic(&mut &[][..]).unwrap(), None);
+
+        // Truncated skippable frame
+        let truncated = &data[..9];
+        assert!(next_frame_magic(&mut &truncated[..]).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use bitvec::prelude::*;
+use std::collections::HashMap;
+use std::io::{prelude::*, Error, ErrorKind, Result};
+
+use super::table::{BitBuffer, TableDecoder};
+use super::tree::{read_code_lengths, HuffmanTree, Serializable};
+use super::{build_dictionary, read_padded, write_padded};
+
+/// Huffman coding with a separate code for each previous byte (order-1
+/// context modelling).
+///
+/// In text a `q` is nearly always followed by a `u` and a space by a
+/// lowercase letter, which a single code for all bytes cannot exploit.
+/// Every code costs its lengths in the output though, so only contexts
+/// seen at least `MIN_CONTEXT_SYMBOLS` times get their own; bytes following
+/// rarer contexts share one order-0 code.
+pub struct ContextHuffmanCodec;
+
+impl crate::codec::Codec for ContextHuffmanCodec {
+    fn name(&self) -> &'static str {
+        "context-huffman"
+    }
+
+    fn encode(&self, data: &[u8], writer: &mut dyn Write) -> Result<u64> {
+        encode(data, writer)
+    }
+
+    fn decode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<usize> {
+        decode(reader, writer)
+    }
+}
+
+/// Contexts followed by fewer bytes than this use the shared code.
+pub const MIN_CONTEXT_SYMBOLS: u64 = 64;
+
+/// Context of the first byte, which has no previous byte.
+const FIRST_CONTEXT: u8 = 0;
+
+/// Encodes the data with a Huffman code per previous byte. The payload is:
+///
+/// 1. A 256 bit map (32 bytes, LSB first) of the contexts with their own code
+/// 2. The code lengths of each of those contexts, in context order
+/// 3. 1 if there is a shared code for the other contexts, else 0 (1 byte)
+/// 4. The shared code lengths, if any
+/// 5. Padding count (1 byte) and the compressed bit data, as for `huffman`
+///
+/// Returns the number of bits in the compressed data.
+pub fn encode<W: Write + ?Sized>(data: &[u8], writer: &mut W) -> Result<u64> {
+    if data.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "cannot encode empty input",
+        ));
+    }
+
+    let mut counts = vec![[0u64; 256]; 256];
+    for (context, byte) in contexts(data) {
+        counts[context as usize][byte as usize] += 1;
+    }
+
+    let mut map = [0u8; 32];
+    let mut trees = vec![None; 256];
+    let mut shared_counts = HashMap::new();
+    for (context, counts) in counts.iter().enumerate() {
+        let nonzero = counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(byte, &count)| (byte as u8, count));
+
+        if counts.iter().sum::<u64>() >= MIN_CONTEXT_SYMBOLS {
+            map[context / 8] |= 1 << (context % 8);
+            trees[context] = HuffmanTree::from_counts(&nonzero.collect());
+        } else {
+            for (byte, count) in nonzero {
+                *shared_counts.entry(byte).or_insert(0) += count;
+            }
+        }
+    }
+    let shared = HuffmanTree::from_counts(&shared_counts);
+
+    writer.write_all(&map)?;
+    for tree in trees.iter().flatten() {
+        tree.serialize(writer)?;
+    }
+    writer.write_all(&[shared.is_some() as u8])?;
+    if let Some(tree) = &shared {
+        tree.serialize(writer)?;
+    }
+
+    let shared_dict = shared.as_ref().map(build_dictionary);
+    let dicts: Vec<_> = trees
+        .iter()
+        .map(|tree| tree.as_ref().map(build_dictionary))
+        .collect();
+
+    let mut bits = BitVec::with_capacity(data.len() * 4);
+    for (context, byte) in contexts(data) {
+        let dict = dicts[context as usize].as_ref().or(shared_dict.as_ref());
+        let dict = dict.expect("every context has a code");
+        bits.extend_from_bitslice(&dict[&byte]);
+    }
+
+    write_padded(bits, writer)
+}
+
+pub fn decode<R: Read + ?Sized, W: Write + ?Sized>(
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<usize> {
+    let mut map = [0u8; 32];
+    reader.read_exact(&mut map)?;
+
+    let mut decoders = Vec::with_capacity(256);
+    for context in 0..256 {
+        let decoder = if map[context / 8] & (1 << (context % 8)) != 0 {
+            Some(TableDecoder::new(&read_code_lengths(reader)?)?)
+        } else {
+            None
+        };
+        decoders.push(decoder);
+    }
+
+    let mut has_shared = [0u8; 1];
+    reader.read_exact(&mut has_shared)?;
+    let shared = match has_shared[0] {
+        0 => None,
+        1 => Some(TableDecoder::new(&read_code_lengths(reader)?)?),
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "invalid shared code flag",
+            ))
+        }
+    };
+
+    let (buffer, num_data_bits) = read_padded(reader)?;
+    let mut bits = BitBuffer::new(&buffer, num_data_bits);
+    let mut output = Vec::with_capacity(num_data_bits / 2);
+    let mut context = FIRST_CONTEXT;
+
+    while !bits.is_empty() {
+        let decoder = decoders[context as usize]
+            .as_ref()
+            .or(shared.as_ref())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "no code for the byte's context"))?;
+        context = decoder.decode_symbol(&mut bits)?;
+        output.push(context);
+    }
+    writer.write_all(&output)?;
+
+    Ok(output.len())
+}
+
+/// Each byte with the byte before it.
+fn contexts(data: &[u8]) -> impl Iterator<Item = (u8, u8)> + '_ {
+    let previous = std::iter::once(FIRST_CONTEXT).chain(data.iter().copied());
+    previous.zip(data.iter().copied())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::tests::LOREM;
+
+    fn roundtrip(data: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        let mut decoded = Vec::new();
+
+        encode(data, &mut encoded).expect("Failed to encode");
+        decode(&mut encoded.as_slice(), &mut decoded).expect("Failed to decode");
+        decoded
+    }
+
+    #[test]
+    fn encodes_and_decodes_text() {
+        assert_eq!(roundtrip(LOREM), LOREM);
+    }
+
+    #[test]
+    fn encodes_and_decodes_edge_cases() {
+        assert_eq!(roundtrip(b"a"), b"a");
+        assert_eq!(roundtrip(&[0; 1000]), [0; 1000]);
+
+        let all_bytes: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+        assert_eq!(roundtrip(&all_bytes), all_bytes);
+    }
+
+    #[test]
+    fn compresses_text_better_than_order_0() {
+        let text = LOREM.repeat(4);
+        let mut order_0 = Vec::new();
+        let mut order_1 = Vec::new();
+
+        super::super::encode(&text, &mut order_0).unwrap();
+        encode(&text, &mut order_1).unwrap();
+        assert!(order_1.len() < order_0.len() * 9 / 10);
+    }
+
+    #[test]
+    fn rejects_corrupted_context_map() {
+        let mut encoded = Vec::new();
+        encode(&[7; 1000], &mut encoded).unwrap();
+        // Only context 7 has its own code, clearing it misreads the rest
+        assert_eq!(encoded[0], 1 << 7);
+        encoded[0] = 0;
+
+        let err = decode(&mut encoded.as_slice(), &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+
+use super::tree::Symbol;
+
+/// An entry in one of package-merge's lists: a single symbol, or a package
+/// of two adjacent entries of the list below.
+#[derive(Debug, Clone, Copy)]
+struct Item {
+    weight: u64,
+    kind: Kind,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Leaf(usize),
+    /// Index of the first of the two packaged entries.
+    Package(usize),
+}
+
+/// Computes optimal code lengths for the given counts with no code longer
+/// than `max_len` bits, using the package-merge algorithm.
+///
+/// Think of each symbol as a coin worth `2^-len` for every length it could
+/// have. Starting at the longest length, adjacent entries are paired into
+/// packages of the next length up and merged, by weight, with the symbols
+/// themselves. Taking the `2n - 2` lightest entries of the final list buys
+/// exactly a complete code at the least cost, and a symbol's code length is
+/// the number of those entries it ends up in.
+///
+/// Returns None if there are no symbols or more than `2^max_len` of them. A
+/// single symbol gets a 1 bit code.
+pub fn limited_code_lengths<S: Symbol>(
+    counts: &HashMap<S, u64>,
+    max_len: u8,
+) -> Option<HashMap<S, u8>> {
+    let mut symbols: Vec<(u64, S)> = counts.iter().map(|(&s, &count)| (count, s)).collect();
+    symbols.sort();
+
+    match symbols.len() {
+        0 => return None,
+        1 => return Some(HashMap::from([(symbols[0].1, 1)])),
+        n if max_len < 64 && n > 1 << max_len => return None,
+        _ => {}
+    }
+
+    let leaves: Vec<Item> = symbols
+        .iter()
+        .enumerate()
+        .map(|(i, &(count, _))| Item {
+            weight: count,
+            kind: Kind::Leaf(i),
+        })
+        .collect();
+
+    // levels[0] holds the candidates for the longest codes
+    let mut levels = vec![leaves.clone()];
+    for _ in 1..max_len {
+        let below = levels.last().unwrap();
+        // Counts may be anywhere up to `u64::MAX`. Saturating keeps each
+        // list sorted, so the code stays complete if no longer optimal.
+        let packages = below.chunks_exact(2).enumerate().map(|(i, pair)| Item {
+            weight: pair[0].weight.saturating_add(pair[1].weight),
+            kind: Kind::Package(2 * i),
+        });
+        levels.push(merge(&leaves, packages));
+    }
+
+    let mut lengths = vec![0u8; symbols.len()];
+    let top = levels.len() - 1;
+    let mut stack: Vec<(usize, usize)> = (0..2 * symbols.len() - 2).map(|i| (top, i)).collect();
+    while let Some((level, index)) = stack.pop() {
+        match levels[level][index].kind {
+            Kind::Leaf(symbol) => lengths[symbol] += 1,
+            Kind::Package(first) => {
+                stack.push((level - 1, first));
+                stack.push((level - 1, first + 1));
+            }
+        }
+    }
+
+    Some(
+        symbols
+            .into_iter()
+            .zip(lengths)
+            .map(|((_, symbol), len)| (symbol, len))
+            .collect(),
+    )
+}
+
+/// Merges two lists sorted by weight, leaves first among equal weights.
+fn merge(leaves: &[Item], packages: impl Iterator<Item = Item>) -> Vec<Item> {
+    let mut merged = Vec::with_capacity(leaves.len() * 2);
+    let mut leaves = leaves.iter().copied().peekable();
+    let mut packages = packages.peekable();
+
+    loop {
+        let take_leaf = match (leaves.peek(), packages.peek()) {
+            (Some(leaf), Some(package)) => leaf.weight <= package.weight,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => return merged,
+        };
+        let item = if take_leaf {
+            leaves.next()
+        } else {
+            packages.next()
+        };
+        merged.extend(item);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kraft_sum(lengths: &HashMap<u8, u8>, max_len: u8) -> u32 {
+        lengths.values().map(|&len| 1 << (max_len - len)).sum()
+    }
+
+    #[test]
+    fn matches_huffman_when_the_limit_is_not_reached() {
+        let counts = HashMap::from([(b'a', 15), (b'b', 7), (b'c', 6), (b'd', 5), (b'e', 4)]);
+        let lengths = limited_code_lengths(&counts, 8).unwrap();
+
+        assert_eq!(
+            lengths,
+            HashMap::from([(b'a', 1), (b'b', 3), (b'c', 3), (b'd', 3), (b'e', 3)])
+        );
+    }
+
+    #[test]
+    fn limits_skewed_counts() {
+        // Fibonacci counts make an unlimited Huffman code as deep as possible
+        let mut counts = HashMap::from([(0u8, 1), (1, 1)]);
+        for i in 2..30u8 {
+            counts.insert(i, counts[&(i - 1)] + counts[&(i - 2)]);
+        }
+
+        let lengths = limited_code_lengths(&counts, 12).unwrap();
+        assert!(lengths.values().all(|&len| (1..=12).contains(&len)));
+        assert_eq!(kraft_sum(&lengths, 12), 1 << 12);
+        // More frequent symbols never get longer codes
+        assert!(lengths[&29] <= lengths[&10] && lengths[&10] <= lengths[&0]);
+    }
+
+    #[test]
+    fn fills_the_code_space_when_the_limit_is_tight() {
+        let counts: HashMap<u8, u64> = (0..16).map(|s| (s, 1 + s as u64 * 100)).collect();
+        let lengths = limited_code_lengths(&counts, 4).unwrap();
+
+        assert!(lengths.values().all(|&len| len == 4));
+    }
+
+    #[test]
+    fn handles_counts_near_the_maximum() {
+        let counts: HashMap<u8, u64> = (0..10).map(|s| (s, u64::MAX - s as u64)).collect();
+        let lengths = limited_code_lengths(&counts, 8).unwrap();
+
+        assert!(lengths.values().all(|&len| (1..=8).contains(&len)));
+        assert_eq!(kraft_sum(&lengths, 8), 1 << 8);
+    }
+
+    #[test]
+    fn handles_edge_cases() {
+        assert_eq!(limited_code_lengths::<u8>(&HashMap::new(), 8), None);
+        assert_eq!(
+            limited_code_lengths(&HashMap::from([(b'x', 9)]), 8),
+            Some(HashMap::from([(b'x', 1)]))
+        );
+
+        let counts: HashMap<u8, u64> = (0..5).map(|s| (s, 1)).collect();
+        assert_eq!(limited_code_lengths(&counts, 2), None);
+    }
+}
@@ -2,15 +2,44 @@ use bitvec::prelude::*;
 use std::collections::HashMap;
 use std::io::{prelude::*, Result};
 
-mod tree;
-
-use self::tree::{HuffmanTree, Link, Serializable};
+pub mod context;
+pub(crate) mod limit;
+pub(crate) mod table;
+pub(crate) mod tree;
+
+use self::table::TableDecoder;
+use self::tree::{
+    canonical_codes, read_code_lengths, HuffmanTree, Serializable, Symbol, MAX_CODE_LEN,
+};
+
+/// Huffman coding of bytes.
+///
+/// Skewed inputs can get very long codes for their rarest bytes. Setting
+/// `max_code_len` limits codes to that many bits, at a small cost in
+/// compression; decoders need no setting, the lengths are in the output.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HuffmanCodec {
+    pub max_code_len: Option<u8>,
+}
 
-pub struct HuffmanCodec;
+impl HuffmanCodec {
+    pub fn with_max_code_len(max_code_len: u8) -> Self {
+        Self {
+            max_code_len: Some(max_code_len),
+        }
+    }
+}
 
 impl crate::codec::Codec for HuffmanCodec {
+    fn name(&self) -> &'static str {
+        "huffman"
+    }
+
     fn encode(&self, data: &[u8], writer: &mut dyn Write) -> Result<u64> {
-        encode(data, writer)
+        match self.max_code_len {
+            Some(max_code_len) => encode_limited(data, writer, max_code_len),
+            None => encode(data, writer),
+        }
     }
 
     fn decode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<usize> {
@@ -24,11 +53,54 @@ pub fn encode<W: Write + ?Sized>(data: &[u8], writer: &mut W) -> Result<u64> {
     let tree = HuffmanTree::build(data).ok_or_else(|| {
         std::io::Error::new(std::io::ErrorKind::InvalidInput, "cannot encode empty input")
     })?;
-    let dict = build_dictionary(&tree);
-    let mut bits = encode_with_dictionary(data, &dict);
+    encode_with_tree(data, &tree, writer)
+}
+
+// Like `encode`, but no code is longer than `max_code_len` bits. Fails if the
+// data has more distinct bytes than there are codes of that length.
+pub fn encode_limited<W: Write + ?Sized>(
+    data: &[u8],
+    writer: &mut W,
+    max_code_len: u8,
+) -> Result<u64> {
+    if max_code_len == 0 || max_code_len > MAX_CODE_LEN {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("maximum code length must be between 1 and {}", MAX_CODE_LEN),
+        ));
+    }
+    if data.is_empty() {
+        return encode(data, writer);
+    }
+    let tree = HuffmanTree::build_limited(data, max_code_len).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "too many distinct bytes for codes of at most {} bits",
+                max_code_len
+            ),
+        )
+    })?;
+    encode_with_tree(data, &tree, writer)
+}
+
+fn encode_with_tree<W: Write + ?Sized>(
+    data: &[u8],
+    tree: &HuffmanTree,
+    writer: &mut W,
+) -> Result<u64> {
+    let dict = build_dictionary(tree);
+    let bits = encode_with_dictionary(data, &dict);
+
+    tree.serialize(writer)?;
+    write_padded(bits, writer)
+}
 
+/// Writes how many bits pad the last byte, then the bits padded with 1s.
+/// Returns the number of bits before padding.
+pub(crate) fn write_padded<W: Write + ?Sized>(mut bits: BitVec, writer: &mut W) -> Result<u64> {
     let num_bits = bits.len();
-    let pad = if num_bits % 8 > 0 {
+    let pad = if !num_bits.is_multiple_of(8) {
         8 - (num_bits % 8)
     } else {
         0
@@ -44,7 +116,6 @@ pub fn encode<W: Write + ?Sized>(data: &[u8], writer: &mut W) -> Result<u64> {
     // Should be nothing left in bits
     debug_assert!(bits.is_empty());
 
-    tree.serialize(writer)?;
     writer.write_all(&[pad as u8])?; // First write how many useless bits were padded at the end
     writer.write_all(&buffer)?; //      Then write the compressed data
 
@@ -52,9 +123,22 @@ pub fn encode<W: Write + ?Sized>(data: &[u8], writer: &mut W) -> Result<u64> {
 }
 
 pub fn decode<R: Read + ?Sized, W: Write + ?Sized>(reader: &mut R, writer: &mut W) -> Result<usize> {
-    // First read in the huffman tree
-    let tree = HuffmanTree::deserialize(reader)?;
+    // First read in the code lengths
+    let decoder = TableDecoder::new(&read_code_lengths(reader)?)?;
+
+    let (buffer, num_data_bits) = read_padded(reader)?;
 
+    // Resolve whole codes with table lookups
+    let mut output = Vec::with_capacity(num_data_bits / 2);
+    decoder.decode(&buffer, num_data_bits, &mut output)?;
+    writer.write_all(&output)?;
+
+    Ok(output.len())
+}
+
+/// Reads what `write_padded` wrote: the data and the number of bits in it
+/// before padding.
+pub(crate) fn read_padded<R: Read + ?Sized>(reader: &mut R) -> Result<(Vec<u8>, usize)> {
     // Read the number of padded bits at the end
     let bits_padded = {
         let mut num_padding_buffer = [0; 1];
@@ -63,47 +147,14 @@ pub fn decode<R: Read + ?Sized, W: Write + ?Sized>(reader: &mut R, writer: &mut
         num_padding_buffer[0] as usize
     };
 
-    let bits = {
-        let mut buffer = Vec::new();
-        reader.read_to_end(&mut buffer)?;
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer)?;
 
-        BitVec::<_, Lsb0>::from_vec(buffer)
-    };
-
-    // Walk bit by bit through the tree. Navigate on each bit, and whenever
-    // we land on a leaf, output that byte and hop back to the root.
-    // Make sure the padded 1-bits at the end to reach a full byte are ignored.
-    let num_data_bits = bits.len() - bits_padded;
-    let mut current = &tree.root;
-    let mut bytes_written: usize = 0;
-    let mut at_root = true;
-
-    for b in &bits[0..num_data_bits] {
-        match current {
-            Link::Node(node, _) => {
-                current = if *b { &node.right } else { &node.left };
-                at_root = false;
-            }
-            // Single-byte alphabet: root is a leaf, each bit represents one byte
-            Link::Leaf(_, _) => {}
-        }
-
-        if let Link::Leaf(_, byte) = current {
-            writer.write_all(&[*byte])?;
-            bytes_written += 1;
-            current = &tree.root;
-            at_root = true;
-        }
-    }
-
-    if !at_root {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "Unexpected end of data: stopped at internal node instead of leaf",
-        ));
-    }
-
-    Ok(bytes_written)
+    // Make sure the padded 1-bits at the end to reach a full byte are ignored
+    let num_data_bits = (buffer.len() * 8).checked_sub(bits_padded).ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "more padding than data")
+    })?;
+    Ok((buffer, num_data_bits))
 }
 
 fn encode_with_dictionary(data: &[u8], dict: &HashMap<u8, BitVec>) -> BitVec {
@@ -114,33 +165,19 @@ fn encode_with_dictionary(data: &[u8], dict: &HashMap<u8, BitVec>) -> BitVec {
     result
 }
 
-/// Depth first search to find the codes for each leaf node
-fn build_dictionary(tree: &HuffmanTree) -> HashMap<u8, BitVec> {
-    let mut frontier = vec![(&tree.root, bitvec![])];
-    let mut codes = HashMap::new();
-
-    while let Some((link, code)) = frontier.pop() {
-        match link {
-            Link::Leaf(_, byte) => {
-                // If the root itself is a leaf (single unique byte), assign
-                // a 1-bit code so each occurrence actually produces output.
-                let code = if code.is_empty() { bitvec![0] } else { code };
-                codes.insert(*byte, code);
-            }
-            Link::Node(node, _) => {
-                let mut left_code = code.clone();
-                left_code.push(false);
-
-                let mut right_code = code.clone();
-                right_code.push(true);
-
-                frontier.push((&node.left, left_code));
-                frontier.push((&node.right, right_code));
-            }
-        };
-    }
+/// Canonical code of each symbol for the tree's code lengths, the codes a
+/// tree read back by `Serializable::deserialize` decodes. The first bit of a
+/// code is the first one read.
+pub(crate) fn build_dictionary<S: Symbol>(tree: &HuffmanTree<S>) -> HashMap<S, BitVec> {
+    let codes = canonical_codes(&tree.code_lengths()).expect("a tree has valid code lengths");
 
     codes
+        .into_iter()
+        .map(|(symbol, len, code)| {
+            let bits = (0..len).rev().map(|i| (code >> i) & 1 == 1).collect();
+            (symbol, bits)
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -155,11 +192,11 @@ mod tests {
         assert_eq!(
             build_dictionary(&tree),
             HashMap::from([
-                (b'a', bitvec![1]),
-                (b'b', bitvec![0, 0, 0]),
-                (b'c', bitvec![0, 0, 1]),
-                (b'd', bitvec![0, 1, 0]),
-                (b'e', bitvec![0, 1, 1]),
+                (b'a', bitvec![0]),
+                (b'b', bitvec![1, 0, 0]),
+                (b'c', bitvec![1, 0, 1]),
+                (b'd', bitvec![1, 1, 0]),
+                (b'e', bitvec![1, 1, 1]),
             ])
         )
     }
@@ -170,12 +207,12 @@ mod tests {
 
         assert_eq!(
             encode_with_dictionary(b"aabcd", &dict),
-            bitvec![1, 1, 0, 0, 0, 0, 0, 1, 0, 1, 0]
+            bitvec![0, 0, 1, 0, 0, 1, 0, 1, 1, 1, 0]
         );
         assert_eq!(encode_with_dictionary(b"", &dict), bitvec![]);
         assert_eq!(
             encode_with_dictionary(b"ee", &dict),
-            bitvec![0, 1, 1, 0, 1, 1]
+            bitvec![1, 1, 1, 1, 1, 1]
         );
     }
 
@@ -203,7 +240,8 @@ mod tests {
     // num_bits = 10
     // Then the padding amount=6 bits as 1 byte just before that
     //
-    // Then we have the actual huffman tree before that
+    // Then we have the code lengths before that: 3 symbols (stored as 2),
+    // each followed by its length
     #[test]
     fn encodes_simple_data_to_correct_buffer() {
         let mut buffer = Vec::new();
@@ -211,10 +249,7 @@ mod tests {
         let result = encode(b"aaaabbc", &mut buffer).expect("failed");
 
         assert_eq!(result, 10);
-        assert_eq!(
-            &buffer,
-            &vec![0, 0, 0, 3, 97, 98, 99, 0, 0, 0, 4, 0, 0, 0, 2, 0, 0, 0, 1, 6, 80, 255]
-        );
+        assert_eq!(&buffer, &vec![2, 97, 1, 98, 2, 99, 2, 6, 80, 255]);
     }
 
     #[test]
@@ -252,4 +287,30 @@ mod tests {
 
         assert_eq!(decode_buffer, data);
     }
+
+    #[test]
+    fn limits_code_lengths_of_skewed_data() {
+        // Byte i appears 2^i times, which unlimited would code with up to 15 bits
+        let data: Vec<u8> = (0..16u8)
+            .flat_map(|b| std::iter::repeat_n(b, 1 << b))
+            .collect();
+        let mut encode_buffer: Vec<u8> = Vec::new();
+        let mut decode_buffer: Vec<u8> = Vec::new();
+
+        encode_limited(&data, &mut encode_buffer, 8).expect("Failed to encode");
+        let tree = HuffmanTree::<u8>::deserialize(&mut encode_buffer.as_slice()).unwrap();
+        assert!(tree.code_lengths().values().all(|&len| len <= 8));
+
+        decode(&mut encode_buffer.as_slice(), &mut decode_buffer).expect("Failed to decode");
+        assert_eq!(decode_buffer, data);
+    }
+
+    #[test]
+    fn rejects_limit_too_small_for_the_alphabet() {
+        let data: Vec<u8> = (0..=255).collect();
+
+        assert!(encode_limited(&data, &mut Vec::new(), 7).is_err());
+        assert!(encode_limited(&data, &mut Vec::new(), 8).is_ok());
+        assert!(encode_limited(&data, &mut Vec::new(), 0).is_err());
+    }
 }
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+
+use super::tree::{complete_canonical_codes, MAX_CODE_LEN};
+
+/// Codes up to this long are resolved with a single table lookup.
+pub const TABLE_BITS: u8 = 11;
+
+/// Decodes a canonical Huffman code from its code lengths with a lookup
+/// table indexed by the next `TABLE_BITS` bits of input.
+///
+/// Bits are read LSB first and a code's first bit is the first one read, so
+/// the table is indexed by codes reversed. Each code of length `len` fills
+/// every entry whose low `len` bits match it. Rare longer codes fall back to
+/// a canonical decode one bit at a time.
+pub struct TableDecoder {
+    /// (symbol, code length) per index; length 0 for longer codes.
+    table: Vec<(u8, u8)>,
+    /// Number of codes of each length.
+    counts: Vec<u64>,
+    /// Symbols in canonical order.
+    symbols: Vec<u8>,
+}
+
+impl TableDecoder {
+    /// Fails unless the lengths describe a complete code, or a single
+    /// symbol with a 1 bit code.
+    pub fn new(lengths: &HashMap<u8, u8>) -> Result<Self> {
+        let codes = complete_canonical_codes(lengths)?;
+        let mut table = vec![(0, 0); 1 << TABLE_BITS];
+        let mut counts = vec![0u64; MAX_CODE_LEN as usize + 1];
+
+        if let [(symbol, _, _)] = codes[..] {
+            // Any bit decodes the lone symbol, the table always resolves it
+            table.fill((symbol, 1));
+            return Ok(Self {
+                table,
+                counts,
+                symbols: vec![symbol],
+            });
+        }
+
+        for &(symbol, len, code) in &codes {
+            counts[len as usize] += 1;
+            if len > TABLE_BITS {
+                continue;
+            }
+            let reversed = (code.reverse_bits() >> (64 - len)) as usize;
+            for index in (reversed..table.len()).step_by(1 << len) {
+                table[index] = (symbol, len);
+            }
+        }
+
+        Ok(Self {
+            table,
+            counts,
+            symbols: codes.into_iter().map(|(symbol, _, _)| symbol).collect(),
+        })
+    }
+
+    /// Decodes the first `num_bits` bits of `data` and appends the symbols to
+    /// `output`. Fails if the bits end in the middle of a code.
+    pub fn decode(&self, data: &[u8], num_bits: usize, output: &mut Vec<u8>) -> Result<()> {
+        let mut bits = BitBuffer::new(data, num_bits);
+        while !bits.is_empty() {
+            output.push(self.decode_symbol(&mut bits)?);
+        }
+        Ok(())
+    }
+
+    /// Decodes the next symbol, for callers that switch between codes.
+    pub fn decode_symbol(&self, bits: &mut BitBuffer) -> Result<u8> {
+        bits.refill();
+        let (symbol, len) = self.table[(bits.buf & ((1 << TABLE_BITS) - 1)) as usize];
+        if len == 0 {
+            let (symbol, len) = self.decode_long(bits)?;
+            bits.seek(bits.pos + len as usize);
+            return Ok(symbol);
+        }
+
+        if bits.pos + len as usize > bits.num_bits {
+            return Err(unexpected_end());
+        }
+        bits.consume(len);
+        Ok(symbol)
+    }
+
+    /// Canonical decode one bit at a time: at each length the codes form a
+    /// contiguous range starting at `first`.
+    fn decode_long(&self, bits: &BitBuffer) -> Result<(u8, u8)> {
+        let mut code = 0u64;
+        let mut first = 0u64;
+        let mut index = 0u64;
+
+        for len in 1..=MAX_CODE_LEN {
+            let pos = bits.pos + len as usize - 1;
+            if pos >= bits.num_bits {
+                return Err(unexpected_end());
+            }
+            code |= ((bits.data[pos / 8] >> (pos % 8)) & 1) as u64;
+
+            let count = self.counts[len as usize];
+            if code.wrapping_sub(first) < count {
+                return Ok((self.symbols[(index + code - first) as usize], len));
+            }
+            index += count;
+            first = first.wrapping_add(count) << 1;
+            code <<= 1;
+        }
+
+        Err(Error::new(ErrorKind::InvalidData, "invalid Huffman code"))
+    }
+}
+
+/// The first `num_bits` bits of some data, read LSB first through a 64 bit
+/// buffer so a table index is a mask away. Past the end of the data the
+/// buffer fills up with zeros.
+pub struct BitBuffer<'a> {
+    data: &'a [u8],
+    num_bits: usize,
+    /// Bits consumed so far.
+    pos: usize,
+    /// Next byte to load into `buf`.
+    next: usize,
+    buf: u64,
+    len: u8,
+}
+
+impl<'a> BitBuffer<'a> {
+    pub fn new(data: &'a [u8], num_bits: usize) -> Self {
+        Self {
+            data,
+            num_bits,
+            pos: 0,
+            next: 0,
+            buf: 0,
+            len: 0,
+        }
+    }
+
+    /// Whether all `num_bits` bits have been consumed.
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.num_bits
+    }
+
+    /// Continues reading at bit `pos`.
+    fn seek(&mut self, pos: usize) {
+        *self = Self {
+            pos: pos - pos % 8,
+            next: pos / 8,
+            ..Self::new(self.data, self.num_bits)
+        };
+        self.refill();
+        self.consume((pos % 8) as u8);
+    }
+
+    /// Tops the buffer up to at least 56 bits.
+    fn refill(&mut self) {
+        while self.len <= 56 {
+            let byte = self.data.get(self.next).copied().unwrap_or(0);
+            self.buf |= (byte as u64) << self.len;
+            self.next += 1;
+            self.len += 8;
+        }
+    }
+
+    fn consume(&mut self, n: u8) {
+        self.buf >>= n;
+        self.len -= n;
+        self.pos += n as usize;
+    }
+}
+
+fn unexpected_end() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "Unexpected end of data: stopped at internal node instead of leaf",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packs bits LSB first, the way the encoder writes them.
+    fn pack(bits: &[u8]) -> Vec<u8> {
+        let mut packed = vec![0u8; bits.len().div_ceil(8)];
+        for (i, &bit) in bits.iter().enumerate() {
+            packed[i / 8] |= bit << (i % 8);
+        }
+        packed
+    }
+
+    #[test]
+    fn decodes_short_codes_from_the_table() {
+        // b: 0, a: 10, c: 110, d: 111
+        let lengths = HashMap::from([(b'a', 2), (b'b', 1), (b'c', 3), (b'd', 3)]);
+        let decoder = TableDecoder::new(&lengths).unwrap();
+        let bits = [1, 1, 1, 0, 1, 0, 1, 1, 0];
+        let mut output = Vec::new();
+
+        decoder
+            .decode(&pack(&bits), bits.len(), &mut output)
+            .unwrap();
+        assert_eq!(output, b"dbac");
+    }
+
+    #[test]
+    fn decodes_codes_longer_than_the_table() {
+        // Symbol i has code 1...10 of length i + 1, the last one all ones
+        let lengths: HashMap<u8, u8> = (0..16).map(|s| (s, (s + 1).min(15))).collect();
+        let decoder = TableDecoder::new(&lengths).unwrap();
+        let mut bits = vec![1; 14];
+        bits.push(0); // symbol 14
+        bits.extend([1; 15]); // symbol 15
+        bits.push(0); // symbol 0
+        let mut output = Vec::new();
+
+        decoder
+            .decode(&pack(&bits), bits.len(), &mut output)
+            .unwrap();
+        assert_eq!(output, vec![14, 15, 0]);
+    }
+
+    #[test]
+    fn rejects_bits_ending_inside_a_code() {
+        let lengths = HashMap::from([(b'a', 1), (b'b', 2), (b'c', 2)]);
+        let decoder = TableDecoder::new(&lengths).unwrap();
+
+        assert!(decoder.decode(&pack(&[0, 1]), 2, &mut Vec::new()).is_err());
+        assert!(decoder.decode(&pack(&[1; 14]), 14, &mut Vec::new()).is_ok());
+    }
+}
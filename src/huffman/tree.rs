@@ -1,9 +1,12 @@
 use std::{
     cmp::Ordering,
     collections::{BinaryHeap, HashMap},
+    hash::Hash,
     io::{Error, ErrorKind, Read, Result, Write},
 };
 
+use super::limit::limited_code_lengths;
+
 pub trait Serializable {
     fn serialize<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize>;
     fn deserialize<R: Read + ?Sized>(reader: &mut R) -> Result<Self>
@@ -11,10 +14,20 @@ pub trait Serializable {
         Self: Sized;
 }
 
+/// Anything that can sit in the leaves of a Huffman tree. Bytes for plain
+/// Huffman coding, wider integers for alphabets like LZ length codes.
+pub trait Symbol: Copy + Ord + Hash {}
+
+impl<T: Copy + Ord + Hash> Symbol for T {}
+
+/// Longest code `from_code_lengths` accepts. Reaching it takes weights
+/// growing like the Fibonacci numbers, some 10^13 symbols of input, and
+/// `from_counts` limits its codes to it should that ever happen.
+pub const MAX_CODE_LEN: u8 = 64;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct HuffmanTree {
-    pub root: Link,
-    counts: HashMap<u8, u32>,
+pub struct HuffmanTree<S: Symbol = u8> {
+    pub root: Link<S>,
 }
 
 impl HuffmanTree {
@@ -24,11 +37,20 @@ impl HuffmanTree {
         Self::from_counts(&counts)
     }
 
-    pub fn from_counts(counts: &HashMap<u8, u32>) -> Option<Self> {
+    /// Like `build`, but with no code longer than `max_len` bits. None for
+    /// empty input or more distinct bytes than there are codes that short.
+    pub fn build_limited(data: &[u8], max_len: u8) -> Option<Self> {
+        let lengths = limited_code_lengths(&count_bytes(data), max_len.min(MAX_CODE_LEN))?;
+        Self::from_code_lengths(&lengths).ok()
+    }
+}
+
+impl<S: Symbol> HuffmanTree<S> {
+    pub fn from_counts(counts: &HashMap<S, u64>) -> Option<Self> {
         // Insert the leaf nodes with the byte counts in a heap
         let mut heap = BinaryHeap::new();
-        for (byte, weight) in counts {
-            heap.push(Link::Leaf(*weight, *byte))
+        for (symbol, weight) in counts {
+            heap.push(Link::Leaf(*weight, *symbol))
         }
 
         // Build the tree
@@ -36,76 +58,281 @@ impl HuffmanTree {
             let right = heap.pop().unwrap(); // smaller weight goes to the right subtree
             let left = heap.pop().unwrap();
 
-            let byte = left.byte();
+            let symbol = left.symbol();
             heap.push(Link::Node(
                 Box::new(Node {
                     weight: left.weight() + right.weight(),
                     left,
                     right,
                 }),
-                byte,
+                symbol,
             ))
         }
 
-        heap.pop().map(|link| Self {
-            root: link,
-            counts: counts.clone(),
-        }) // This may be None in the case of empty input
+        let tree = Self { root: heap.pop()? }; // This may be None in the case of empty input
+        if tree.code_lengths().values().all(|&len| len <= MAX_CODE_LEN) {
+            return Some(tree);
+        }
+        let lengths = limited_code_lengths(counts, MAX_CODE_LEN)?;
+        Self::from_code_lengths(&lengths).ok()
+    }
+
+    /// Builds the tree of the canonical code with the given lengths, see
+    /// `complete_canonical_codes`. Leaves of the result have weight 0.
+    pub fn from_code_lengths(lengths: &HashMap<S, u8>) -> Result<Self> {
+        let codes = complete_canonical_codes(lengths)?;
+        if let [(symbol, _, _)] = codes[..] {
+            return Ok(Self {
+                root: Link::Leaf(0, symbol),
+            });
+        }
+
+        Ok(Self {
+            root: subtree(&codes, 0),
+        })
+    }
+
+    /// Length of each symbol's code, its depth in the tree. A tree that is a
+    /// single leaf codes its symbol with one bit.
+    pub fn code_lengths(&self) -> HashMap<S, u8> {
+        let mut frontier = vec![(&self.root, 0u8)];
+        let mut lengths = HashMap::new();
+
+        while let Some((link, depth)) = frontier.pop() {
+            match link {
+                Link::Leaf(_, symbol) => {
+                    lengths.insert(*symbol, depth.max(1));
+                }
+                Link::Node(node, _) => {
+                    frontier.push((&node.left, depth + 1));
+                    frontier.push((&node.right, depth + 1));
+                }
+            }
+        }
+
+        lengths
+    }
+
+    /// Reads bits until a leaf is reached and returns its symbol, or None if
+    /// the bits run out first. A tree that is a single leaf consumes one bit
+    /// per symbol.
+    pub fn read_symbol<I: Iterator<Item = bool>>(&self, bits: &mut I) -> Option<S> {
+        let mut current = &self.root;
+        loop {
+            match current {
+                Link::Leaf(_, symbol) => {
+                    if std::ptr::eq(current, &self.root) {
+                        bits.next()?;
+                    }
+                    return Some(*symbol);
+                }
+                Link::Node(node, _) => {
+                    current = if bits.next()? {
+                        &node.right
+                    } else {
+                        &node.left
+                    };
+                }
+            }
+        }
     }
 }
 
+/// Symbols used by a byte tree with fewer entries than this are listed as
+/// (symbol, length) pairs, otherwise as a length for every byte value.
+const SPARSE_LIMIT: usize = 128;
+
+/// Stores the canonical code lengths:
+/// 1. Number of symbols minus one (1 byte)
+/// 2. Fewer than `SPARSE_LIMIT` symbols: (symbol, length) pairs, 2 bytes
+///    each, in symbol order. Otherwise: the length of every byte value, 0
+///    when unused (256 bytes)
+///
+/// which takes at most 257 bytes. The tree read back assigns the same codes
+/// as `build_dictionary` does for the original.
 impl Serializable for HuffmanTree {
     fn serialize<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
-        let mut bytes: Vec<u8> = self.counts.keys().copied().collect();
-        bytes.sort();
-        let counts: Vec<u32> = bytes.iter().map(|b| self.counts[b]).collect();
-        let num_bytes = bytes.len() as u32;
-
-        // Write the u32 describing how many unique bytes
-        writer.write_all(&num_bytes.to_be_bytes())?;
-        // Write the bytes
-        writer.write_all(&bytes)?;
-        // Write the counts
-        for count in &counts {
-            writer.write_all(&count.to_be_bytes())?;
+        let lengths = self.code_lengths();
+        let mut header = vec![(lengths.len() - 1) as u8];
+
+        if lengths.len() < SPARSE_LIMIT {
+            let mut symbols: Vec<u8> = lengths.keys().copied().collect();
+            symbols.sort();
+            for symbol in symbols {
+                header.extend_from_slice(&[symbol, lengths[&symbol]]);
+            }
+        } else {
+            header.extend((0..=255).map(|b| lengths.get(&b).copied().unwrap_or(0)));
+        }
+
+        writer.write_all(&header)?;
+        Ok(header.len())
+    }
+
+    fn deserialize<R: Read + ?Sized>(reader: &mut R) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        HuffmanTree::from_code_lengths(&read_code_lengths(reader)?)
+    }
+}
+
+/// Reads the code lengths written by `HuffmanTree::serialize`, for decoders
+/// that work from the lengths rather than a tree.
+pub fn read_code_lengths<R: Read + ?Sized>(reader: &mut R) -> Result<HashMap<u8, u8>> {
+    let mut num_symbols = [0; 1];
+    reader.read_exact(&mut num_symbols)?;
+    let num_symbols = num_symbols[0] as usize + 1;
+
+    let mut lengths = HashMap::new();
+    if num_symbols < SPARSE_LIMIT {
+        let mut pairs = vec![0; num_symbols * 2];
+        reader.read_exact(&mut pairs)?;
+        for pair in pairs.chunks(2) {
+            if lengths.insert(pair[0], pair[1]).is_some() {
+                return Err(invalid_data("symbol listed twice"));
+            }
+        }
+    } else {
+        let mut table = [0; 256];
+        reader.read_exact(&mut table)?;
+        for (b, &len) in table.iter().enumerate().filter(|(_, &len)| len > 0) {
+            lengths.insert(b as u8, len);
+        }
+        if lengths.len() != num_symbols {
+            return Err(invalid_data("symbol count does not match the lengths"));
+        }
+    }
+
+    Ok(lengths)
+}
+
+/// Wide-alphabet variant: the symbol count (2 bytes), then (symbol, length)
+/// pairs of 3 bytes each, in symbol order.
+impl Serializable for HuffmanTree<u16> {
+    fn serialize<W: Write + ?Sized>(&self, writer: &mut W) -> Result<usize> {
+        let lengths = self.code_lengths();
+        let mut symbols: Vec<u16> = lengths.keys().copied().collect();
+        symbols.sort();
+
+        writer.write_all(&(symbols.len() as u16).to_be_bytes())?;
+        for symbol in &symbols {
+            writer.write_all(&symbol.to_be_bytes())?;
+            writer.write_all(&[lengths[symbol]])?;
         }
 
-        Ok(4 + bytes.len() + counts.len() * 4)
+        Ok(2 + symbols.len() * 3)
     }
 
     fn deserialize<R: Read + ?Sized>(reader: &mut R) -> Result<Self>
     where
         Self: Sized,
     {
-        // Read the u32 indicating how many unique bytes
-        let mut num_bytes_buffer = [0; 4];
-        reader.read_exact(&mut num_bytes_buffer)?;
-        let num_bytes = u32::from_be_bytes(num_bytes_buffer) as usize;
-
-        // Read the bytes
-        let mut byte_values = vec![0u8; num_bytes];
-        reader.read_exact(&mut byte_values)?;
-
-        // For each byte value, read its count
-        let mut counts = HashMap::new();
-        for b in &byte_values {
-            let mut count_buffer = [0; 4];
-            reader.read_exact(&mut count_buffer)?;
-            counts.insert(*b, u32::from_be_bytes(count_buffer));
+        let mut num_symbols_buffer = [0; 2];
+        reader.read_exact(&mut num_symbols_buffer)?;
+        let num_symbols = u16::from_be_bytes(num_symbols_buffer) as usize;
+
+        let mut lengths = HashMap::new();
+        for _ in 0..num_symbols {
+            let mut entry = [0; 3];
+            reader.read_exact(&mut entry)?;
+            if lengths
+                .insert(u16::from_be_bytes([entry[0], entry[1]]), entry[2])
+                .is_some()
+            {
+                return Err(invalid_data("symbol listed twice"));
+            }
         }
 
-        HuffmanTree::from_counts(&counts).ok_or(Error::new(
-            ErrorKind::Other,
-            "failed to build tree from counts",
-        ))
+        HuffmanTree::from_code_lengths(&lengths)
     }
 }
 
-fn count_bytes(source: &[u8]) -> HashMap<u8, u32> {
-    let mut counts = [0u32; 256];
-    for &b in source {
-        counts[b as usize] += 1;
+/// Assigns canonical codes to the given lengths, returned as (symbol,
+/// length, code) in code order. Fails on lengths of 0 or above
+/// `MAX_CODE_LEN`, or if there are no symbols at all.
+pub fn canonical_codes<S: Symbol>(lengths: &HashMap<S, u8>) -> Result<Vec<(S, u8, u64)>> {
+    let mut symbols: Vec<(S, u8)> = lengths.iter().map(|(&s, &len)| (s, len)).collect();
+    if symbols.is_empty() {
+        return Err(invalid_data("empty symbol table"));
+    }
+    if symbols
+        .iter()
+        .any(|&(_, len)| len == 0 || len > MAX_CODE_LEN)
+    {
+        return Err(invalid_data("code length out of range"));
+    }
+    symbols.sort_by_key(|&(symbol, len)| (len, symbol));
+
+    let mut codes = Vec::with_capacity(symbols.len());
+    let mut code = 0u64;
+    let mut prev_len = symbols[0].1;
+    for (i, (symbol, len)) in symbols.into_iter().enumerate() {
+        if i > 0 {
+            code = (code + 1) << (len - prev_len);
+        }
+        prev_len = len;
+        codes.push((symbol, len, code));
+    }
+
+    Ok(codes)
+}
+
+/// Like `canonical_codes`, but also checks that every bit sequence leads to
+/// a symbol. The lengths must describe a complete code, except that a single
+/// symbol has a 1 bit code, of which only `0` is used.
+pub fn complete_canonical_codes<S: Symbol>(lengths: &HashMap<S, u8>) -> Result<Vec<(S, u8, u64)>> {
+    let codes = canonical_codes(lengths)?;
+    if let [(_, len, _)] = codes[..] {
+        if len != 1 {
+            return Err(invalid_data("a lone symbol must have a 1 bit code"));
+        }
+        return Ok(codes);
+    }
+
+    let kraft_sum: u128 = codes
+        .iter()
+        .map(|&(_, len, _)| 1u128 << (MAX_CODE_LEN - len))
+        .sum();
+    if kraft_sum != 1 << MAX_CODE_LEN {
+        return Err(invalid_data("code lengths do not form a complete code"));
+    }
+
+    Ok(codes)
+}
+
+/// Builds the part of a complete canonical code's tree below `depth`, from
+/// the codes that share its first `depth` bits.
+fn subtree<S: Symbol>(codes: &[(S, u8, u64)], depth: u8) -> Link<S> {
+    if let [(symbol, len, _)] = codes[..] {
+        if len == depth {
+            return Link::Leaf(0, symbol);
+        }
     }
+
+    // Canonical codes are ordered, so those continuing with a 0 come first
+    let split = codes.partition_point(|&(_, len, code)| (code >> (len - depth - 1)) & 1 == 0);
+    let left = subtree(&codes[..split], depth + 1);
+    let right = subtree(&codes[split..], depth + 1);
+    let symbol = left.symbol();
+    Link::Node(
+        Box::new(Node {
+            weight: 0,
+            left,
+            right,
+        }),
+        symbol,
+    )
+}
+
+fn invalid_data(msg: &'static str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Counts are 64 bit, so input over 4 GiB of a single byte is fine.
+pub(crate) fn count_bytes(source: &[u8]) -> HashMap<u8, u64> {
+    let mut counts = [0u64; 256];
+    add_byte_counts(&mut counts, source);
     counts
         .iter()
         .enumerate()
@@ -114,43 +341,49 @@ fn count_bytes(source: &[u8]) -> HashMap<u8, u32> {
         .collect()
 }
 
+fn add_byte_counts(counts: &mut [u64; 256], source: &[u8]) {
+    for &b in source {
+        counts[b as usize] += 1;
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Node {
-    weight: u32,
-    pub left: Link,
-    pub right: Link,
+pub struct Node<S = u8> {
+    weight: u64,
+    pub left: Link<S>,
+    pub right: Link<S>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Link {
-    Leaf(u32, u8),
-    Node(Box<Node>, u8),
+pub enum Link<S = u8> {
+    Leaf(u64, S),
+    Node(Box<Node<S>>, S),
 }
 
-impl Link {
-    pub fn weight(&self) -> u32 {
+impl<S: Symbol> Link<S> {
+    pub fn weight(&self) -> u64 {
         match self {
             Link::Leaf(weight, _) => *weight,
             Link::Node(node, _) => node.weight,
         }
     }
 
-    // The "representative" byte of a Leaf/Node. Needed to break ties in weight.
-    pub fn byte(&self) -> u8 {
+    // The "representative" symbol of a Leaf/Node. Needed to break ties in weight.
+    pub fn symbol(&self) -> S {
         match self {
-            Link::Leaf(_, byte) => *byte,
-            Link::Node(_, byte) => *byte,
+            Link::Leaf(_, symbol) => *symbol,
+            Link::Node(_, symbol) => *symbol,
         }
     }
 }
 
-impl Ord for Link {
+impl<S: Symbol> Ord for Link<S> {
     fn cmp(&self, other: &Self) -> Ordering {
-        (other.weight(), other.byte()).cmp(&(self.weight(), self.byte()))
+        (other.weight(), other.symbol()).cmp(&(self.weight(), self.symbol()))
     }
 }
 
-impl PartialOrd for Link {
+impl<S: Symbol> PartialOrd for Link<S> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
@@ -162,10 +395,7 @@ pub mod tests {
 
     #[test]
     fn test_count_bytes() {
-        assert_eq!(
-            count_bytes(b"mamma"),
-            HashMap::from([(b'm', 3), (b'a', 2)])
-        );
+        assert_eq!(count_bytes(b"mamma"), HashMap::from([(b'm', 3), (b'a', 2)]));
         assert_eq!(count_bytes(b""), HashMap::new());
         assert_eq!(
             count_bytes(b"abcd"),
@@ -173,6 +403,47 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn counts_bytes_past_u32_max() {
+        let mut counts = [u32::MAX as u64 - 1; 256];
+        add_byte_counts(&mut counts, b"aaab");
+
+        assert_eq!(counts[b'a' as usize], u32::MAX as u64 + 2);
+        assert_eq!(counts[b'b' as usize], u32::MAX as u64);
+    }
+
+    #[test]
+    fn builds_tree_from_counts_above_u32() {
+        // Over 8 GiB of input in total
+        let counts = HashMap::from([(b'a', 5_000_000_000), (b'b', 3_000_000_000), (b'c', 1)]);
+        let tree = HuffmanTree::from_counts(&counts).unwrap();
+
+        assert_eq!(tree.root.weight(), 8_000_000_001);
+        assert_eq!(
+            tree.code_lengths(),
+            HashMap::from([(b'a', 1), (b'b', 2), (b'c', 2)])
+        );
+
+        let mut buffer = Vec::<u8>::new();
+        tree.serialize(&mut buffer).unwrap();
+        let read = HuffmanTree::deserialize(&mut buffer.as_slice()).unwrap();
+        assert_eq!(read.code_lengths(), tree.code_lengths());
+    }
+
+    #[test]
+    fn limits_trees_deeper_than_max_code_len() {
+        // Fibonacci counts give a tree one level deeper per symbol
+        let mut counts: HashMap<u16, u64> = HashMap::from([(0, 1), (1, 1)]);
+        for i in 2..80u16 {
+            counts.insert(i, counts[&(i - 1)] + counts[&(i - 2)]);
+        }
+        let tree = HuffmanTree::from_counts(&counts).unwrap();
+        let lengths = tree.code_lengths();
+
+        assert_eq!(lengths.values().max(), Some(&MAX_CODE_LEN));
+        assert!(HuffmanTree::from_code_lengths(&lengths).is_ok());
+    }
+
     #[test]
     fn build_huffman_tree_for_simple_case() {
         let expected = build_correct_tree();
@@ -187,7 +458,6 @@ pub mod tests {
             HuffmanTree::build(b"a"),
             Option::Some(HuffmanTree {
                 root: Link::Leaf(1, b'a'),
-                counts: HashMap::from([(b'a', 1)])
             })
         );
         assert_eq!(HuffmanTree::build(b""), None);
@@ -224,22 +494,97 @@ pub mod tests {
     }
 
     #[test]
-    fn can_serialize_and_deserialize_to_eq_object() {
+    fn can_serialize_and_deserialize_code_lengths() {
         let original = build_correct_tree();
         let mut buffer = Vec::<u8>::new();
 
-        original.serialize(&mut buffer).unwrap();
+        let written = original.serialize(&mut buffer).unwrap();
+        assert_eq!(written, buffer.len());
+        assert_eq!(buffer, vec![4, b'a', 1, b'b', 3, b'c', 3, b'd', 3, b'e', 3]);
+
+        let read = HuffmanTree::deserialize(&mut buffer.as_slice()).unwrap();
+        assert_eq!(read.code_lengths(), original.code_lengths());
+    }
+
+    #[test]
+    fn serializes_full_alphabet_as_length_table() {
+        let counts: HashMap<u8, u64> = (0..=255).map(|b| (b, 1 + b as u64 % 7)).collect();
+        let original = HuffmanTree::from_counts(&counts).unwrap();
+        let mut buffer = Vec::<u8>::new();
+
+        assert_eq!(original.serialize(&mut buffer).unwrap(), 257);
 
         let read = HuffmanTree::deserialize(&mut buffer.as_slice()).unwrap();
-        assert_eq!(original, read);
+        assert_eq!(read.code_lengths(), original.code_lengths());
+    }
+
+    #[test]
+    fn can_serialize_and_deserialize_wide_symbols() {
+        let counts = HashMap::from([(0u16, 3), (256, 1), (300, 7)]);
+        let original = HuffmanTree::from_counts(&counts).unwrap();
+        let mut buffer = Vec::<u8>::new();
+
+        let written = original.serialize(&mut buffer).unwrap();
+        assert_eq!(written, buffer.len());
+
+        let read = HuffmanTree::<u16>::deserialize(&mut buffer.as_slice()).unwrap();
+        assert_eq!(read.code_lengths(), original.code_lengths());
+    }
+
+    #[test]
+    fn builds_canonical_tree_from_code_lengths() {
+        let lengths = HashMap::from([(b'a', 2), (b'b', 1), (b'c', 3), (b'd', 3)]);
+        let tree = HuffmanTree::from_code_lengths(&lengths).unwrap();
+        // b: 0, a: 10, c: 110, d: 111
+        let mut bits = [true, true, true, false, true, false, true, true, false].into_iter();
+
+        assert_eq!(tree.read_symbol(&mut bits), Some(b'd'));
+        assert_eq!(tree.read_symbol(&mut bits), Some(b'b'));
+        assert_eq!(tree.read_symbol(&mut bits), Some(b'a'));
+        assert_eq!(tree.read_symbol(&mut bits), Some(b'c'));
+        assert_eq!(tree.code_lengths(), lengths);
+    }
+
+    #[test]
+    fn rejects_invalid_code_lengths() {
+        let incomplete = HashMap::from([(b'a', 1), (b'b', 2)]);
+        let over_subscribed = HashMap::from([(b'a', 1), (b'b', 1), (b'c', 1)]);
+        let lone_long_code = HashMap::from([(b'a', 2)]);
+        let zero_length = HashMap::from([(b'a', 1), (b'b', 0)]);
+
+        for lengths in [incomplete, over_subscribed, lone_long_code, zero_length] {
+            assert!(HuffmanTree::from_code_lengths(&lengths).is_err());
+        }
+        assert!(HuffmanTree::<u8>::from_code_lengths(&HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn reads_symbols_from_bits() {
+        let tree = build_correct_tree();
+        let mut bits = [true, false, false, false, false, true, true, false].into_iter();
+
+        assert_eq!(tree.read_symbol(&mut bits), Some(b'a'));
+        assert_eq!(tree.read_symbol(&mut bits), Some(b'b'));
+        assert_eq!(tree.read_symbol(&mut bits), Some(b'e'));
+        assert_eq!(tree.read_symbol(&mut bits), None);
+    }
+
+    #[test]
+    fn reads_symbols_from_single_leaf_tree() {
+        let tree = HuffmanTree::build(b"zz").unwrap();
+        let mut bits = [false, false].into_iter();
+
+        assert_eq!(tree.read_symbol(&mut bits), Some(b'z'));
+        assert_eq!(tree.read_symbol(&mut bits), Some(b'z'));
+        assert_eq!(tree.read_symbol(&mut bits), None);
     }
 
-    /// Correct codes for this tree should be:
-    ///     a: 1
-    ///     b: 000
-    ///     c: 001
-    ///     d: 010
-    ///     e: 011
+    /// Correct canonical codes for this tree should be:
+    ///     a: 0
+    ///     b: 100
+    ///     c: 101
+    ///     d: 110
+    ///     e: 111
     pub fn build_correct_tree() -> HuffmanTree {
         let a = Link::Leaf(15, b'a');
         let b = Link::Leaf(7, b'b');
@@ -271,7 +616,6 @@ pub mod tests {
                 }),
                 b'b',
             ),
-            counts: HashMap::from([(b'e', 4), (b'd', 5), (b'c', 6), (b'b', 7), (b'a', 15)]),
         }
     }
 }
@@ -1,3 +1,21 @@
+pub mod adaptive_huffman;
+pub mod arith;
+pub mod bwt;
+pub mod checksum;
 pub mod codec;
+pub mod container;
+pub mod deflate;
+mod format;
 pub mod huffman;
+pub mod lz4;
+pub mod lz77;
+pub mod lz78;
+pub mod lzh;
+pub mod lzma;
+pub mod lzw;
+pub mod pipeline;
+pub mod rans;
 pub mod rle;
+pub mod snappy;
+pub mod stream;
+pub mod zstd;
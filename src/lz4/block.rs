@@ -0,0 +1,233 @@
+//! The LZ4 block format: a sequence of literal runs, each followed by a
+//! match except for the last.
+
+use std::io;
+
+use super::invalid_data;
+use crate::format::u32_le_at;
+
+/// Shortest match a sequence can describe.
+const MIN_MATCH: usize = 4;
+/// The last bytes of a block are always literals.
+const LAST_LITERALS: usize = 5;
+/// No match may start in the last bytes of a block.
+const MF_LIMIT: usize = 12;
+/// Farthest back a match can reach.
+pub const MAX_OFFSET: usize = 65535;
+
+const HASH_BITS: u32 = 14;
+/// The search step grows by one every `1 << SKIP_SHIFT` misses, so
+/// incompressible data is skipped over quickly.
+const SKIP_SHIFT: u32 = 6;
+
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Compresses `data` into a single block appended to `output`, with the
+/// greedy single-probe search of the reference LZ4 compressor.
+pub fn compress(data: &[u8], output: &mut Vec<u8>) {
+    let mut table = vec![0u32; 1 << HASH_BITS];
+    let mut anchor = 0;
+
+    if data.len() > MF_LIMIT {
+        let match_limit = data.len() - MF_LIMIT;
+        let mut pos = 1;
+
+        while pos < match_limit {
+            // Find a match, probing a hash table of the last position of
+            // each 4 byte sequence
+            let mut misses = 1 << SKIP_SHIFT;
+            let candidate = loop {
+                let sequence = u32_le_at(data, pos);
+                let slot = &mut table[hash(sequence)];
+                let candidate = *slot as usize;
+                *slot = pos as u32;
+                if candidate < pos
+                    && pos - candidate <= MAX_OFFSET
+                    && u32_le_at(data, candidate) == sequence
+                {
+                    break Some(candidate);
+                }
+                pos += misses >> SKIP_SHIFT;
+                misses += 1;
+                if pos >= match_limit {
+                    break None;
+                }
+            };
+            let Some(mut candidate) = candidate else {
+                break;
+            };
+
+            // Extend backwards over literals that also match
+            while pos > anchor && candidate > 0 && data[pos - 1] == data[candidate - 1] {
+                pos -= 1;
+                candidate -= 1;
+            }
+            let end = data.len() - LAST_LITERALS;
+            let mut len = MIN_MATCH;
+            while pos + len < end && data[pos + len] == data[candidate + len] {
+                len += 1;
+            }
+
+            write_sequence(output, &data[anchor..pos], pos - candidate, len);
+            pos += len;
+            anchor = pos;
+            if pos < match_limit {
+                table[hash(u32_le_at(data, pos - 2))] = (pos - 2) as u32;
+            }
+        }
+    }
+
+    write_last_literals(output, &data[anchor..]);
+}
+
+/// Writes a sequence: the token holding both lengths' first 4 bits, the
+/// rest of the literal length, the literals, the offset (2 bytes) and the
+/// rest of the match length.
+fn write_sequence(output: &mut Vec<u8>, literals: &[u8], offset: usize, match_len: usize) {
+    let match_len = match_len - MIN_MATCH;
+    let token = (literals.len().min(15) << 4 | match_len.min(15)) as u8;
+    output.push(token);
+    write_length(output, literals.len());
+    output.extend_from_slice(literals);
+    output.extend_from_slice(&(offset as u16).to_le_bytes());
+    write_length(output, match_len);
+}
+
+fn write_last_literals(output: &mut Vec<u8>, literals: &[u8]) {
+    output.push((literals.len().min(15) << 4) as u8);
+    write_length(output, literals.len());
+    output.extend_from_slice(literals);
+}
+
+/// Lengths of 15 and more continue after the token nibble in bytes of 255,
+/// up to a last byte below 255.
+fn write_length(output: &mut Vec<u8>, len: usize) {
+    if len < 15 {
+        return;
+    }
+    let mut rest = len - 15;
+    while rest >= 255 {
+        output.push(255);
+        rest -= 255;
+    }
+    output.push(rest as u8);
+}
+
+/// Decompresses a block, appending to `output`. Matches may reach back into
+/// what `output` already holds, as they do in linked blocks. Fails if the
+/// block would add more than `max_len` bytes.
+pub fn decompress(block: &[u8], output: &mut Vec<u8>, max_len: usize) -> io::Result<()> {
+    let limit = output.len() + max_len;
+    let mut pos = 0;
+
+    loop {
+        let token = *block
+            .get(pos)
+            .ok_or_else(|| invalid_data("truncated block"))?;
+        pos += 1;
+
+        let literals = read_length(block, &mut pos, (token >> 4) as usize)?;
+        let literals = block
+            .get(pos..pos + literals)
+            .ok_or_else(|| invalid_data("truncated block"))?;
+        if output.len() + literals.len() > limit {
+            return Err(invalid_data("block larger than the maximum block size"));
+        }
+        output.extend_from_slice(literals);
+        pos += literals.len();
+        if pos == block.len() {
+            return Ok(());
+        }
+
+        let offset = block
+            .get(pos..pos + 2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+            .ok_or_else(|| invalid_data("truncated block"))?;
+        pos += 2;
+        let len = read_length(block, &mut pos, (token & 0x0F) as usize)? + MIN_MATCH;
+        if offset == 0 || offset > output.len() {
+            return Err(invalid_data("match offset points before start of output"));
+        }
+        if output.len() + len > limit {
+            return Err(invalid_data("block larger than the maximum block size"));
+        }
+
+        // A match overlapping the bytes it produces repeats its first
+        // `offset` bytes, so it is copied in whole repeats, doubling each time
+        let start = output.len() - offset;
+        let mut remaining = len;
+        while remaining > 0 {
+            let n = remaining.min(output.len() - start);
+            output.extend_from_within(start..start + n);
+            remaining -= n;
+        }
+    }
+}
+
+fn read_length(block: &[u8], pos: &mut usize, nibble: usize) -> io::Result<usize> {
+    let mut len = nibble;
+    if nibble == 15 {
+        loop {
+            let byte = *block
+                .get(*pos)
+                .ok_or_else(|| invalid_data("truncated block"))?;
+            *pos += 1;
+            len += byte as usize;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::tests::LOREM;
+
+    fn roundtrip(data: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        let mut output = Vec::new();
+
+        compress(data, &mut compressed);
+        decompress(&compressed, &mut output, data.len()).expect("Failed to decompress");
+        output
+    }
+
+    #[test]
+    fn compresses_and_decompresses() {
+        assert_eq!(roundtrip(LOREM), LOREM);
+        for data in [&b""[..], b"a", b"abcabcabcabcabc", &[7; 100_000]] {
+            assert_eq!(roundtrip(data), data);
+        }
+
+        let mut compressed = Vec::new();
+        compress(&[7; 100_000], &mut compressed);
+        assert!(compressed.len() < 500);
+    }
+
+    #[test]
+    fn writes_sequences() {
+        let mut compressed = Vec::new();
+        compress(b"abcdabcdabcdabcdabcdabcd", &mut compressed);
+
+        // 4 literals then a match 4 back of 15 bytes, then 5 literals
+        let mut expected = vec![0x4B, b'a', b'b', b'c', b'd', 4, 0, 0x50];
+        expected.extend_from_slice(b"dabcd");
+        assert_eq!(compressed, expected);
+    }
+
+    #[test]
+    fn rejects_invalid_blocks() {
+        let mut output = Vec::new();
+        // A match 1 back of 4 bytes with nothing before it
+        assert!(decompress(&[0x00, 1, 0], &mut output, 100).is_err());
+        // 3 literals where 2 are given
+        assert!(decompress(&[0x30, b'a', b'b'], &mut output, 100).is_err());
+        // More output than allowed
+        assert!(decompress(&[0x1F, b'a', 1, 0, 200, 0x00], &mut output, 100).is_err());
+    }
+}
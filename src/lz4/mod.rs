@@ -0,0 +1,497 @@
+use std::io::{self, Read, Write};
+
+use crate::checksum::{self, xxhash32, ChecksumKind, XxHash32};
+use crate::format::{self, read_u32_le};
+
+pub mod block;
+
+/// Magic bytes of an LZ4 frame (`.lz4` file).
+pub const MAGIC: [u8; 4] = [0x04, 0x22, 0x4D, 0x18];
+
+/// Frame descriptor flags (FLG byte).
+const VERSION: u8 = 0b01 << 6;
+const VERSION_MASK: u8 = 0b11 << 6;
+const BLOCK_INDEPENDENCE: u8 = 0x20;
+const BLOCK_CHECKSUM: u8 = 0x10;
+const CONTENT_SIZE: u8 = 0x08;
+const CONTENT_CHECKSUM: u8 = 0x04;
+const DICT_ID: u8 = 0x01;
+
+/// Set in a block's size when the block is stored uncompressed.
+const UNCOMPRESSED: u32 = 1 << 31;
+
+/// Block sizes a frame may use, for block size ids 4 to 7.
+pub const BLOCK_SIZES: [usize; 4] = [64 << 10, 256 << 10, 1 << 20, 4 << 20];
+const FIRST_BLOCK_SIZE_ID: u8 = 4;
+
+/// How much earlier output a linked block's matches can reach.
+const HISTORY: usize = block::MAX_OFFSET;
+
+/// Settings for the frames `Lz4Encoder` writes. The defaults match the
+/// `lz4` tool's.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameOptions {
+    /// Input compressed at a time, one of `BLOCK_SIZES`.
+    pub block_size: usize,
+    /// Adds an xxHash32 of every block, so corruption is caught before the
+    /// block is decompressed.
+    pub block_checksum: bool,
+    /// Adds an xxHash32 of all the data at the end.
+    pub content_checksum: bool,
+}
+
+impl Default for FrameOptions {
+    fn default() -> Self {
+        Self {
+            block_size: 4 << 20,
+            block_checksum: false,
+            content_checksum: true,
+        }
+    }
+}
+
+/// LZ4 in its frame format, as written and read by the `lz4` tool.
+///
+/// LZ4 trades ratio for speed: matches are found with a single hash table
+/// probe and sequences are byte aligned, with no entropy coding, so both
+/// compression and decompression run at memory speed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lz4Codec {
+    pub options: FrameOptions,
+}
+
+impl Lz4Codec {
+    pub fn with_options(options: FrameOptions) -> Self {
+        Self { options }
+    }
+}
+
+impl crate::codec::Codec for Lz4Codec {
+    fn name(&self) -> &'static str {
+        "lz4"
+    }
+
+    fn encode(&self, data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
+        let mut encoder = Lz4Encoder::with_options(writer, self.options)?;
+        encoder.write_all(data)?;
+        encoder.try_finish()?;
+        Ok(encoder.bytes_written * 8)
+    }
+
+    fn decode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+        let bytes_written = io::copy(&mut Lz4Decoder::new(reader)?, writer)?;
+        Ok(bytes_written as usize)
+    }
+}
+
+/// Compresses data into an LZ4 frame as it is written, one block of
+/// `block_size` bytes at a time. Call `finish` to write the last block and
+/// the end of the frame.
+///
+/// Blocks are compressed independently of each other.
+pub struct Lz4Encoder<W: Write> {
+    writer: W,
+    options: FrameOptions,
+    buffer: Vec<u8>,
+    compressed: Vec<u8>,
+    content: XxHash32,
+    bytes_written: u64,
+    header_written: bool,
+    finished: bool,
+}
+
+impl<W: Write> Lz4Encoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_options(writer, FrameOptions::default()).expect("default options are valid")
+    }
+
+    /// Fails unless `options.block_size` is one of `BLOCK_SIZES`.
+    pub fn with_options(writer: W, options: FrameOptions) -> io::Result<Self> {
+        if !BLOCK_SIZES.contains(&options.block_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "LZ4 block size must be 64K, 256K, 1M or 4M",
+            ));
+        }
+        Ok(Self {
+            writer,
+            options,
+            buffer: Vec::with_capacity(options.block_size),
+            compressed: Vec::new(),
+            content: XxHash32::new(),
+            bytes_written: 0,
+            header_written: false,
+            finished: false,
+        })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Writes the magic and the frame descriptor: flags, block size id and
+    /// a header checksum, the second byte of their xxHash32.
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            let mut flags = VERSION | BLOCK_INDEPENDENCE;
+            if self.options.block_checksum {
+                flags |= BLOCK_CHECKSUM;
+            }
+            if self.options.content_checksum {
+                flags |= CONTENT_CHECKSUM;
+            }
+            let id = BLOCK_SIZES
+                .iter()
+                .position(|&size| size == self.options.block_size)
+                .unwrap() as u8;
+            let descriptor = [flags, (FIRST_BLOCK_SIZE_ID + id) << 4];
+
+            self.writer.write_all(&MAGIC)?;
+            self.writer.write_all(&descriptor)?;
+            self.writer
+                .write_all(&[(xxhash32(&descriptor) >> 8) as u8])?;
+            self.bytes_written += 7;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    /// Writes the buffered input as a block, stored as-is if compressing
+    /// does not shrink it.
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.content.update(&self.buffer);
+        self.compressed.clear();
+        block::compress(&self.buffer, &mut self.compressed);
+
+        let (data, size) = if self.compressed.len() < self.buffer.len() {
+            (&self.compressed, self.compressed.len() as u32)
+        } else {
+            (&self.buffer, self.buffer.len() as u32 | UNCOMPRESSED)
+        };
+        self.writer.write_all(&size.to_le_bytes())?;
+        self.writer.write_all(data)?;
+        self.bytes_written += 4 + data.len() as u64;
+        if self.options.block_checksum {
+            self.writer.write_all(&xxhash32(data).to_le_bytes())?;
+            self.bytes_written += 4;
+        }
+
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Writes the last block, the end mark and the content checksum. Does
+    /// nothing if the frame is already finished.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            self.write_header()?;
+            self.write_block()?;
+            self.writer.write_all(&0u32.to_le_bytes())?;
+            self.bytes_written += 4;
+            if self.options.content_checksum {
+                self.writer
+                    .write_all(&self.content.finish().to_le_bytes())?;
+                self.bytes_written += 4;
+            }
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for Lz4Encoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.write_header()?;
+        let n = data.len().min(self.options.block_size - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..n]);
+        if self.buffer.len() == self.options.block_size {
+            self.write_block()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// What the descriptor of the frame being read says.
+struct Frame {
+    block_max: usize,
+    independent: bool,
+    block_checksum: bool,
+    content_checksum: bool,
+    content_size: Option<u64>,
+}
+
+/// Decompresses LZ4 frames as they are read. Frames written one after the
+/// other decode to their concatenated data, and skippable frames are
+/// skipped.
+pub struct Lz4Decoder<R: Read> {
+    reader: R,
+    frame: Frame,
+    /// Data of the current block, after up to `HISTORY` bytes of the ones
+    /// before it for linked blocks to refer to.
+    buffer: Vec<u8>,
+    /// Bytes of `buffer` already returned.
+    pos: usize,
+    block: Vec<u8>,
+    content: XxHash32,
+    content_len: u64,
+    done: bool,
+}
+
+impl<R: Read> Lz4Decoder<R> {
+    /// Reads the first frame's header.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let frame = read_frame_header(&mut reader)?
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        Ok(Self {
+            reader,
+            frame,
+            buffer: Vec::new(),
+            pos: 0,
+            block: Vec::new(),
+            content: XxHash32::new(),
+            content_len: 0,
+            done: false,
+        })
+    }
+
+    /// Decodes the next block into `buffer`, or finishes the frame at its
+    /// end mark and moves on to the next one.
+    fn read_block(&mut self) -> io::Result<()> {
+        let size = read_u32_le(&mut self.reader)?;
+        if size == 0 {
+            return self.end_frame();
+        }
+
+        let len = (size & !UNCOMPRESSED) as usize;
+        if len > self.frame.block_max {
+            return Err(invalid_data("block larger than the maximum block size"));
+        }
+        self.block.resize(len, 0);
+        self.reader.read_exact(&mut self.block)?;
+        if self.frame.block_checksum {
+            let expected = read_u32_le(&mut self.reader)?;
+            checksum::verify(ChecksumKind::XxHash32, expected, xxhash32(&self.block))?;
+        }
+
+        if self.frame.independent {
+            self.buffer.clear();
+        } else if self.buffer.len() > HISTORY {
+            self.buffer.drain(..self.buffer.len() - HISTORY);
+        }
+        self.pos = self.buffer.len();
+        if size & UNCOMPRESSED != 0 {
+            self.buffer.extend_from_slice(&self.block);
+        } else {
+            block::decompress(&self.block, &mut self.buffer, self.frame.block_max)?;
+        }
+
+        self.content.update(&self.buffer[self.pos..]);
+        self.content_len += (self.buffer.len() - self.pos) as u64;
+        Ok(())
+    }
+
+    fn end_frame(&mut self) -> io::Result<()> {
+        if self.frame.content_checksum {
+            let expected = read_u32_le(&mut self.reader)?;
+            checksum::verify(ChecksumKind::XxHash32, expected, self.content.finish())?;
+        }
+        if self
+            .frame
+            .content_size
+            .is_some_and(|size| size != self.content_len)
+        {
+            return Err(invalid_data("content size mismatch"));
+        }
+
+        match read_frame_header(&mut self.reader)? {
+            Some(frame) => {
+                self.frame = frame;
+                self.buffer.clear();
+                self.pos = 0;
+                self.content = XxHash32::new();
+                self.content_len = 0;
+            }
+            None => self.done = true,
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Lz4Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buffer.len() && !self.done {
+            self.read_block()?;
+        }
+
+        let n = buf.len().min(self.buffer.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Reads frames up to the header of the next LZ4 frame, skipping skippable
+/// ones. None at the end of the input.
+fn read_frame_header<R: Read>(reader: &mut R) -> io::Result<Option<Frame>> {
+    match format::next_frame_magic(reader)? {
+        None => Ok(None),
+        Some(MAGIC) => read_descriptor(reader).map(Some),
+        Some(_) => Err(invalid_data("not an LZ4 frame")),
+    }
+}
+
+fn read_descriptor<R: Read>(reader: &mut R) -> io::Result<Frame> {
+    let mut descriptor = [0; 2 + 8];
+    reader.read_exact(&mut descriptor[..2])?;
+    let [flags, bd, ..] = descriptor;
+
+    if flags & VERSION_MASK != VERSION {
+        return Err(invalid_data("unsupported frame version"));
+    }
+    if flags & DICT_ID != 0 {
+        return Err(invalid_data("frames using a dictionary are not supported"));
+    }
+    let id = (bd >> 4) & 0x07;
+    if id < FIRST_BLOCK_SIZE_ID {
+        return Err(invalid_data("invalid block size"));
+    }
+
+    let mut len = 2;
+    let mut content_size = None;
+    if flags & CONTENT_SIZE != 0 {
+        reader.read_exact(&mut descriptor[2..10])?;
+        content_size = Some(u64::from_le_bytes(descriptor[2..10].try_into().unwrap()));
+        len = 10;
+    }
+    let mut checksum = [0; 1];
+    reader.read_exact(&mut checksum)?;
+    if checksum[0] != (xxhash32(&descriptor[..len]) >> 8) as u8 {
+        return Err(invalid_data("header checksum mismatch"));
+    }
+
+    Ok(Frame {
+        block_max: BLOCK_SIZES[(id - FIRST_BLOCK_SIZE_ID) as usize],
+        independent: flags & BLOCK_INDEPENDENCE != 0,
+        block_checksum: flags & BLOCK_CHECKSUM != 0,
+        content_checksum: flags & CONTENT_CHECKSUM != 0,
+        content_size,
+    })
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    format::invalid_data("LZ4", msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Codec;
+    use crate::deflate::tests::LOREM;
+
+    fn roundtrip(codec: Lz4Codec, data: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        let mut decoded = Vec::new();
+
+        codec.encode(data, &mut encoded).expect("Failed to encode");
+        codec
+            .decode(&mut encoded.as_slice(), &mut decoded)
+            .expect("Failed to decode");
+        decoded
+    }
+
+    #[test]
+    fn decodes_file_from_lz4() {
+        // Written by `lz4 -9 -BD -BX --content-size`: linked blocks, block
+        // checksums and the content size
+        let data = include_bytes!("testdata/lorem.txt.lz4");
+        let mut decoded = Vec::new();
+
+        Lz4Codec::default()
+            .decode(&mut data.as_slice(), &mut decoded)
+            .expect("Failed to decode");
+        assert_eq!(decoded, LOREM);
+    }
+
+    #[test]
+    fn encodes_and_decodes_frames() {
+        let codec = Lz4Codec::default();
+        assert_eq!(roundtrip(codec, LOREM), LOREM);
+        assert_eq!(roundtrip(codec, b""), b"");
+
+        let mut encoded = Vec::new();
+        codec.encode(b"", &mut encoded).unwrap();
+        // Magic, descriptor, end mark and content checksum
+        assert_eq!(encoded.len(), 4 + 3 + 4 + 4);
+        assert_eq!(encoded[..6], [0x04, 0x22, 0x4D, 0x18, 0x64, 0x70]);
+
+        // Several blocks, some of them incompressible, with every checksum
+        let codec = Lz4Codec::with_options(FrameOptions {
+            block_size: 64 << 10,
+            block_checksum: true,
+            content_checksum: true,
+        });
+        let mut data: Vec<u8> = (0..100_000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+            .collect();
+        data.extend(LOREM.repeat(20));
+        assert_eq!(roundtrip(codec, &data), data);
+    }
+
+    #[test]
+    fn decodes_concatenated_and_skippable_frames() {
+        let mut data = Vec::new();
+        Lz4Codec::default().encode(b"head", &mut data).unwrap();
+        data.extend_from_slice(&0x184D2A53u32.to_le_bytes());
+        data.extend_from_slice(&3u32.to_le_bytes());
+        data.extend_from_slice(b"xyz");
+        Lz4Codec::default().encode(b"tail", &mut data).unwrap();
+
+        let mut decoded = Vec::new();
+        Lz4Codec::default()
+            .decode(&mut data.as_slice(), &mut decoded)
+            .unwrap();
+        assert_eq!(decoded, b"headtail");
+    }
+
+    #[test]
+    fn detects_corruption() {
+        let codec = Lz4Codec::default();
+        let mut encoded = Vec::new();
+        codec.encode(LOREM, &mut encoded).unwrap();
+
+        let mut bad_header = encoded.clone();
+        bad_header[5] ^= 0x10;
+        assert!(codec
+            .decode(&mut bad_header.as_slice(), &mut Vec::new())
+            .is_err());
+
+        let last = encoded.len() - 1;
+        encoded[last] ^= 0x01;
+        let err = codec
+            .decode(&mut encoded.as_slice(), &mut Vec::new())
+            .unwrap_err();
+        assert!(err
+            .get_ref()
+            .is_some_and(|e| e.is::<checksum::ChecksumMismatch>()));
+
+        let invalid_size = FrameOptions {
+            block_size: 1000,
+            ..FrameOptions::default()
+        };
+        assert!(Lz4Codec::with_options(invalid_size)
+            .encode(b"a", &mut Vec::new())
+            .is_err());
+    }
+}
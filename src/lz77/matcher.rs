@@ -0,0 +1,143 @@
+/// Number of bits used for the hash table heads.
+const HASH_BITS: u32 = 15;
+
+/// Upper bound on how many chain entries are inspected per lookup. Keeps the
+/// search linear-ish on highly repetitive data at a small cost in ratio.
+const MAX_CHAIN: usize = 128;
+
+/// Marks an empty slot in `head`/`prev`.
+const NONE: usize = usize::MAX;
+
+/// Finds back-references in a sliding window using hash chains.
+///
+/// Every position is hashed on its first `min(min_match, 3)` bytes. `head`
+/// holds the most recent position for each hash and `prev` links each
+/// position to the previous one with the same hash, stored as a ring buffer
+/// the size of the window since older positions are out of reach anyway.
+pub struct MatchFinder<'a> {
+    data: &'a [u8],
+    window_size: usize,
+    hash_len: usize,
+    head: Vec<usize>,
+    prev: Vec<usize>,
+}
+
+impl<'a> MatchFinder<'a> {
+    pub fn new(data: &'a [u8], window_size: usize, min_match: usize) -> Self {
+        Self {
+            data,
+            window_size,
+            hash_len: min_match.min(3),
+            head: vec![NONE; 1 << HASH_BITS],
+            prev: vec![NONE; window_size],
+        }
+    }
+
+    /// Registers `pos` as a possible match start for later positions.
+    pub fn insert(&mut self, pos: usize) {
+        if let Some(h) = self.hash(pos) {
+            self.prev[pos % self.window_size] = self.head[h];
+            self.head[h] = pos;
+        }
+    }
+
+    /// Returns the `(distance, length)` of the longest match for the bytes at
+    /// `pos`, considering at most `max_len` bytes. `pos` itself must not have
+    /// been inserted yet.
+    pub fn longest_match(&self, pos: usize, max_len: usize) -> Option<(usize, usize)> {
+        let h = self.hash(pos)?;
+        let max_len = max_len.min(self.data.len() - pos);
+
+        let mut best: Option<(usize, usize)> = None;
+        let mut candidate = self.head[h];
+        let mut steps = 0;
+
+        while candidate != NONE && steps < MAX_CHAIN {
+            let distance = pos - candidate;
+            if distance > self.window_size {
+                break;
+            }
+
+            let len = self.match_length(candidate, pos, max_len);
+            if len > best.map_or(0, |(_, l)| l) {
+                best = Some((distance, len));
+                if len == max_len {
+                    break;
+                }
+            }
+
+            // The ring buffer may have been overwritten by a newer position,
+            // which shows up as the chain jumping forward. Stop there.
+            let next = self.prev[candidate % self.window_size];
+            if next == NONE || next >= candidate {
+                break;
+            }
+            candidate = next;
+            steps += 1;
+        }
+
+        best
+    }
+
+    fn match_length(&self, candidate: usize, pos: usize, max_len: usize) -> usize {
+        let mut len = 0;
+        while len < max_len && self.data[candidate + len] == self.data[pos + len] {
+            len += 1;
+        }
+        len
+    }
+
+    fn hash(&self, pos: usize) -> Option<usize> {
+        let bytes = self.data.get(pos..pos + self.hash_len)?;
+        let key = bytes.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32);
+
+        Some((key.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finder_at(data: &[u8], pos: usize) -> MatchFinder<'_> {
+        let mut finder = MatchFinder::new(data, 32, 3);
+        for i in 0..pos {
+            finder.insert(i);
+        }
+        finder
+    }
+
+    #[test]
+    fn finds_longest_previous_match() {
+        let data = b"abcXabcdYabcd";
+        let finder = finder_at(data, 9);
+
+        assert_eq!(finder.longest_match(9, 10), Some((5, 4)));
+    }
+
+    #[test]
+    fn finds_overlapping_match() {
+        let data = b"aaaaaaaa";
+        let finder = finder_at(data, 1);
+
+        assert_eq!(finder.longest_match(1, 10), Some((1, 7)));
+    }
+
+    #[test]
+    fn respects_window_size() {
+        let mut data = b"abc".to_vec();
+        data.extend(vec![b'x'; 40]);
+        data.extend(b"abc");
+        let finder = finder_at(&data, 43);
+
+        assert_eq!(finder.longest_match(43, 10), None);
+    }
+
+    #[test]
+    fn no_match_near_end_of_input() {
+        let data = b"abab";
+        let finder = finder_at(data, 3);
+
+        assert_eq!(finder.longest_match(3, 10), None);
+    }
+}
@@ -0,0 +1,479 @@
+use std::io::{self, Read, Write};
+
+pub(crate) mod matcher;
+
+use self::matcher::MatchFinder;
+
+/// Default distance a match may reach back into already seen data.
+pub const DEFAULT_WINDOW_SIZE: usize = 32 * 1024;
+
+/// Largest window allowed, which bounds the decoder's memory.
+pub const MAX_WINDOW_SIZE: usize = 1 << 24;
+
+/// Default shortest match worth emitting as a back-reference.
+pub const DEFAULT_MIN_MATCH: usize = 3;
+
+/// Longest match a single token can describe.
+pub const MAX_MATCH: usize = 258;
+
+/// Decoded bytes beyond the window written out at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+pub struct Lz77Codec {
+    pub window_size: usize,
+    pub min_match: usize,
+}
+
+impl Lz77Codec {
+    pub fn new(window_size: usize, min_match: usize) -> Self {
+        Self {
+            window_size,
+            min_match,
+        }
+    }
+}
+
+impl Default for Lz77Codec {
+    fn default() -> Self {
+        Self::new(DEFAULT_WINDOW_SIZE, DEFAULT_MIN_MATCH)
+    }
+}
+
+impl crate::codec::Codec for Lz77Codec {
+    fn name(&self) -> &'static str {
+        "lz77"
+    }
+
+    fn encode(&self, data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
+        encode(data, writer, self.window_size, self.min_match)
+    }
+
+    fn decode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+        decode(reader, writer)
+    }
+}
+
+/// A classic LZ77 triple: copy `length` bytes from `offset` bytes back, then
+/// emit `literal`. An offset of 0 means there is no back-reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token {
+    pub offset: usize,
+    pub length: usize,
+    pub literal: u8,
+}
+
+/// Encodes data as a sequence of LZ77 `(offset, length, literal)` tokens.
+///
+/// The payload starts with `window_size` (4 bytes), followed by the tokens.
+/// Each token is written as:
+/// - `offset` as a LEB128 varint (0 = no match)
+/// - `length` as a LEB128 varint, only present when `offset > 0`
+/// - `literal` as a single byte
+///
+/// Matches never run to the very end of the input so that every token can
+/// carry a literal. Empty input encodes to nothing. Returns the number of
+/// bits in the encoded output.
+pub fn encode(
+    data: &[u8],
+    writer: &mut dyn Write,
+    window_size: usize,
+    min_match: usize,
+) -> io::Result<u64> {
+    let tokens = tokenize(data, window_size, min_match)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+
+    writer.write_all(&(window_size as u32).to_be_bytes())?;
+    let mut total_bytes: u64 = 4;
+    for token in &tokens {
+        total_bytes += write_varint(writer, token.offset as u64)?;
+        if token.offset > 0 {
+            total_bytes += write_varint(writer, token.length as u64)?;
+        }
+        writer.write_all(&[token.literal])?;
+        total_bytes += 1;
+    }
+
+    Ok(total_bytes * 8)
+}
+
+/// Splits data into LZ77 tokens using a sliding window of `window_size` bytes
+/// and only emitting back-references of at least `min_match` bytes.
+pub fn tokenize(data: &[u8], window_size: usize, min_match: usize) -> io::Result<Vec<Token>> {
+    if !(1..=MAX_WINDOW_SIZE).contains(&window_size) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("LZ77 window size must be between 1 and {}", MAX_WINDOW_SIZE),
+        ));
+    }
+    if min_match == 0 || min_match > MAX_MATCH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "LZ77 minimum match length must be between 1 and {}",
+                MAX_MATCH
+            ),
+        ));
+    }
+
+    let mut finder = MatchFinder::new(data, window_size, min_match);
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < data.len() {
+        // Leave at least one byte behind the match for the literal
+        let max_len = (data.len() - i - 1).min(MAX_MATCH);
+
+        let token = match finder.longest_match(i, max_len) {
+            Some((offset, length)) if length >= min_match => Token {
+                offset,
+                length,
+                literal: data[i + length],
+            },
+            _ => Token {
+                offset: 0,
+                length: 0,
+                literal: data[i],
+            },
+        };
+
+        for pos in i..=i + token.length {
+            finder.insert(pos);
+        }
+        i += token.length + 1;
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// Decodes a stream of LZ77 tokens produced by `encode`, against the window
+/// size it records. Only the window is kept, earlier output is written out
+/// as decoding goes.
+///
+/// Returns the number of bytes written to output.
+pub fn decode(reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
+    if buf.is_empty() {
+        return Ok(0);
+    }
+
+    let header = buf.get(..4).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "LZ77 decode error: truncated header",
+        )
+    })?;
+    let window_size = u32::from_be_bytes(header.try_into().unwrap()) as usize;
+    if !(1..=MAX_WINDOW_SIZE).contains(&window_size) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "LZ77 decode error: invalid window size",
+        ));
+    }
+
+    // The window, followed by what is not written out yet
+    let mut output: Vec<u8> = Vec::new();
+    let mut bytes_written = 0;
+    let mut i = 4;
+
+    while i < buf.len() {
+        let offset = read_varint(&buf, &mut i)? as usize;
+        if offset > 0 {
+            let length = read_varint(&buf, &mut i)? as usize;
+            if length > MAX_MATCH {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "LZ77 decode error: match longer than the longest match",
+                ));
+            }
+            if offset > window_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "LZ77 decode error: offset points outside the window",
+                ));
+            }
+            if offset > output.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "LZ77 decode error: offset points before start of output",
+                ));
+            }
+
+            // Copy byte by byte, the match may overlap the bytes it produces
+            let start = output.len() - offset;
+            for k in 0..length {
+                output.push(output[start + k]);
+            }
+        }
+
+        match buf.get(i) {
+            Some(&literal) => output.push(literal),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "LZ77 decode error: truncated token",
+                ))
+            }
+        }
+        i += 1;
+
+        if output.len() >= window_size + CHUNK_SIZE {
+            let done = output.len() - window_size;
+            writer.write_all(&output[..done])?;
+            output.drain(..done);
+            bytes_written += done;
+        }
+    }
+
+    writer.write_all(&output)?;
+    Ok(bytes_written + output.len())
+}
+
+fn write_varint(writer: &mut dyn Write, mut value: u64) -> io::Result<u64> {
+    let mut written = 0;
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        written += 1;
+        if value == 0 {
+            writer.write_all(&[byte])?;
+            return Ok(written);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint(buf: &[u8], i: &mut usize) -> io::Result<u64> {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *buf.get(*i).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "LZ77 decode error: truncated token",
+            )
+        })?;
+        *i += 1;
+
+        if shift >= 64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "LZ77 decode error: varint too long",
+            ));
+        }
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(data: &[u8], window_size: usize, min_match: usize) -> Vec<u8> {
+        let mut enc_buf: Vec<u8> = Vec::new();
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        encode(data, &mut enc_buf, window_size, min_match).expect("Failed to encode");
+        decode(&mut enc_buf.as_slice(), &mut dec_buf).expect("Failed to decode");
+
+        dec_buf
+    }
+
+    /// Tokens behind the header `encode` writes for `window_size`.
+    fn with_window(window_size: usize, tokens: &[u8]) -> Vec<u8> {
+        let mut data = (window_size as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(tokens);
+        data
+    }
+
+    #[test]
+    fn encodes_and_decodes_simple_data() {
+        let data = b"abracadabra abracadabra abracadabra";
+
+        assert_eq!(
+            roundtrip(data, DEFAULT_WINDOW_SIZE, DEFAULT_MIN_MATCH),
+            data
+        );
+    }
+
+    #[test]
+    fn encodes_empty_input() {
+        let mut buf: Vec<u8> = Vec::new();
+        let bits = encode(b"", &mut buf, DEFAULT_WINDOW_SIZE, DEFAULT_MIN_MATCH)
+            .expect("Failed to encode");
+
+        assert_eq!(bits, 0);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn tokenizes_repeated_substring() {
+        let tokens = tokenize(b"abcabcabcX", DEFAULT_WINDOW_SIZE, 3).unwrap();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token {
+                    offset: 0,
+                    length: 0,
+                    literal: b'a'
+                },
+                Token {
+                    offset: 0,
+                    length: 0,
+                    literal: b'b'
+                },
+                Token {
+                    offset: 0,
+                    length: 0,
+                    literal: b'c'
+                },
+                Token {
+                    offset: 3,
+                    length: 6,
+                    literal: b'X'
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn encodes_to_correct_bytes() {
+        let mut buf: Vec<u8> = Vec::new();
+
+        // The window size, 3 literals, then (offset 3, length 6, 'X')
+        let bits = encode(b"abcabcabcX", &mut buf, 1000, 3).expect("Failed to encode");
+        assert_eq!(
+            buf,
+            vec![0, 0, 0x03, 0xE8, 0, b'a', 0, b'b', 0, b'c', 3, 6, b'X']
+        );
+        assert_eq!(bits, 104);
+    }
+
+    #[test]
+    fn ignores_matches_shorter_than_min_match() {
+        let tokens = tokenize(b"abcabcX", DEFAULT_WINDOW_SIZE, 4).unwrap();
+
+        assert!(tokens.iter().all(|t| t.offset == 0));
+    }
+
+    #[test]
+    fn ignores_matches_outside_window() {
+        let mut data = b"abcdefgh".to_vec();
+        data.extend((0..100u8).map(|b| b.wrapping_mul(37)));
+        data.extend(b"abcdefgh");
+
+        let tokens = tokenize(&data, 16, 3).unwrap();
+        assert!(tokens.iter().all(|t| t.offset <= 16));
+        assert_eq!(roundtrip(&data, 16, 3), data);
+
+        // Longer than the window and a chunk, so output is written as it goes
+        let data = data.repeat(2000);
+        assert_eq!(roundtrip(&data, 16, 3), data);
+    }
+
+    #[test]
+    fn encodes_and_decodes_binary_data() {
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+
+        assert_eq!(
+            roundtrip(&data, DEFAULT_WINDOW_SIZE, DEFAULT_MIN_MATCH),
+            data
+        );
+    }
+
+    #[test]
+    fn encodes_and_decodes_highly_repetitive_data() {
+        let data = vec![0x00; 10_000];
+        let mut enc_buf: Vec<u8> = Vec::new();
+
+        encode(&data, &mut enc_buf, DEFAULT_WINDOW_SIZE, DEFAULT_MIN_MATCH)
+            .expect("Failed to encode");
+        assert!(enc_buf.len() < 200);
+
+        assert_eq!(
+            roundtrip(&data, DEFAULT_WINDOW_SIZE, DEFAULT_MIN_MATCH),
+            data
+        );
+    }
+
+    #[test]
+    fn encodes_and_decodes_with_min_match_of_one() {
+        let data = b"abababbbaaab";
+
+        assert_eq!(roundtrip(data, 8, 1), data);
+    }
+
+    #[test]
+    fn rejects_invalid_parameters() {
+        let mut buf: Vec<u8> = Vec::new();
+
+        assert!(encode(b"abc", &mut buf, 0, DEFAULT_MIN_MATCH).is_err());
+        assert!(encode(b"abc", &mut buf, MAX_WINDOW_SIZE + 1, DEFAULT_MIN_MATCH).is_err());
+        assert!(encode(b"abc", &mut buf, DEFAULT_WINDOW_SIZE, 0).is_err());
+        assert!(encode(b"abc", &mut buf, DEFAULT_WINDOW_SIZE, MAX_MATCH + 1).is_err());
+    }
+
+    #[test]
+    fn decode_rejects_offset_before_start() {
+        // offset 5, length 2, literal 'a' with no prior output
+        let bad_data = with_window(DEFAULT_WINDOW_SIZE, &[5, 2, b'a']);
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        assert!(decode(&mut bad_data.as_slice(), &mut dec_buf).is_err());
+    }
+
+    #[test]
+    fn decode_rejects_offset_outside_window() {
+        // 20 literals, then offset 17 with a window of 16
+        let mut tokens: Vec<u8> = (0..20).flat_map(|b| [0, b]).collect();
+        tokens.extend([17, 3, b'a']);
+
+        let data = with_window(17, &tokens);
+        assert!(decode(&mut data.as_slice(), &mut Vec::new()).is_ok());
+        let bad_data = with_window(16, &tokens);
+        assert!(decode(&mut bad_data.as_slice(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn decode_rejects_invalid_window() {
+        for window_size in [0, MAX_WINDOW_SIZE + 1] {
+            let bad_data = with_window(window_size, &[0, b'a']);
+            let err = decode(&mut bad_data.as_slice(), &mut Vec::new()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let truncated = [0, 0, 1];
+        assert!(decode(&mut &truncated[..], &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn decode_rejects_match_longer_than_max() {
+        // A literal, then a match of 2^32 bytes at offset 1
+        let bad_data = with_window(
+            DEFAULT_WINDOW_SIZE,
+            &[0, b'a', 1, 0x80, 0x80, 0x80, 0x80, 0x10, b'a'],
+        );
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        let err = decode(&mut bad_data.as_slice(), &mut dec_buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(dec_buf.is_empty());
+    }
+
+    #[test]
+    fn decode_rejects_truncated_token() {
+        // offset 1, length 2, but the literal is missing
+        let bad_data = with_window(DEFAULT_WINDOW_SIZE, &[0, b'a', 1, 2]);
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        assert!(decode(&mut bad_data.as_slice(), &mut dec_buf).is_err());
+    }
+}
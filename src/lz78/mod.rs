@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use crate::deflate::bits::{BitReader, BitWriter};
+use crate::format;
+
+/// Default number of phrases the dictionary holds, so indices fit 16 bits.
+pub const DEFAULT_MAX_PHRASES: u32 = (1 << 16) - 1;
+
+/// Largest dictionary allowed, which bounds the decoder's memory.
+pub const MAX_PHRASES: u32 = 1 << 24;
+
+/// What happens once the dictionary holds `max_phrases` phrases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResetPolicy {
+    /// Empty the dictionary and start learning phrases again, which keeps
+    /// up with data that changes along the way.
+    #[default]
+    Clear,
+    /// Keep the phrases learned so far and stop adding new ones.
+    Freeze,
+}
+
+/// LZ78 compression: the data is split into phrases, each being the longest
+/// phrase already in the dictionary plus the byte that follows it. Every
+/// phrase is written as the index of that earlier phrase and the byte, and
+/// then added to the dictionary under the next index.
+///
+/// Unlike LZ77, references point into a dictionary of phrases rather than
+/// into a window of past data. LZW is LZ78 without the explicit byte.
+#[derive(Debug, Clone, Copy)]
+pub struct Lz78Codec {
+    /// Number of phrases the dictionary holds, from 1 to `MAX_PHRASES`.
+    pub max_phrases: u32,
+    pub reset: ResetPolicy,
+}
+
+impl Lz78Codec {
+    pub fn new(max_phrases: u32, reset: ResetPolicy) -> Self {
+        Self { max_phrases, reset }
+    }
+}
+
+impl Default for Lz78Codec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_PHRASES, ResetPolicy::default())
+    }
+}
+
+impl crate::codec::Codec for Lz78Codec {
+    fn name(&self) -> &'static str {
+        "lz78"
+    }
+
+    fn encode(&self, data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
+        encode(data, writer, self.max_phrases, self.reset)
+    }
+
+    fn decode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+        decode(reader, writer)
+    }
+}
+
+/// The phrase dictionary as a trie. Index 0 is the empty phrase at the
+/// root, every other node is its parent's phrase plus one byte.
+struct Trie {
+    /// Child of each node for each byte that follows it.
+    children: HashMap<(u32, u8), u32>,
+    /// Parent and last byte of each node but the root.
+    nodes: Vec<(u32, u8)>,
+}
+
+impl Trie {
+    fn new() -> Self {
+        Self {
+            children: HashMap::new(),
+            nodes: vec![(0, 0)],
+        }
+    }
+
+    /// Number of phrases, not counting the empty one.
+    fn phrases(&self) -> u32 {
+        self.nodes.len() as u32 - 1
+    }
+
+    fn child(&self, node: u32, byte: u8) -> Option<u32> {
+        self.children.get(&(node, byte)).copied()
+    }
+
+    fn insert(&mut self, node: u32, byte: u8) {
+        self.children.insert((node, byte), self.nodes.len() as u32);
+        self.nodes.push((node, byte));
+    }
+
+    fn clear(&mut self) {
+        self.children.clear();
+        self.nodes.truncate(1);
+    }
+
+    /// Adds the phrase just coded, or applies `reset` if the dictionary is
+    /// full. The decoder does the same after each phrase it decodes.
+    fn learn(&mut self, node: u32, byte: u8, max_phrases: u32, reset: ResetPolicy) {
+        if self.phrases() < max_phrases {
+            self.insert(node, byte);
+        } else if reset == ResetPolicy::Clear {
+            self.clear();
+        }
+    }
+}
+
+/// Bits needed for an index into a dictionary of `phrases` phrases plus the
+/// empty one: none before the first phrase is added.
+fn index_bits(phrases: u32) -> u8 {
+    (u32::BITS - phrases.leading_zeros()) as u8
+}
+
+/// Encodes data as LZ78 `(index, byte)` pairs.
+///
+/// The payload starts with the reset policy (1 byte, 0 to clear and 1 to
+/// freeze) and `max_phrases` (4 bytes), followed by the pairs, LSB first:
+/// the index in just enough bits for the current dictionary, then the byte
+/// in 8 bits. Data ending in the middle of a known phrase ends with the
+/// pair that phrase was added as. Empty input encodes to nothing.
+///
+/// Returns the number of bits written.
+pub fn encode(
+    data: &[u8],
+    writer: &mut dyn Write,
+    max_phrases: u32,
+    reset: ResetPolicy,
+) -> io::Result<u64> {
+    if !(1..=MAX_PHRASES).contains(&max_phrases) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "LZ78 dictionary size must be between 1 and {} phrases",
+                MAX_PHRASES
+            ),
+        ));
+    }
+    if data.is_empty() {
+        return Ok(0);
+    }
+
+    let mut bits = BitWriter::new(writer);
+    let policy = match reset {
+        ResetPolicy::Clear => 0,
+        ResetPolicy::Freeze => 1,
+    };
+    bits.write_bytes(&[policy])?;
+    bits.write_bytes(&max_phrases.to_be_bytes())?;
+
+    let mut trie = Trie::new();
+    let mut node = 0;
+    for &byte in data {
+        if let Some(child) = trie.child(node, byte) {
+            node = child;
+            continue;
+        }
+        bits.write_bits(node, index_bits(trie.phrases()))?;
+        bits.write_bits(byte as u32, 8)?;
+        trie.learn(node, byte, max_phrases, reset);
+        node = 0;
+    }
+    if node != 0 {
+        let (parent, byte) = trie.nodes[node as usize];
+        bits.write_bits(parent, index_bits(trie.phrases()))?;
+        bits.write_bits(byte as u32, 8)?;
+    }
+
+    bits.align_to_byte()?;
+    Ok(bits.bits_written())
+}
+
+/// Decodes data produced by `encode`, writing out each phrase as soon as
+/// it is decoded. Only the dictionary is kept.
+///
+/// Returns the number of bytes written to output.
+pub fn decode(reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+    let mut header = [0u8; 5];
+    match reader.read_exact(&mut header[..1]) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+        result => result?,
+    }
+    reader
+        .read_exact(&mut header[1..])
+        .map_err(|_| invalid_data("truncated header"))?;
+    let reset = match header[0] {
+        0 => ResetPolicy::Clear,
+        1 => ResetPolicy::Freeze,
+        _ => return Err(invalid_data("unknown reset policy")),
+    };
+    let max_phrases = u32::from_be_bytes(header[1..].try_into().unwrap());
+    if !(1..=MAX_PHRASES).contains(&max_phrases) {
+        return Err(invalid_data("invalid dictionary size"));
+    }
+
+    let mut bits = BitReader::new(reader);
+    let mut trie = Trie::new();
+    let mut bytes_written = 0;
+    let mut phrase = Vec::new();
+
+    // The padding after the last pair is always shorter than its byte
+    loop {
+        let index_bits = index_bits(trie.phrases());
+        let pair = bits
+            .read_bits(index_bits)
+            .and_then(|node| Ok((node, bits.read_bits(8)?)));
+        let (node, byte) = match pair {
+            Ok(pair) => pair,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        };
+        if node > trie.phrases() {
+            return Err(invalid_data("phrase not in the dictionary"));
+        }
+
+        // The trie gives the phrase from its last byte back
+        phrase.clear();
+        phrase.push(byte as u8);
+        let mut prefix = node;
+        while prefix != 0 {
+            let (parent, last) = trie.nodes[prefix as usize];
+            phrase.push(last);
+            prefix = parent;
+        }
+        phrase.reverse();
+        writer.write_all(&phrase)?;
+        bytes_written += phrase.len();
+
+        trie.learn(node, byte as u8, max_phrases, reset);
+    }
+
+    Ok(bytes_written)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    format::invalid_data("LZ78", msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::tests::LOREM;
+
+    fn roundtrip(data: &[u8], max_phrases: u32, reset: ResetPolicy) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        let mut decoded = Vec::new();
+
+        encode(data, &mut encoded, max_phrases, reset).expect("Failed to encode");
+        decode(&mut encoded.as_slice(), &mut decoded).expect("Failed to decode");
+        decoded
+    }
+
+    #[test]
+    fn encodes_and_decodes_text() {
+        assert_eq!(
+            roundtrip(LOREM, DEFAULT_MAX_PHRASES, ResetPolicy::Clear),
+            LOREM
+        );
+    }
+
+    #[test]
+    fn encodes_and_decodes_edge_cases() {
+        for data in [&b""[..], b"a", b"ab", &[b'x'; 1000]] {
+            assert_eq!(
+                roundtrip(data, DEFAULT_MAX_PHRASES, ResetPolicy::Clear),
+                data
+            );
+        }
+        // Ends in the middle of the phrase "ab"
+        assert_eq!(
+            roundtrip(b"abaa", DEFAULT_MAX_PHRASES, ResetPolicy::Clear),
+            b"abaa"
+        );
+    }
+
+    #[test]
+    fn splits_data_into_new_phrases() {
+        let mut encoded = Vec::new();
+        encode(
+            b"aababc",
+            &mut encoded,
+            DEFAULT_MAX_PHRASES,
+            ResetPolicy::Clear,
+        )
+        .unwrap();
+
+        // (0, a) in 0 + 8 bits, (1, b) in 1 + 8 bits, (2, c) in 2 + 8 bits
+        let mut bits = BitReader::new(&encoded[5..]);
+        assert_eq!(bits.read_bits(8).unwrap(), b'a' as u32);
+        assert_eq!(bits.read_bits(1).unwrap(), 1);
+        assert_eq!(bits.read_bits(8).unwrap(), b'b' as u32);
+        assert_eq!(bits.read_bits(2).unwrap(), 2);
+        assert_eq!(bits.read_bits(8).unwrap(), b'c' as u32);
+        assert_eq!(encoded.len(), 5 + 4);
+    }
+
+    #[test]
+    fn clears_or_freezes_a_full_dictionary() {
+        // With room for one phrase, "a" fills the dictionary and "aa" is
+        // coded against it, then the policies part ways
+        let mut cleared = Vec::new();
+        let mut frozen = Vec::new();
+        encode(b"aaaaaa", &mut cleared, 1, ResetPolicy::Clear).unwrap();
+        encode(b"aaaaaa", &mut frozen, 1, ResetPolicy::Freeze).unwrap();
+        assert_ne!(cleared[5..], frozen[5..]);
+
+        // Clearing starts over from "a": (0, a), (1, a), (0, a), (1, a),
+        // the third index taking no bits in the emptied dictionary
+        let mut bits = BitReader::new(&cleared[5..]);
+        assert_eq!(bits.read_bits(8).unwrap(), b'a' as u32);
+        for (index, index_bits) in [(1, 1), (0, 0), (1, 1)] {
+            assert_eq!(bits.read_bits(index_bits).unwrap(), index);
+            assert_eq!(bits.read_bits(8).unwrap(), b'a' as u32);
+        }
+
+        // Freezing keeps coding "aa" as (1, a) and never learns "aaa":
+        // (0, a), (1, a), (1, a), then the trailing "a" as (0, a)
+        let mut bits = BitReader::new(&frozen[5..]);
+        assert_eq!(bits.read_bits(8).unwrap(), b'a' as u32);
+        for index in [1, 1, 0] {
+            assert_eq!(bits.read_bits(1).unwrap(), index);
+            assert_eq!(bits.read_bits(8).unwrap(), b'a' as u32);
+        }
+
+        for reset in [ResetPolicy::Clear, ResetPolicy::Freeze] {
+            assert_eq!(roundtrip(b"aaaaaa", 1, reset), b"aaaaaa");
+        }
+    }
+
+    #[test]
+    fn limits_the_dictionary() {
+        // Text that changes halfway, so phrases learned from the first half
+        // stop matching
+        let mut data = LOREM.to_vec();
+        data.extend_from_slice(&LOREM.to_ascii_uppercase());
+
+        for reset in [ResetPolicy::Clear, ResetPolicy::Freeze] {
+            for max_phrases in [1, 256, 4096] {
+                assert_eq!(
+                    roundtrip(&data, max_phrases, reset),
+                    data,
+                    "{:?} {}",
+                    reset,
+                    max_phrases
+                );
+            }
+        }
+
+        // Clearing learns the phrases of the second half, freezing is stuck
+        // with the ones of the first
+        let mut cleared = Vec::new();
+        let mut frozen = Vec::new();
+        encode(&data, &mut cleared, 256, ResetPolicy::Clear).unwrap();
+        encode(&data, &mut frozen, 256, ResetPolicy::Freeze).unwrap();
+        assert!(cleared.len() < frozen.len());
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        assert!(encode(b"a", &mut Vec::new(), 0, ResetPolicy::Clear).is_err());
+        assert!(encode(b"a", &mut Vec::new(), MAX_PHRASES + 1, ResetPolicy::Clear).is_err());
+
+        let bad_policy = [2, 0, 0, 1, 0, b'a'];
+        assert!(decode(&mut &bad_policy[..], &mut Vec::new()).is_err());
+
+        // The third pair refers to phrase 3 when there are only 2
+        let mut bad_index = BitWriter::new(Vec::new());
+        bad_index.write_bytes(&[0, 0, 0, 1, 0]).unwrap();
+        for (index, index_bits, byte) in [(0, 0, b'a'), (1, 1, b'b'), (3, 2, b'c')] {
+            bad_index.write_bits(index, index_bits).unwrap();
+            bad_index.write_bits(byte as u32, 8).unwrap();
+        }
+        bad_index.align_to_byte().unwrap();
+        let bad_index = bad_index.into_inner();
+        let err = decode(&mut &bad_index[..], &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
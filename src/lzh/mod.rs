@@ -0,0 +1,353 @@
+use bitvec::prelude::*;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+pub(crate) mod symbols;
+
+use self::symbols::{distance_symbol, length_symbol, DISTANCE_CODES, END_OF_BLOCK, LENGTH_CODES};
+use crate::format;
+use crate::huffman::build_dictionary;
+use crate::huffman::tree::{HuffmanTree, Serializable};
+use crate::lz77::matcher::MatchFinder;
+
+/// Matches may reach back at most this far, the largest distance the
+/// distance alphabet can express.
+pub const WINDOW_SIZE: usize = 32 * 1024;
+
+pub const MIN_MATCH: usize = 3;
+pub const MAX_MATCH: usize = 258;
+
+/// Decoded bytes beyond the window written out at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// DEFLATE-style pipeline: LZ77 matching followed by Huffman coding of the
+/// resulting literals/lengths and distances with two separate alphabets.
+pub struct LzhCodec;
+
+impl crate::codec::Codec for LzhCodec {
+    fn name(&self) -> &'static str {
+        "lzh"
+    }
+
+    fn encode(&self, data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
+        encode(data, writer)
+    }
+
+    fn decode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+        decode(reader, writer)
+    }
+}
+
+/// Output of the LZ stage: either a plain byte or a back-reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+/// Splits data into literals and matches, using one step of lazy matching:
+/// a match is deferred when the next position has a longer one.
+pub fn tokenize(data: &[u8]) -> Vec<Token> {
+    let mut finder = MatchFinder::new(data, WINDOW_SIZE, MIN_MATCH);
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < data.len() {
+        let found = finder
+            .longest_match(i, MAX_MATCH)
+            .filter(|&(_, len)| len >= MIN_MATCH);
+        finder.insert(i);
+
+        let Some((distance, length)) = found else {
+            tokens.push(Token::Literal(data[i]));
+            i += 1;
+            continue;
+        };
+
+        let next_is_longer = finder
+            .longest_match(i + 1, MAX_MATCH)
+            .is_some_and(|(_, next_len)| next_len > length);
+        if next_is_longer {
+            tokens.push(Token::Literal(data[i]));
+            i += 1;
+            continue;
+        }
+
+        tokens.push(Token::Match {
+            length: length as u16,
+            distance: distance as u16,
+        });
+        for pos in i + 1..i + length {
+            finder.insert(pos);
+        }
+        i += length;
+    }
+
+    tokens
+}
+
+/// Encodes data with LZ77 + Huffman.
+///
+/// Output layout:
+/// 1. Canonical code lengths of the literal/length tree
+/// 2. Distance tree flag (1 byte, 0 when the input has no matches)
+/// 3. Canonical code lengths of the distance tree, if present
+/// 4. Bit stream of coded tokens, terminated by `END_OF_BLOCK` and padded
+///    with zeros to a full byte
+///
+/// Returns the number of bits in the token stream (excluding headers/padding).
+pub fn encode(data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
+    let tokens = tokenize(data);
+
+    let mut lit_counts: HashMap<u16, u64> = HashMap::from([(END_OF_BLOCK, 1)]);
+    let mut dist_counts: HashMap<u16, u64> = HashMap::new();
+    for token in &tokens {
+        match *token {
+            Token::Literal(byte) => *lit_counts.entry(byte as u16).or_default() += 1,
+            Token::Match { length, distance } => {
+                *lit_counts.entry(length_symbol(length).symbol).or_default() += 1;
+                *dist_counts
+                    .entry(distance_symbol(distance).symbol)
+                    .or_default() += 1;
+            }
+        }
+    }
+
+    let lit_tree = HuffmanTree::from_counts(&lit_counts).expect("always has END_OF_BLOCK");
+    let dist_tree = HuffmanTree::from_counts(&dist_counts);
+    let lit_dict = build_dictionary(&lit_tree);
+    let dist_dict = dist_tree.as_ref().map(build_dictionary).unwrap_or_default();
+
+    let mut bits: BitVec<u8, Lsb0> = BitVec::with_capacity(data.len() * 4);
+    for token in &tokens {
+        match *token {
+            Token::Literal(byte) => bits.extend_from_bitslice(&lit_dict[&(byte as u16)]),
+            Token::Match { length, distance } => {
+                let len = length_symbol(length);
+                bits.extend_from_bitslice(&lit_dict[&len.symbol]);
+                push_bits(&mut bits, len.extra_value, len.extra_bits);
+
+                let dist = distance_symbol(distance);
+                bits.extend_from_bitslice(&dist_dict[&dist.symbol]);
+                push_bits(&mut bits, dist.extra_value, dist.extra_bits);
+            }
+        }
+    }
+    bits.extend_from_bitslice(&lit_dict[&END_OF_BLOCK]);
+    let num_bits = bits.len();
+
+    lit_tree.serialize(writer)?;
+    match &dist_tree {
+        Some(tree) => {
+            writer.write_all(&[1])?;
+            tree.serialize(writer)?;
+        }
+        None => writer.write_all(&[0])?,
+    }
+    writer.write_all(&bits.into_vec())?;
+
+    Ok(num_bits as u64)
+}
+
+/// Decodes data produced by `encode`. Only the window is kept, earlier
+/// output is written out as decoding goes.
+///
+/// Returns the number of bytes written to output.
+pub fn decode(reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+    let lit_tree = HuffmanTree::<u16>::deserialize(reader)?;
+
+    let mut flag = [0; 1];
+    reader.read_exact(&mut flag)?;
+    let dist_tree = match flag[0] {
+        0 => None,
+        1 => Some(HuffmanTree::<u16>::deserialize(reader)?),
+        _ => return Err(invalid_data("invalid distance tree flag")),
+    };
+
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer)?;
+    let payload = BitVec::<u8, Lsb0>::from_vec(buffer);
+    let mut bits = payload.iter().by_vals();
+
+    // The window, followed by what is not written out yet
+    let mut output: Vec<u8> = Vec::new();
+    let mut bytes_written = 0;
+    loop {
+        if output.len() >= WINDOW_SIZE + CHUNK_SIZE {
+            let done = output.len() - WINDOW_SIZE;
+            writer.write_all(&output[..done])?;
+            output.drain(..done);
+            bytes_written += done;
+        }
+
+        let symbol = lit_tree
+            .read_symbol(&mut bits)
+            .ok_or_else(|| invalid_data("unexpected end of data"))?;
+
+        if symbol < END_OF_BLOCK {
+            output.push(symbol as u8);
+            continue;
+        }
+        if symbol == END_OF_BLOCK {
+            break;
+        }
+
+        let (base, extra) = *LENGTH_CODES
+            .get((symbol - END_OF_BLOCK - 1) as usize)
+            .ok_or_else(|| invalid_data("invalid length symbol"))?;
+        let length = base as usize + read_bits(&mut bits, extra)? as usize;
+
+        let dist_symbol = dist_tree
+            .as_ref()
+            .ok_or_else(|| invalid_data("match without distance tree"))?
+            .read_symbol(&mut bits)
+            .ok_or_else(|| invalid_data("unexpected end of data"))?;
+        let (base, extra) = *DISTANCE_CODES
+            .get(dist_symbol as usize)
+            .ok_or_else(|| invalid_data("invalid distance symbol"))?;
+        let distance = base as usize + read_bits(&mut bits, extra)? as usize;
+
+        if distance > output.len() {
+            return Err(invalid_data("distance points before start of output"));
+        }
+        let start = output.len() - distance;
+        for k in 0..length {
+            output.push(output[start + k]);
+        }
+    }
+
+    writer.write_all(&output)?;
+    Ok(bytes_written + output.len())
+}
+
+/// Appends the low `count` bits of `value`, least significant first.
+fn push_bits(bits: &mut BitVec<u8, Lsb0>, value: u16, count: u8) {
+    for k in 0..count {
+        bits.push((value >> k) & 1 == 1);
+    }
+}
+
+fn read_bits<I: Iterator<Item = bool>>(bits: &mut I, count: u8) -> io::Result<u16> {
+    let mut value = 0;
+    for k in 0..count {
+        let bit = bits
+            .next()
+            .ok_or_else(|| invalid_data("unexpected end of data"))?;
+        value |= (bit as u16) << k;
+    }
+    Ok(value)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    format::invalid_data("LZH", msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(data: &[u8]) -> Vec<u8> {
+        let mut enc_buf: Vec<u8> = Vec::new();
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        encode(data, &mut enc_buf).expect("Failed to encode");
+        decode(&mut enc_buf.as_slice(), &mut dec_buf).expect("Failed to decode");
+
+        dec_buf
+    }
+
+    #[test]
+    fn tokenizes_repeated_substring() {
+        assert_eq!(
+            tokenize(b"abcabcabcX"),
+            vec![
+                Token::Literal(b'a'),
+                Token::Literal(b'b'),
+                Token::Literal(b'c'),
+                Token::Match {
+                    length: 6,
+                    distance: 3
+                },
+                Token::Literal(b'X'),
+            ]
+        );
+    }
+
+    #[test]
+    fn defers_match_when_next_one_is_longer() {
+        // At the second "ab" only "abc" (3) matches, one byte later "bcde" (4) does
+        let tokens = tokenize(b"abcXbcdeYabcde");
+
+        assert_eq!(tokens[9], Token::Literal(b'a'));
+        assert_eq!(
+            tokens[10],
+            Token::Match {
+                length: 4,
+                distance: 6
+            }
+        );
+    }
+
+    #[test]
+    fn encodes_and_decodes_simple_data() {
+        let data = b"abracadabra abracadabra abracadabra";
+
+        assert_eq!(roundtrip(data), data);
+    }
+
+    #[test]
+    fn encodes_and_decodes_empty_input() {
+        assert_eq!(roundtrip(b""), b"");
+    }
+
+    #[test]
+    fn encodes_and_decodes_without_matches() {
+        assert_eq!(roundtrip(b"abcdefg"), b"abcdefg");
+    }
+
+    #[test]
+    fn encodes_and_decodes_binary_data() {
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+
+        assert_eq!(roundtrip(&data), data);
+    }
+
+    #[test]
+    fn encodes_and_decodes_long_runs_and_far_matches() {
+        let mut data = vec![0x00; 10_000];
+        data.extend((0..20_000u32).map(|i| (i * 7 % 251) as u8));
+        data.extend(vec![0x00; 300]);
+
+        assert_eq!(roundtrip(&data), data);
+
+        // Longer than the window and a chunk, so output is written as it goes
+        let data = data.repeat(4);
+        assert_eq!(roundtrip(&data), data);
+    }
+
+    #[test]
+    fn compresses_better_than_lz77_on_repetitive_text() {
+        let data: Vec<u8> = (0..2000)
+            .flat_map(|i| format!("GET /page/{} 200 {}ms\n", i % 37, i * 13 % 1000).into_bytes())
+            .collect();
+        let mut lzh_buf: Vec<u8> = Vec::new();
+        let mut lz77_buf: Vec<u8> = Vec::new();
+
+        encode(&data, &mut lzh_buf).expect("Failed to encode");
+        crate::lz77::encode(&data, &mut lz77_buf, WINDOW_SIZE, MIN_MATCH)
+            .expect("Failed to encode");
+
+        assert!(lzh_buf.len() < lz77_buf.len());
+    }
+
+    #[test]
+    fn decode_rejects_truncated_data() {
+        let mut enc_buf: Vec<u8> = Vec::new();
+        let mut dec_buf: Vec<u8> = Vec::new();
+
+        encode(b"abracadabra abracadabra", &mut enc_buf).expect("Failed to encode");
+        enc_buf.truncate(enc_buf.len() - 2);
+
+        assert!(decode(&mut enc_buf.as_slice(), &mut dec_buf).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+//! Length and distance alphabets shared by the LZ + Huffman codecs.
+//!
+//! These are the tables from RFC 1951 (DEFLATE): a match length or distance
+//! is sent as a Huffman-coded symbol that selects a base value, followed by a
+//! few raw "extra bits" that are added to the base.
+
+/// Marks the end of the token stream in the literal/length alphabet.
+pub const END_OF_BLOCK: u16 = 256;
+
+/// First symbol of the length codes in the literal/length alphabet.
+pub const FIRST_LENGTH_SYMBOL: u16 = 257;
+
+/// `(base length, extra bits)` for length symbols 257..=285.
+pub const LENGTH_CODES: [(u16, u8); 29] = [
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 0),
+    (7, 0),
+    (8, 0),
+    (9, 0),
+    (10, 0),
+    (11, 1),
+    (13, 1),
+    (15, 1),
+    (17, 1),
+    (19, 2),
+    (23, 2),
+    (27, 2),
+    (31, 2),
+    (35, 3),
+    (43, 3),
+    (51, 3),
+    (59, 3),
+    (67, 4),
+    (83, 4),
+    (99, 4),
+    (115, 4),
+    (131, 5),
+    (163, 5),
+    (195, 5),
+    (227, 5),
+    (258, 0),
+];
+
+/// `(base distance, extra bits)` for distance symbols 0..=29.
+pub const DISTANCE_CODES: [(u16, u8); 30] = [
+    (1, 0),
+    (2, 0),
+    (3, 0),
+    (4, 0),
+    (5, 1),
+    (7, 1),
+    (9, 2),
+    (13, 2),
+    (17, 3),
+    (25, 3),
+    (33, 4),
+    (49, 4),
+    (65, 5),
+    (97, 5),
+    (129, 6),
+    (193, 6),
+    (257, 7),
+    (385, 7),
+    (513, 8),
+    (769, 8),
+    (1025, 9),
+    (1537, 9),
+    (2049, 10),
+    (3073, 10),
+    (4097, 11),
+    (6145, 11),
+    (8193, 12),
+    (12289, 12),
+    (16385, 13),
+    (24577, 13),
+];
+
+/// A symbol plus the extra bits needed to reconstruct the exact value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Coded {
+    pub symbol: u16,
+    pub extra_bits: u8,
+    pub extra_value: u16,
+}
+
+/// Maps a match length (3..=258) to its literal/length symbol.
+pub fn length_symbol(length: u16) -> Coded {
+    let index = LENGTH_CODES
+        .iter()
+        .rposition(|&(base, _)| base <= length)
+        .expect("match length below minimum");
+    let (base, extra_bits) = LENGTH_CODES[index];
+
+    Coded {
+        symbol: FIRST_LENGTH_SYMBOL + index as u16,
+        extra_bits,
+        extra_value: length - base,
+    }
+}
+
+/// Maps a match distance (1..=32768) to its distance symbol.
+pub fn distance_symbol(distance: u16) -> Coded {
+    let index = DISTANCE_CODES
+        .iter()
+        .rposition(|&(base, _)| base <= distance)
+        .expect("match distance below minimum");
+    let (base, extra_bits) = DISTANCE_CODES[index];
+
+    Coded {
+        symbol: index as u16,
+        extra_bits,
+        extra_value: distance - base,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_lengths_to_symbols() {
+        assert_eq!(
+            length_symbol(3),
+            Coded {
+                symbol: 257,
+                extra_bits: 0,
+                extra_value: 0
+            }
+        );
+        assert_eq!(
+            length_symbol(12),
+            Coded {
+                symbol: 265,
+                extra_bits: 1,
+                extra_value: 1
+            }
+        );
+        assert_eq!(
+            length_symbol(257),
+            Coded {
+                symbol: 284,
+                extra_bits: 5,
+                extra_value: 30
+            }
+        );
+        assert_eq!(
+            length_symbol(258),
+            Coded {
+                symbol: 285,
+                extra_bits: 0,
+                extra_value: 0
+            }
+        );
+    }
+
+    #[test]
+    fn maps_distances_to_symbols() {
+        assert_eq!(
+            distance_symbol(1),
+            Coded {
+                symbol: 0,
+                extra_bits: 0,
+                extra_value: 0
+            }
+        );
+        assert_eq!(
+            distance_symbol(6),
+            Coded {
+                symbol: 4,
+                extra_bits: 1,
+                extra_value: 1
+            }
+        );
+        assert_eq!(
+            distance_symbol(32768),
+            Coded {
+                symbol: 29,
+                extra_bits: 13,
+                extra_value: 8191
+            }
+        );
+    }
+}
@@ -0,0 +1,147 @@
+//! Decodes LZMA symbols into a dictionary: literals, matches at a new
+//! distance, and matches at one of the last four distances.
+
+use std::io::{self, BufRead};
+
+use super::invalid_data;
+use super::model::{self, Model, Properties, END_MARKER, LITERAL_STATES};
+use super::rangecoder::RangeDecoder;
+
+pub struct LzDecoder {
+    model: Model,
+    dict_size: usize,
+    state: usize,
+    /// The last four distances, minus one.
+    reps: [u32; 4],
+    /// Decoded data, the last `dict_size` bytes of which matches may copy.
+    pub output: Vec<u8>,
+    /// Bytes decoded since the dictionary was last reset.
+    position: u64,
+}
+
+impl LzDecoder {
+    pub fn new(props: Properties, dict_size: usize) -> Self {
+        Self {
+            model: Model::new(props),
+            dict_size,
+            state: 0,
+            reps: [0; 4],
+            output: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Forgets what was learned about the data, but not the data itself.
+    pub fn reset_state(&mut self, props: Properties) {
+        self.model = Model::new(props);
+        self.state = 0;
+        self.reps = [0; 4];
+    }
+
+    /// Forgets the data, so that matches cannot refer to it.
+    pub fn reset_dict(&mut self) {
+        self.position = 0;
+    }
+
+    pub fn props(&self) -> Properties {
+        self.model.props()
+    }
+
+    /// Drops data matches can no longer reach, once there is plenty of it.
+    /// Call only once all of `output` was used.
+    pub fn trim(&mut self) {
+        if self.output.len() > self.dict_size.saturating_mul(2) {
+            self.output.drain(..self.output.len() - self.dict_size);
+        }
+    }
+
+    pub fn append_uncompressed(&mut self, data: &[u8]) {
+        self.output.extend_from_slice(data);
+        self.position += data.len() as u64;
+    }
+
+    /// Decodes one symbol onto `output`. Returns false at the end of stream
+    /// marker.
+    pub fn decode_symbol<R: BufRead>(&mut self, rc: &mut RangeDecoder<R>) -> io::Result<bool> {
+        let position = self.position;
+        let index = self.model.state_index(self.state, position);
+
+        if rc.bit(&mut self.model.is_match[index])? == 0 {
+            let prev_byte = match position {
+                0 => 0,
+                _ => self.output[self.output.len() - 1],
+            };
+            let match_byte = if self.state >= LITERAL_STATES {
+                let dist = self.check_distance()?;
+                Some(self.output[self.output.len() - dist])
+            } else {
+                None
+            };
+            let byte = self
+                .model
+                .decode_literal(rc, position, prev_byte, match_byte)?;
+            self.output.push(byte);
+            self.position += 1;
+            self.state = model::after_literal(self.state);
+            return Ok(true);
+        }
+
+        let pos_state = self.model.pos_state(position);
+        let len = if rc.bit(&mut self.model.is_rep[self.state])? == 0 {
+            let len = self.model.len.decode(rc, pos_state)?;
+            let dist = self.model.decode_distance(rc, len)?;
+            if dist == END_MARKER {
+                return Ok(false);
+            }
+            self.reps = [dist, self.reps[0], self.reps[1], self.reps[2]];
+            self.state = model::after_match(self.state);
+            len
+        } else {
+            if rc.bit(&mut self.model.is_rep0[self.state])? == 0 {
+                if rc.bit(&mut self.model.is_rep0_long[index])? == 0 {
+                    // A single byte at the last distance
+                    self.state = model::after_short_rep(self.state);
+                    self.copy_match(1)?;
+                    return Ok(true);
+                }
+            } else {
+                let [rep0, rep1, rep2, rep3] = self.reps;
+                self.reps = if rc.bit(&mut self.model.is_rep1[self.state])? == 0 {
+                    [rep1, rep0, rep2, rep3]
+                } else if rc.bit(&mut self.model.is_rep2[self.state])? == 0 {
+                    [rep2, rep0, rep1, rep3]
+                } else {
+                    [rep3, rep0, rep1, rep2]
+                };
+            }
+            self.state = model::after_rep(self.state);
+            self.model.rep_len.decode(rc, pos_state)?
+        };
+
+        self.copy_match(len)?;
+        Ok(true)
+    }
+
+    /// The last distance, if it stays within the dictionary.
+    fn check_distance(&self) -> io::Result<usize> {
+        let dist = self.reps[0] as usize + 1;
+        if dist as u64 > self.position || dist > self.dict_size {
+            return Err(invalid_data("match distance beyond the dictionary"));
+        }
+        Ok(dist)
+    }
+
+    /// Appends `len` bytes from the last distance back.
+    fn copy_match(&mut self, len: usize) -> io::Result<()> {
+        let dist = self.check_distance()?;
+        let start = self.output.len() - dist;
+        let mut copied = 0;
+        while copied < len {
+            let n = (len - copied).min(dist + copied);
+            self.output.extend_from_within(start..start + n);
+            copied += n;
+        }
+        self.position += len as u64;
+        Ok(())
+    }
+}
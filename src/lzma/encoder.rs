@@ -0,0 +1,319 @@
+//! Encodes data as LZMA symbols. Matches are found with hash chains over the
+//! last `dict_size` bytes and chosen greedily with one step of lookahead,
+//! like the fast mode of the reference encoder.
+
+use super::model::{self, Model, Properties, END_MARKER, LITERAL_STATES, MATCH_LEN_MAX};
+use super::rangecoder::RangeEncoder;
+
+const HASH_BITS: u32 = 20;
+/// How many earlier positions with the same hash are tried.
+const SEARCH_DEPTH: usize = 48;
+/// Matches at least this long are taken without looking further.
+const NICE_LEN: usize = 64;
+/// Bytes that must follow a position before it is encoded, so that the
+/// longest match and the lookahead after it are known.
+const LOOKAHEAD: usize = MATCH_LEN_MAX + 1;
+
+pub struct LzEncoder {
+    model: Model,
+    rc: RangeEncoder,
+    dict_size: usize,
+    state: usize,
+    /// The last four distances, minus one.
+    reps: [u32; 4],
+    /// Input from position `base` on: the dictionary, then what is not
+    /// encoded yet.
+    window: Vec<u8>,
+    base: u64,
+    /// Position of the next byte to encode.
+    pos: u64,
+    /// Next position to add to the hash chains.
+    hashed: u64,
+    /// Last position (truncated to 32 bits) with each hash, and for each
+    /// position the one before it with the same hash.
+    head: Vec<u32>,
+    chain: Vec<u32>,
+    /// Matches found at a position, each longer than the one before.
+    matches: Vec<(usize, usize)>,
+}
+
+impl LzEncoder {
+    pub fn new(dict_size: usize) -> Self {
+        Self {
+            model: Model::new(Properties::DEFAULT),
+            rc: RangeEncoder::new(),
+            dict_size,
+            state: 0,
+            reps: [0; 4],
+            window: Vec::new(),
+            base: 0,
+            pos: 0,
+            hashed: 0,
+            head: vec![0; 1 << HASH_BITS],
+            chain: vec![0; dict_size],
+            matches: Vec::new(),
+        }
+    }
+
+    /// Encoded bytes, to be taken by the caller.
+    pub fn output(&mut self) -> &mut Vec<u8> {
+        &mut self.rc.output
+    }
+
+    /// Adds data and encodes what can be encoded without seeing more.
+    pub fn write(&mut self, data: &[u8]) {
+        let history = (self.pos - self.base) as usize;
+        if history > 2 * self.dict_size {
+            let drop = history - self.dict_size;
+            self.window.drain(..drop);
+            self.base += drop as u64;
+            // Long repeated matches skip the hash chains
+            self.hashed = self.hashed.max(self.base);
+        }
+        self.window.extend_from_slice(data);
+
+        let end = self.end().saturating_sub(LOOKAHEAD as u64);
+        self.encode_until(end);
+    }
+
+    /// Encodes the rest of the data and the end of stream marker.
+    pub fn finish(&mut self) {
+        self.encode_until(self.end());
+
+        let index = self.model.state_index(self.state, self.pos);
+        let pos_state = self.model.pos_state(self.pos);
+        self.rc.bit(&mut self.model.is_match[index], 1);
+        self.rc.bit(&mut self.model.is_rep[self.state], 0);
+        self.model
+            .len
+            .encode(&mut self.rc, model::MATCH_LEN_MIN, pos_state);
+        self.model
+            .encode_distance(&mut self.rc, model::MATCH_LEN_MIN, END_MARKER);
+        self.rc.finish();
+    }
+
+    fn end(&self) -> u64 {
+        self.base + self.window.len() as u64
+    }
+
+    fn encode_until(&mut self, end: u64) {
+        while self.pos < end {
+            let avail = (self.end() - self.pos) as usize;
+            let (len, rep) = self.choose(avail);
+            match rep {
+                _ if len < 2 => self.encode_literal(),
+                Some(index) => self.encode_rep(index, len),
+                None => {
+                    let dist = self.matches.last().unwrap().1;
+                    self.encode_match(len, dist);
+                }
+            }
+            self.pos += len.max(1) as u64;
+        }
+    }
+
+    /// Picks what to encode at `pos`: a literal (length 1), a repeated match
+    /// with the index of its distance, or the last of `matches`.
+    fn choose(&mut self, avail: usize) -> (usize, Option<usize>) {
+        if avail < 2 {
+            return (1, None);
+        }
+        let limit = avail.min(MATCH_LEN_MAX);
+        let nice_len = NICE_LEN.min(limit);
+
+        let mut rep_len = 0;
+        let mut rep_index = 0;
+        for i in 0..4 {
+            let len = self.match_len(self.pos, self.reps[i] as usize + 1, limit);
+            if len >= nice_len {
+                return (len, Some(i));
+            }
+            if len > rep_len {
+                rep_len = len;
+                rep_index = i;
+            }
+        }
+
+        self.find_matches(self.pos, limit);
+        let (mut main_len, mut main_dist) = self.matches.last().copied().unwrap_or((0, 0));
+        if main_len >= nice_len {
+            return (main_len, None);
+        }
+        // A slightly shorter match is better if it is much closer
+        while let [.., (len, dist), _] = self.matches[..] {
+            if len + 1 != main_len || !far_closer(dist, main_dist) {
+                break;
+            }
+            self.matches.pop();
+            (main_len, main_dist) = (len, dist);
+        }
+
+        if rep_len >= 2
+            && (rep_len + 1 >= main_len
+                || (rep_len + 2 >= main_len && main_dist > 1 << 9)
+                || (rep_len + 3 >= main_len && main_dist > 1 << 15))
+        {
+            return (rep_len, Some(rep_index));
+        }
+        if main_len < 3 || avail <= 3 {
+            return (1, None);
+        }
+
+        // Prefer a literal if a better match starts at the next byte
+        let matches = std::mem::take(&mut self.matches);
+        self.find_matches(self.pos + 1, limit - 1);
+        let (next_len, next_dist) = self.matches.last().copied().unwrap_or((0, 0));
+        self.matches = matches;
+        if next_len >= 3
+            && ((next_len >= main_len && next_dist < main_dist)
+                || (next_len == main_len + 1 && !far_closer(main_dist, next_dist))
+                || next_len > main_len + 1
+                || (next_len + 1 >= main_len && far_closer(next_dist, main_dist)))
+        {
+            return (1, None);
+        }
+        let rep_limit = (main_len - 1).max(2);
+        for i in 0..4 {
+            let dist = self.reps[i] as usize + 1;
+            if self.match_len(self.pos + 1, dist, rep_limit) == rep_limit {
+                return (1, None);
+            }
+        }
+
+        (main_len, None)
+    }
+
+    /// Length of the match at `pos` with the data `dist` bytes before it, up
+    /// to `limit`.
+    fn match_len(&self, pos: u64, dist: usize, limit: usize) -> usize {
+        let cur = (pos - self.base) as usize;
+        if dist > cur || dist > self.dict_size {
+            return 0;
+        }
+        let data = &self.window[cur..cur + limit];
+        let earlier = &self.window[cur - dist..];
+        data.iter().zip(earlier).take_while(|(a, b)| a == b).count()
+    }
+
+    fn hash(&self, pos: u64) -> usize {
+        let cur = (pos - self.base) as usize;
+        let bytes = &self.window[cur..cur + 3];
+        let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]);
+        (value.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    }
+
+    /// Adds the positions before `pos` to the hash chains.
+    fn insert_until(&mut self, pos: u64) {
+        while self.hashed < pos && self.hashed + 3 <= self.end() {
+            let hash = self.hash(self.hashed);
+            self.chain[self.hashed as usize % self.dict_size] = self.head[hash];
+            self.head[hash] = self.hashed as u32;
+            self.hashed += 1;
+        }
+    }
+
+    /// Fills `matches` with the matches of at least 3 bytes at `pos`, up to
+    /// `limit` bytes long.
+    fn find_matches(&mut self, pos: u64, limit: usize) {
+        self.matches.clear();
+        self.insert_until(pos);
+        if limit < 3 {
+            return;
+        }
+
+        let cur = (pos - self.base) as usize;
+        let mut candidate = self.head[self.hash(pos)];
+        let mut best = 2;
+        let mut prev_dist = 0;
+        for _ in 0..SEARCH_DEPTH {
+            // Chains may lead to positions overwritten since, or too far
+            let dist = (pos as u32).wrapping_sub(candidate) as usize;
+            if dist <= prev_dist || dist > cur || dist > self.dict_size {
+                break;
+            }
+            prev_dist = dist;
+
+            if self.window[cur - dist + best] == self.window[cur + best] {
+                let len = self.match_len(pos, dist, limit);
+                if len > best {
+                    best = len;
+                    self.matches.push((len, dist));
+                    if len >= NICE_LEN || len == limit {
+                        break;
+                    }
+                }
+            }
+            candidate = self.chain[candidate as usize % self.dict_size];
+        }
+    }
+
+    fn encode_literal(&mut self) {
+        let cur = (self.pos - self.base) as usize;
+        let index = self.model.state_index(self.state, self.pos);
+        self.rc.bit(&mut self.model.is_match[index], 0);
+
+        let prev_byte = if self.pos == 0 {
+            0
+        } else {
+            self.window[cur - 1]
+        };
+        let match_byte =
+            (self.state >= LITERAL_STATES).then(|| self.window[cur - self.reps[0] as usize - 1]);
+        self.model.encode_literal(
+            &mut self.rc,
+            self.pos,
+            prev_byte,
+            match_byte,
+            self.window[cur],
+        );
+        self.state = model::after_literal(self.state);
+    }
+
+    fn encode_match(&mut self, len: usize, dist: usize) {
+        let index = self.model.state_index(self.state, self.pos);
+        let pos_state = self.model.pos_state(self.pos);
+        self.rc.bit(&mut self.model.is_match[index], 1);
+        self.rc.bit(&mut self.model.is_rep[self.state], 0);
+        self.model.len.encode(&mut self.rc, len, pos_state);
+
+        let dist = dist as u32 - 1;
+        self.model.encode_distance(&mut self.rc, len, dist);
+        self.reps = [dist, self.reps[0], self.reps[1], self.reps[2]];
+        self.state = model::after_match(self.state);
+    }
+
+    fn encode_rep(&mut self, rep: usize, len: usize) {
+        let index = self.model.state_index(self.state, self.pos);
+        let pos_state = self.model.pos_state(self.pos);
+        self.rc.bit(&mut self.model.is_match[index], 1);
+        self.rc.bit(&mut self.model.is_rep[self.state], 1);
+
+        let [rep0, rep1, rep2, rep3] = self.reps;
+        if rep == 0 {
+            self.rc.bit(&mut self.model.is_rep0[self.state], 0);
+            self.rc.bit(&mut self.model.is_rep0_long[index], 1);
+        } else {
+            self.rc.bit(&mut self.model.is_rep0[self.state], 1);
+            if rep == 1 {
+                self.rc.bit(&mut self.model.is_rep1[self.state], 0);
+                self.reps = [rep1, rep0, rep2, rep3];
+            } else {
+                self.rc.bit(&mut self.model.is_rep1[self.state], 1);
+                self.rc
+                    .bit(&mut self.model.is_rep2[self.state], (rep == 3) as u32);
+                self.reps = match rep {
+                    2 => [rep2, rep0, rep1, rep3],
+                    _ => [rep3, rep0, rep1, rep2],
+                };
+            }
+        }
+        self.model.rep_len.encode(&mut self.rc, len, pos_state);
+        self.state = model::after_rep(self.state);
+    }
+}
+
+/// True if `small` is so much smaller than `big` that the match at it is
+/// worth being a byte shorter.
+fn far_closer(small: usize, big: usize) -> bool {
+    big >> 7 > small
+}
@@ -0,0 +1,368 @@
+use std::io::{self, BufReader, Read, Write};
+
+mod decoder;
+mod encoder;
+pub mod model;
+pub mod rangecoder;
+pub mod xz;
+
+use self::decoder::LzDecoder;
+use self::encoder::LzEncoder;
+use self::model::Properties;
+use self::rangecoder::RangeDecoder;
+use self::xz::XzDecoder;
+use crate::format;
+
+/// Dictionary size of the files written, that of `xz -6`.
+pub const DEFAULT_DICT_SIZE: u32 = 1 << 23;
+/// Smallest dictionary a decoder uses, whatever the header says.
+const MIN_DICT_SIZE: u32 = 1 << 12;
+/// Length of a `.lzma` header: properties, dictionary size, data size.
+const HEADER_LEN: usize = 13;
+/// Data size of files ending with an end marker instead.
+const UNKNOWN_SIZE: u64 = u64::MAX;
+/// Input taken by the encoder, and output made by the decoder, at a time.
+const CHUNK_SIZE: usize = 1 << 16;
+
+/// LZMA, as in `.lzma` and `.xz` files.
+///
+/// Encoding writes `.lzma` files (the "LZMA_Alone" format of `xz
+/// --format=lzma`): a 13-byte header, then the range coded data ending with
+/// an end marker. Matches are found with hash chains over the dictionary.
+/// Decoding takes `.lzma` files and `.xz` files of LZMA2 blocks, told apart
+/// by the xz magic bytes. Only xz files carry a checksum, CRC32 and CRC64
+/// checks being verified.
+#[derive(Debug, Clone, Copy)]
+pub struct LzmaCodec {
+    pub dict_size: u32,
+}
+
+impl Default for LzmaCodec {
+    fn default() -> Self {
+        Self {
+            dict_size: DEFAULT_DICT_SIZE,
+        }
+    }
+}
+
+impl crate::codec::Codec for LzmaCodec {
+    fn name(&self) -> &'static str {
+        "lzma"
+    }
+
+    fn encode(&self, data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
+        let mut encoder = LzmaEncoder::with_dict_size(writer, self.dict_size);
+        encoder.write_all(data)?;
+        encoder.try_finish()?;
+        Ok(encoder.bytes_written * 8)
+    }
+
+    fn decode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+        let mut magic = Vec::new();
+        reader
+            .take(xz::MAGIC.len() as u64)
+            .read_to_end(&mut magic)?;
+        let reader = io::Cursor::new(magic).chain(reader);
+        let bytes_written = if reader.get_ref().0.get_ref()[..] == xz::MAGIC {
+            io::copy(&mut XzDecoder::new(reader)?, writer)?
+        } else {
+            io::copy(&mut LzmaDecoder::new(reader)?, writer)?
+        };
+        Ok(bytes_written as usize)
+    }
+}
+
+/// True if `prefix` looks like the start of a `.lzma` file, which has no
+/// magic bytes: valid properties, a dictionary size of 2^n or 2^n + 2^(n-1)
+/// as tools write them, and a size either unknown or below 2^40.
+pub fn is_lzma_header(prefix: &[u8]) -> bool {
+    let [props, d0, d1, d2, d3, size @ ..] = prefix else {
+        return false;
+    };
+    let dict_size = u32::from_le_bytes([*d0, *d1, *d2, *d3]);
+    *props < 225
+        && dict_size != 0
+        && matches!(dict_size >> dict_size.trailing_zeros(), 1 | 3)
+        && size.len() >= 6
+        && (size[..6] == [0xFF; 6] || size[5] == 0)
+}
+
+/// Writes a `.lzma` file as data is written to it, compressing whenever
+/// enough input is buffered. Call `finish` to write the end marker.
+pub struct LzmaEncoder<W: Write> {
+    writer: W,
+    dict_size: u32,
+    /// Boxed, its hash chains being large.
+    lz: Box<LzEncoder>,
+    bytes_written: u64,
+    header_written: bool,
+    finished: bool,
+}
+
+impl<W: Write> LzmaEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_dict_size(writer, DEFAULT_DICT_SIZE)
+    }
+
+    pub fn with_dict_size(writer: W, dict_size: u32) -> Self {
+        let dict_size = dict_size.max(MIN_DICT_SIZE);
+        Self {
+            writer,
+            dict_size,
+            lz: Box::new(LzEncoder::new(dict_size as usize)),
+            bytes_written: 0,
+            header_written: false,
+            finished: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            let mut header = [0; HEADER_LEN];
+            header[0] = Properties::DEFAULT.to_byte();
+            header[1..5].copy_from_slice(&self.dict_size.to_le_bytes());
+            header[5..].copy_from_slice(&UNKNOWN_SIZE.to_le_bytes());
+            self.writer.write_all(&header)?;
+            self.bytes_written += HEADER_LEN as u64;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    /// Writes out the range coder's finished bytes.
+    fn write_output(&mut self) -> io::Result<()> {
+        let output = self.lz.output();
+        self.writer.write_all(output)?;
+        self.bytes_written += output.len() as u64;
+        output.clear();
+        Ok(())
+    }
+
+    /// Encodes the rest of the input and the end marker. Does nothing if
+    /// the file is already finished.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            self.write_header()?;
+            self.lz.finish();
+            self.write_output()?;
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for LzmaEncoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.write_header()?;
+        let n = data.len().min(CHUNK_SIZE);
+        self.lz.write(&data[..n]);
+        self.write_output()?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Decompresses a `.lzma` file as it is read, up to 64K at a time.
+pub struct LzmaDecoder<R: Read> {
+    rc: RangeDecoder<BufReader<R>>,
+    lz: LzDecoder,
+    /// Bytes of `lz.output` already returned.
+    pos: usize,
+    /// Bytes left to decode, if the header gives the size.
+    remaining: Option<u64>,
+    done: bool,
+}
+
+impl<R: Read> LzmaDecoder<R> {
+    /// Reads the header and the start of the range coded data.
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let mut header = [0; HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        let props = Properties::from_byte(header[0])?;
+        let dict_size = u32::from_le_bytes(header[1..5].try_into().unwrap());
+        let size = u64::from_le_bytes(header[5..].try_into().unwrap());
+
+        Ok(Self {
+            rc: RangeDecoder::new(reader)?,
+            lz: LzDecoder::new(props, dict_size.max(MIN_DICT_SIZE) as usize),
+            pos: 0,
+            remaining: (size != UNKNOWN_SIZE).then_some(size),
+            done: size == 0,
+        })
+    }
+
+    fn read_chunk(&mut self) -> io::Result<()> {
+        self.lz.trim();
+        self.pos = self.lz.output.len();
+        while self.lz.output.len() - self.pos < CHUNK_SIZE {
+            if !self.lz.decode_symbol(&mut self.rc)? {
+                if self.remaining.is_some() {
+                    return Err(invalid_data("end marker before the end of the data"));
+                }
+                if !self.rc.is_finished() {
+                    return Err(invalid_data("data after the end marker"));
+                }
+                self.done = true;
+                break;
+            }
+            if let Some(remaining) = self.remaining {
+                let decoded = (self.lz.output.len() - self.pos) as u64;
+                if decoded >= remaining {
+                    if decoded > remaining {
+                        return Err(invalid_data("data longer than its header says"));
+                    }
+                    // An end marker may follow, but is not needed
+                    self.done = true;
+                    break;
+                }
+            }
+        }
+
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= (self.lz.output.len() - self.pos) as u64;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for LzmaDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.lz.output.len() && !self.done {
+            self.read_chunk()?;
+        }
+
+        let n = buf.len().min(self.lz.output.len() - self.pos);
+        buf[..n].copy_from_slice(&self.lz.output[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    format::invalid_data("LZMA", msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::ChecksumMismatch;
+    use crate::codec::Codec;
+    use crate::deflate::tests::LOREM;
+
+    fn decode(data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        LzmaCodec::default().decode(&mut &data[..], &mut decoded)?;
+        Ok(decoded)
+    }
+
+    #[test]
+    fn decodes_files_from_xz() {
+        // Written by `xz -6`: one block, CRC64 check
+        let data = include_bytes!("testdata/lorem.txt.xz");
+        assert_eq!(decode(data).unwrap(), LOREM);
+
+        // Written by `xz -C crc32 --block-size=3000`: three blocks giving
+        // their sizes in their headers
+        let blocks = include_bytes!("testdata/blocks.xz");
+        assert_eq!(decode(blocks).unwrap(), LOREM);
+
+        // Written by `xz --format=lzma`
+        let data = include_bytes!("testdata/lorem.txt.lzma");
+        assert_eq!(decode(data).unwrap(), LOREM);
+    }
+
+    #[test]
+    fn decodes_concatenated_streams() {
+        let mut data = include_bytes!("testdata/lorem.txt.xz").to_vec();
+        data.extend([0; 8]);
+        data.extend(include_bytes!("testdata/blocks.xz"));
+        assert_eq!(decode(&data).unwrap(), LOREM.repeat(2));
+
+        data.extend([0; 3]);
+        assert!(decode(&data).is_err());
+    }
+
+    #[test]
+    fn encodes_lzma_files() {
+        let mut data = LOREM.repeat(20);
+        data.extend(vec![7; 150_000]);
+        data.extend(LOREM);
+        for data in [&data[..], LOREM, b"a", b""] {
+            let mut encoded = Vec::new();
+            LzmaCodec::default().encode(data, &mut encoded).unwrap();
+            assert!(is_lzma_header(&encoded));
+            assert_eq!(decode(&encoded).unwrap(), data);
+        }
+
+        // A small dictionary keeps matches within it
+        let codec = LzmaCodec { dict_size: 4096 };
+        let mut encoded = Vec::new();
+        codec.encode(&data, &mut encoded).unwrap();
+        assert_eq!(decode(&encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn stops_at_the_size_in_the_header() {
+        let mut encoded = Vec::new();
+        LzmaCodec::default().encode(LOREM, &mut encoded).unwrap();
+        encoded[5..13].copy_from_slice(&(LOREM.len() as u64).to_le_bytes());
+        assert_eq!(decode(&encoded).unwrap(), LOREM);
+
+        encoded[5..13].copy_from_slice(&(LOREM.len() as u64 + 1).to_le_bytes());
+        assert!(decode(&encoded).is_err());
+        encoded[5..13].copy_from_slice(&100u64.to_le_bytes());
+        assert_eq!(decode(&encoded).unwrap(), &LOREM[..100]);
+    }
+
+    #[test]
+    fn detects_corruption() {
+        // The CRC32 of the first block of blocks.xz
+        let mut data = include_bytes!("testdata/blocks.xz").to_vec();
+        data[1040] ^= 0x01;
+        let err = decode(&data).unwrap_err();
+        assert!(err.get_ref().is_some_and(|e| e.is::<ChecksumMismatch>()));
+
+        // The top byte of the CRC64 of lorem.txt.xz, all 64 bits reported
+        let mut data = include_bytes!("testdata/lorem.txt.xz").to_vec();
+        data[2299] ^= 0x01;
+        let err = decode(&data).unwrap_err();
+        let mismatch = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<ChecksumMismatch>())
+            .unwrap();
+        assert_eq!(mismatch.checksum, "crc64");
+        assert_eq!(mismatch.actual, 0xC080_431E_2419_231B);
+        assert_eq!(mismatch.expected, mismatch.actual ^ 1 << 56);
+
+        // Flipping bits anywhere is caught one way or another
+        let original = include_bytes!("testdata/lorem.txt.xz");
+        for i in (0..original.len()).step_by(37) {
+            let mut corrupted = original.to_vec();
+            corrupted[i] ^= 0x10;
+            assert!(decode(&corrupted).is_err(), "byte {}", i);
+        }
+    }
+
+    #[test]
+    fn recognizes_lzma_headers() {
+        assert!(is_lzma_header(include_bytes!("testdata/lorem.txt.lzma")));
+        assert!(!is_lzma_header(include_bytes!("testdata/lorem.txt.xz")));
+        assert!(!is_lzma_header(b"\x5D\x00\x00\x80"));
+        assert!(!is_lzma_header(
+            b"\x5D\x00\x00\x00\x00\xFF\xFF\xFF\xFF\xFF\xFF"
+        ));
+    }
+}
@@ -0,0 +1,349 @@
+//! The probabilities LZMA adapts as it codes, shared by the encoder and the
+//! decoder, and the state machine that picks among them.
+
+use std::io::{self, BufRead};
+
+use super::invalid_data;
+use super::rangecoder::{RangeDecoder, RangeEncoder, PROB_INIT};
+
+pub const MATCH_LEN_MIN: usize = 2;
+pub const MATCH_LEN_MAX: usize = 273;
+
+/// States remembering the kinds of the last few symbols; the first
+/// `LITERAL_STATES` follow a literal.
+pub const STATES: usize = 12;
+pub const LITERAL_STATES: usize = 7;
+const POS_STATES_MAX: usize = 1 << 4;
+
+/// Distances are coded as a 6-bit slot, conditioned on the length, and
+/// extra bits: coded in reverse for slots below `DIST_MODEL_END`, otherwise
+/// direct bits and 4 aligned ones.
+const DIST_STATES: usize = 4;
+const DIST_SLOT_BITS: u32 = 6;
+const DIST_MODEL_START: u32 = 4;
+const DIST_MODEL_END: u32 = 14;
+const FULL_DISTANCES: usize = 1 << (DIST_MODEL_END / 2);
+const ALIGN_BITS: u32 = 4;
+
+/// The distance of the end of stream marker.
+pub const END_MARKER: u32 = u32::MAX;
+
+/// Literal context and position bits, the "lc", "lp" and "pb" properties.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Properties {
+    pub lc: u32,
+    pub lp: u32,
+    pub pb: u32,
+}
+
+impl Properties {
+    /// The defaults of every LZMA encoder: 3 bits of the previous byte as
+    /// literal context, and 2 bits of the position for everything else.
+    pub const DEFAULT: Self = Self {
+        lc: 3,
+        lp: 0,
+        pb: 2,
+    };
+
+    pub fn from_byte(byte: u8) -> io::Result<Self> {
+        if byte >= 9 * 5 * 5 {
+            return Err(invalid_data("invalid properties"));
+        }
+        let byte = byte as u32;
+        Ok(Self {
+            lc: byte % 9,
+            lp: byte / 9 % 5,
+            pb: byte / 45,
+        })
+    }
+
+    pub fn to_byte(self) -> u8 {
+        ((self.pb * 5 + self.lp) * 9 + self.lc) as u8
+    }
+}
+
+pub fn after_literal(state: usize) -> usize {
+    match state {
+        0..=3 => 0,
+        4..=9 => state - 3,
+        _ => state - 6,
+    }
+}
+
+pub fn after_match(state: usize) -> usize {
+    if state < LITERAL_STATES {
+        7
+    } else {
+        10
+    }
+}
+
+pub fn after_rep(state: usize) -> usize {
+    if state < LITERAL_STATES {
+        8
+    } else {
+        11
+    }
+}
+
+pub fn after_short_rep(state: usize) -> usize {
+    if state < LITERAL_STATES {
+        9
+    } else {
+        11
+    }
+}
+
+#[derive(Clone)]
+pub struct Model {
+    props: Properties,
+    literal: Vec<u16>,
+    pub is_match: [u16; STATES * POS_STATES_MAX],
+    pub is_rep: [u16; STATES],
+    pub is_rep0: [u16; STATES],
+    pub is_rep1: [u16; STATES],
+    pub is_rep2: [u16; STATES],
+    pub is_rep0_long: [u16; STATES * POS_STATES_MAX],
+    dist_slot: [[u16; 1 << DIST_SLOT_BITS]; DIST_STATES],
+    /// One more than needed, so that every slot's tree starts at a
+    /// non-negative index.
+    dist_special: [u16; FULL_DISTANCES - DIST_MODEL_END as usize + 1],
+    dist_align: [u16; 1 << ALIGN_BITS],
+    pub len: LengthModel,
+    pub rep_len: LengthModel,
+}
+
+impl Model {
+    pub fn new(props: Properties) -> Self {
+        Self {
+            props,
+            literal: vec![PROB_INIT; 0x300 << (props.lc + props.lp)],
+            is_match: [PROB_INIT; STATES * POS_STATES_MAX],
+            is_rep: [PROB_INIT; STATES],
+            is_rep0: [PROB_INIT; STATES],
+            is_rep1: [PROB_INIT; STATES],
+            is_rep2: [PROB_INIT; STATES],
+            is_rep0_long: [PROB_INIT; STATES * POS_STATES_MAX],
+            dist_slot: [[PROB_INIT; 1 << DIST_SLOT_BITS]; DIST_STATES],
+            dist_special: [PROB_INIT; FULL_DISTANCES - DIST_MODEL_END as usize + 1],
+            dist_align: [PROB_INIT; 1 << ALIGN_BITS],
+            len: LengthModel::new(),
+            rep_len: LengthModel::new(),
+        }
+    }
+
+    pub fn props(&self) -> Properties {
+        self.props
+    }
+
+    /// The bits of `position` that select among the match probabilities.
+    pub fn pos_state(&self, position: u64) -> usize {
+        (position & ((1 << self.props.pb) - 1)) as usize
+    }
+
+    /// Index into `is_match` or `is_rep0_long`.
+    pub fn state_index(&self, state: usize, position: u64) -> usize {
+        state * POS_STATES_MAX + self.pos_state(position)
+    }
+
+    /// Decodes a literal; `match_byte` is the byte at the last distance,
+    /// which predicts it right after a match.
+    pub fn decode_literal<R: BufRead>(
+        &mut self,
+        rc: &mut RangeDecoder<R>,
+        position: u64,
+        prev_byte: u8,
+        match_byte: Option<u8>,
+    ) -> io::Result<u8> {
+        let probs = self.literal_probs(position, prev_byte);
+        let mut node = 1usize;
+        if let Some(match_byte) = match_byte {
+            // While the bits agree with the match byte's, they are coded with
+            // probabilities that depend on its bits
+            for i in (0..8).rev() {
+                let match_bit = (match_byte >> i) as usize & 1;
+                let bit = rc.bit(&mut probs[0x100 + (match_bit << 8) + node])? as usize;
+                node = node << 1 | bit;
+                if bit != match_bit {
+                    break;
+                }
+            }
+        }
+        while node < 0x100 {
+            node = node << 1 | rc.bit(&mut probs[node])? as usize;
+        }
+        Ok(node as u8)
+    }
+
+    pub fn encode_literal(
+        &mut self,
+        rc: &mut RangeEncoder,
+        position: u64,
+        prev_byte: u8,
+        match_byte: Option<u8>,
+        byte: u8,
+    ) {
+        let probs = self.literal_probs(position, prev_byte);
+        let mut node = 1usize;
+        let mut i = 8;
+        if let Some(match_byte) = match_byte {
+            while i > 0 {
+                i -= 1;
+                let match_bit = (match_byte >> i) as usize & 1;
+                let bit = (byte >> i) as usize & 1;
+                rc.bit(&mut probs[0x100 + (match_bit << 8) + node], bit as u32);
+                node = node << 1 | bit;
+                if bit != match_bit {
+                    break;
+                }
+            }
+        }
+        while i > 0 {
+            i -= 1;
+            let bit = (byte >> i) as usize & 1;
+            rc.bit(&mut probs[node], bit as u32);
+            node = node << 1 | bit;
+        }
+    }
+
+    fn literal_probs(&mut self, position: u64, prev_byte: u8) -> &mut [u16] {
+        let Properties { lc, lp, .. } = self.props;
+        let low_position = (position & ((1 << lp) - 1)) as usize;
+        let context = (low_position << lc) + (prev_byte as usize >> (8 - lc));
+        &mut self.literal[0x300 * context..0x300 * (context + 1)]
+    }
+
+    /// Decodes the distance minus one of a match of length `len`.
+    pub fn decode_distance<R: BufRead>(
+        &mut self,
+        rc: &mut RangeDecoder<R>,
+        len: usize,
+    ) -> io::Result<u32> {
+        let dist_state = (len - MATCH_LEN_MIN).min(DIST_STATES - 1);
+        let slot = rc.bittree(&mut self.dist_slot[dist_state], DIST_SLOT_BITS)?;
+        if slot < DIST_MODEL_START {
+            return Ok(slot);
+        }
+
+        let extra_bits = (slot >> 1) - 1;
+        let base = (2 | (slot & 1)) << extra_bits;
+        if slot < DIST_MODEL_END {
+            let probs = &mut self.dist_special[(base - slot) as usize..];
+            return Ok(base + rc.reverse_bittree(probs, extra_bits)?);
+        }
+        let high = rc.direct_bits(extra_bits - ALIGN_BITS)? << ALIGN_BITS;
+        let low = rc.reverse_bittree(&mut self.dist_align, ALIGN_BITS)?;
+        Ok(base + high + low)
+    }
+
+    pub fn encode_distance(&mut self, rc: &mut RangeEncoder, len: usize, dist: u32) {
+        let dist_state = (len - MATCH_LEN_MIN).min(DIST_STATES - 1);
+        if dist < DIST_MODEL_START {
+            rc.bittree(&mut self.dist_slot[dist_state], DIST_SLOT_BITS, dist);
+            return;
+        }
+
+        let top = 31 - dist.leading_zeros();
+        let slot = top << 1 | (dist >> (top - 1)) & 1;
+        rc.bittree(&mut self.dist_slot[dist_state], DIST_SLOT_BITS, slot);
+        let extra_bits = (slot >> 1) - 1;
+        let base = (2 | (slot & 1)) << extra_bits;
+        let extra = dist - base;
+        if slot < DIST_MODEL_END {
+            let probs = &mut self.dist_special[(base - slot) as usize..];
+            rc.reverse_bittree(probs, extra_bits, extra);
+        } else {
+            rc.direct_bits(extra >> ALIGN_BITS, extra_bits - ALIGN_BITS);
+            rc.reverse_bittree(&mut self.dist_align, ALIGN_BITS, extra & 0x0F);
+        }
+    }
+}
+
+/// Match lengths: 8 short ones and 8 medium ones coded per position state,
+/// then 256 long ones.
+#[derive(Clone)]
+pub struct LengthModel {
+    choice: u16,
+    choice2: u16,
+    low: [[u16; 8]; POS_STATES_MAX],
+    mid: [[u16; 8]; POS_STATES_MAX],
+    high: [u16; 256],
+}
+
+impl LengthModel {
+    fn new() -> Self {
+        Self {
+            choice: PROB_INIT,
+            choice2: PROB_INIT,
+            low: [[PROB_INIT; 8]; POS_STATES_MAX],
+            mid: [[PROB_INIT; 8]; POS_STATES_MAX],
+            high: [PROB_INIT; 256],
+        }
+    }
+
+    pub fn decode<R: BufRead>(
+        &mut self,
+        rc: &mut RangeDecoder<R>,
+        pos_state: usize,
+    ) -> io::Result<usize> {
+        let len = if rc.bit(&mut self.choice)? == 0 {
+            rc.bittree(&mut self.low[pos_state], 3)?
+        } else if rc.bit(&mut self.choice2)? == 0 {
+            8 + rc.bittree(&mut self.mid[pos_state], 3)?
+        } else {
+            16 + rc.bittree(&mut self.high, 8)?
+        };
+        Ok(MATCH_LEN_MIN + len as usize)
+    }
+
+    pub fn encode(&mut self, rc: &mut RangeEncoder, len: usize, pos_state: usize) {
+        let len = (len - MATCH_LEN_MIN) as u32;
+        if len < 8 {
+            rc.bit(&mut self.choice, 0);
+            rc.bittree(&mut self.low[pos_state], 3, len);
+        } else if len < 16 {
+            rc.bit(&mut self.choice, 1);
+            rc.bit(&mut self.choice2, 0);
+            rc.bittree(&mut self.mid[pos_state], 3, len - 8);
+        } else {
+            rc.bit(&mut self.choice, 1);
+            rc.bit(&mut self.choice2, 1);
+            rc.bittree(&mut self.high, 8, len - 16);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_lengths_and_distances() {
+        let mut encoder = RangeEncoder::new();
+        let mut model = Model::new(Properties::DEFAULT);
+        let distances = [0, 1, 3, 4, 5, 100, 127, 128, 5000, 1 << 30, END_MARKER];
+        for len in [2, 9, 17, 273] {
+            model.len.encode(&mut encoder, len, 1);
+            for &dist in &distances {
+                model.encode_distance(&mut encoder, len, dist);
+            }
+        }
+        encoder.finish();
+
+        let mut decoder = RangeDecoder::new(encoder.output.as_slice()).unwrap();
+        let mut model = Model::new(Properties::DEFAULT);
+        for len in [2, 9, 17, 273] {
+            assert_eq!(model.len.decode(&mut decoder, 1).unwrap(), len);
+            for &dist in &distances {
+                assert_eq!(model.decode_distance(&mut decoder, len).unwrap(), dist);
+            }
+        }
+    }
+
+    #[test]
+    fn properties_roundtrip_through_byte() {
+        assert_eq!(Properties::DEFAULT.to_byte(), 0x5D);
+        assert_eq!(Properties::from_byte(0x5D).unwrap(), Properties::DEFAULT);
+        assert!(Properties::from_byte(225).is_err());
+    }
+}
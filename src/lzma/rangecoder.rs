@@ -0,0 +1,253 @@
+//! The binary range coder LZMA codes every decision with. Each bit is coded
+//! with an adaptive 11-bit probability of it being 0.
+
+use std::io::{self, BufRead};
+
+use super::invalid_data;
+
+/// Initial probability of every bit, one half.
+pub const PROB_INIT: u16 = 1 << 10;
+
+const MODEL_BITS: u32 = 11;
+const MOVE_BITS: u32 = 5;
+/// The range is renormalized whenever it drops below this.
+const TOP: u32 = 1 << 24;
+
+pub struct RangeDecoder<R: BufRead> {
+    input: R,
+    range: u32,
+    code: u32,
+}
+
+impl<R: BufRead> RangeDecoder<R> {
+    /// Reads the 5 initial bytes, the first of which is always 0.
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut init = [0; 5];
+        input.read_exact(&mut init)?;
+        if init[0] != 0 {
+            return Err(invalid_data("range coder does not start with 0"));
+        }
+        Ok(Self {
+            input,
+            range: u32::MAX,
+            code: u32::from_be_bytes(init[1..].try_into().unwrap()),
+        })
+    }
+
+    /// True if the encoder flushed its state here, as it does at the end.
+    pub fn is_finished(&self) -> bool {
+        self.code == 0
+    }
+
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+
+    fn normalize(&mut self) -> io::Result<()> {
+        if self.range < TOP {
+            let byte = match self.input.fill_buf()? {
+                [] => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                [byte, ..] => *byte,
+            };
+            self.input.consume(1);
+            self.range <<= 8;
+            self.code = self.code << 8 | byte as u32;
+        }
+        Ok(())
+    }
+
+    pub fn bit(&mut self, prob: &mut u16) -> io::Result<u32> {
+        let bound = (self.range >> MODEL_BITS) * *prob as u32;
+        let bit = if self.code < bound {
+            self.range = bound;
+            *prob += ((1 << MODEL_BITS) - *prob) >> MOVE_BITS;
+            0
+        } else {
+            self.range -= bound;
+            self.code -= bound;
+            *prob -= *prob >> MOVE_BITS;
+            1
+        };
+        self.normalize()?;
+        Ok(bit)
+    }
+
+    /// Decodes `bits` bits, the highest first, each with the probability at
+    /// the node of `probs` its prefix leads to.
+    pub fn bittree(&mut self, probs: &mut [u16], bits: u32) -> io::Result<u32> {
+        let mut node = 1;
+        for _ in 0..bits {
+            node = node << 1 | self.bit(&mut probs[node as usize])?;
+        }
+        Ok(node - (1 << bits))
+    }
+
+    /// Like `bittree`, but the lowest bit first.
+    pub fn reverse_bittree(&mut self, probs: &mut [u16], bits: u32) -> io::Result<u32> {
+        let mut node = 1;
+        let mut value = 0;
+        for i in 0..bits {
+            let bit = self.bit(&mut probs[node as usize])?;
+            node = node << 1 | bit;
+            value |= bit << i;
+        }
+        Ok(value)
+    }
+
+    /// Decodes `count` bits with fixed probabilities of one half.
+    pub fn direct_bits(&mut self, count: u32) -> io::Result<u32> {
+        let mut value = 0;
+        for _ in 0..count {
+            self.range >>= 1;
+            let bit = if self.code >= self.range {
+                self.code -= self.range;
+                1
+            } else {
+                0
+            };
+            value = value << 1 | bit;
+            self.normalize()?;
+        }
+        if self.code >= self.range {
+            return Err(invalid_data("range coder out of range"));
+        }
+        Ok(value)
+    }
+}
+
+/// Encodes into `output`. Bytes there are final; a carry can only reach
+/// the ones still held back in `cache`.
+pub struct RangeEncoder {
+    low: u64,
+    range: u32,
+    cache: u8,
+    cache_size: u64,
+    pub output: Vec<u8>,
+}
+
+impl RangeEncoder {
+    pub fn new() -> Self {
+        Self {
+            low: 0,
+            range: u32::MAX,
+            cache: 0,
+            cache_size: 1,
+            output: Vec::new(),
+        }
+    }
+
+    /// Bytes the data encoded so far takes once flushed, `output` included.
+    pub fn pending_len(&self) -> u64 {
+        self.output.len() as u64 + self.cache_size + 4
+    }
+
+    fn shift_low(&mut self) {
+        if (self.low as u32) < 0xFF00_0000 || self.low >> 32 != 0 {
+            let carry = (self.low >> 32) as u8;
+            let mut byte = self.cache;
+            while self.cache_size > 0 {
+                self.output.push(byte.wrapping_add(carry));
+                byte = 0xFF;
+                self.cache_size -= 1;
+            }
+            self.cache = (self.low >> 24) as u8;
+        }
+        self.cache_size += 1;
+        self.low = (self.low & 0x00FF_FFFF) << 8;
+    }
+
+    pub fn bit(&mut self, prob: &mut u16, bit: u32) {
+        let bound = (self.range >> MODEL_BITS) * *prob as u32;
+        if bit == 0 {
+            self.range = bound;
+            *prob += ((1 << MODEL_BITS) - *prob) >> MOVE_BITS;
+        } else {
+            self.low += bound as u64;
+            self.range -= bound;
+            *prob -= *prob >> MOVE_BITS;
+        }
+        while self.range < TOP {
+            self.range <<= 8;
+            self.shift_low();
+        }
+    }
+
+    pub fn bittree(&mut self, probs: &mut [u16], bits: u32, value: u32) {
+        let mut node = 1;
+        for i in (0..bits).rev() {
+            let bit = (value >> i) & 1;
+            self.bit(&mut probs[node as usize], bit);
+            node = node << 1 | bit;
+        }
+    }
+
+    pub fn reverse_bittree(&mut self, probs: &mut [u16], bits: u32, value: u32) {
+        let mut node = 1;
+        for i in 0..bits {
+            let bit = (value >> i) & 1;
+            self.bit(&mut probs[node as usize], bit);
+            node = node << 1 | bit;
+        }
+    }
+
+    pub fn direct_bits(&mut self, value: u32, count: u32) {
+        for i in (0..count).rev() {
+            self.range >>= 1;
+            if (value >> i) & 1 == 1 {
+                self.low += self.range as u64;
+            }
+            while self.range < TOP {
+                self.range <<= 8;
+                self.shift_low();
+            }
+        }
+    }
+
+    /// Writes out what is left of the state.
+    pub fn finish(&mut self) {
+        for _ in 0..5 {
+            self.shift_low();
+        }
+    }
+}
+
+impl Default for RangeEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_what_was_encoded() {
+        let mut encoder = RangeEncoder::new();
+        let mut probs = [PROB_INIT; 256];
+        let mut prob = PROB_INIT;
+        for i in 0..1000u32 {
+            encoder.bit(&mut prob, (i % 7 == 0) as u32);
+            encoder.bittree(&mut probs, 8, i % 251);
+            encoder.reverse_bittree(&mut probs[..16], 4, i % 16);
+            encoder.direct_bits(i, 10);
+        }
+        encoder.finish();
+        assert_eq!(encoder.output[0], 0);
+
+        let mut decoder = RangeDecoder::new(encoder.output.as_slice()).unwrap();
+        let mut probs = [PROB_INIT; 256];
+        let mut prob = PROB_INIT;
+        for i in 0..1000u32 {
+            assert_eq!(decoder.bit(&mut prob).unwrap(), (i % 7 == 0) as u32);
+            assert_eq!(decoder.bittree(&mut probs, 8).unwrap(), i % 251);
+            assert_eq!(
+                decoder.reverse_bittree(&mut probs[..16], 4).unwrap(),
+                i % 16
+            );
+            assert_eq!(decoder.direct_bits(10).unwrap(), i % 1024);
+        }
+        assert!(decoder.is_finished());
+        assert!(decoder.into_inner().is_empty());
+    }
+}
@@ -0,0 +1,440 @@
+//! The `.xz` container: streams of blocks of LZMA2 data, each followed by
+//! the check its stream uses, then an index of the blocks.
+
+use std::io::{self, Read};
+
+use super::decoder::LzDecoder;
+use super::invalid_data;
+use super::model::Properties;
+use super::rangecoder::RangeDecoder;
+use crate::checksum::{self, crc32, ChecksumKind, Crc32, Crc64};
+use crate::format::read_u8;
+
+/// Magic bytes every xz stream starts with.
+pub const MAGIC: [u8; 6] = [0xFD, b'7', b'z', b'X', b'Z', 0x00];
+const FOOTER_MAGIC: [u8; 2] = *b"YZ";
+
+const FILTER_LZMA2: u64 = 0x21;
+
+/// Check types verified; others are skipped, as the format allows.
+const CHECK_NONE: u8 = 0x00;
+const CHECK_CRC32: u8 = 0x01;
+const CHECK_CRC64: u8 = 0x04;
+
+/// Block header flags.
+const FILTER_COUNT_MASK: u8 = 0x03;
+const COMPRESSED_SIZE: u8 = 0x40;
+const UNCOMPRESSED_SIZE: u8 = 0x80;
+
+/// Size of the check of each type, in bytes.
+fn check_size(check_type: u8) -> u64 {
+    match check_type {
+        0 => 0,
+        _ => 4 << ((check_type - 1) / 3),
+    }
+}
+
+enum Check {
+    None,
+    Crc32(Crc32),
+    Crc64(Crc64),
+    Unverified,
+}
+
+impl Check {
+    fn new(check_type: u8) -> Self {
+        match check_type {
+            CHECK_NONE => Check::None,
+            CHECK_CRC32 => Check::Crc32(Crc32::new()),
+            CHECK_CRC64 => Check::Crc64(Crc64::new()),
+            _ => Check::Unverified,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Check::Crc32(crc) => crc.update(data),
+            Check::Crc64(crc) => crc.update(data),
+            Check::None | Check::Unverified => {}
+        }
+    }
+}
+
+/// The block being read.
+struct Block {
+    header_len: u64,
+    compressed_size: Option<u64>,
+    uncompressed_size: Option<u64>,
+    /// LZMA2 data read and decoded so far.
+    compressed: u64,
+    uncompressed: u64,
+    need_dict_reset: bool,
+    need_props: bool,
+}
+
+/// Decompresses xz streams as they are read, one LZMA2 chunk at a time.
+/// Streams written one after the other decode to their concatenated data.
+///
+/// Only blocks using LZMA2 alone are supported, not those adding filters
+/// like BCJ or delta in front of it.
+pub struct XzDecoder<R: Read> {
+    reader: R,
+    stream_flags: [u8; 2],
+    block: Option<Block>,
+    lz: LzDecoder,
+    /// Bytes of `lz.output` already returned.
+    pos: usize,
+    check: Check,
+    /// Unpadded and uncompressed size of each block of the stream, for
+    /// comparing with its index.
+    records: Vec<(u64, u64)>,
+    chunk: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Read> XzDecoder<R> {
+    /// Reads the stream header.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 12];
+        reader.read_exact(&mut header)?;
+        let stream_flags = parse_stream_header(&header)?;
+        Ok(Self {
+            reader,
+            stream_flags,
+            block: None,
+            lz: LzDecoder::new(Properties::DEFAULT, 0),
+            pos: 0,
+            check: Check::None,
+            records: Vec::new(),
+            chunk: Vec::new(),
+            done: false,
+        })
+    }
+
+    fn check_type(&self) -> u8 {
+        self.stream_flags[1]
+    }
+
+    /// Decodes the next chunk of the current block, or starts the next
+    /// block, or reads the index.
+    fn step(&mut self) -> io::Result<()> {
+        if self.block.is_some() {
+            return self.read_chunk();
+        }
+
+        let size = read_u8(&mut self.reader)?;
+        if size == 0 {
+            return self.read_index();
+        }
+        let header_len = (size as usize + 1) * 4;
+        let mut header = vec![size; header_len];
+        self.reader.read_exact(&mut header[1..])?;
+        let (fields, crc) = header.split_at(header_len - 4);
+        if crc32(fields) != u32::from_le_bytes(crc.try_into().unwrap()) {
+            return Err(invalid_data("block header checksum mismatch"));
+        }
+
+        let flags = fields[1];
+        if flags & !(FILTER_COUNT_MASK | COMPRESSED_SIZE | UNCOMPRESSED_SIZE) != 0 {
+            return Err(invalid_data("reserved block header flags set"));
+        }
+        let mut fields = &fields[2..];
+        let compressed_size = match flags & COMPRESSED_SIZE {
+            0 => None,
+            _ => Some(read_vli(&mut fields)?),
+        };
+        let uncompressed_size = match flags & UNCOMPRESSED_SIZE {
+            0 => None,
+            _ => Some(read_vli(&mut fields)?),
+        };
+
+        let filter_count = (flags & FILTER_COUNT_MASK) + 1;
+        let filter = read_vli(&mut fields)?;
+        if filter_count > 1 || filter != FILTER_LZMA2 {
+            return Err(invalid_data(
+                "only blocks with just the LZMA2 filter are supported",
+            ));
+        }
+        if read_vli(&mut fields)? != 1 || fields.is_empty() {
+            return Err(invalid_data("invalid LZMA2 properties"));
+        }
+        let dict_bits = fields[0];
+        let dict_size = match dict_bits {
+            0..=39 => (2 | (dict_bits & 1) as u32) << (dict_bits / 2 + 11),
+            40 => u32::MAX,
+            _ => return Err(invalid_data("invalid LZMA2 dictionary size")),
+        };
+        if fields[1..].iter().any(|&byte| byte != 0) {
+            return Err(invalid_data("block header padding is not zero"));
+        }
+
+        self.lz = LzDecoder::new(Properties::DEFAULT, dict_size as usize);
+        self.pos = 0;
+        self.check = Check::new(self.check_type());
+        self.block = Some(Block {
+            header_len: header_len as u64,
+            compressed_size,
+            uncompressed_size,
+            compressed: 0,
+            uncompressed: 0,
+            need_dict_reset: true,
+            need_props: true,
+        });
+        Ok(())
+    }
+
+    /// Decodes an LZMA2 chunk onto `lz.output`, or ends the block at the
+    /// end mark.
+    fn read_chunk(&mut self) -> io::Result<()> {
+        let block = self.block.as_mut().unwrap();
+        let control = read_u8(&mut self.reader)?;
+        block.compressed += 1;
+        if control == 0x00 {
+            return self.end_block();
+        }
+
+        self.lz.trim();
+        self.pos = self.lz.output.len();
+        let mut sizes = [0; 4];
+        match control {
+            // Uncompressed chunks, the first resetting the dictionary
+            0x01 | 0x02 => {
+                self.reader.read_exact(&mut sizes[..2])?;
+                let size = u16::from_be_bytes([sizes[0], sizes[1]]) as usize + 1;
+                if control == 0x01 {
+                    self.lz.reset_dict();
+                    block.need_dict_reset = false;
+                } else if block.need_dict_reset {
+                    return Err(invalid_data("first chunk does not reset the dictionary"));
+                }
+                self.chunk.resize(size, 0);
+                self.reader.read_exact(&mut self.chunk)?;
+                self.lz.append_uncompressed(&self.chunk);
+                block.compressed += 2 + size as u64;
+            }
+            0x80..=0xFF => {
+                self.reader.read_exact(&mut sizes)?;
+                let unpacked = ((control as usize & 0x1F) << 16)
+                    + u16::from_be_bytes([sizes[0], sizes[1]]) as usize
+                    + 1;
+                let packed = u16::from_be_bytes([sizes[2], sizes[3]]) as usize + 1;
+                block.compressed += 4 + packed as u64;
+
+                // What is reset: 1 the state, 2 also the properties, 3 also
+                // the dictionary
+                let reset = (control >> 5) & 0x03;
+                if reset == 3 {
+                    self.lz.reset_dict();
+                    block.need_dict_reset = false;
+                } else if block.need_dict_reset {
+                    return Err(invalid_data("first chunk does not reset the dictionary"));
+                }
+                if reset >= 2 {
+                    let props = Properties::from_byte(read_u8(&mut self.reader)?)?;
+                    if props.lc + props.lp > 4 {
+                        return Err(invalid_data("invalid LZMA2 properties"));
+                    }
+                    block.compressed += 1;
+                    self.lz.reset_state(props);
+                    block.need_props = false;
+                } else if block.need_props {
+                    return Err(invalid_data("first LZMA chunk does not set properties"));
+                } else if reset == 1 {
+                    self.lz.reset_state(self.lz.props());
+                }
+
+                self.chunk.resize(packed, 0);
+                self.reader.read_exact(&mut self.chunk)?;
+                let end = self.pos + unpacked;
+                let mut rc = RangeDecoder::new(self.chunk.as_slice())?;
+                while self.lz.output.len() < end {
+                    if !self.lz.decode_symbol(&mut rc)? {
+                        return Err(invalid_data("end marker in LZMA2 chunk"));
+                    }
+                }
+                if self.lz.output.len() != end || !rc.is_finished() || !rc.into_inner().is_empty() {
+                    return Err(invalid_data("LZMA2 chunk does not match its sizes"));
+                }
+            }
+            _ => return Err(invalid_data("invalid LZMA2 chunk")),
+        }
+
+        let data = &self.lz.output[self.pos..];
+        self.check.update(data);
+        block.uncompressed += data.len() as u64;
+        Ok(())
+    }
+
+    /// Verifies the sizes and the check after the block's data.
+    fn end_block(&mut self) -> io::Result<()> {
+        let block = self.block.take().unwrap();
+        if block
+            .compressed_size
+            .is_some_and(|size| size != block.compressed)
+            || block
+                .uncompressed_size
+                .is_some_and(|size| size != block.uncompressed)
+        {
+            return Err(invalid_data("block sizes do not match its header"));
+        }
+
+        let mut padding = vec![0; (4 - block.compressed as usize % 4) % 4];
+        self.reader.read_exact(&mut padding)?;
+        if padding.iter().any(|&byte| byte != 0) {
+            return Err(invalid_data("block padding is not zero"));
+        }
+
+        let size = check_size(self.check_type());
+        let mut expected = vec![0; size as usize];
+        self.reader.read_exact(&mut expected)?;
+        match &self.check {
+            Check::Crc32(crc) => checksum::verify(
+                ChecksumKind::Crc32,
+                u32::from_le_bytes(expected[..].try_into().unwrap()),
+                crc.finish(),
+            )?,
+            Check::Crc64(crc) => checksum::verify_named(
+                "crc64",
+                u64::from_le_bytes(expected[..].try_into().unwrap()),
+                crc.finish(),
+            )?,
+            Check::None | Check::Unverified => {}
+        }
+
+        self.records.push((
+            block.header_len + block.compressed + size,
+            block.uncompressed,
+        ));
+        Ok(())
+    }
+
+    /// Reads the index, which must list the blocks just read, and the stream
+    /// footer, then moves on to the next stream if there is one.
+    fn read_index(&mut self) -> io::Result<()> {
+        let mut index = HashingReader {
+            inner: &mut self.reader,
+            crc: Crc32::new(),
+            len: 0,
+        };
+        index.crc.update(&[0]);
+        index.len = 1;
+
+        let mismatch = || invalid_data("index does not match the blocks");
+        if read_vli_from(&mut index)? != self.records.len() as u64 {
+            return Err(mismatch());
+        }
+        for &(unpadded, uncompressed) in &self.records {
+            if read_vli_from(&mut index)? != unpadded || read_vli_from(&mut index)? != uncompressed
+            {
+                return Err(mismatch());
+            }
+        }
+        while !index.len.is_multiple_of(4) {
+            if read_u8(&mut index)? != 0 {
+                return Err(invalid_data("index padding is not zero"));
+            }
+        }
+        let (crc, index_len) = (index.crc.finish(), index.len);
+        let mut expected = [0; 4];
+        self.reader.read_exact(&mut expected)?;
+        if u32::from_le_bytes(expected) != crc {
+            return Err(invalid_data("index checksum mismatch"));
+        }
+
+        let mut footer = [0; 12];
+        self.reader.read_exact(&mut footer)?;
+        let backward_size = u32::from_le_bytes(footer[4..8].try_into().unwrap());
+        if crc32(&footer[4..10]) != u32::from_le_bytes(footer[..4].try_into().unwrap())
+            || (backward_size as u64 + 1) * 4 != index_len + 4
+            || footer[8..10] != self.stream_flags
+            || footer[10..] != FOOTER_MAGIC
+        {
+            return Err(invalid_data("invalid stream footer"));
+        }
+        self.records.clear();
+
+        // Streams may be followed by padding in multiples of 4 zero bytes,
+        // and by more streams
+        loop {
+            let mut header = [0; 12];
+            if self.reader.read(&mut header[..1])? == 0 {
+                self.done = true;
+                return Ok(());
+            }
+            self.reader.read_exact(&mut header[1..4])?;
+            if header[..4] != [0; 4] {
+                self.reader.read_exact(&mut header[4..])?;
+                self.stream_flags = parse_stream_header(&header)?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for XzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.lz.output.len() && !self.done {
+            self.step()?;
+        }
+
+        let n = buf.len().min(self.lz.output.len() - self.pos);
+        buf[..n].copy_from_slice(&self.lz.output[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Returns the stream flags.
+fn parse_stream_header(header: &[u8; 12]) -> io::Result<[u8; 2]> {
+    if header[..6] != MAGIC {
+        return Err(invalid_data("not an xz stream"));
+    }
+    let flags = [header[6], header[7]];
+    if crc32(&flags) != u32::from_le_bytes(header[8..].try_into().unwrap()) {
+        return Err(invalid_data("stream header checksum mismatch"));
+    }
+    if flags[0] != 0 || flags[1] > 0x0F {
+        return Err(invalid_data("unsupported stream flags"));
+    }
+    Ok(flags)
+}
+
+/// Counts and checksums what is read through it.
+struct HashingReader<'a, R: Read> {
+    inner: &'a mut R,
+    crc: Crc32,
+    len: u64,
+}
+
+impl<R: Read> Read for HashingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.crc.update(&buf[..n]);
+        self.len += n as u64;
+        Ok(n)
+    }
+}
+
+/// Reads a variable length integer: 7 bits per byte, lowest first, the
+/// high bit set on all bytes but the last.
+fn read_vli_from<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut value = 0;
+    for i in 0..9 {
+        let byte = read_u8(reader)?;
+        value |= ((byte & 0x7F) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            if byte == 0 && i > 0 {
+                return Err(invalid_data("integer not minimally encoded"));
+            }
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("integer too large"))
+}
+
+fn read_vli(data: &mut &[u8]) -> io::Result<u64> {
+    read_vli_from(data).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => invalid_data("truncated block header"),
+        _ => err,
+    })
+}
@@ -0,0 +1,502 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use crate::deflate::bits::BitReader;
+use crate::format;
+
+/// Magic bytes of a Unix `compress` (`.Z`) file.
+pub const MAGIC: [u8; 2] = [0x1F, 0x9D];
+
+/// Flag in the header's third byte: a `CLEAR` code resets the dictionary.
+const BLOCK_MODE: u8 = 0x80;
+const MAX_BITS_MASK: u8 = 0x1F;
+
+/// Codes start out 9 bits wide and grow up to `max_bits`.
+pub const MIN_BITS: u8 = 9;
+pub const MAX_BITS: u8 = 16;
+
+/// Code that empties the dictionary and narrows codes back to `MIN_BITS`.
+const CLEAR: u32 = 256;
+/// First code given to a dictionary entry; 0-255 are the single bytes.
+const FIRST: u32 = 257;
+
+/// Lempel-Ziv-Welch compression in the Unix `compress` (`.Z`) format, which
+/// `uncompress` and `gzip -d` read.
+///
+/// LZW builds its dictionary of strings as it goes: each code stands for a
+/// string seen before plus the byte after it. Codes start 9 bits wide and
+/// widen by a bit each time the dictionary outgrows them, up to `max_bits`.
+/// A full dictionary is cleared so codes keep adapting to the data.
+#[derive(Debug, Clone, Copy)]
+pub struct LzwCodec {
+    /// Widest code, from `MIN_BITS` to `MAX_BITS`. Smaller dictionaries
+    /// use less memory when decoding but compress less.
+    pub max_bits: u8,
+}
+
+impl Default for LzwCodec {
+    fn default() -> Self {
+        Self { max_bits: MAX_BITS }
+    }
+}
+
+impl LzwCodec {
+    pub fn with_max_bits(max_bits: u8) -> Self {
+        Self { max_bits }
+    }
+}
+
+impl crate::codec::Codec for LzwCodec {
+    fn name(&self) -> &'static str {
+        "lzw"
+    }
+
+    fn encode(&self, data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
+        let mut encoder = LzwEncoder::with_max_bits(writer, self.max_bits)?;
+        encoder.write_all(data)?;
+        encoder.try_finish()?;
+        Ok(encoder.bytes_written * 8)
+    }
+
+    fn decode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+        let bytes_written = io::copy(&mut LzwDecoder::new(reader)?, writer)?;
+        Ok(bytes_written as usize)
+    }
+}
+
+/// Highest code of `MIN_BITS` wide codes. `compress` starts out with it
+/// even when `max_bits` is `MIN_BITS`, and so moves on to 10 bit codes once
+/// that dictionary is full.
+const FIRST_MAX_CODE: u32 = (1 << MIN_BITS) - 1;
+
+/// The highest code that fits in `bits`, or for the widest codes the
+/// number of codes, which is never reached.
+fn max_code(bits: u8, max_bits: u8) -> u32 {
+    if bits == max_bits {
+        1 << max_bits
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+/// Compresses data into a `.Z` file as it is written. Call `finish` to
+/// write the last code.
+///
+/// Codes are written LSB first in groups of eight, `bits` bytes per group.
+/// `compress` always writes whole groups until the very end, even when it
+/// widens the codes or clears the dictionary in the middle of one, and
+/// decoders skip the unused rest, so this encoder does the same.
+pub struct LzwEncoder<W: Write> {
+    writer: W,
+    max_bits: u8,
+    /// Code of each entry, keyed by the prefix's code and the next byte.
+    dictionary: HashMap<(u32, u8), u32>,
+    /// Code of the longest match so far, None before the first byte.
+    current: Option<u32>,
+    next_code: u32,
+    bits: u8,
+    max_code: u32,
+    group: [u8; MAX_BITS as usize],
+    group_bits: usize,
+    bytes_written: u64,
+    header_written: bool,
+    finished: bool,
+}
+
+impl<W: Write> LzwEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_max_bits(writer, MAX_BITS).expect("MAX_BITS is valid")
+    }
+
+    /// Fails unless `max_bits` is from `MIN_BITS` to `MAX_BITS`.
+    pub fn with_max_bits(writer: W, max_bits: u8) -> io::Result<Self> {
+        if !(MIN_BITS..=MAX_BITS).contains(&max_bits) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "maximum code width must be between {} and {} bits",
+                    MIN_BITS, MAX_BITS
+                ),
+            ));
+        }
+        Ok(Self {
+            writer,
+            max_bits,
+            dictionary: HashMap::new(),
+            current: None,
+            next_code: FIRST,
+            bits: MIN_BITS,
+            max_code: FIRST_MAX_CODE,
+            group: [0; MAX_BITS as usize],
+            group_bits: 0,
+            bytes_written: 0,
+            header_written: false,
+            finished: false,
+        })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            self.writer.write_all(&MAGIC)?;
+            self.writer.write_all(&[BLOCK_MODE | self.max_bits])?;
+            self.bytes_written += 3;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    /// Writes the last code. Does nothing if the output is already
+    /// finished.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            self.write_header()?;
+            if let Some(code) = self.current.take() {
+                self.write_code(code, false)?;
+            }
+            let len = self.group_bits.div_ceil(8);
+            self.writer.write_all(&self.group[..len])?;
+            self.bytes_written += len as u64;
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.writer)
+    }
+
+    fn encode_byte(&mut self, byte: u8) -> io::Result<()> {
+        let Some(current) = self.current else {
+            self.current = Some(byte as u32);
+            return Ok(());
+        };
+        if let Some(&code) = self.dictionary.get(&(current, byte)) {
+            self.current = Some(code);
+            return Ok(());
+        }
+
+        self.write_code(current, false)?;
+        self.current = Some(byte as u32);
+        if self.next_code < 1 << self.max_bits {
+            self.dictionary.insert((current, byte), self.next_code);
+            self.next_code += 1;
+        } else {
+            self.dictionary.clear();
+            self.next_code = FIRST;
+            self.write_code(CLEAR, true)?;
+        }
+        Ok(())
+    }
+
+    /// Adds a code to the current group, then widens the codes if the next
+    /// entry will not fit, or narrows them again after a `CLEAR`, starting
+    /// a new group either way.
+    fn write_code(&mut self, code: u32, clear: bool) -> io::Result<()> {
+        for i in 0..self.bits as usize {
+            let bit = self.group_bits + i;
+            self.group[bit / 8] |= (((code >> i) & 1) as u8) << (bit % 8);
+        }
+        self.group_bits += self.bits as usize;
+
+        let group_len = self.bits as usize;
+        if self.group_bits == group_len * 8 {
+            self.flush_group(group_len)?;
+        }
+        if clear || self.next_code > self.max_code {
+            if self.group_bits > 0 {
+                self.flush_group(group_len)?;
+            }
+            if clear {
+                self.bits = MIN_BITS;
+                self.max_code = FIRST_MAX_CODE;
+            } else {
+                self.bits += 1;
+                self.max_code = max_code(self.bits, self.max_bits);
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_group(&mut self, len: usize) -> io::Result<()> {
+        self.writer.write_all(&self.group[..len])?;
+        self.bytes_written += len as u64;
+        self.group = [0; MAX_BITS as usize];
+        self.group_bits = 0;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for LzwEncoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.write_header()?;
+        for &byte in data {
+            self.encode_byte(byte)?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Decompresses a `.Z` file as it is read.
+///
+/// The decoder adds each entry one code later than the encoder, once it
+/// knows the entry's last byte, so it widens codes on the same condition
+/// one entry behind.
+pub struct LzwDecoder<R: Read> {
+    bits_in: BitReader<R>,
+    max_bits: u8,
+    block_mode: bool,
+    /// Prefix code and last byte of each entry.
+    prefixes: Vec<u32>,
+    suffixes: Vec<u8>,
+    previous: Option<u32>,
+    first_byte: u8,
+    next_code: u32,
+    bits: u8,
+    max_code: u32,
+    /// Codes read at the current width, to find the end of their group.
+    codes_in_width: usize,
+    /// Decoded bytes not yet returned, in reverse order.
+    pending: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Read> LzwDecoder<R> {
+    /// Reads and checks the header.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 3];
+        reader.read_exact(&mut header)?;
+        if header[..2] != MAGIC {
+            return Err(invalid_data("not a .Z file"));
+        }
+        let max_bits = header[2] & MAX_BITS_MASK;
+        if !(MIN_BITS..=MAX_BITS).contains(&max_bits) {
+            return Err(invalid_data("unsupported maximum code width"));
+        }
+        let block_mode = header[2] & BLOCK_MODE != 0;
+
+        Ok(Self {
+            bits_in: BitReader::new(reader),
+            max_bits,
+            block_mode,
+            prefixes: vec![0; 1 << max_bits],
+            suffixes: (0..1u32 << max_bits).map(|code| code as u8).collect(),
+            previous: None,
+            first_byte: 0,
+            next_code: if block_mode { FIRST } else { CLEAR },
+            bits: MIN_BITS,
+            max_code: FIRST_MAX_CODE,
+            codes_in_width: 0,
+            pending: Vec::new(),
+            done: false,
+        })
+    }
+
+    /// Reads the next code, None at the end of the input.
+    fn read_code(&mut self) -> io::Result<Option<u32>> {
+        if self.next_code > self.max_code {
+            self.skip_rest_of_group()?;
+            self.bits += 1;
+            self.max_code = max_code(self.bits, self.max_bits);
+        }
+        match self.bits_in.read_bits(self.bits) {
+            Ok(code) => {
+                self.codes_in_width += 1;
+                Ok(Some(code))
+            }
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn skip_rest_of_group(&mut self) -> io::Result<()> {
+        let unused = (8 - self.codes_in_width % 8) % 8;
+        for _ in 0..unused {
+            match self.bits_in.read_bits(self.bits) {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                result => result?,
+            };
+        }
+        self.codes_in_width = 0;
+        Ok(())
+    }
+
+    /// Decodes one code into `pending`. Returns false at the end.
+    fn decode_code(&mut self) -> io::Result<bool> {
+        let Some(code) = self.read_code()? else {
+            return Ok(false);
+        };
+        let Some(previous) = self.previous else {
+            if code >= CLEAR {
+                return Err(invalid_data("first code is not a byte"));
+            }
+            self.previous = Some(code);
+            self.first_byte = code as u8;
+            self.pending.push(code as u8);
+            return Ok(true);
+        };
+
+        if code == CLEAR && self.block_mode {
+            // The entry the next code completes goes into the unused slot
+            // of `CLEAR`, so the encoder's next entry is `FIRST` again
+            self.next_code = FIRST - 1;
+            self.skip_rest_of_group()?;
+            self.bits = MIN_BITS;
+            self.max_code = FIRST_MAX_CODE;
+            return Ok(true);
+        }
+
+        // A code one past the last entry is the previous string plus its
+        // own first byte, which the encoder used before we could add it
+        let mut string_code = code;
+        if code >= self.next_code {
+            if code > self.next_code {
+                return Err(invalid_data("code not in the dictionary"));
+            }
+            self.pending.push(self.first_byte);
+            string_code = previous;
+        }
+        while string_code >= CLEAR {
+            self.pending.push(self.suffixes[string_code as usize]);
+            string_code = self.prefixes[string_code as usize];
+        }
+        self.first_byte = string_code as u8;
+        self.pending.push(self.first_byte);
+
+        if self.next_code < 1 << self.max_bits {
+            self.prefixes[self.next_code as usize] = previous;
+            self.suffixes[self.next_code as usize] = self.first_byte;
+            self.next_code += 1;
+        }
+        self.previous = Some(code);
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for LzwDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending.is_empty() && !self.done {
+            self.done = !self.decode_code()?;
+        }
+
+        let n = buf.len().min(self.pending.len());
+        for byte in &mut buf[..n] {
+            *byte = self.pending.pop().unwrap();
+        }
+        Ok(n)
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    format::invalid_data("LZW", msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Codec;
+    use crate::deflate::tests::LOREM;
+
+    fn roundtrip(codec: LzwCodec, data: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        let mut decoded = Vec::new();
+
+        codec.encode(data, &mut encoded).expect("Failed to encode");
+        codec
+            .decode(&mut encoded.as_slice(), &mut decoded)
+            .expect("Failed to decode");
+        decoded
+    }
+
+    #[test]
+    fn encodes_and_decodes_text() {
+        assert_eq!(roundtrip(LzwCodec::default(), LOREM), LOREM);
+    }
+
+    #[test]
+    fn encodes_and_decodes_edge_cases() {
+        let codec = LzwCodec::default();
+        assert_eq!(roundtrip(codec, b""), b"");
+        assert_eq!(roundtrip(codec, b"a"), b"a");
+        // Every new entry is used right away, before the decoder has it
+        assert_eq!(roundtrip(codec, &[b'x'; 1000]), [b'x'; 1000]);
+    }
+
+    #[test]
+    fn writes_the_compress_header() {
+        let mut encoded = Vec::new();
+        LzwCodec::default().encode(b"abab", &mut encoded).unwrap();
+
+        // a, b, then "ab" as code 257, 9 bits each
+        assert_eq!(encoded[..3], [0x1F, 0x9D, 0x90]);
+        assert_eq!(encoded.len(), 3 + 4);
+    }
+
+    #[test]
+    fn widens_codes_and_clears_the_dictionary() {
+        // Enough distinct strings to fill a 9 bit dictionary many times
+        let data: Vec<u8> = (0..40_000u32)
+            .map(|i| (i * 7 % 251) as u8 ^ (i / 97) as u8)
+            .collect();
+
+        for max_bits in [MIN_BITS, 12, MAX_BITS] {
+            let codec = LzwCodec::with_max_bits(max_bits);
+            assert_eq!(roundtrip(codec, &data), data, "max_bits {}", max_bits);
+        }
+    }
+
+    #[test]
+    fn decodes_files_from_compress() {
+        // Written by the compress 4.0 algorithm with 16 bit codes, and read
+        // back by `gzip -d` and `uncompress`
+        let codec = LzwCodec::default();
+        let data = include_bytes!("testdata/lorem.txt.Z");
+        let mut decoded = Vec::new();
+        codec
+            .decode(&mut data.as_slice(), &mut decoded)
+            .expect("Failed to decode");
+        assert_eq!(decoded, LOREM);
+
+        // Same with `-b 10` from LOREM twice and 4096 pseudo-random bytes:
+        // codes widen to 10 bits, then the ratio drops once the dictionary
+        // is full and a CLEAR starts over at 9 bits
+        let data = include_bytes!("testdata/clear.Z");
+        let mut expected = LOREM.repeat(2);
+        let mut state = 1u32;
+        expected.extend((0..4096).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 24) as u8
+        }));
+        let mut decoded = Vec::new();
+        codec
+            .decode(&mut data.as_slice(), &mut decoded)
+            .expect("Failed to decode");
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        let codec = LzwCodec::default();
+        let not_z = [0x1F, 0x8B, 0x90, 0];
+        assert!(codec.decode(&mut &not_z[..], &mut Vec::new()).is_err());
+
+        // 300 as the very first code
+        let bad_code = [0x1F, 0x9D, 0x90, 0x2C, 0x01];
+        let err = codec
+            .decode(&mut &bad_code[..], &mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        assert!(LzwCodec::with_max_bits(17)
+            .encode(b"a", &mut Vec::new())
+            .is_err());
+    }
+}
@@ -1,7 +1,21 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
 use std::time::Instant;
-use std::{env, fs, process};
+use std::{env, process};
 
+use comprust::checksum::ChecksumKind;
 use comprust::codec::{self, Codec, DEFAULT_ALGORITHM};
+use comprust::container::{self, DEFAULT_BLOCK_SIZE};
+use comprust::stream::{Decoder, DecoderOptions, Encoder, EncoderOptions};
+
+/// Values of the command line flags, `None` when not given.
+#[derive(Default)]
+struct Options {
+    algorithm: Option<String>,
+    checksum: Option<String>,
+    block_size: Option<String>,
+    threads: Option<String>,
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -11,26 +25,39 @@ fn main() {
         process::exit(1);
     }
 
-    // Parse: comprust <command> [-a algorithm] <input> <output>
+    // Parse: comprust <command> [options] <input> <output>
     let command = args[1].as_str();
-    let (algorithm, rest) = parse_algorithm_flag(&args[2..]);
+    let (options, rest) = parse_flags(&args[2..]);
 
     match command {
         "encode" => {
             if rest.len() < 2 {
-                eprintln!("Usage: comprust encode [-a algorithm] <input-file> <output-file>");
+                eprintln!("Usage: comprust encode [-a algorithm] [-c checksum] [-b block-size] [-t threads] <input-file> <output-file>");
                 process::exit(1);
             }
-            let codec = resolve_codec(&algorithm);
-            cmd_encode(codec.as_ref(), &rest[0], &rest[1]);
+            let codec = resolve_codec(options.algorithm.as_deref().unwrap_or(DEFAULT_ALGORITHM));
+            let encoder_options = EncoderOptions {
+                checksum: resolve_checksum(options.checksum.as_deref().unwrap_or("crc32")),
+                block_size: options
+                    .block_size
+                    .as_deref()
+                    .map_or(DEFAULT_BLOCK_SIZE, resolve_block_size),
+                threads: resolve_threads(options.threads.as_deref()),
+            };
+            cmd_encode(codec, &encoder_options, &rest[0], &rest[1]);
         }
         "decode" => {
             if rest.len() < 2 {
-                eprintln!("Usage: comprust decode [-a algorithm] <input-file> <output-file>");
+                eprintln!(
+                    "Usage: comprust decode [-a algorithm] [-t threads] <input-file> <output-file>"
+                );
                 process::exit(1);
             }
-            let codec = resolve_codec(&algorithm);
-            cmd_decode(codec.as_ref(), &rest[0], &rest[1]);
+            let codec = options.algorithm.as_deref().map(resolve_codec);
+            let decoder_options = DecoderOptions {
+                threads: resolve_threads(options.threads.as_deref()),
+            };
+            cmd_decode(codec.as_deref(), &decoder_options, &rest[0], &rest[1]);
         }
         _ => {
             eprintln!("Unknown command: {}", command);
@@ -40,41 +67,112 @@ fn main() {
     }
 }
 
-/// Extract `-a <name>` or `--algorithm <name>` from args, return the rest.
-fn parse_algorithm_flag(args: &[String]) -> (String, Vec<String>) {
-    let mut algorithm = DEFAULT_ALGORITHM.to_string();
+/// Extract `-a/--algorithm <name>`, `-c/--checksum <name>`,
+/// `-b/--block-size <size>` and `-t/--threads <n>` from args, return the rest.
+fn parse_flags(args: &[String]) -> (Options, Vec<String>) {
+    let mut options = Options::default();
     let mut rest = Vec::new();
     let mut iter = args.iter();
 
     while let Some(arg) = iter.next() {
-        if arg == "-a" || arg == "--algorithm" {
-            match iter.next() {
-                Some(name) => algorithm = name.clone(),
-                None => {
-                    eprintln!("Missing value for {}", arg);
-                    process::exit(1);
-                }
+        let slot = match arg.as_str() {
+            "-a" | "--algorithm" => &mut options.algorithm,
+            "-c" | "--checksum" => &mut options.checksum,
+            "-b" | "--block-size" => &mut options.block_size,
+            "-t" | "--threads" => &mut options.threads,
+            _ => {
+                rest.push(arg.clone());
+                continue;
+            }
+        };
+        match iter.next() {
+            Some(value) => *slot = Some(value.clone()),
+            None => {
+                eprintln!("Missing value for {}", arg);
+                process::exit(1);
             }
-        } else {
-            rest.push(arg.clone());
         }
     }
 
-    (algorithm, rest)
+    (options, rest)
 }
 
 fn resolve_codec(name: &str) -> Box<dyn Codec> {
     match codec::get_codec(name) {
         Some(c) => c,
         None => {
-            eprintln!("Unknown algorithm: '{}'. Available: huffman, rle", name);
+            eprintln!(
+                "Unknown algorithm: '{}'. Available: {}",
+                name,
+                codec::available()
+            );
+            process::exit(1);
+        }
+    }
+}
+
+fn resolve_checksum(name: &str) -> ChecksumKind {
+    match ChecksumKind::from_name(name) {
+        Some(kind) => kind,
+        None => {
+            eprintln!(
+                "Unknown checksum: '{}'. Available: {}",
+                name,
+                available_checksums()
+            );
+            process::exit(1);
+        }
+    }
+}
+
+fn available_checksums() -> String {
+    ChecksumKind::ALL
+        .iter()
+        .map(|kind| kind.name())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Parses a size in bytes, optionally with a `K` or `M` suffix (KiB, MiB).
+fn resolve_block_size(value: &str) -> usize {
+    let upper = value.to_ascii_uppercase();
+    let (digits, unit) = if let Some(digits) = upper.strip_suffix('K') {
+        (digits, 1024)
+    } else if let Some(digits) = upper.strip_suffix('M') {
+        (digits, 1024 * 1024)
+    } else {
+        (upper.as_str(), 1)
+    };
+    let size = match digits.parse::<usize>() {
+        Ok(n) => n.saturating_mul(unit),
+        Err(_) => {
+            eprintln!("Invalid block size: '{}'", value);
+            process::exit(1);
+        }
+    };
+    if let Err(e) = container::check_block_size(size) {
+        eprintln!("Invalid block size: {}", e);
+        process::exit(1);
+    }
+    size
+}
+
+/// Parses a thread count, defaulting to one per available core.
+fn resolve_threads(value: Option<&str>) -> usize {
+    let Some(value) = value else {
+        return container::worker_count(0);
+    };
+    match value.parse::<usize>() {
+        Ok(n) if n > 0 => n,
+        _ => {
+            eprintln!("Invalid thread count: '{}'", value);
             process::exit(1);
         }
     }
 }
 
 fn print_usage() {
-    eprintln!("Usage: comprust <command> [-a algorithm] <input-file> <output-file>");
+    eprintln!("Usage: comprust <command> [options] <input-file> <output-file>");
     eprintln!();
     eprintln!("Commands:");
     eprintln!("  encode    Compress a file");
@@ -82,72 +180,117 @@ fn print_usage() {
     eprintln!();
     eprintln!("Options:");
     eprintln!("  -a, --algorithm <name>    Compression algorithm (default: huffman)");
-    eprintln!("                            Available: huffman, rle");
+    eprintln!("                            Available: {}", codec::available());
+    eprintln!("                            Join names with + to chain codecs, e.g. mtf+rle+huffman");
+    eprintln!("                            Detected automatically when decoding,");
+    eprintln!("                            except for raw deflate streams");
+    eprintln!("  -c, --checksum <name>     Checksum stored to detect corruption (default: crc32)");
+    eprintln!("                            Available: {}", available_checksums());
+    eprintln!("  -b, --block-size <size>   Input compressed at a time, 64K to 4M (default: 1M)");
+    eprintln!("  -t, --threads <n>         Blocks coded in parallel (default: one per core)");
 }
 
-fn cmd_encode(codec: &dyn Codec, input_path: &str, output_path: &str) {
-    let data = match fs::read(input_path) {
-        Ok(d) => d,
-        Err(e) => {
-            eprintln!("Failed to read '{}': {}", input_path, e);
-            process::exit(1);
-        }
-    };
+fn cmd_encode(
+    codec: Box<dyn Codec>,
+    options: &EncoderOptions,
+    input_path: &str,
+    output_path: &str,
+) {
+    let mut input = open_input(input_path);
+    let output = create_output(output_path);
 
     let start = Instant::now();
-    let mut output = Vec::new();
-    let num_bits = match codec.encode(&data, &mut output) {
-        Ok(n) => n,
-        Err(e) => {
-            eprintln!("Failed to encode: {}", e);
-            process::exit(1);
-        }
+    let mut encoder = match Encoder::with_options(BufWriter::new(output), codec, options) {
+        Ok(encoder) => encoder,
+        Err(e) => abort(output_path, "Failed to encode", e),
     };
+    if let Err(e) = io::copy(&mut input, &mut encoder).and_then(|_| encoder.try_finish()) {
+        abort(output_path, "Failed to encode", e);
+    }
+    let (raw, compressed, num_bits) = (
+        encoder.total_in(),
+        encoder.total_out(),
+        encoder.payload_bits(),
+    );
+    if let Err(e) = encoder.finish().and_then(finish_output) {
+        let context = format!("Failed to write '{}'", output_path);
+        abort(output_path, &context, e);
+    }
     let elapsed = start.elapsed();
 
-    if let Err(e) = fs::write(output_path, &output) {
-        eprintln!("Failed to write '{}': {}", output_path, e);
-        process::exit(1);
+    let ratio = compressed as f64 / raw as f64;
+    println!("=> Raw: {} bytes", raw);
+    println!("=> Compressed: {} bytes", compressed);
+    println!("=> Compressed: {} bits", num_bits);
+    println!("=> Ratio: {:.2}%", ratio * 100.0);
+    println!("=> Time: {:.3?}", elapsed);
+    println!("=> Written to: {}", output_path);
+}
+
+fn cmd_decode(
+    codec: Option<&dyn Codec>,
+    options: &DecoderOptions,
+    input_path: &str,
+    output_path: &str,
+) {
+    let input = open_input(input_path);
+    let compressed = input.metadata().map(|m| m.len()).unwrap_or(0);
+    let output = create_output(output_path);
+
+    let start = Instant::now();
+    let input = BufReader::new(input);
+    let mut decoder = match Decoder::with_options(input, codec, options) {
+        Ok(decoder) => decoder,
+        Err(e) => abort(output_path, "Failed to decode", e),
+    };
+    let mut writer = BufWriter::new(output);
+    let restored = match io::copy(&mut decoder, &mut writer) {
+        Ok(n) => n,
+        Err(e) => abort(output_path, "Failed to decode", e),
+    };
+    if let Err(e) = finish_output(writer) {
+        let context = format!("Failed to write '{}'", output_path);
+        abort(output_path, &context, e);
     }
+    let elapsed = start.elapsed();
 
-    let ratio = output.len() as f64 / data.len() as f64;
-    println!("=> Raw: {} bytes", data.len());
-    println!("=> Compressed: {} bytes", output.len());
-    println!("=> Compressed: {} bits", num_bits);
+    let ratio = compressed as f64 / restored as f64;
+    println!("=> Algorithm: {}", decoder.codec());
+    println!("=> Compressed: {} bytes", compressed);
+    println!("=> Restored: {} bytes", restored);
     println!("=> Ratio: {:.2}%", ratio * 100.0);
     println!("=> Time: {:.3?}", elapsed);
     println!("=> Written to: {}", output_path);
 }
 
-fn cmd_decode(codec: &dyn Codec, input_path: &str, output_path: &str) {
-    let data = match fs::read(input_path) {
-        Ok(d) => d,
+fn open_input(path: &str) -> File {
+    match File::open(path) {
+        Ok(file) => file,
         Err(e) => {
-            eprintln!("Failed to read '{}': {}", input_path, e);
+            eprintln!("Failed to read '{}': {}", path, e);
             process::exit(1);
         }
-    };
+    }
+}
 
-    let start = Instant::now();
-    let mut output = Vec::new();
-    let bytes_written = match codec.decode(&mut data.as_slice(), &mut output) {
-        Ok(n) => n,
+fn create_output(path: &str) -> File {
+    match File::create(path) {
+        Ok(file) => file,
         Err(e) => {
-            eprintln!("Failed to decode: {}", e);
+            eprintln!("Failed to write '{}': {}", path, e);
             process::exit(1);
         }
-    };
-    let elapsed = start.elapsed();
-
-    if let Err(e) = fs::write(output_path, &output) {
-        eprintln!("Failed to write '{}': {}", output_path, e);
-        process::exit(1);
     }
+}
 
-    let ratio = data.len() as f64 / bytes_written as f64;
-    println!("=> Compressed: {} bytes", data.len());
-    println!("=> Restored: {} bytes", bytes_written);
-    println!("=> Ratio: {:.2}%", ratio * 100.0);
-    println!("=> Time: {:.3?}", elapsed);
-    println!("=> Written to: {}", output_path);
+/// Flushes buffered output, reporting errors that dropping would swallow.
+fn finish_output(writer: BufWriter<File>) -> io::Result<()> {
+    writer.into_inner().map(drop).map_err(|e| e.into_error())
+}
+
+/// Reports the error and removes the partially written output file.
+fn abort(output_path: &str, context: &str, e: io::Error) -> ! {
+    eprintln!("{}: {}", context, e);
+    let _ = fs::remove_file(output_path);
+    process::exit(1);
 }
@@ -0,0 +1,274 @@
+use std::io::{self, Read, Write};
+
+use crate::codec::{self, Codec};
+
+/// Separators accepted between stage names, as in `mtf+rle+huffman` or
+/// `bwt,huffman`.
+pub const SEPARATORS: [char; 2] = ['+', ','];
+
+/// A chain of codecs applied one after the other, each encoding the output
+/// of the one before.
+///
+/// The ids of the stages are recorded at the start of every payload, so a
+/// `Pipeline` decodes any chain, whatever stages it was built with. The
+/// one registered as `pipeline` has none, it copies its input as-is.
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Codec>>,
+}
+
+impl Pipeline {
+    /// Fails if a stage is not registered or is itself a pipeline.
+    pub fn new(stages: Vec<Box<dyn Codec>>) -> io::Result<Self> {
+        for stage in &stages {
+            stage_id(stage.as_ref())?;
+        }
+        Ok(Self { stages })
+    }
+
+    /// Builds the pipeline for stage names joined by `SEPARATORS`. None if
+    /// a name is unknown or names a pipeline.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let stages = spec
+            .split(SEPARATORS)
+            .map(|name| codec::get_codec(name.trim()))
+            .collect::<Option<Vec<_>>>()?;
+        Self::new(stages).ok()
+    }
+
+    /// Names of the stages, in encoding order.
+    pub fn stages(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.stages.iter().map(|stage| stage.name())
+    }
+}
+
+impl Codec for Pipeline {
+    fn name(&self) -> &'static str {
+        "pipeline"
+    }
+
+    /// Writes the number of stages (1 byte) and their codec ids (1 byte
+    /// each), then the last stage's payload, or the data itself if there
+    /// are no stages.
+    ///
+    /// Returns the number of bits the last stage reports.
+    fn encode(&self, data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
+        if self.stages.len() > u8::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pipeline has too many stages",
+            ));
+        }
+        writer.write_all(&[self.stages.len() as u8])?;
+        for stage in &self.stages {
+            writer.write_all(&[stage_id(stage.as_ref())?])?;
+        }
+
+        let Some((last, first)) = self.stages.split_last() else {
+            writer.write_all(data)?;
+            return Ok(data.len() as u64 * 8);
+        };
+
+        let mut current = data.to_vec();
+        for stage in first {
+            let mut output = Vec::new();
+            stage.encode(&current, &mut output)?;
+            // The next stage's decoder would reject it
+            if output.len() > codec::MAX_DECODED_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("'{}' grows the data too much for a pipeline", stage.name()),
+                ));
+            }
+            current = output;
+        }
+        last.encode(&current, writer)
+    }
+
+    fn decode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+        let mut len = [0u8; 1];
+        reader.read_exact(&mut len)?;
+        let mut ids = vec![0u8; len[0] as usize];
+        reader.read_exact(&mut ids)?;
+
+        let stages = ids
+            .iter()
+            .map(|&id| {
+                codec::codec_info_by_id(id)
+                    .filter(|info| info.name != "pipeline")
+                    .and_then(|info| codec::get_codec(info.name))
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Pipeline decode error: unknown stage id {}", id),
+                        )
+                    })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut current = Vec::new();
+        reader.read_to_end(&mut current)?;
+        for stage in stages.iter().rev() {
+            let mut output = StageWriter {
+                data: Vec::new(),
+                stage: stage.name(),
+            };
+            stage.decode(&mut current.as_slice(), &mut output)?;
+            current = output.data;
+        }
+
+        writer.write_all(&current)?;
+        Ok(current.len())
+    }
+}
+
+/// Collects a stage's decoded data, failing as soon as it grows past
+/// `codec::MAX_DECODED_LEN`, the most the encoder lets a stage produce.
+struct StageWriter {
+    data: Vec<u8>,
+    stage: &'static str,
+}
+
+impl Write for StageWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > codec::MAX_DECODED_LEN - self.data.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Pipeline decode error: '{}' decodes to more than {} bytes",
+                    self.stage,
+                    codec::MAX_DECODED_LEN
+                ),
+            ));
+        }
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn stage_id(stage: &dyn Codec) -> io::Result<u8> {
+    codec::codec_info(stage.name())
+        .filter(|info| info.name != "pipeline")
+        .map(|info| info.id)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' cannot be a pipeline stage", stage.name()),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::get_codec;
+    use crate::deflate::tests::LOREM;
+
+    fn roundtrip(pipeline: &Pipeline, data: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        let mut decoded = Vec::new();
+
+        pipeline
+            .encode(data, &mut encoded)
+            .expect("Failed to encode");
+        // Decoding needs no stages, they are read from the payload
+        Pipeline::default()
+            .decode(&mut encoded.as_slice(), &mut decoded)
+            .expect("Failed to decode");
+        decoded
+    }
+
+    #[test]
+    fn parses_stage_names() {
+        let pipeline = Pipeline::parse("mtf+rle+huffman").unwrap();
+        assert_eq!(
+            pipeline.stages().collect::<Vec<_>>(),
+            ["mtf", "rle", "huffman"]
+        );
+
+        let pipeline = Pipeline::parse("bwt, huffman").unwrap();
+        assert_eq!(pipeline.stages().collect::<Vec<_>>(), ["bwt", "huffman"]);
+
+        assert!(Pipeline::parse("mtf+nope").is_none());
+        assert!(Pipeline::parse("mtf+").is_none());
+        assert!(Pipeline::parse("pipeline+huffman").is_none());
+    }
+
+    #[test]
+    fn records_the_chain_and_reverses_it() {
+        let pipeline = Pipeline::parse("mtf+rle+huffman").unwrap();
+        let mut encoded = Vec::new();
+        pipeline.encode(LOREM, &mut encoded).unwrap();
+
+        let ids: Vec<u8> = ["mtf", "rle", "huffman"]
+            .iter()
+            .map(|name| codec::codec_info(name).unwrap().id)
+            .collect();
+        assert_eq!(encoded[0], 3);
+        assert_eq!(encoded[1..4], ids);
+
+        assert_eq!(roundtrip(&pipeline, LOREM), LOREM);
+    }
+
+    #[test]
+    fn chains_any_codecs() {
+        for spec in [
+            "huffman",
+            "lz77+huffman",
+            "rle,arith",
+            "lzh+rans",
+            "deflate+gzip",
+        ] {
+            let pipeline = Pipeline::parse(spec).unwrap();
+            assert_eq!(roundtrip(&pipeline, LOREM), LOREM, "{}", spec);
+        }
+
+        let stages = vec![get_codec("rle").unwrap(), get_codec("huffman").unwrap()];
+        let pipeline = Pipeline::new(stages).unwrap();
+        assert_eq!(
+            roundtrip(&pipeline, b"aaaaaaaabbbbbbbbc"),
+            b"aaaaaaaabbbbbbbbc"
+        );
+    }
+
+    #[test]
+    fn copies_data_without_stages() {
+        let mut encoded = Vec::new();
+        Pipeline::default().encode(b"abc", &mut encoded).unwrap();
+
+        assert_eq!(encoded, b"\0abc");
+        assert_eq!(roundtrip(&Pipeline::default(), b"abc"), b"abc");
+    }
+
+    #[test]
+    fn rejects_invalid_chains() {
+        let nested = Pipeline::new(vec![Box::new(Pipeline::default())]);
+        assert!(nested.is_err());
+
+        let unknown_stage = [1u8, 255, 0];
+        let err = Pipeline::default()
+            .decode(&mut &unknown_stage[..], &mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_stages_decoding_past_the_limit() {
+        // Runs of 129 bytes adding up to one run more than the limit
+        let runs = codec::MAX_DECODED_LEN / 129 + 1;
+        let mut payload = vec![1, codec::codec_info("rle").unwrap().id];
+        payload.extend([0xFF, b'a'].repeat(runs));
+
+        let mut decoded = Vec::new();
+        let err = Pipeline::default()
+            .decode(&mut payload.as_slice(), &mut decoded)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("'rle' decodes to more than"));
+        assert!(decoded.is_empty());
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use crate::huffman::tree::count_bytes;
+
+/// Range asymmetric numeral systems (rANS) coding of bytes.
+///
+/// Like an arithmetic coder it spends close to `-log2(p)` bits on a byte
+/// of probability `p`, but its whole state is a single integer: encoding a
+/// byte grows the state by about that factor and decoding shrinks it back,
+/// resolving each byte with one table lookup. Several states take turns so
+/// the CPU can work on consecutive bytes in parallel.
+///
+/// States are last in first out, so the encoder works through the data
+/// backwards and the decoder reads it forwards.
+pub struct RansCodec;
+
+impl crate::codec::Codec for RansCodec {
+    fn name(&self) -> &'static str {
+        "rans"
+    }
+
+    fn encode(&self, data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
+        encode(data, writer)
+    }
+
+    fn decode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+        decode(reader, writer, crate::codec::MAX_DECODED_LEN)
+    }
+}
+
+/// Frequencies are normalized to sum to `1 << SCALE_BITS`, the size of the
+/// decoding table.
+pub const SCALE_BITS: u32 = 14;
+const SCALE: u32 = 1 << SCALE_BITS;
+
+/// Number of interleaved states; byte `i` is coded with state `i % STATES`.
+pub const STATES: usize = 4;
+
+/// States are kept in `RANS_L..RANS_L << 8` by moving whole bytes in and
+/// out of them.
+const RANS_L: u32 = 1 << 23;
+
+/// Decoded bytes written out at a time.
+const CHUNK_SIZE: usize = 1 << 16;
+
+/// Scales byte counts to frequencies summing to exactly `SCALE`. Every byte
+/// that occurs keeps a frequency of at least 1.
+pub fn normalize(counts: &HashMap<u8, u64>) -> [u32; 256] {
+    let mut freqs = [0u32; 256];
+    let total: u64 = counts.values().sum();
+    if total == 0 {
+        return freqs;
+    }
+    for (&byte, &count) in counts {
+        freqs[byte as usize] = (count * SCALE as u64 / total).max(1) as u32;
+    }
+
+    let mut by_freq: Vec<usize> = (0..256).filter(|&s| freqs[s] > 0).collect();
+    by_freq.sort_by_key(|&s| std::cmp::Reverse(freqs[s]));
+
+    // Rounding rare bytes up to 1 can overshoot, take it back from the
+    // most frequent ones where it costs the least
+    let mut sum: u32 = freqs.iter().sum();
+    while sum > SCALE {
+        for &s in &by_freq {
+            if sum > SCALE && freqs[s] > 1 {
+                freqs[s] -= 1;
+                sum -= 1;
+            }
+        }
+    }
+    freqs[by_freq[0]] += SCALE - sum;
+    freqs
+}
+
+/// Where each byte's slice of `0..SCALE` starts.
+fn cumulative(freqs: &[u32; 256]) -> [u32; 256] {
+    let mut starts = [0u32; 256];
+    let mut start = 0;
+    for (s, &freq) in freqs.iter().enumerate() {
+        starts[s] = start;
+        start += freq;
+    }
+    starts
+}
+
+/// Encodes data with rANS. The payload is:
+///
+/// 1. Length of the data (4 bytes)
+/// 2. Number of distinct bytes (2 bytes), then each byte with its
+///    normalized frequency (2 bytes)
+/// 3. The final encoder states (4 bytes each), then the bytes they shifted
+///    out, both in the order the decoder reads them
+///
+/// Returns the number of bits in part 3.
+pub fn encode(data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
+    let len = u32::try_from(data.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "input too large for rANS"))?;
+    let freqs = normalize(&count_bytes(data));
+    let starts = cumulative(&freqs);
+
+    let mut states = [RANS_L; STATES];
+    let mut out = Vec::with_capacity(data.len() / 2);
+    for (i, &byte) in data.iter().enumerate().rev() {
+        let x = &mut states[i % STATES];
+        let freq = freqs[byte as usize];
+
+        // Shift out bytes until coding the byte keeps the state in range
+        let x_max = ((RANS_L >> SCALE_BITS) << 8) * freq;
+        while *x >= x_max {
+            out.push(*x as u8);
+            *x >>= 8;
+        }
+        *x = ((*x / freq) << SCALE_BITS) + (*x % freq) + starts[byte as usize];
+    }
+    for x in states.iter().rev() {
+        out.extend(x.to_le_bytes());
+    }
+    out.reverse();
+
+    writer.write_all(&len.to_be_bytes())?;
+    let entries: Vec<usize> = (0..256).filter(|&s| freqs[s] > 0).collect();
+    writer.write_all(&(entries.len() as u16).to_be_bytes())?;
+    for s in entries {
+        writer.write_all(&[s as u8])?;
+        writer.write_all(&(freqs[s] as u16).to_be_bytes())?;
+    }
+    writer.write_all(&out)?;
+
+    Ok(out.len() as u64 * 8)
+}
+
+/// Decodes data produced by `encode`, failing if the header gives a length
+/// over `max_len`. A byte of frequency `SCALE` takes no bits at all, so the
+/// length is all that bounds the output. It is written out a chunk at a
+/// time.
+///
+/// Returns the number of bytes written to output.
+pub fn decode(reader: &mut dyn Read, writer: &mut dyn Write, max_len: usize) -> io::Result<usize> {
+    let mut header = [0u8; 6];
+    reader.read_exact(&mut header)?;
+    let len = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+    if len > max_len {
+        return Err(corrupted("length is larger than the limit"));
+    }
+    let num_entries = u16::from_be_bytes([header[4], header[5]]);
+
+    let mut freqs = [0u32; 256];
+    for _ in 0..num_entries {
+        let mut entry = [0u8; 3];
+        reader.read_exact(&mut entry)?;
+        let freq = u16::from_be_bytes([entry[1], entry[2]]) as u32;
+        if freq == 0 || freqs[entry[0] as usize] != 0 {
+            return Err(corrupted("invalid frequency table"));
+        }
+        freqs[entry[0] as usize] = freq;
+    }
+    let sum: u32 = freqs.iter().sum();
+    if sum != SCALE && (len > 0 || sum > 0) {
+        return Err(corrupted("frequencies do not sum to the table size"));
+    }
+
+    let starts = cumulative(&freqs);
+    let mut slots = vec![0u8; SCALE as usize];
+    for s in 0..256 {
+        let start = starts[s] as usize;
+        slots[start..start + freqs[s] as usize].fill(s as u8);
+    }
+
+    let mut input = Vec::new();
+    reader.read_to_end(&mut input)?;
+    let mut bytes = input.iter().copied();
+    let mut next_byte = || bytes.next().ok_or_else(|| corrupted("data ends early"));
+
+    let mut states = [0u32; STATES];
+    for x in &mut states {
+        for _ in 0..4 {
+            *x = *x << 8 | next_byte()? as u32;
+        }
+        if *x < RANS_L {
+            return Err(corrupted("invalid initial state"));
+        }
+    }
+
+    let mut output = Vec::with_capacity(len.min(CHUNK_SIZE));
+    for i in 0..len {
+        let x = &mut states[i % STATES];
+        let slot = *x & (SCALE - 1);
+        let s = slots[slot as usize] as usize;
+        *x = freqs[s] * (*x >> SCALE_BITS) + slot - starts[s];
+        while *x < RANS_L {
+            *x = *x << 8 | next_byte()? as u32;
+        }
+        output.push(s as u8);
+        if output.len() == CHUNK_SIZE {
+            writer.write_all(&output)?;
+            output.clear();
+        }
+    }
+
+    // Decoding undoes every step of the encoder, back to its initial states
+    if states.iter().any(|&x| x != RANS_L) || next_byte().is_ok() {
+        return Err(corrupted("states do not return to their initial value"));
+    }
+
+    writer.write_all(&output)?;
+    Ok(len)
+}
+
+fn corrupted(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("rANS decode error: {}", reason),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::tests::LOREM;
+
+    fn roundtrip(data: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        let mut decoded = Vec::new();
+
+        encode(data, &mut encoded).expect("Failed to encode");
+        decode(&mut encoded.as_slice(), &mut decoded, data.len()).expect("Failed to decode");
+        decoded
+    }
+
+    #[test]
+    fn encodes_and_decodes_text() {
+        assert_eq!(roundtrip(LOREM), LOREM);
+    }
+
+    #[test]
+    fn encodes_and_decodes_edge_cases() {
+        assert_eq!(roundtrip(b""), b"");
+        assert_eq!(roundtrip(b"a"), b"a");
+        assert_eq!(roundtrip(b"abc"), b"abc");
+        assert_eq!(roundtrip(&[0; 10_000]), [0; 10_000]);
+
+        let all_bytes: Vec<u8> = (0..=255).cycle().take(5000).collect();
+        assert_eq!(roundtrip(&all_bytes), all_bytes);
+    }
+
+    #[test]
+    fn normalizes_frequencies_to_the_table_size() {
+        // One very common byte and 255 rare ones that all round up to 1
+        let mut counts: HashMap<u8, u64> = (0..=255).map(|s| (s, 1)).collect();
+        counts.insert(b'a', 1_000_000);
+        let freqs = normalize(&counts);
+
+        assert_eq!(freqs.iter().sum::<u32>(), SCALE);
+        assert!(freqs.iter().all(|&freq| freq >= 1));
+        assert_eq!(freqs[b'a' as usize], SCALE - 255);
+
+        let freqs = normalize(&HashMap::from([(b'x', 3)]));
+        assert_eq!(freqs[b'x' as usize], SCALE);
+    }
+
+    #[test]
+    fn spends_less_than_a_bit_on_likely_bytes() {
+        let mut data = vec![b'a'; 100_000];
+        data.extend(b"bcd");
+
+        let mut encoded = Vec::new();
+        let bits = encode(&data, &mut encoded).unwrap();
+        assert!(bits < 2_000, "took {} bits", bits);
+        assert_eq!(roundtrip(&data), data);
+    }
+
+    #[test]
+    fn detects_corruption() {
+        let mut encoded = Vec::new();
+        encode(LOREM, &mut encoded).unwrap();
+
+        let mut truncated = encoded.clone();
+        truncated.truncate(encoded.len() - 10);
+        let err = decode(&mut truncated.as_slice(), &mut Vec::new(), LOREM.len()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut flipped = encoded.clone();
+        let last = flipped.len() - 1;
+        flipped[last] ^= 0x10;
+        let err = decode(&mut flipped.as_slice(), &mut Vec::new(), LOREM.len()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_lengths_over_the_limit() {
+        let mut encoded = Vec::new();
+        encode(&[b'a'; 1000], &mut encoded).unwrap();
+        assert!(decode(&mut encoded.as_slice(), &mut Vec::new(), 999).is_err());
+
+        // A single byte takes no bits, only the length stops it
+        encoded[..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        let mut decoded = Vec::new();
+        let err = decode(&mut encoded.as_slice(), &mut decoded, 1 << 20).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(decoded.is_empty());
+    }
+}
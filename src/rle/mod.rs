@@ -3,6 +3,10 @@ use std::io::{self, Read, Write};
 pub struct RleCodec;
 
 impl crate::codec::Codec for RleCodec {
+    fn name(&self) -> &'static str {
+        "rle"
+    }
+
     fn encode(&self, data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
         encode(data, writer)
     }
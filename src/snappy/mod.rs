@@ -0,0 +1,403 @@
+use std::io::{self, Read, Write};
+
+use crate::checksum::{self, crc32c};
+use crate::format;
+
+pub mod raw;
+
+/// The stream identifier chunk every Snappy framed stream starts with.
+pub const MAGIC: [u8; 10] = [0xFF, 0x06, 0x00, 0x00, b's', b'N', b'a', b'P', b'p', b'Y'];
+
+/// Chunk types of the framing format.
+const COMPRESSED: u8 = 0x00;
+const UNCOMPRESSED: u8 = 0x01;
+const PADDING: u8 = 0xFE;
+const STREAM_IDENTIFIER: u8 = 0xFF;
+/// Types from 0x02 to 0x7F are reserved and must not be skipped, those from
+/// 0x80 to 0xFD are reserved but skippable.
+const FIRST_SKIPPABLE: u8 = 0x80;
+
+/// Most uncompressed data a chunk may hold.
+pub const MAX_CHUNK_SIZE: usize = 1 << 16;
+
+/// Which of Snappy's two formats to write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// The framing format: data in checksummed chunks of up to 64K after a
+    /// stream identifier, as in `.sz` files.
+    #[default]
+    Framed,
+    /// A single raw block with no framing or checksum.
+    Raw,
+}
+
+/// Google's Snappy, in its framing or raw format.
+///
+/// Like LZ4, Snappy aims for speed over ratio: byte aligned literals and
+/// copies found with a single hash table probe. Decoding accepts both
+/// formats, taking input that does not start with the stream identifier
+/// as a raw block.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SnappyCodec {
+    pub format: Format,
+}
+
+impl SnappyCodec {
+    pub fn with_format(format: Format) -> Self {
+        Self { format }
+    }
+}
+
+impl crate::codec::Codec for SnappyCodec {
+    fn name(&self) -> &'static str {
+        "snappy"
+    }
+
+    fn encode(&self, data: &[u8], writer: &mut dyn Write) -> io::Result<u64> {
+        if self.format == Format::Raw {
+            let mut compressed = Vec::new();
+            raw::compress(data, &mut compressed);
+            writer.write_all(&compressed)?;
+            return Ok(compressed.len() as u64 * 8);
+        }
+
+        let mut encoder = SnappyEncoder::new(writer);
+        encoder.write_all(data)?;
+        encoder.try_finish()?;
+        Ok(encoder.bytes_written * 8)
+    }
+
+    fn decode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+        let bytes_written = io::copy(&mut SnappyDecoder::new(reader)?, writer)?;
+        Ok(bytes_written as usize)
+    }
+}
+
+/// The CRC-32C of a chunk's data, rotated and offset as the framing format
+/// requires, so that a checksum of data containing checksums stays robust.
+pub fn masked_crc32c(data: &[u8]) -> u32 {
+    let crc = crc32c(data);
+    crc.rotate_right(15).wrapping_add(0xA282EAD8)
+}
+
+/// Compresses data into the Snappy framing format as it is written, in
+/// chunks of `MAX_CHUNK_SIZE`. Call `finish` to write the last chunk.
+pub struct SnappyEncoder<W: Write> {
+    writer: W,
+    buffer: Vec<u8>,
+    compressed: Vec<u8>,
+    bytes_written: u64,
+    header_written: bool,
+    finished: bool,
+}
+
+impl<W: Write> SnappyEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            buffer: Vec::with_capacity(MAX_CHUNK_SIZE),
+            compressed: Vec::new(),
+            bytes_written: 0,
+            header_written: false,
+            finished: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            self.writer.write_all(&MAGIC)?;
+            self.bytes_written += MAGIC.len() as u64;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    /// Writes the buffered input as a chunk, uncompressed if compressing
+    /// saves less than an eighth of it, like the reference implementation.
+    fn write_chunk(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.compressed.clear();
+        raw::compress(&self.buffer, &mut self.compressed);
+
+        let (chunk_type, data) =
+            if self.compressed.len() < self.buffer.len() - self.buffer.len() / 8 {
+                (COMPRESSED, &self.compressed)
+            } else {
+                (UNCOMPRESSED, &self.buffer)
+            };
+        let len = (4 + data.len() as u32).to_le_bytes();
+        self.writer
+            .write_all(&[chunk_type, len[0], len[1], len[2]])?;
+        self.writer
+            .write_all(&masked_crc32c(&self.buffer).to_le_bytes())?;
+        self.writer.write_all(data)?;
+        self.bytes_written += 8 + data.len() as u64;
+
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Writes the last chunk. Does nothing if the stream is already
+    /// finished.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            self.write_header()?;
+            self.write_chunk()?;
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for SnappyEncoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.write_header()?;
+        let n = data.len().min(MAX_CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..n]);
+        if self.buffer.len() == MAX_CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Decompresses Snappy data as it is read: a framed stream one chunk at a
+/// time, or, if the input does not start with the stream identifier, a raw
+/// block all at once.
+pub struct SnappyDecoder<R: Read> {
+    reader: R,
+    buffer: Vec<u8>,
+    /// Bytes of `buffer` already returned.
+    pos: usize,
+    chunk: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Read> SnappyDecoder<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(MAGIC.len());
+        (&mut reader)
+            .take(MAGIC.len() as u64)
+            .read_to_end(&mut header)?;
+
+        let mut decoder = Self {
+            reader,
+            buffer: Vec::new(),
+            pos: 0,
+            chunk: Vec::new(),
+            done: false,
+        };
+        if header != MAGIC {
+            decoder.reader.read_to_end(&mut header)?;
+            raw::decompress(&header, &mut decoder.buffer, usize::MAX)?;
+            decoder.done = true;
+        }
+        Ok(decoder)
+    }
+
+    /// Decodes the next chunk into `buffer`, which stays empty for chunks
+    /// without data.
+    fn read_chunk(&mut self) -> io::Result<()> {
+        let mut header = [0; 4];
+        if self.reader.read(&mut header[..1])? == 0 {
+            self.done = true;
+            return Ok(());
+        }
+        self.reader.read_exact(&mut header[1..])?;
+        let len = u32::from_le_bytes([header[1], header[2], header[3], 0]) as usize;
+        self.chunk.resize(len, 0);
+        self.reader.read_exact(&mut self.chunk)?;
+
+        self.buffer.clear();
+        self.pos = 0;
+        match header[0] {
+            COMPRESSED | UNCOMPRESSED => {
+                if len < 4 {
+                    return Err(invalid_data("chunk too short for its checksum"));
+                }
+                let expected = u32::from_le_bytes(self.chunk[..4].try_into().unwrap());
+                let data = &self.chunk[4..];
+                if header[0] == COMPRESSED {
+                    raw::decompress(data, &mut self.buffer, MAX_CHUNK_SIZE)?;
+                } else if data.len() > MAX_CHUNK_SIZE {
+                    return Err(invalid_data("chunk larger than 64K"));
+                } else {
+                    self.buffer.extend_from_slice(data);
+                }
+                checksum::verify_named(
+                    "crc32c",
+                    expected.into(),
+                    masked_crc32c(&self.buffer).into(),
+                )?;
+            }
+            // Streams may be concatenated, repeating the identifier
+            STREAM_IDENTIFIER if self.chunk == MAGIC[4..] => {}
+            STREAM_IDENTIFIER => return Err(invalid_data("invalid stream identifier")),
+            PADDING => {}
+            chunk_type if chunk_type >= FIRST_SKIPPABLE => {}
+            _ => return Err(invalid_data("reserved unskippable chunk")),
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for SnappyDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buffer.len() && !self.done {
+            self.read_chunk()?;
+        }
+
+        let n = buf.len().min(self.buffer.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    format::invalid_data("Snappy", msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Codec;
+    use crate::deflate::tests::LOREM;
+
+    fn roundtrip(codec: SnappyCodec, data: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        let mut decoded = Vec::new();
+
+        codec.encode(data, &mut encoded).expect("Failed to encode");
+        codec
+            .decode(&mut encoded.as_slice(), &mut decoded)
+            .expect("Failed to decode");
+        decoded
+    }
+
+    /// Types of the chunks after the stream identifier.
+    fn chunk_types(mut encoded: &[u8]) -> Vec<u8> {
+        encoded = &encoded[MAGIC.len()..];
+        let mut types = Vec::new();
+        while let [chunk_type, l0, l1, l2, rest @ ..] = encoded {
+            types.push(*chunk_type);
+            encoded = &rest[u32::from_le_bytes([*l0, *l1, *l2, 0]) as usize..];
+        }
+        types
+    }
+
+    #[test]
+    fn encodes_and_decodes_both_formats() {
+        for format in [Format::Framed, Format::Raw] {
+            let codec = SnappyCodec::with_format(format);
+            assert_eq!(roundtrip(codec, LOREM), LOREM);
+            assert_eq!(roundtrip(codec, b""), b"");
+        }
+    }
+
+    #[test]
+    fn splits_data_at_64k() {
+        // A run across the 64K boundary, which chunks and the fragments of
+        // raw data split and copies cannot cross
+        let mut data = LOREM.repeat(8)[..MAX_CHUNK_SIZE - 100].to_vec();
+        data.extend([b'x'; 1000]);
+        data.extend(LOREM);
+        for format in [Format::Framed, Format::Raw] {
+            let codec = SnappyCodec::with_format(format);
+            assert_eq!(roundtrip(codec, &data), data, "{:?}", format);
+        }
+
+        let mut encoded = Vec::new();
+        SnappyCodec::default().encode(&data, &mut encoded).unwrap();
+        assert_eq!(chunk_types(&encoded), [COMPRESSED, COMPRESSED]);
+        encoded.clear();
+        SnappyCodec::default()
+            .encode(&data[..MAX_CHUNK_SIZE], &mut encoded)
+            .unwrap();
+        assert_eq!(chunk_types(&encoded), [COMPRESSED]);
+    }
+
+    #[test]
+    fn stores_chunks_without_copies() {
+        // A big-endian counter repeats no 4 bytes, so there is nothing to copy
+        let data: Vec<u8> = (0..40_000u16).flat_map(|i| i.to_be_bytes()).collect();
+        let mut encoded = Vec::new();
+        SnappyCodec::default().encode(&data, &mut encoded).unwrap();
+
+        assert_eq!(chunk_types(&encoded), [UNCOMPRESSED, UNCOMPRESSED]);
+        assert_eq!(roundtrip(SnappyCodec::default(), &data), data);
+    }
+
+    #[test]
+    fn decodes_framed_stream() {
+        // From the framing format description: the stream identifier, a
+        // padding chunk, an uncompressed and a compressed chunk
+        let mut data = MAGIC.to_vec();
+        data.extend([PADDING, 2, 0, 0, 0, 0]);
+        data.extend([UNCOMPRESSED, 7, 0, 0]);
+        data.extend(masked_crc32c(b"abc").to_le_bytes());
+        data.extend(b"abc");
+        let mut compressed = Vec::new();
+        raw::compress(b"defdefdefdefdefdef", &mut compressed);
+        data.extend([COMPRESSED, 4 + compressed.len() as u8, 0, 0]);
+        data.extend(masked_crc32c(b"defdefdefdefdefdef").to_le_bytes());
+        data.extend(compressed);
+        data.extend(MAGIC);
+
+        let mut decoded = Vec::new();
+        SnappyCodec::default()
+            .decode(&mut data.as_slice(), &mut decoded)
+            .unwrap();
+        assert_eq!(decoded, b"abcdefdefdefdefdefdef");
+
+        // The checksum of an empty chunk
+        assert_eq!(masked_crc32c(b""), 0xA282EAD8);
+    }
+
+    #[test]
+    fn decodes_file_from_commons_compress() {
+        // Written by Apache Commons Compress' FramedSnappyCompressorOutputStream
+        let data = include_bytes!("testdata/lorem.txt.sz");
+        let mut decoded = Vec::new();
+
+        SnappyCodec::default()
+            .decode(&mut data.as_slice(), &mut decoded)
+            .expect("Failed to decode");
+        assert_eq!(decoded, LOREM);
+    }
+
+    #[test]
+    fn detects_corruption() {
+        let codec = SnappyCodec::default();
+        let mut encoded = Vec::new();
+        codec.encode(LOREM, &mut encoded).unwrap();
+
+        let last = encoded.len() - 1;
+        encoded[last] ^= 0x01;
+        let err = codec
+            .decode(&mut encoded.as_slice(), &mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut reserved = MAGIC.to_vec();
+        reserved.extend([0x02, 0, 0, 0]);
+        assert!(codec
+            .decode(&mut reserved.as_slice(), &mut Vec::new())
+            .is_err());
+    }
+}
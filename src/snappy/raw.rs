@@ -0,0 +1,299 @@
+//! Snappy's raw format: the uncompressed length, then a sequence of
+//! literals and copies, each introduced by a tag byte whose low 2 bits say
+//! which it is.
+
+use std::io;
+
+use super::invalid_data;
+use crate::format::u32_le_at;
+
+const LITERAL: u8 = 0;
+/// Copy of 4 to 11 bytes up to 2047 bytes back, in 2 bytes.
+const COPY_1: u8 = 1;
+/// Copy of 1 to 64 bytes up to 65535 bytes back, in 3 bytes.
+const COPY_2: u8 = 2;
+/// Copy of 1 to 64 bytes with a 4 byte offset, in 5 bytes.
+const COPY_4: u8 = 3;
+
+/// Input is compressed in fragments of this size, which keeps offsets
+/// within `COPY_2` range.
+const FRAGMENT_SIZE: usize = 1 << 16;
+const MIN_MATCH: usize = 4;
+/// Matches are not searched for in the last bytes of a fragment.
+const INPUT_MARGIN: usize = 15;
+const HASH_BITS: u32 = 14;
+/// The search step grows by one every `1 << SKIP_SHIFT` misses.
+const SKIP_SHIFT: u32 = 5;
+
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(0x1E35A7BD) >> (32 - HASH_BITS)) as usize
+}
+
+/// Compresses `data` in the raw format, appending to `output`.
+pub fn compress(data: &[u8], output: &mut Vec<u8>) {
+    let mut len = data.len() as u64;
+    while len >= 0x80 {
+        output.push(len as u8 | 0x80);
+        len >>= 7;
+    }
+    output.push(len as u8);
+
+    let mut table = vec![0u16; 1 << HASH_BITS];
+    for fragment in data.chunks(FRAGMENT_SIZE) {
+        table.fill(0);
+        compress_fragment(fragment, &mut table, output);
+    }
+}
+
+/// Greedy single-probe match search, as in the reference implementation.
+/// Positions in `table` are relative to the fragment, so 0 doubles as
+/// "empty" and is checked like any other candidate.
+fn compress_fragment(data: &[u8], table: &mut [u16], output: &mut Vec<u8>) {
+    let mut anchor = 0;
+
+    if data.len() >= INPUT_MARGIN {
+        let limit = data.len() - INPUT_MARGIN;
+        let mut pos = 1;
+
+        'search: while pos <= limit {
+            let mut misses = 1 << SKIP_SHIFT;
+            let candidate = loop {
+                let sequence = u32_le_at(data, pos);
+                let slot = &mut table[hash(sequence)];
+                let candidate = *slot as usize;
+                *slot = pos as u16;
+                if candidate < pos && u32_le_at(data, candidate) == sequence {
+                    break candidate;
+                }
+                pos += misses >> SKIP_SHIFT;
+                misses += 1;
+                if pos > limit {
+                    break 'search;
+                }
+            };
+
+            emit_literal(output, &data[anchor..pos]);
+            let mut len = MIN_MATCH;
+            while pos + len < data.len() && data[pos + len] == data[candidate + len] {
+                len += 1;
+            }
+            emit_copy(output, pos - candidate, len);
+            pos += len;
+            anchor = pos;
+            if pos <= limit {
+                table[hash(u32_le_at(data, pos - 1))] = (pos - 1) as u16;
+            }
+        }
+    }
+
+    emit_literal(output, &data[anchor..]);
+}
+
+/// Literals of up to 60 bytes keep their length minus one in the tag,
+/// longer ones in the 1 to 4 bytes after it.
+fn emit_literal(output: &mut Vec<u8>, literal: &[u8]) {
+    if literal.is_empty() {
+        return;
+    }
+    let n = literal.len() - 1;
+    if n < 60 {
+        output.push((n as u8) << 2 | LITERAL);
+    } else {
+        let bytes = (n as u32).to_le_bytes();
+        let count = 4 - (n as u32).leading_zeros() as usize / 8;
+        output.push((59 + count as u8) << 2 | LITERAL);
+        output.extend_from_slice(&bytes[..count]);
+    }
+    output.extend_from_slice(literal);
+}
+
+/// Splits a match into copies of at most 64 bytes, never leaving less
+/// than 4 for the last one so it can use `COPY_1`.
+fn emit_copy(output: &mut Vec<u8>, offset: usize, mut len: usize) {
+    while len >= 68 {
+        emit_copy_upto_64(output, offset, 64);
+        len -= 64;
+    }
+    if len > 64 {
+        emit_copy_upto_64(output, offset, 60);
+        len -= 60;
+    }
+    emit_copy_upto_64(output, offset, len);
+}
+
+fn emit_copy_upto_64(output: &mut Vec<u8>, offset: usize, len: usize) {
+    if (4..12).contains(&len) && offset < 2048 {
+        output.push(((offset >> 8) as u8) << 5 | ((len - 4) as u8) << 2 | COPY_1);
+        output.push(offset as u8);
+    } else {
+        output.push(((len - 1) as u8) << 2 | COPY_2);
+        output.extend_from_slice(&(offset as u16).to_le_bytes());
+    }
+}
+
+/// Reads the uncompressed length at the start of raw data, returning it and
+/// the length of the varint.
+pub fn decompressed_len(input: &[u8]) -> io::Result<(usize, usize)> {
+    let mut len = 0u64;
+    for (i, &byte) in input.iter().enumerate().take(5) {
+        len |= ((byte & 0x7F) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return u32::try_from(len)
+                .map(|len| (len as usize, i + 1))
+                .map_err(|_| invalid_data("uncompressed length too large"));
+        }
+    }
+    Err(invalid_data("invalid uncompressed length"))
+}
+
+/// Decompresses raw data, appending to `output`. Fails unless it holds
+/// exactly as many bytes as its header says, or more than `max_len`.
+pub fn decompress(input: &[u8], output: &mut Vec<u8>, max_len: usize) -> io::Result<()> {
+    let (len, mut pos) = decompressed_len(input)?;
+    if len > max_len {
+        return Err(invalid_data("uncompressed length too large"));
+    }
+    let start = output.len();
+    let end = start + len;
+    // The header alone can claim 4 GiB, so trust it only as far as the
+    // input could possibly expand
+    output.reserve(len.min(input.len() * 32));
+
+    let truncated = || invalid_data("truncated data");
+    while pos < input.len() {
+        let tag = input[pos];
+        pos += 1;
+
+        let (offset, copy_len) = match tag & 0x03 {
+            LITERAL => {
+                let mut n = (tag >> 2) as usize;
+                if n >= 60 {
+                    let count = n - 59;
+                    let bytes = input.get(pos..pos + count).ok_or_else(truncated)?;
+                    n = bytes
+                        .iter()
+                        .rev()
+                        .fold(0, |n, &byte| n << 8 | byte as usize);
+                    pos += count;
+                }
+                let literal = input.get(pos..pos + n + 1).ok_or_else(truncated)?;
+                if output.len() + literal.len() > end {
+                    return Err(invalid_data("data longer than its header says"));
+                }
+                output.extend_from_slice(literal);
+                pos += literal.len();
+                continue;
+            }
+            COPY_1 => {
+                let byte = *input.get(pos).ok_or_else(truncated)?;
+                pos += 1;
+                let offset = ((tag >> 5) as usize) << 8 | byte as usize;
+                (offset, ((tag >> 2) & 0x07) as usize + 4)
+            }
+            COPY_2 => {
+                let bytes = input.get(pos..pos + 2).ok_or_else(truncated)?;
+                pos += 2;
+                let offset = u16::from_le_bytes([bytes[0], bytes[1]]) as usize;
+                (offset, (tag >> 2) as usize + 1)
+            }
+            COPY_4 => {
+                let bytes = input.get(pos..pos + 4).ok_or_else(truncated)?;
+                pos += 4;
+                let offset = u32_le_at(bytes, 0) as usize;
+                (offset, (tag >> 2) as usize + 1)
+            }
+            _ => unreachable!("tags have 2 type bits"),
+        };
+
+        if offset == 0 || offset > output.len() - start {
+            return Err(invalid_data("copy offset points before start of output"));
+        }
+        if output.len() + copy_len > end {
+            return Err(invalid_data("data longer than its header says"));
+        }
+        // Overlapping copies repeat their first `offset` bytes
+        let from = output.len() - offset;
+        let mut remaining = copy_len;
+        while remaining > 0 {
+            let n = remaining.min(output.len() - from);
+            output.extend_from_within(from..from + n);
+            remaining -= n;
+        }
+    }
+
+    if output.len() != end {
+        return Err(invalid_data("data shorter than its header says"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::tests::LOREM;
+
+    fn roundtrip(data: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        let mut output = Vec::new();
+
+        compress(data, &mut compressed);
+        decompress(&compressed, &mut output, usize::MAX).expect("Failed to decompress");
+        output
+    }
+
+    #[test]
+    fn compresses_and_decompresses() {
+        assert_eq!(roundtrip(LOREM), LOREM);
+        for data in [&b""[..], b"a", b"abcabcabcabcabcabcabc", &[7; 200_000]] {
+            assert_eq!(roundtrip(data), data);
+        }
+
+        let all_bytes: Vec<u8> = (0..=255).cycle().take(70_000).collect();
+        assert_eq!(roundtrip(&all_bytes), all_bytes);
+    }
+
+    #[test]
+    fn decodes_every_element() {
+        // Length 19: literal "abcd", COPY_1 of 5 from 4 back, COPY_2 of 4
+        // from 2 back, COPY_4 of 2 from 13 back, then a literal of 4 with
+        // its length in the byte after the tag
+        let mut input = vec![19, 3 << 2, b'a', b'b', b'c', b'd'];
+        input.extend([1 << 2 | COPY_1, 4]);
+        input.extend([3 << 2 | COPY_2, 2, 0]);
+        input.extend([1 << 2 | COPY_4, 13, 0, 0, 0]);
+        input.extend([60 << 2, 3, b'w', b'x', b'y', b'z']);
+
+        let mut output = Vec::new();
+        decompress(&input, &mut output, 100).unwrap();
+        assert_eq!(output, b"abcdabcdadadaabwxyz");
+    }
+
+    #[test]
+    fn decodes_copies_with_4_byte_offsets() {
+        // The encoder stays within 64K, other encoders may reach further
+        let literal: Vec<u8> = (0..35_000u16).flat_map(|i| i.to_be_bytes()).collect();
+        let mut input = vec![0xB0, 0xA3, 0x04]; // 70_000 + 64
+        input.push(62 << 2 | LITERAL);
+        input.extend(&(literal.len() as u32 - 1).to_le_bytes()[..3]);
+        input.extend(&literal);
+        input.push(63 << 2 | COPY_4);
+        input.extend(70_000u32.to_le_bytes());
+
+        let mut output = Vec::new();
+        decompress(&input, &mut output, usize::MAX).unwrap();
+        assert_eq!(output[..70_000], literal);
+        assert_eq!(output[70_000..], literal[..64]);
+    }
+
+    #[test]
+    fn rejects_invalid_data() {
+        let mut output = Vec::new();
+        // Copy with nothing before it
+        assert!(decompress(&[4, 1 << 2 | COPY_1, 1], &mut output, 100).is_err());
+        // Header says 5 bytes, data has 1
+        assert!(decompress(&[5, 0, b'a'], &mut output, 100).is_err());
+        // Header says 1 byte, data has 2
+        assert!(decompress(&[1, 1 << 2, b'a', b'b'], &mut output, 100).is_err());
+        assert!(decompress(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF], &mut output, 100).is_err());
+    }
+}
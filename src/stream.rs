@@ -0,0 +1,536 @@
+//! Streaming compression with any registered codec, in the spirit of
+//! flate2's encoders and decoders: `Encoder` compresses what is written to
+//! it and `Decoder` decompresses what is read from it, so input of any size
+//! is processed in bounded memory.
+//!
+//! Output of comprust's own codecs is framed by `container::Writer` and
+//! `container::Reader`; standalone formats use their own streaming types.
+
+use std::io::{self, Read, Write};
+
+use crate::bwt::bzip2::{self, Bzip2Decoder, Bzip2Encoder};
+use crate::checksum::ChecksumKind;
+use crate::codec::{self, Codec};
+use crate::container::{self, invalid_data, Header, DEFAULT_BLOCK_SIZE, HEADER_LEN, MAGIC};
+use crate::deflate::gzip::{self, GzDecoder, GzEncoder};
+use crate::deflate::zlib::{ZlibDecoder, ZlibEncoder};
+use crate::deflate::{DeflateDecoder, DeflateEncoder};
+use crate::lz4::{self, Lz4Decoder, Lz4Encoder};
+use crate::lzma::xz::{self, XzDecoder};
+use crate::lzma::{self, LzmaDecoder, LzmaEncoder};
+use crate::lzw::{self, LzwDecoder, LzwEncoder};
+use crate::snappy::{self, SnappyDecoder, SnappyEncoder};
+use crate::zstd::{self, ZstdDecoder};
+
+/// Settings for the container written around comprust's own codecs.
+/// Standalone formats have their own framing and checksums and ignore them.
+#[derive(Debug, Clone)]
+pub struct EncoderOptions {
+    /// Checksum of the original data, verified when decoding.
+    pub checksum: ChecksumKind,
+    /// Input bytes per block, between `container::MIN_BLOCK_SIZE` and
+    /// `container::MAX_BLOCK_SIZE`. Smaller blocks adapt faster to changing
+    /// data, larger ones spread each block's header over more input.
+    pub block_size: usize,
+    /// Blocks encoded in parallel, 0 for one per available core. Memory use
+    /// grows with `threads * block_size`.
+    pub threads: usize,
+}
+
+impl Default for EncoderOptions {
+    fn default() -> Self {
+        Self {
+            checksum: ChecksumKind::Crc32,
+            block_size: DEFAULT_BLOCK_SIZE,
+            threads: 1,
+        }
+    }
+}
+
+/// Settings for decoding the container around comprust's own codecs.
+/// Standalone formats ignore them.
+#[derive(Debug, Clone)]
+pub struct DecoderOptions {
+    /// Blocks decoded in parallel, 0 for one per available core.
+    pub threads: usize,
+}
+
+impl Default for DecoderOptions {
+    fn default() -> Self {
+        Self { threads: 1 }
+    }
+}
+
+/// Passes writes through, counting the bytes.
+struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+enum EncoderInner<W: Write> {
+    Container(container::Writer<CountingWriter<W>>),
+    Deflate(DeflateEncoder<CountingWriter<W>>),
+    Zlib(ZlibEncoder<CountingWriter<W>>),
+    Gzip(GzEncoder<CountingWriter<W>>),
+    Lzw(LzwEncoder<CountingWriter<W>>),
+    Lz4(Lz4Encoder<CountingWriter<W>>),
+    Snappy(SnappyEncoder<CountingWriter<W>>),
+    Lzma(LzmaEncoder<CountingWriter<W>>),
+    Bzip2(Bzip2Encoder<CountingWriter<W>>),
+}
+
+/// Compresses everything written to it with a codec.
+///
+/// Call `finish` when done; dropping the encoder without it leaves the
+/// output truncated.
+pub struct Encoder<W: Write> {
+    inner: EncoderInner<W>,
+    total_in: u64,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Fails if `codec` is not registered.
+    pub fn new(writer: W, codec: Box<dyn Codec>) -> io::Result<Self> {
+        Self::with_options(writer, codec, &EncoderOptions::default())
+    }
+
+    /// Fails if `codec` is not registered or `options` are invalid.
+    pub fn with_options(
+        writer: W,
+        codec: Box<dyn Codec>,
+        options: &EncoderOptions,
+    ) -> io::Result<Self> {
+        let writer = CountingWriter {
+            inner: writer,
+            count: 0,
+        };
+        let standalone = codec::codec_info(codec.name()).is_some_and(|info| info.standalone);
+
+        let inner = if !standalone {
+            EncoderInner::Container(container::Writer::new(writer, codec, options)?)
+        } else {
+            match codec.name() {
+                "deflate" => EncoderInner::Deflate(DeflateEncoder::new(writer)),
+                "zlib" => EncoderInner::Zlib(ZlibEncoder::new(writer)),
+                "gzip" => EncoderInner::Gzip(GzEncoder::new(writer)),
+                "lzw" => EncoderInner::Lzw(LzwEncoder::new(writer)),
+                "lz4" => EncoderInner::Lz4(Lz4Encoder::new(writer)),
+                "snappy" => EncoderInner::Snappy(SnappyEncoder::new(writer)),
+                "zstd" => return Err(zstd::no_encoder()),
+                "lzma" => EncoderInner::Lzma(LzmaEncoder::new(writer)),
+                "bzip2" => EncoderInner::Bzip2(Bzip2Encoder::new(writer)),
+                name => return Err(no_streaming_support(name)),
+            }
+        };
+
+        Ok(Self { inner, total_in: 0 })
+    }
+
+    /// Bytes written to the encoder so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Compressed bytes written to the underlying writer so far.
+    pub fn total_out(&self) -> u64 {
+        self.counter().count
+    }
+
+    /// Bits of codec output so far, as reported by the codec. For standalone
+    /// formats this is all of their output.
+    pub fn payload_bits(&self) -> u64 {
+        match &self.inner {
+            EncoderInner::Container(writer) => writer.payload_bits(),
+            _ => self.total_out() * 8,
+        }
+    }
+
+    fn counter(&self) -> &CountingWriter<W> {
+        match &self.inner {
+            EncoderInner::Container(writer) => writer.get_ref(),
+            EncoderInner::Deflate(encoder) => encoder.get_ref(),
+            EncoderInner::Zlib(encoder) => encoder.get_ref(),
+            EncoderInner::Gzip(encoder) => encoder.get_ref(),
+            EncoderInner::Lzw(encoder) => encoder.get_ref(),
+            EncoderInner::Lz4(encoder) => encoder.get_ref(),
+            EncoderInner::Snappy(encoder) => encoder.get_ref(),
+            EncoderInner::Lzma(encoder) => encoder.get_ref(),
+            EncoderInner::Bzip2(encoder) => encoder.get_ref(),
+        }
+    }
+
+    /// Compresses what is left and writes any trailer. Does nothing if the
+    /// output is already finished.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        match &mut self.inner {
+            EncoderInner::Container(writer) => writer.try_finish(),
+            EncoderInner::Deflate(encoder) => encoder.try_finish(),
+            EncoderInner::Zlib(encoder) => encoder.try_finish(),
+            EncoderInner::Gzip(encoder) => encoder.try_finish(),
+            EncoderInner::Lzw(encoder) => encoder.try_finish(),
+            EncoderInner::Lz4(encoder) => encoder.try_finish(),
+            EncoderInner::Snappy(encoder) => encoder.try_finish(),
+            EncoderInner::Lzma(encoder) => encoder.try_finish(),
+            EncoderInner::Bzip2(encoder) => encoder.try_finish(),
+        }
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        let counter = match self.inner {
+            EncoderInner::Container(writer) => writer.finish()?,
+            EncoderInner::Deflate(encoder) => encoder.finish()?,
+            EncoderInner::Zlib(encoder) => encoder.finish()?,
+            EncoderInner::Gzip(encoder) => encoder.finish()?,
+            EncoderInner::Lzw(encoder) => encoder.finish()?,
+            EncoderInner::Lz4(encoder) => encoder.finish()?,
+            EncoderInner::Snappy(encoder) => encoder.finish()?,
+            EncoderInner::Lzma(encoder) => encoder.finish()?,
+            EncoderInner::Bzip2(encoder) => encoder.finish()?,
+        };
+        Ok(counter.inner)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let n = match &mut self.inner {
+            EncoderInner::Container(writer) => writer.write(data)?,
+            EncoderInner::Deflate(encoder) => encoder.write(data)?,
+            EncoderInner::Zlib(encoder) => encoder.write(data)?,
+            EncoderInner::Gzip(encoder) => encoder.write(data)?,
+            EncoderInner::Lzw(encoder) => encoder.write(data)?,
+            EncoderInner::Lz4(encoder) => encoder.write(data)?,
+            EncoderInner::Snappy(encoder) => encoder.write(data)?,
+            EncoderInner::Lzma(encoder) => encoder.write(data)?,
+            EncoderInner::Bzip2(encoder) => encoder.write(data)?,
+        };
+        self.total_in += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.inner {
+            EncoderInner::Container(writer) => writer.flush(),
+            EncoderInner::Deflate(encoder) => encoder.flush(),
+            EncoderInner::Zlib(encoder) => encoder.flush(),
+            EncoderInner::Gzip(encoder) => encoder.flush(),
+            EncoderInner::Lzw(encoder) => encoder.flush(),
+            EncoderInner::Lz4(encoder) => encoder.flush(),
+            EncoderInner::Snappy(encoder) => encoder.flush(),
+            EncoderInner::Lzma(encoder) => encoder.flush(),
+            EncoderInner::Bzip2(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Input of a standalone format, with the bytes read while detecting the
+/// format put back in front.
+type Prefixed<R> = io::Chain<io::Cursor<Vec<u8>>, R>;
+
+enum DecoderInner<R: Read> {
+    Container(container::Reader<R>),
+    Deflate(DeflateDecoder<Prefixed<R>>),
+    Zlib(ZlibDecoder<Prefixed<R>>),
+    Gzip(GzDecoder<Prefixed<R>>),
+    Lzw(LzwDecoder<Prefixed<R>>),
+    Lz4(Lz4Decoder<Prefixed<R>>),
+    Snappy(SnappyDecoder<Prefixed<R>>),
+    Zstd(ZstdDecoder<Prefixed<R>>),
+    Lzma(LzmaDecoder<Prefixed<R>>),
+    Bzip2(Bzip2Decoder<Prefixed<R>>),
+    Xz(XzDecoder<Prefixed<R>>),
+}
+
+/// Decompresses everything read from it.
+///
+/// Corrupted input shows up as an error from `read`, at the latest once the
+/// end is reached and the checksum is verified.
+pub struct Decoder<R: Read> {
+    inner: DecoderInner<R>,
+    codec: &'static str,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Picks the codec from the container header or, for standalone formats,
+    /// from their magic bytes. Raw deflate streams have neither and need
+    /// `with_codec`.
+    pub fn new(reader: R) -> io::Result<Self> {
+        Self::with_options(reader, None, &DecoderOptions::default())
+    }
+
+    /// Expects input encoded with `codec`. Containers written by a different
+    /// codec are rejected; input without a container header is only
+    /// accepted for standalone formats, which is how those that cannot be
+    /// detected (like raw deflate) are decoded.
+    pub fn with_codec(reader: R, codec: &dyn Codec) -> io::Result<Self> {
+        Self::with_options(reader, Some(codec), &DecoderOptions::default())
+    }
+
+    /// Like `with_codec` when `codec` is given and `new` otherwise.
+    pub fn with_options(
+        reader: R,
+        codec: Option<&dyn Codec>,
+        options: &DecoderOptions,
+    ) -> io::Result<Self> {
+        match codec {
+            Some(codec) => Self::expect(reader, codec, options),
+            None => Self::detect(reader, options),
+        }
+    }
+
+    fn detect(mut reader: R, options: &DecoderOptions) -> io::Result<Self> {
+        let (prefix, len) = read_prefix(&mut reader)?;
+
+        if prefix[..4] == MAGIC {
+            if len < HEADER_LEN {
+                return Err(invalid_data("truncated container header"));
+            }
+            return Self::from_container(reader, Header::parse(&prefix)?, options);
+        }
+
+        let name = detect_standalone(&prefix[..len]).ok_or_else(|| {
+            invalid_data("unrecognized format, use -a to select the codec it was encoded with")
+        })?;
+        Self::standalone(name, prefixed(&prefix[..len], reader))
+    }
+
+    fn expect(mut reader: R, codec: &dyn Codec, options: &DecoderOptions) -> io::Result<Self> {
+        let (prefix, len) = read_prefix(&mut reader)?;
+
+        if len == HEADER_LEN && prefix[..4] == MAGIC {
+            let decoder = Self::from_container(reader, Header::parse(&prefix)?, options)?;
+            if decoder.codec != codec.name() {
+                return Err(invalid_data(&format!(
+                    "input was encoded with '{}', not '{}'",
+                    decoder.codec,
+                    codec.name()
+                )));
+            }
+            return Ok(decoder);
+        }
+
+        let standalone = codec::codec_info(codec.name()).is_some_and(|info| info.standalone);
+        if !standalone {
+            return Err(invalid_data(&format!(
+                "input has no container header, so it was not encoded with '{}'",
+                codec.name()
+            )));
+        }
+        Self::standalone(codec.name(), prefixed(&prefix[..len], reader))
+    }
+
+    /// Name of the codec the input was encoded with.
+    pub fn codec(&self) -> &'static str {
+        self.codec
+    }
+
+    fn from_container(reader: R, header: Header, options: &DecoderOptions) -> io::Result<Self> {
+        let reader = container::Reader::new(reader, header, options.threads)?;
+        Ok(Self {
+            codec: reader.codec().name(),
+            inner: DecoderInner::Container(reader),
+        })
+    }
+
+    fn standalone(name: &str, reader: Prefixed<R>) -> io::Result<Self> {
+        let (codec, inner) = match name {
+            "deflate" => (
+                "deflate",
+                DecoderInner::Deflate(DeflateDecoder::new(reader)),
+            ),
+            "zlib" => ("zlib", DecoderInner::Zlib(ZlibDecoder::new(reader)?)),
+            "gzip" => ("gzip", DecoderInner::Gzip(GzDecoder::new(reader)?)),
+            "lzw" => ("lzw", DecoderInner::Lzw(LzwDecoder::new(reader)?)),
+            "lz4" => ("lz4", DecoderInner::Lz4(Lz4Decoder::new(reader)?)),
+            "snappy" => ("snappy", DecoderInner::Snappy(SnappyDecoder::new(reader)?)),
+            "zstd" => ("zstd", DecoderInner::Zstd(ZstdDecoder::new(reader)?)),
+            // .xz and .lzma files are both read as "lzma"
+            "lzma" if reader.get_ref().0.get_ref().starts_with(&xz::MAGIC) => {
+                ("lzma", DecoderInner::Xz(XzDecoder::new(reader)?))
+            }
+            "lzma" => ("lzma", DecoderInner::Lzma(LzmaDecoder::new(reader)?)),
+            "bzip2" => ("bzip2", DecoderInner::Bzip2(Bzip2Decoder::new(reader)?)),
+            _ => return Err(no_streaming_support(name)),
+        };
+        Ok(Self { inner, codec })
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.inner {
+            DecoderInner::Container(reader) => reader.read(buf),
+            DecoderInner::Deflate(decoder) => decoder.read(buf),
+            DecoderInner::Zlib(decoder) => decoder.read(buf),
+            DecoderInner::Gzip(decoder) => decoder.read(buf),
+            DecoderInner::Lzw(decoder) => decoder.read(buf),
+            DecoderInner::Lz4(decoder) => decoder.read(buf),
+            DecoderInner::Snappy(decoder) => decoder.read(buf),
+            DecoderInner::Zstd(decoder) => decoder.read(buf),
+            DecoderInner::Lzma(decoder) => decoder.read(buf),
+            DecoderInner::Bzip2(decoder) => decoder.read(buf),
+            DecoderInner::Xz(decoder) => decoder.read(buf),
+        }
+    }
+}
+
+/// Identifies standalone formats by their leading bytes.
+fn detect_standalone(prefix: &[u8]) -> Option<&'static str> {
+    match prefix {
+        [a, b, ..] if [*a, *b] == gzip::MAGIC => Some("gzip"),
+        [a, b, ..] if [*a, *b] == lzw::MAGIC => Some("lzw"),
+        prefix if prefix.starts_with(&lz4::MAGIC) => Some("lz4"),
+        prefix if prefix.starts_with(&snappy::MAGIC) => Some("snappy"),
+        prefix if prefix.starts_with(&zstd::MAGIC) => Some("zstd"),
+        prefix if prefix.starts_with(&xz::MAGIC) => Some("lzma"),
+        [b, z, h, level, ..] if [*b, *z, *h] == bzip2::MAGIC && (b'1'..=b'9').contains(level) => {
+            Some("bzip2")
+        }
+        // zlib: compression method 8, window <= 32K and a valid header check
+        [cmf, flg, ..]
+            if cmf & 0x0F == 8 && cmf >> 4 <= 7 && u16::from_be_bytes([*cmf, *flg]) % 31 == 0 =>
+        {
+            Some("zlib")
+        }
+        // .lzma files have no magic bytes, so they are tried last
+        prefix if lzma::is_lzma_header(prefix) => Some("lzma"),
+        _ => None,
+    }
+}
+
+/// Reads up to a container header's worth of bytes. Returns the buffer and
+/// how many bytes were actually available.
+fn read_prefix<R: Read>(reader: &mut R) -> io::Result<([u8; HEADER_LEN], usize)> {
+    let mut prefix = [0; HEADER_LEN];
+    let mut len = 0;
+    while len < HEADER_LEN {
+        match reader.read(&mut prefix[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok((prefix, len))
+}
+
+fn prefixed<R: Read>(prefix: &[u8], reader: R) -> Prefixed<R> {
+    io::Cursor::new(prefix.to_vec()).chain(reader)
+}
+
+fn no_streaming_support(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("'{}' does not support streaming", name),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{get_codec, CODECS};
+    use crate::deflate::tests::LOREM;
+
+    /// Writes `data` in small pieces, the way `io::copy` from a file would.
+    fn encode_in_pieces(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut encoder = Encoder::new(Vec::new(), get_codec(name).unwrap()).unwrap();
+        for piece in data.chunks(1000) {
+            encoder.write_all(piece).unwrap();
+        }
+        assert_eq!(encoder.total_in(), data.len() as u64);
+        encoder.finish().unwrap()
+    }
+
+    /// Reads everything back through a small buffer.
+    fn decode_in_pieces(encoded: &[u8]) -> (&'static str, Vec<u8>) {
+        let mut decoder = Decoder::new(encoded).unwrap();
+        let mut output = Vec::new();
+        let mut buf = [0; 333];
+        loop {
+            match decoder.read(&mut buf).unwrap() {
+                0 => break,
+                n => output.extend_from_slice(&buf[..n]),
+            }
+        }
+        (decoder.codec(), output)
+    }
+
+    #[test]
+    fn streams_every_codec() {
+        let mut data = LOREM.repeat(4);
+        data.extend(vec![0u8; 50_000]);
+
+        // Raw deflate has no magic bytes and zstd is only decoded
+        for info in CODECS
+            .iter()
+            .filter(|info| !matches!(info.name, "deflate" | "zstd"))
+        {
+            let encoded = encode_in_pieces(info.name, &data);
+            let (codec, output) = decode_in_pieces(&encoded);
+
+            assert_eq!(codec, info.name);
+            assert_eq!(output, data);
+        }
+    }
+
+    #[test]
+    fn streams_raw_deflate_with_codec() {
+        let codec = get_codec("deflate").unwrap();
+        let encoded = encode_in_pieces("deflate", LOREM);
+        let mut decoder = Decoder::with_codec(encoded.as_slice(), codec.as_ref()).unwrap();
+        let mut output = Vec::new();
+
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(output, LOREM);
+    }
+
+    #[test]
+    fn streams_gzip_across_deflate_chunks() {
+        // Larger than the chunk DeflateEncoder compresses at a time
+        let data: Vec<u8> = (0..3_000_000u32)
+            .map(|i| (i % 253) as u8 ^ (i >> 12) as u8)
+            .collect();
+        let encoded = encode_in_pieces("gzip", &data);
+        let (_, output) = decode_in_pieces(&encoded);
+
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn counts_bytes_in_and_out() {
+        let options = EncoderOptions {
+            checksum: ChecksumKind::None,
+            ..EncoderOptions::default()
+        };
+        let mut encoder =
+            Encoder::with_options(Vec::new(), get_codec("rle").unwrap(), &options).unwrap();
+        encoder.write_all(b"aaaa").unwrap();
+        encoder.try_finish().unwrap();
+
+        assert_eq!(encoder.total_in(), 4);
+        // Header, one block of 2 bytes, end marker and trailer
+        assert_eq!(encoder.total_out(), 11 + 9 + 2 + 1 + 12);
+        assert_eq!(encoder.payload_bits(), 16);
+        assert_eq!(encoder.finish().unwrap().len(), 35);
+    }
+
+    #[test]
+    fn reports_corruption_from_read() {
+        let mut encoded = encode_in_pieces("huffman", LOREM);
+        let last = encoded.len() - 1;
+        encoded[last] ^= 0x01;
+        let mut decoder = Decoder::new(encoded.as_slice()).unwrap();
+
+        assert!(decoder.read_to_end(&mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use std::io;
+
+use super::invalid_data;
+
+/// Reads a bitstream from its end towards its start, the way zstd's FSE and
+/// Huffman streams are read. The highest set bit of the last byte marks
+/// where the stream begins; the bits above it are padding.
+///
+/// Reading past the start yields zero bits, so decoders can tell when they
+/// have read too far with `overflowed` instead of failing on every read.
+pub struct BackwardBitReader<'a> {
+    data: &'a [u8],
+    /// Bits left to read, negative once reads went past the start.
+    pos: isize,
+}
+
+impl<'a> BackwardBitReader<'a> {
+    pub fn new(data: &'a [u8]) -> io::Result<Self> {
+        let last = *data.last().ok_or_else(|| invalid_data("empty bitstream"))?;
+        if last == 0 {
+            return Err(invalid_data("bitstream has no start marker"));
+        }
+        let padding = last.leading_zeros() as isize + 1;
+        Ok(Self {
+            data,
+            pos: data.len() as isize * 8 - padding,
+        })
+    }
+
+    /// The next `count` (at most 56) bits without consuming them, the first
+    /// read being the most significant.
+    pub fn peek_bits(&self, count: u8) -> u64 {
+        let count = count as isize;
+        let start = self.pos - count;
+        if start >= 0 {
+            self.bits_at(start as usize, count as usize)
+        } else if self.pos > 0 {
+            self.bits_at(0, self.pos as usize) << -start
+        } else {
+            0
+        }
+    }
+
+    pub fn consume(&mut self, count: u8) {
+        self.pos -= count as isize;
+    }
+
+    pub fn read_bits(&mut self, count: u8) -> u64 {
+        let value = self.peek_bits(count);
+        self.consume(count);
+        value
+    }
+
+    /// True once every bit has been read, and no more.
+    pub fn is_empty(&self) -> bool {
+        self.pos == 0
+    }
+
+    /// True once more bits were read than the stream holds.
+    pub fn overflowed(&self) -> bool {
+        self.pos < 0
+    }
+
+    /// `count` bits starting at bit `start`, counting from the first byte's
+    /// lowest bit.
+    fn bits_at(&self, start: usize, count: usize) -> u64 {
+        if count == 0 {
+            return 0;
+        }
+        let byte = start / 8;
+        let mut word = [0u8; 8];
+        let available = (self.data.len() - byte).min(8);
+        word[..available].copy_from_slice(&self.data[byte..byte + available]);
+        (u64::from_le_bytes(word) >> (start % 8)) & ((1 << count) - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_from_the_end() {
+        // Marker at bit 5 of the last byte, then 10110 and 1 from the first
+        let data = [0b1000_0000, 0b0011_0110];
+        let mut bits = BackwardBitReader::new(&data).unwrap();
+
+        assert_eq!(bits.read_bits(3), 0b101);
+        assert_eq!(bits.peek_bits(2), 0b10);
+        assert_eq!(bits.read_bits(3), 0b101);
+        assert_eq!(bits.read_bits(7), 0);
+        assert!(bits.is_empty());
+
+        assert_eq!(bits.read_bits(2), 0);
+        assert!(bits.overflowed());
+        assert!(BackwardBitReader::new(&[1, 0]).is_err());
+    }
+}
@@ -0,0 +1,450 @@
+//! Compressed blocks: a literals section, then sequences that interleave
+//! runs of those literals with matches into the data decoded before.
+
+use std::io;
+
+use super::bits::BackwardBitReader;
+use super::fse::{FseState, FseTable};
+use super::huffman::HuffmanTable;
+use super::invalid_data;
+
+/// Most data a block may decode to.
+pub const MAX_BLOCK_SIZE: usize = 128 * 1024;
+
+/// Literals section types.
+const RAW_LITERALS: u8 = 0;
+const RLE_LITERALS: u8 = 1;
+const COMPRESSED_LITERALS: u8 = 2;
+const TREELESS_LITERALS: u8 = 3;
+
+/// Ways a sequence code table can be given.
+const PREDEFINED: u8 = 0;
+const RLE: u8 = 1;
+const FSE_COMPRESSED: u8 = 2;
+const REPEAT: u8 = 3;
+
+/// How one of the three sequence codes is coded.
+struct CodeKind {
+    max_symbol: u8,
+    max_log: u8,
+    predefined: &'static [i16],
+    predefined_log: u8,
+}
+
+const LITERAL_LENGTHS: CodeKind = CodeKind {
+    max_symbol: 35,
+    max_log: 9,
+    predefined: &[
+        4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1,
+        1, 1, -1, -1, -1, -1,
+    ],
+    predefined_log: 6,
+};
+
+const MATCH_LENGTHS: CodeKind = CodeKind {
+    max_symbol: 52,
+    max_log: 9,
+    predefined: &[
+        1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+    ],
+    predefined_log: 6,
+};
+
+const OFFSETS: CodeKind = CodeKind {
+    max_symbol: 31,
+    max_log: 8,
+    predefined: &[
+        1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+    ],
+    predefined_log: 5,
+};
+
+/// Baseline and extra bits of literal length codes 16 and up; lower codes
+/// are the length itself.
+const LITERAL_LENGTH_CODES: [(u32, u8); 20] = [
+    (16, 1),
+    (18, 1),
+    (20, 1),
+    (22, 1),
+    (24, 2),
+    (28, 2),
+    (32, 3),
+    (40, 3),
+    (48, 4),
+    (64, 6),
+    (128, 7),
+    (256, 8),
+    (512, 9),
+    (1024, 10),
+    (2048, 11),
+    (4096, 12),
+    (8192, 13),
+    (16384, 14),
+    (32768, 15),
+    (65536, 16),
+];
+
+/// Baseline and extra bits of match length codes 32 and up; lower codes
+/// are the length minus 3.
+const MATCH_LENGTH_CODES: [(u32, u8); 21] = [
+    (35, 1),
+    (37, 1),
+    (39, 1),
+    (41, 1),
+    (43, 2),
+    (47, 2),
+    (51, 3),
+    (59, 3),
+    (67, 4),
+    (83, 4),
+    (99, 5),
+    (131, 7),
+    (259, 8),
+    (515, 9),
+    (1027, 10),
+    (2051, 11),
+    (4099, 12),
+    (8195, 13),
+    (16387, 14),
+    (32771, 15),
+    (65539, 16),
+];
+
+/// Decodes the compressed blocks of a frame, keeping what later blocks may
+/// reuse: the Huffman and FSE tables and the three repeat offsets.
+pub struct BlockDecoder {
+    huffman: Option<HuffmanTable>,
+    literal_lengths: Option<FseTable>,
+    offsets: Option<FseTable>,
+    match_lengths: Option<FseTable>,
+    repeat_offsets: [usize; 3],
+    literals: Vec<u8>,
+}
+
+impl BlockDecoder {
+    pub fn new() -> Self {
+        Self {
+            huffman: None,
+            literal_lengths: None,
+            offsets: None,
+            match_lengths: None,
+            repeat_offsets: [1, 4, 8],
+            literals: Vec::new(),
+        }
+    }
+
+    /// Decodes a compressed block onto the end of `output`, which holds the
+    /// frame's data so far for matches to refer to, up to `window_size`
+    /// bytes back.
+    pub fn decode(
+        &mut self,
+        block: &[u8],
+        output: &mut Vec<u8>,
+        window_size: usize,
+    ) -> io::Result<()> {
+        let len = self.read_literals(block)?;
+        let sequences = &block[len..];
+
+        let (count, len) = match *sequences {
+            [] => return Err(invalid_data("missing sequences section")),
+            [0, ..] => (0, 1),
+            [byte, ..] if byte < 128 => (byte as usize, 1),
+            [byte, next, ..] if byte < 255 => ((((byte - 128) as usize) << 8) + next as usize, 2),
+            [255, low, high, ..] => (0x7F00 + u16::from_le_bytes([low, high]) as usize, 3),
+            _ => return Err(invalid_data("truncated sequences section")),
+        };
+        if count == 0 {
+            if len != sequences.len() {
+                return Err(invalid_data("data after an empty sequences section"));
+            }
+            output.extend_from_slice(&self.literals);
+            return Ok(());
+        }
+        self.read_sequences(&sequences[len..], count, output, window_size)
+    }
+
+    /// Decodes the literals section into `literals`. Returns its length.
+    fn read_literals(&mut self, block: &[u8]) -> io::Result<usize> {
+        let byte = *block
+            .first()
+            .ok_or_else(|| invalid_data("empty compressed block"))?;
+        let literals_type = byte & 0x03;
+        let size_format = (byte >> 2) & 0x03;
+        let field = |len: usize| -> io::Result<u64> {
+            let bytes = block
+                .get(..len)
+                .ok_or_else(|| invalid_data("truncated literals header"))?;
+            Ok(bytes
+                .iter()
+                .rev()
+                .fold(0, |value, &byte| value << 8 | byte as u64))
+        };
+
+        self.literals.clear();
+        if literals_type == RAW_LITERALS || literals_type == RLE_LITERALS {
+            let (header_len, size) = match size_format {
+                0 | 2 => (1, byte as usize >> 3),
+                1 => (2, field(2)? as usize >> 4),
+                _ => (3, field(3)? as usize >> 4),
+            };
+            if size > MAX_BLOCK_SIZE {
+                return Err(invalid_data("more than 128K literals"));
+            }
+            if literals_type == RAW_LITERALS {
+                let data = block
+                    .get(header_len..header_len + size)
+                    .ok_or_else(|| invalid_data("truncated literals"))?;
+                self.literals.extend_from_slice(data);
+                return Ok(header_len + size);
+            }
+            let &value = block
+                .get(header_len)
+                .ok_or_else(|| invalid_data("truncated literals"))?;
+            self.literals.resize(size, value);
+            return Ok(header_len + 1);
+        }
+
+        // Sizes of 10, 14 or 18 bits each, the regenerated one first
+        let (header_len, size_bits) = match size_format {
+            0 | 1 => (3, 10),
+            2 => (4, 14),
+            _ => (5, 18),
+        };
+        let header = field(header_len)?;
+        let mask = (1 << size_bits) - 1;
+        let size = (header >> 4 & mask) as usize;
+        let compressed_size = (header >> (4 + size_bits) & mask) as usize;
+        if size > MAX_BLOCK_SIZE {
+            return Err(invalid_data("more than 128K literals"));
+        }
+        let mut data = block
+            .get(header_len..header_len + compressed_size)
+            .ok_or_else(|| invalid_data("truncated literals"))?;
+
+        if literals_type == COMPRESSED_LITERALS {
+            let (table, len) = HuffmanTable::read(data)?;
+            self.huffman = Some(table);
+            data = &data[len..];
+        } else {
+            debug_assert_eq!(literals_type, TREELESS_LITERALS);
+        }
+        let table = self
+            .huffman
+            .as_ref()
+            .ok_or_else(|| invalid_data("literals reuse a Huffman tree that was never sent"))?;
+        table.decode(data, size, size_format != 0, &mut self.literals)?;
+        Ok(header_len + compressed_size)
+    }
+
+    /// Reads the code tables, then executes `count` sequences.
+    fn read_sequences(
+        &mut self,
+        data: &[u8],
+        count: usize,
+        output: &mut Vec<u8>,
+        window_size: usize,
+    ) -> io::Result<()> {
+        let modes = *data
+            .first()
+            .ok_or_else(|| invalid_data("truncated sequences section"))?;
+        if modes & 0x03 != 0 {
+            return Err(invalid_data("reserved bits set in the sequence modes"));
+        }
+        let mut pos = 1;
+        for (shift, kind, table) in [
+            (6, &LITERAL_LENGTHS, &mut self.literal_lengths),
+            (4, &OFFSETS, &mut self.offsets),
+            (2, &MATCH_LENGTHS, &mut self.match_lengths),
+        ] {
+            pos += read_table((modes >> shift) & 0x03, kind, &data[pos..], table)?;
+        }
+        let (Some(ll_table), Some(of_table), Some(ml_table)) =
+            (&self.literal_lengths, &self.offsets, &self.match_lengths)
+        else {
+            return Err(invalid_data("sequences reuse a table that was never sent"));
+        };
+
+        let mut bits = BackwardBitReader::new(&data[pos..])?;
+        let mut ll_state = FseState::new(ll_table, &mut bits);
+        let mut of_state = FseState::new(of_table, &mut bits);
+        let mut ml_state = FseState::new(ml_table, &mut bits);
+        let mut literals = self.literals.as_slice();
+        let start = output.len();
+
+        for i in 0..count {
+            let of_code = of_state.symbol();
+            let ml_code = ml_state.symbol() as usize;
+            let ll_code = ll_state.symbol() as usize;
+            let offset_value = (1usize << of_code) + bits.read_bits(of_code) as usize;
+            let match_len = match ml_code {
+                0..=31 => ml_code + 3,
+                _ => {
+                    let (baseline, extra) = MATCH_LENGTH_CODES[ml_code - 32];
+                    baseline as usize + bits.read_bits(extra) as usize
+                }
+            };
+            let literal_len = match ll_code {
+                0..=15 => ll_code,
+                _ => {
+                    let (baseline, extra) = LITERAL_LENGTH_CODES[ll_code - 16];
+                    baseline as usize + bits.read_bits(extra) as usize
+                }
+            };
+
+            if i + 1 < count {
+                ll_state.update(&mut bits);
+                ml_state.update(&mut bits);
+                of_state.update(&mut bits);
+            }
+            if bits.overflowed() {
+                return Err(invalid_data("sequences read past the bitstream"));
+            }
+
+            let offset = resolve_offset(&mut self.repeat_offsets, offset_value, literal_len)?;
+            if literal_len > literals.len() {
+                return Err(invalid_data("sequence uses more literals than decoded"));
+            }
+            output.extend_from_slice(&literals[..literal_len]);
+            literals = &literals[literal_len..];
+
+            if offset > output.len() {
+                return Err(invalid_data("match offset before the start of the frame"));
+            }
+            if offset > window_size {
+                return Err(invalid_data("match offset beyond the window"));
+            }
+            if output.len() + match_len - start > MAX_BLOCK_SIZE {
+                return Err(invalid_data("block decodes to more than 128K"));
+            }
+            copy_match(output, offset, match_len);
+        }
+
+        if !bits.is_empty() {
+            return Err(invalid_data("sequences do not use the whole bitstream"));
+        }
+        output.extend_from_slice(literals);
+        if output.len() - start > MAX_BLOCK_SIZE {
+            return Err(invalid_data("block decodes to more than 128K"));
+        }
+        Ok(())
+    }
+}
+
+impl Default for BlockDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Turns an offset value into a distance, updating the repeat offsets.
+/// Values 1 to 3 pick a repeat offset, shifted by one after a sequence
+/// without literals; larger ones are the distance plus 3.
+fn resolve_offset(rep: &mut [usize; 3], value: usize, literal_len: usize) -> io::Result<usize> {
+    if value > 3 {
+        let offset = value - 3;
+        *rep = [offset, rep[0], rep[1]];
+        return Ok(offset);
+    }
+
+    let index = if literal_len == 0 { value + 1 } else { value };
+    let offset = match index {
+        1 => return Ok(rep[0]),
+        2 => rep[1],
+        3 => rep[2],
+        _ => rep[0] - 1,
+    };
+    if offset == 0 {
+        return Err(invalid_data("zero match offset"));
+    }
+    if index == 2 {
+        *rep = [offset, rep[0], rep[2]];
+    } else {
+        *rep = [offset, rep[0], rep[1]];
+    }
+    Ok(offset)
+}
+
+/// Sets `table` from a sequence code table description in `mode`. Returns
+/// the number of bytes read.
+fn read_table(
+    mode: u8,
+    kind: &CodeKind,
+    data: &[u8],
+    table: &mut Option<FseTable>,
+) -> io::Result<usize> {
+    match mode {
+        PREDEFINED => {
+            *table = Some(FseTable::new(kind.predefined, kind.predefined_log)?);
+            Ok(0)
+        }
+        RLE => {
+            let &symbol = data
+                .first()
+                .ok_or_else(|| invalid_data("truncated sequences section"))?;
+            if symbol > kind.max_symbol {
+                return Err(invalid_data("invalid sequence code"));
+            }
+            *table = Some(FseTable::rle(symbol));
+            Ok(1)
+        }
+        FSE_COMPRESSED => {
+            let (fse, len) = FseTable::read(data, kind.max_symbol, kind.max_log)?;
+            *table = Some(fse);
+            Ok(len)
+        }
+        _ => {
+            debug_assert_eq!(mode, REPEAT);
+            Ok(0)
+        }
+    }
+}
+
+/// Appends `len` bytes copied from `offset` back, which may overlap what
+/// is being appended.
+fn copy_match(output: &mut Vec<u8>, offset: usize, len: usize) {
+    let start = output.len() - offset;
+    let mut copied = 0;
+    while copied < len {
+        let n = (len - copied).min(offset + copied);
+        output.extend_from_within(start..start + n);
+        copied += n;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updates_repeat_offsets() {
+        let mut rep = [1, 4, 8];
+
+        // A new offset pushes the others back
+        assert_eq!(resolve_offset(&mut rep, 13, 1).unwrap(), 10);
+        assert_eq!(rep, [10, 1, 4]);
+        // The second one moves to the front
+        assert_eq!(resolve_offset(&mut rep, 2, 1).unwrap(), 1);
+        assert_eq!(rep, [1, 10, 4]);
+        // Without literals, 1 means the second, and 3 the first minus one
+        assert_eq!(resolve_offset(&mut rep, 1, 0).unwrap(), 10);
+        assert_eq!(rep, [10, 1, 4]);
+        assert_eq!(resolve_offset(&mut rep, 3, 0).unwrap(), 9);
+        assert_eq!(rep, [9, 10, 1]);
+        assert_eq!(resolve_offset(&mut rep, 1, 5).unwrap(), 9);
+        assert_eq!(rep, [9, 10, 1]);
+    }
+
+    #[test]
+    fn decodes_raw_literals_without_sequences() {
+        // Raw literals header of 5 bytes, then no sequences
+        let block = [5 << 3, b'h', b'e', b'l', b'l', b'o', 0];
+        let mut output = b"say ".to_vec();
+        BlockDecoder::new().decode(&block, &mut output, 4).unwrap();
+        assert_eq!(output, b"say hello");
+
+        assert!(BlockDecoder::new()
+            .decode(&block[..6], &mut Vec::new(), 4)
+            .is_err());
+    }
+}
@@ -0,0 +1,248 @@
+//! Finite State Entropy (tANS) decoding tables, as zstd uses for sequence
+//! codes and Huffman weights.
+
+use std::io;
+
+use super::bits::BackwardBitReader;
+use super::invalid_data;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Entry {
+    symbol: u8,
+    num_bits: u8,
+    baseline: u16,
+}
+
+/// A decoding table: each state gives a symbol, and the bits to read to
+/// get from it to the next state.
+#[derive(Debug, Clone)]
+pub struct FseTable {
+    accuracy_log: u8,
+    entries: Vec<Entry>,
+}
+
+impl FseTable {
+    /// Builds the table for normalized counts that sum to
+    /// `1 << accuracy_log`, -1 standing for a "less than 1" probability.
+    pub fn new(counts: &[i16], accuracy_log: u8) -> io::Result<Self> {
+        let size = 1usize << accuracy_log;
+        let mut entries = vec![Entry::default(); size];
+        let mut next_state = vec![0u16; counts.len()];
+
+        // Symbols with probability "less than 1" take the last cells
+        let mut high = size;
+        for (symbol, &count) in counts.iter().enumerate() {
+            if count == -1 {
+                high = high
+                    .checked_sub(1)
+                    .ok_or_else(|| invalid_data("FSE table overflow"))?;
+                entries[high].symbol = symbol as u8;
+                next_state[symbol] = 1;
+            } else {
+                next_state[symbol] = count.max(0) as u16;
+            }
+        }
+
+        // The others are spread over the rest, skipping those cells
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mut pos = 0;
+        let mut placed = 0;
+        for (symbol, &count) in counts.iter().enumerate() {
+            for _ in 0..count.max(0) {
+                entries[pos].symbol = symbol as u8;
+                placed += 1;
+                loop {
+                    pos = (pos + step) & (size - 1);
+                    if pos < high {
+                        break;
+                    }
+                }
+            }
+        }
+        if placed != high || pos != 0 {
+            return Err(invalid_data("FSE counts do not fill the table"));
+        }
+
+        for entry in &mut entries {
+            let state = next_state[entry.symbol as usize];
+            next_state[entry.symbol as usize] += 1;
+            entry.num_bits = accuracy_log - (15 - state.leading_zeros() as u8);
+            entry.baseline = (((state as usize) << entry.num_bits) - size) as u16;
+        }
+
+        Ok(Self {
+            accuracy_log,
+            entries,
+        })
+    }
+
+    /// A table that always decodes `symbol` and reads no bits.
+    pub fn rle(symbol: u8) -> Self {
+        Self {
+            accuracy_log: 0,
+            entries: vec![Entry {
+                symbol,
+                num_bits: 0,
+                baseline: 0,
+            }],
+        }
+    }
+
+    /// Reads normalized counts for symbols up to `max_symbol` in the compact
+    /// format zstd stores them in, with an accuracy log of at most
+    /// `max_log`. Returns the table and the number of bytes read.
+    pub fn read(data: &[u8], max_symbol: u8, max_log: u8) -> io::Result<(Self, usize)> {
+        let mut bits = ForwardBits { data, pos: 0 };
+        let accuracy_log = bits.read(4)? as u8 + 5;
+        if accuracy_log > max_log {
+            return Err(invalid_data("FSE accuracy log too large"));
+        }
+
+        let mut counts = Vec::new();
+        let mut remaining = (1i32 << accuracy_log) + 1;
+        let mut threshold = 1i32 << accuracy_log;
+        let mut num_bits = accuracy_log + 1;
+        while remaining > 1 {
+            if counts.len() > max_symbol as usize {
+                return Err(invalid_data("too many FSE symbols"));
+            }
+
+            // Values below `max` fit one bit less
+            let max = 2 * threshold - 1 - remaining;
+            let low = bits.peek(num_bits - 1)? as i32;
+            let value = if low < max {
+                bits.pos += num_bits as usize - 1;
+                low
+            } else {
+                let value = bits.peek(num_bits)? as i32;
+                bits.pos += num_bits as usize;
+                if value >= threshold {
+                    value - max
+                } else {
+                    value
+                }
+            };
+
+            let count = value - 1;
+            remaining -= count.abs();
+            counts.push(count as i16);
+            while remaining < threshold {
+                num_bits -= 1;
+                threshold >>= 1;
+            }
+
+            // A zero count is followed by 2 bit repeat counts of more zeros
+            if count == 0 {
+                loop {
+                    let repeat = bits.read(2)?;
+                    counts.extend(std::iter::repeat_n(0, repeat as usize));
+                    if repeat != 3 {
+                        break;
+                    }
+                }
+            }
+        }
+        if remaining != 1 || counts.len() > max_symbol as usize + 1 {
+            return Err(invalid_data("invalid FSE counts"));
+        }
+
+        Ok((Self::new(&counts, accuracy_log)?, bits.pos.div_ceil(8)))
+    }
+}
+
+/// Decoding state over an `FseTable`.
+pub struct FseState<'t> {
+    table: &'t FseTable,
+    state: usize,
+}
+
+impl<'t> FseState<'t> {
+    pub fn new(table: &'t FseTable, bits: &mut BackwardBitReader) -> Self {
+        let state = bits.read_bits(table.accuracy_log) as usize;
+        Self { table, state }
+    }
+
+    pub fn symbol(&self) -> u8 {
+        self.table.entries[self.state].symbol
+    }
+
+    pub fn update(&mut self, bits: &mut BackwardBitReader) {
+        let entry = self.table.entries[self.state];
+        self.state = entry.baseline as usize + bits.read_bits(entry.num_bits) as usize;
+    }
+}
+
+/// Little-endian bits read from the start, for the table descriptions.
+struct ForwardBits<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl ForwardBits<'_> {
+    fn peek(&self, count: u8) -> io::Result<u32> {
+        let mut value = 0u32;
+        for i in 0..count as usize {
+            let bit = self.pos + i;
+            let byte = *self
+                .data
+                .get(bit / 8)
+                .ok_or_else(|| invalid_data("truncated FSE table"))?;
+            value |= ((byte >> (bit % 8)) as u32 & 1) << i;
+        }
+        Ok(value)
+    }
+
+    fn read(&mut self, count: u8) -> io::Result<u32> {
+        let value = self.peek(count)?;
+        self.pos += count as usize;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_tables_from_counts() {
+        // The predefined offset code distribution
+        let counts = [
+            1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1,
+            -1,
+        ];
+        let table = FseTable::new(&counts, 5).unwrap();
+
+        // Matches the table printed in RFC 8878, appendix A
+        let row = |state: usize| {
+            let entry = table.entries[state];
+            (entry.symbol, entry.num_bits, entry.baseline)
+        };
+        assert_eq!(row(0), (0, 5, 0));
+        assert_eq!(row(1), (6, 4, 0));
+        assert_eq!(row(2), (9, 5, 0));
+        assert_eq!(row(31), (24, 5, 0));
+        assert_eq!(row(27), (28, 5, 0));
+
+        assert!(FseTable::new(&[3, 3], 2).is_err());
+    }
+
+    #[test]
+    fn reads_compact_counts() {
+        // Accuracy log 5 and counts 16, 15 and 1, stored plus one: 17 in 5
+        // bits, 16 in 5 bits as 30, and 2 in 2 bits as 3
+        let mut value: u64 = 0;
+        let mut pos = 0;
+        for (field, width) in [(0, 4), (17, 5), (30, 5), (3, 2)] {
+            value |= (field as u64) << pos;
+            pos += width;
+        }
+        let data = value.to_le_bytes();
+
+        let (table, len) = FseTable::read(&data, 255, 9).unwrap();
+        assert_eq!(len, 2);
+        assert_eq!(table.accuracy_log, 5);
+        let symbols: Vec<u8> = table.entries.iter().map(|entry| entry.symbol).collect();
+        assert_eq!(symbols.iter().filter(|&&s| s == 1).count(), 15);
+        assert_eq!(symbols.iter().filter(|&&s| s == 2).count(), 1);
+    }
+}
@@ -0,0 +1,211 @@
+//! Huffman coded literals: the tree is described by symbol weights, and
+//! decoded with a table indexed by the next `max_bits` bits.
+
+use std::io;
+
+use super::bits::BackwardBitReader;
+use super::fse::{FseState, FseTable};
+use super::invalid_data;
+
+/// Longest code zstd allows.
+const MAX_BITS: u8 = 11;
+/// Highest accuracy log of the FSE table that compresses the weights.
+const WEIGHTS_MAX_LOG: u8 = 6;
+
+#[derive(Debug, Clone)]
+pub struct HuffmanTable {
+    max_bits: u8,
+    /// Symbol and code length for each value of the next `max_bits` bits.
+    entries: Vec<(u8, u8)>,
+}
+
+impl HuffmanTable {
+    /// Reads a tree description. Returns the table and the number of bytes
+    /// read.
+    pub fn read(data: &[u8]) -> io::Result<(Self, usize)> {
+        let header = *data
+            .first()
+            .ok_or_else(|| invalid_data("missing Huffman tree"))?;
+
+        let (weights, len) = if header < 128 {
+            // FSE compressed weights, decoded with two interleaved states
+            let len = 1 + header as usize;
+            let compressed = data
+                .get(1..len)
+                .ok_or_else(|| invalid_data("truncated Huffman tree"))?;
+            let (table, table_len) = FseTable::read(compressed, 255, WEIGHTS_MAX_LOG)?;
+            let mut bits = BackwardBitReader::new(&compressed[table_len..])?;
+            let mut states = [
+                FseState::new(&table, &mut bits),
+                FseState::new(&table, &mut bits),
+            ];
+
+            let mut weights = Vec::new();
+            'decode: loop {
+                for i in 0..2 {
+                    weights.push(states[i].symbol());
+                    states[i].update(&mut bits);
+                    if bits.overflowed() {
+                        weights.push(states[1 - i].symbol());
+                        break 'decode;
+                    }
+                }
+                if weights.len() > 255 {
+                    return Err(invalid_data("too many Huffman weights"));
+                }
+            }
+            (weights, len)
+        } else {
+            // 4 bit weights, two per byte
+            let count = header as usize - 127;
+            let len = 1 + count.div_ceil(2);
+            let packed = data
+                .get(1..len)
+                .ok_or_else(|| invalid_data("truncated Huffman tree"))?;
+            let weights = packed
+                .iter()
+                .flat_map(|&byte| [byte >> 4, byte & 0x0F])
+                .take(count)
+                .collect();
+            (weights, len)
+        };
+
+        Ok((Self::from_weights(weights)?, len))
+    }
+
+    /// Builds the table from the weights of all symbols but the last, whose
+    /// weight is whatever makes the code complete.
+    fn from_weights(mut weights: Vec<u8>) -> io::Result<Self> {
+        if weights.len() > 255 {
+            return Err(invalid_data("too many Huffman weights"));
+        }
+        let mut total = 0u32;
+        for &weight in &weights {
+            if weight > MAX_BITS {
+                return Err(invalid_data("Huffman weight too large"));
+            }
+            if weight > 0 {
+                total += 1 << (weight - 1);
+            }
+        }
+        if total == 0 {
+            return Err(invalid_data("Huffman tree without symbols"));
+        }
+        let max_bits = (32 - total.leading_zeros()) as u8;
+        let rest = (1 << max_bits) - total;
+        if max_bits > MAX_BITS || !rest.is_power_of_two() {
+            return Err(invalid_data("invalid Huffman weights"));
+        }
+        weights.push(rest.trailing_zeros() as u8 + 1);
+
+        // Lower weights, meaning longer codes, come first, and within a
+        // weight symbols in order
+        let mut entries = Vec::with_capacity(1 << max_bits);
+        for weight in 1..=max_bits {
+            for (symbol, _) in weights.iter().enumerate().filter(|(_, &w)| w == weight) {
+                let len = max_bits + 1 - weight;
+                let span = 1 << (weight - 1);
+                entries.extend(std::iter::repeat_n((symbol as u8, len), span));
+            }
+        }
+
+        Ok(Self { max_bits, entries })
+    }
+
+    /// Decodes one stream of exactly `count` symbols.
+    pub fn decode_stream(&self, data: &[u8], count: usize, output: &mut Vec<u8>) -> io::Result<()> {
+        let mut bits = BackwardBitReader::new(data)?;
+        for _ in 0..count {
+            let (symbol, len) = self.entries[bits.peek_bits(self.max_bits) as usize];
+            bits.consume(len);
+            output.push(symbol);
+        }
+        if !bits.is_empty() {
+            return Err(invalid_data(
+                "Huffman stream does not end with its last symbol",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Decodes `count` symbols from 1 stream, or from 4 after a jump table
+    /// of the first three streams' sizes.
+    pub fn decode(
+        &self,
+        data: &[u8],
+        count: usize,
+        four_streams: bool,
+        output: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        if !four_streams {
+            return self.decode_stream(data, count, output);
+        }
+
+        if data.len() < 6 {
+            return Err(invalid_data("truncated jump table"));
+        }
+        let mut starts = [6, 0, 0, 0, data.len()];
+        for i in 0..3 {
+            let size = u16::from_le_bytes([data[2 * i], data[2 * i + 1]]) as usize;
+            starts[i + 1] = starts[i] + size;
+        }
+        if starts[3] > data.len() {
+            return Err(invalid_data("jump table points past the literals"));
+        }
+
+        let per_stream = count.div_ceil(4);
+        if per_stream * 3 > count {
+            return Err(invalid_data("too few literals for 4 streams"));
+        }
+        for i in 0..4 {
+            let n = if i < 3 {
+                per_stream
+            } else {
+                count - 3 * per_stream
+            };
+            self.decode_stream(&data[starts[i]..starts[i + 1]], n, output)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_codes_from_weights() {
+        // Weights 1, 1, 2 and an implied 3: codes of 3, 3, 2 and 1 bits
+        let table = HuffmanTable::from_weights(vec![1, 1, 2]).unwrap();
+        assert_eq!(table.max_bits, 3);
+        assert_eq!(
+            table.entries,
+            [
+                (0, 3),
+                (1, 3),
+                (2, 2),
+                (2, 2),
+                (3, 1),
+                (3, 1),
+                (3, 1),
+                (3, 1)
+            ]
+        );
+
+        // Weights that cannot be completed
+        assert!(HuffmanTable::from_weights(vec![2, 2, 1]).is_err());
+    }
+
+    #[test]
+    fn decodes_streams() {
+        let table = HuffmanTable::from_weights(vec![1, 1, 2]).unwrap();
+        // Symbols 3, 2 and 0 read from the end: the marker, then 1, 01
+        // and 000
+        let data = [0b0110_1000];
+        let mut output = Vec::new();
+        table.decode_stream(&data, 3, &mut output).unwrap();
+        assert_eq!(output, [3, 2, 0]);
+
+        assert!(table.decode_stream(&data, 2, &mut Vec::new()).is_err());
+    }
+}
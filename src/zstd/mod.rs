@@ -0,0 +1,370 @@
+use std::io::{self, Read, Write};
+
+use crate::checksum::{self, XxHash64};
+use crate::format;
+
+pub mod bits;
+pub mod block;
+pub mod fse;
+pub mod huffman;
+
+use self::block::{BlockDecoder, MAX_BLOCK_SIZE};
+
+/// Magic number every zstd frame starts with, 0xFD2FB528 little-endian.
+pub const MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// Frame header descriptor flags.
+const SINGLE_SEGMENT: u8 = 0x20;
+const RESERVED: u8 = 0x08;
+const CONTENT_CHECKSUM: u8 = 0x04;
+
+/// Block types.
+const RAW_BLOCK: u8 = 0;
+const RLE_BLOCK: u8 = 1;
+const COMPRESSED_BLOCK: u8 = 2;
+
+/// Largest window accepted, the default limit of the reference decoder.
+const MAX_WINDOW_LOG: u32 = 27;
+
+/// Zstandard frames, as in `.zst` files.
+///
+/// Decoding supports the whole format except dictionaries: raw, RLE and
+/// compressed blocks, Huffman coded literals, FSE coded sequences with
+/// repeat offsets, concatenated and skippable frames, and the xxHash64
+/// content checksum. There is no encoder.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ZstdCodec;
+
+impl crate::codec::Codec for ZstdCodec {
+    fn name(&self) -> &'static str {
+        "zstd"
+    }
+
+    fn encode(&self, _data: &[u8], _writer: &mut dyn Write) -> io::Result<u64> {
+        Err(no_encoder())
+    }
+
+    fn decode(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<usize> {
+        let bytes_written = io::copy(&mut ZstdDecoder::new(reader)?, writer)?;
+        Ok(bytes_written as usize)
+    }
+}
+
+/// What the header of the frame being read says.
+struct Frame {
+    window_size: usize,
+    block_max: usize,
+    checksum: bool,
+    content_size: Option<u64>,
+}
+
+/// Decompresses zstd frames as they are read, one block at a time. Frames
+/// written one after the other decode to their concatenated data, and
+/// skippable frames are skipped.
+pub struct ZstdDecoder<R: Read> {
+    reader: R,
+    frame: Frame,
+    /// Boxed, its tables being large next to the rest.
+    blocks: Box<BlockDecoder>,
+    /// Data of the current block, after at least a window's worth of the
+    /// frame's data before it for matches to refer to.
+    buffer: Vec<u8>,
+    /// Bytes of `buffer` already returned.
+    pos: usize,
+    block: Vec<u8>,
+    content: XxHash64,
+    content_len: u64,
+    /// The frame's last block was read.
+    last_block: bool,
+    done: bool,
+}
+
+impl<R: Read> ZstdDecoder<R> {
+    /// Reads the first frame's header.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let frame = read_frame_header(&mut reader)?
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        Ok(Self {
+            reader,
+            frame,
+            blocks: Box::default(),
+            buffer: Vec::new(),
+            pos: 0,
+            block: Vec::new(),
+            content: XxHash64::new(),
+            content_len: 0,
+            last_block: false,
+            done: false,
+        })
+    }
+
+    /// Decodes the next block into `buffer`, or finishes the frame after
+    /// its last block and moves on to the next one.
+    fn read_block(&mut self) -> io::Result<()> {
+        if self.last_block {
+            return self.end_frame();
+        }
+
+        let mut header = [0; 4];
+        self.reader.read_exact(&mut header[..3])?;
+        let header = u32::from_le_bytes(header);
+        let block_type = (header >> 1 & 0x03) as u8;
+        let size = (header >> 3) as usize;
+        self.last_block = header & 1 != 0;
+        if size > self.frame.block_max {
+            return Err(invalid_data("block larger than the maximum block size"));
+        }
+
+        // Drop what matches can no longer reach, now and then rather than
+        // on every block
+        if self.buffer.len() > 2 * self.frame.window_size {
+            self.buffer
+                .drain(..self.buffer.len() - self.frame.window_size);
+        }
+        self.pos = self.buffer.len();
+        match block_type {
+            RAW_BLOCK => {
+                self.block.resize(size, 0);
+                self.reader.read_exact(&mut self.block)?;
+                self.buffer.extend_from_slice(&self.block);
+            }
+            RLE_BLOCK => {
+                let mut byte = [0; 1];
+                self.reader.read_exact(&mut byte)?;
+                self.buffer.resize(self.pos + size, byte[0]);
+            }
+            COMPRESSED_BLOCK => {
+                self.block.resize(size, 0);
+                self.reader.read_exact(&mut self.block)?;
+                self.blocks
+                    .decode(&self.block, &mut self.buffer, self.frame.window_size)?;
+                if self.buffer.len() - self.pos > self.frame.block_max {
+                    return Err(invalid_data("block larger than the maximum block size"));
+                }
+            }
+            _ => return Err(invalid_data("reserved block type")),
+        }
+
+        self.content.update(&self.buffer[self.pos..]);
+        self.content_len += (self.buffer.len() - self.pos) as u64;
+        Ok(())
+    }
+
+    fn end_frame(&mut self) -> io::Result<()> {
+        if self.frame.checksum {
+            let mut expected = [0; 4];
+            self.reader.read_exact(&mut expected)?;
+            // The frame keeps the low 32 bits of the xxHash64
+            checksum::verify_named(
+                "xxhash64",
+                u32::from_le_bytes(expected).into(),
+                self.content.finish() & 0xFFFF_FFFF,
+            )?;
+        }
+        if self
+            .frame
+            .content_size
+            .is_some_and(|size| size != self.content_len)
+        {
+            return Err(invalid_data("content size mismatch"));
+        }
+
+        match read_frame_header(&mut self.reader)? {
+            Some(frame) => {
+                self.frame = frame;
+                *self.blocks = BlockDecoder::new();
+                self.buffer.clear();
+                self.pos = 0;
+                self.content = XxHash64::new();
+                self.content_len = 0;
+                self.last_block = false;
+            }
+            None => self.done = true,
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ZstdDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buffer.len() && !self.done {
+            self.read_block()?;
+        }
+
+        let n = buf.len().min(self.buffer.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Reads frames up to the header of the next zstd frame, skipping skippable
+/// ones. None at the end of the input.
+fn read_frame_header<R: Read>(reader: &mut R) -> io::Result<Option<Frame>> {
+    match format::next_frame_magic(reader)? {
+        None => Ok(None),
+        Some(MAGIC) => read_descriptor(reader).map(Some),
+        Some(_) => Err(invalid_data("not a zstd frame")),
+    }
+}
+
+fn read_descriptor<R: Read>(reader: &mut R) -> io::Result<Frame> {
+    let mut flags = [0; 1];
+    reader.read_exact(&mut flags)?;
+    let flags = flags[0];
+    if flags & RESERVED != 0 {
+        return Err(invalid_data("reserved frame header bit set"));
+    }
+    let single_segment = flags & SINGLE_SEGMENT != 0;
+
+    let mut window_size = 0;
+    if !single_segment {
+        let mut descriptor = [0; 1];
+        reader.read_exact(&mut descriptor)?;
+        let window_log = 10 + (descriptor[0] >> 3) as u32;
+        if window_log > MAX_WINDOW_LOG {
+            return Err(invalid_data("window larger than 128M"));
+        }
+        let base = 1usize << window_log;
+        window_size = base + (base / 8) * (descriptor[0] & 0x07) as usize;
+    }
+
+    let dict_id_len = [0, 1, 2, 4][(flags & 0x03) as usize];
+    let mut dict_id = [0; 4];
+    reader.read_exact(&mut dict_id[..dict_id_len])?;
+    if dict_id != [0; 4] {
+        return Err(invalid_data("frames using a dictionary are not supported"));
+    }
+
+    let content_size_len = match flags >> 6 {
+        0 if single_segment => 1,
+        0 => 0,
+        1 => 2,
+        2 => 4,
+        _ => 8,
+    };
+    let mut content_size = None;
+    if content_size_len > 0 {
+        let mut bytes = [0; 8];
+        reader.read_exact(&mut bytes[..content_size_len])?;
+        let mut size = u64::from_le_bytes(bytes);
+        if content_size_len == 2 {
+            size += 256;
+        }
+        content_size = Some(size);
+    }
+
+    if single_segment {
+        // The window is the whole content
+        let size = content_size.unwrap_or_default();
+        if size > 1 << MAX_WINDOW_LOG {
+            return Err(invalid_data("window larger than 128M"));
+        }
+        window_size = size as usize;
+    }
+
+    Ok(Frame {
+        window_size,
+        block_max: window_size.min(MAX_BLOCK_SIZE),
+        checksum: flags & CONTENT_CHECKSUM != 0,
+        content_size,
+    })
+}
+
+pub(crate) fn no_encoder() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "zstd can only be decoded, not encoded",
+    )
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    format::invalid_data("zstd", msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Codec;
+    use crate::deflate::tests::LOREM;
+
+    fn decode(data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        ZstdCodec.decode(&mut &data[..], &mut decoded)?;
+        Ok(decoded)
+    }
+
+    #[test]
+    fn decodes_files_from_zstd() {
+        // Written by `zstd -19`: compressed literals and sequences
+        let data = include_bytes!("testdata/lorem.txt.zst");
+        assert_eq!(decode(data).unwrap(), LOREM);
+
+        // Written by `zstd -3` from LOREM repeated 40 times and a run of
+        // zeros: several blocks, repeat offsets and tables reused from the
+        // block before
+        let data = include_bytes!("testdata/blocks.zst");
+        let mut expected = LOREM.repeat(40);
+        expected.extend(vec![0; 200_000]);
+        assert_eq!(decode(data).unwrap(), expected);
+    }
+
+    /// A frame of a single raw block, with a one-byte content size and no
+    /// checksum.
+    fn stored_frame(data: &[u8]) -> Vec<u8> {
+        let mut frame = MAGIC.to_vec();
+        frame.extend([SINGLE_SEGMENT, data.len() as u8]);
+        let header = (data.len() as u32) << 3 | (RAW_BLOCK as u32) << 1 | 1;
+        frame.extend(&header.to_le_bytes()[..3]);
+        frame.extend(data);
+        frame
+    }
+
+    #[test]
+    fn decodes_concatenated_and_skippable_frames() {
+        let mut data = stored_frame(b"abc");
+        data.extend(0x184D2A53u32.to_le_bytes());
+        data.extend(3u32.to_le_bytes());
+        data.extend(b"xyz");
+        data.extend(stored_frame(b"def"));
+
+        assert_eq!(decode(&data).unwrap(), b"abcdef");
+    }
+
+    #[test]
+    fn does_not_encode() {
+        let err = ZstdCodec.encode(LOREM, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn rejects_matches_beyond_the_window() {
+        // Matches reach further back than this, though not before the
+        // start of the frame
+        let data = include_bytes!("testdata/lorem.txt.zst");
+        let mut decoder = ZstdDecoder::new(&data[..]).unwrap();
+        decoder.frame.window_size = 1024;
+
+        let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("beyond the window"));
+    }
+
+    #[test]
+    fn detects_corruption() {
+        let mut encoded = include_bytes!("testdata/lorem.txt.zst").to_vec();
+        let last = encoded.len() - 1;
+        encoded[last] ^= 0x01;
+        let err = decode(&encoded).unwrap_err();
+        assert!(err
+            .get_ref()
+            .is_some_and(|e| e.is::<checksum::ChecksumMismatch>()));
+
+        // Flipping bits in the compressed data is caught one way or another
+        let original = include_bytes!("testdata/lorem.txt.zst");
+        for i in (6..original.len() - 4).step_by(97) {
+            let mut corrupted = original.to_vec();
+            corrupted[i] ^= 0x10;
+            assert!(decode(&corrupted).is_err(), "byte {}", i);
+        }
+    }
+}